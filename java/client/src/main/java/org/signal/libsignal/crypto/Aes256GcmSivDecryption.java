@@ -0,0 +1,66 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.crypto;
+
+import static org.signal.libsignal.internal.FilterExceptions.filterExceptions;
+
+import org.signal.libsignal.internal.Native;
+import org.signal.libsignal.internal.NativeHandleGuard;
+import org.signal.libsignal.protocol.InvalidKeyException;
+import org.signal.libsignal.protocol.InvalidMessageException;
+
+/**
+ * An AES-256-GCM-SIV decryption that accepts the ciphertext in pieces.
+ *
+ * <p>The entire ciphertext is kept in memory, and nothing is decrypted until the tag has been
+ * checked by {@link #finish}.
+ */
+public class Aes256GcmSivDecryption implements NativeHandleGuard.Owner {
+  public static final int TAG_SIZE_IN_BYTES = 16;
+
+  private long unsafeHandle;
+
+  public Aes256GcmSivDecryption(byte[] key, byte[] nonce, byte[] associatedData)
+      throws InvalidKeyException {
+    this.unsafeHandle =
+        filterExceptions(
+            InvalidKeyException.class,
+            () -> Native.Aes256GcmSivDecryption_New(key, nonce, associatedData));
+  }
+
+  @Override
+  @SuppressWarnings("deprecation")
+  protected void finalize() {
+    Native.Aes256GcmSivDecryption_Destroy(this.unsafeHandle);
+  }
+
+  public long unsafeNativeHandleWithoutGuard() {
+    return this.unsafeHandle;
+  }
+
+  public void update(byte[] ciphertext, int offset, int length) {
+    try (NativeHandleGuard guard = new NativeHandleGuard(this)) {
+      Native.Aes256GcmSivDecryption_Update(guard.nativeHandle(), ciphertext, offset, length);
+    }
+  }
+
+  public void update(byte[] ciphertext) {
+    update(ciphertext, 0, ciphertext.length);
+  }
+
+  /** Checks {@code tag} and returns the plaintext. */
+  public byte[] finish(byte[] tag) throws InvalidMessageException {
+    try (NativeHandleGuard guard = new NativeHandleGuard(this)) {
+      byte[] plaintext =
+          filterExceptions(
+              InvalidMessageException.class,
+              () -> Native.Aes256GcmSivDecryption_Finalize(guard.nativeHandle(), tag));
+      Native.Aes256GcmSivDecryption_Destroy(guard.nativeHandle());
+      this.unsafeHandle = 0;
+      return plaintext;
+    }
+  }
+}
@@ -0,0 +1,61 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.crypto;
+
+import static org.signal.libsignal.internal.FilterExceptions.filterExceptions;
+
+import org.signal.libsignal.internal.Native;
+import org.signal.libsignal.internal.NativeHandleGuard;
+import org.signal.libsignal.protocol.InvalidKeyException;
+
+/**
+ * An AES-256-GCM-SIV encryption that accepts the plaintext in pieces.
+ *
+ * <p>GCM-SIV can't produce any ciphertext until it has seen the whole message, so the entire
+ * plaintext is kept in memory until {@link #finish} is called. Use {@link Aes256GcmEncryption} to
+ * encrypt messages too large to hold in memory.
+ */
+public class Aes256GcmSivEncryption implements NativeHandleGuard.Owner {
+  private long unsafeHandle;
+
+  public Aes256GcmSivEncryption(byte[] key, byte[] nonce, byte[] associatedData)
+      throws InvalidKeyException {
+    this.unsafeHandle =
+        filterExceptions(
+            InvalidKeyException.class,
+            () -> Native.Aes256GcmSivEncryption_New(key, nonce, associatedData));
+  }
+
+  @Override
+  @SuppressWarnings("deprecation")
+  protected void finalize() {
+    Native.Aes256GcmSivEncryption_Destroy(this.unsafeHandle);
+  }
+
+  public long unsafeNativeHandleWithoutGuard() {
+    return this.unsafeHandle;
+  }
+
+  public void update(byte[] plaintext, int offset, int length) {
+    try (NativeHandleGuard guard = new NativeHandleGuard(this)) {
+      Native.Aes256GcmSivEncryption_Update(guard.nativeHandle(), plaintext, offset, length);
+    }
+  }
+
+  public void update(byte[] plaintext) {
+    update(plaintext, 0, plaintext.length);
+  }
+
+  /** Returns the ciphertext followed by the 16-byte tag. */
+  public byte[] finish() {
+    try (NativeHandleGuard guard = new NativeHandleGuard(this)) {
+      byte[] ciphertext = Native.Aes256GcmSivEncryption_Finalize(guard.nativeHandle());
+      Native.Aes256GcmSivEncryption_Destroy(guard.nativeHandle());
+      this.unsafeHandle = 0;
+      return ciphertext;
+    }
+  }
+}
@@ -0,0 +1,56 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.crypto;
+
+import static org.signal.libsignal.internal.FilterExceptions.filterExceptions;
+
+import org.signal.libsignal.internal.Native;
+import org.signal.libsignal.internal.NativeHandleGuard;
+import org.signal.libsignal.protocol.InvalidKeyException;
+
+public class ChaCha20Poly1305Encryption implements NativeHandleGuard.Owner {
+  private long unsafeHandle;
+
+  public ChaCha20Poly1305Encryption(byte[] key, byte[] nonce, byte[] associatedData)
+      throws InvalidKeyException {
+    this.unsafeHandle =
+        filterExceptions(
+            InvalidKeyException.class,
+            () -> Native.ChaCha20Poly1305Encryption_New(key, nonce, associatedData));
+  }
+
+  @Override
+  @SuppressWarnings("deprecation")
+  protected void finalize() {
+    Native.ChaCha20Poly1305Encryption_Destroy(this.unsafeHandle);
+  }
+
+  public long unsafeNativeHandleWithoutGuard() {
+    return this.unsafeHandle;
+  }
+
+  public void encrypt(byte[] plaintext, int offset, int length) {
+    try (NativeHandleGuard guard = new NativeHandleGuard(this)) {
+      Native.ChaCha20Poly1305Encryption_Update(guard.nativeHandle(), plaintext, offset, length);
+    }
+  }
+
+  public void encrypt(byte[] plaintext) {
+    try (NativeHandleGuard guard = new NativeHandleGuard(this)) {
+      Native.ChaCha20Poly1305Encryption_Update(
+          guard.nativeHandle(), plaintext, 0, plaintext.length);
+    }
+  }
+
+  public byte[] computeTag() {
+    try (NativeHandleGuard guard = new NativeHandleGuard(this)) {
+      byte[] tag = Native.ChaCha20Poly1305Encryption_ComputeTag(guard.nativeHandle());
+      Native.ChaCha20Poly1305Encryption_Destroy(guard.nativeHandle());
+      this.unsafeHandle = 0;
+      return tag;
+    }
+  }
+}
@@ -44,4 +44,11 @@ public class CryptographicMac implements NativeHandleGuard.Owner {
       return Native.CryptographicMac_Finalize(guard.nativeHandle());
     }
   }
+
+  /** Finishes the MAC and checks, in constant time, whether it matches {@code expected}. */
+  public boolean verify(byte[] expected) {
+    try (NativeHandleGuard guard = new NativeHandleGuard(this)) {
+      return Native.CryptographicMac_Verify(guard.nativeHandle(), expected);
+    }
+  }
 }
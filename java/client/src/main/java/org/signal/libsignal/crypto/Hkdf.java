@@ -0,0 +1,45 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.crypto;
+
+import static org.signal.libsignal.internal.FilterExceptions.filterExceptions;
+
+import org.signal.libsignal.internal.Native;
+import org.signal.libsignal.protocol.InvalidKeyException;
+
+/** HKDF (RFC 5869), with its extract and expand steps available separately. */
+public class Hkdf {
+  private final String algo;
+
+  /**
+   * @param algo "HkdfSha256" or "HkdfSha512"
+   */
+  public Hkdf(String algo) {
+    this.algo = algo;
+  }
+
+  public byte[] extract(byte[] salt, byte[] ikm) {
+    return filterExceptions(() -> Native.Hkdf_Extract(this.algo, salt, ikm));
+  }
+
+  /**
+   * @throws InvalidKeyException if {@code prk} is shorter than the hash output
+   * @throws IllegalArgumentException if {@code outputLength} is more than 255 times the hash output
+   */
+  public byte[] expand(byte[] prk, byte[] info, int outputLength) throws InvalidKeyException {
+    return filterExceptions(
+        InvalidKeyException.class,
+        () -> Native.Hkdf_Expand(this.algo, prk, info, outputLength));
+  }
+
+  public byte[] deriveSecrets(byte[] salt, byte[] ikm, byte[] info, int outputLength) {
+    try {
+      return expand(extract(salt, ikm), info, outputLength);
+    } catch (InvalidKeyException e) {
+      throw new AssertionError("extract always produces a full-length key", e);
+    }
+  }
+}
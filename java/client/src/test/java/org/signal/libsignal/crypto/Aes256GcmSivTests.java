@@ -6,6 +6,7 @@
 package org.signal.libsignal.crypto;
 
 import java.io.IOException;
+import java.util.Arrays;
 import junit.framework.TestCase;
 import org.signal.libsignal.protocol.InvalidKeyException;
 import org.signal.libsignal.protocol.InvalidMessageException;
@@ -45,6 +46,39 @@ public class Aes256GcmSivTests extends TestCase {
         "874296d5cc1fd16132");
   }
 
+  public void testAesGcmSivIncremental() throws Exception {
+    byte[] key =
+        Hex.fromStringCondensed("bae8e37fc83441b16034566b7a806c46bb91c3c5aedb64a6c590bc84d1a5e269");
+    byte[] plaintext = Hex.fromStringCondensed("671fdd4fbdc66f146545fc880c94a95198");
+    byte[] nonce = Hex.fromStringCondensed("e4b47801afc0577e34699b9e");
+    byte[] ad = Hex.fromStringCondensed("874296d5cc1fd16132");
+    String hex_ciphertext = "9209cfae7372e0a3ec2e5d072d5e26b7b9f3acb73908e54cddf7be1864914e13cf";
+
+    Aes256GcmSivEncryption gcmSivEnc = new Aes256GcmSivEncryption(key, nonce, ad);
+    gcmSivEnc.update(plaintext, 0, 1);
+    gcmSivEnc.update(plaintext, 1, plaintext.length - 1);
+    byte[] ciphertextAndTag = gcmSivEnc.finish();
+    assertEquals(Hex.toStringCondensed(ciphertextAndTag), hex_ciphertext);
+
+    int ciphertextLength = ciphertextAndTag.length - Aes256GcmSivDecryption.TAG_SIZE_IN_BYTES;
+    byte[] tag = Arrays.copyOfRange(ciphertextAndTag, ciphertextLength, ciphertextAndTag.length);
+
+    Aes256GcmSivDecryption gcmSivDec = new Aes256GcmSivDecryption(key, nonce, ad);
+    gcmSivDec.update(ciphertextAndTag, 0, 1);
+    gcmSivDec.update(ciphertextAndTag, 1, ciphertextLength - 1);
+    assertEquals(Hex.toStringCondensed(gcmSivDec.finish(tag)), Hex.toStringCondensed(plaintext));
+
+    tag[0] ^= 1;
+    Aes256GcmSivDecryption gcmSivDec2 = new Aes256GcmSivDecryption(key, nonce, ad);
+    gcmSivDec2.update(ciphertextAndTag, 0, ciphertextLength);
+    try {
+      gcmSivDec2.finish(tag);
+      throw new AssertionError("Should not have decrypted");
+    } catch (InvalidMessageException e) {
+      /* good */
+    }
+  }
+
   private static void testAesGcmSivKat(
       String hex_key,
       String hex_plaintext,
@@ -0,0 +1,90 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.crypto;
+
+import java.util.Arrays;
+import junit.framework.TestCase;
+import org.signal.libsignal.protocol.InvalidKeyException;
+import org.signal.libsignal.protocol.util.Hex;
+
+public class ChaCha20Poly1305Tests extends TestCase {
+
+  public void testChaCha20Poly1305InvalidInputs() throws Exception {
+    try {
+      new ChaCha20Poly1305Encryption(new byte[16], new byte[12], new byte[0]);
+      throw new AssertionError("Invalid key length accepted");
+    } catch (InvalidKeyException e) {
+      /* good */
+    }
+
+    try {
+      new ChaCha20Poly1305Decryption(new byte[32], new byte[8], new byte[0]);
+      throw new AssertionError("Invalid nonce length accepted");
+    } catch (IllegalArgumentException e) {
+      /* good */
+    }
+  }
+
+  public void testChaCha20Poly1305Kats() throws Exception {
+    // RFC 8439 section 2.8.2
+    testChaCha20Poly1305Kat(
+        "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+        "4c616469657320616e642047656e746c656d656e206f662074686520636c617373206f66202739393a204966204920636f756c64206f6666657220796f75206f6e6c79206f6e652074697020666f7220746865206675747572652c2073756e73637265656e20776f756c642062652069742e",
+        "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b6116",
+        "1ae10b594f09e26a7e902ecbd0600691",
+        "070000004041424344454647",
+        "50515253c0c1c2c3c4c5c6c7");
+  }
+
+  private static void testChaCha20Poly1305Kat(
+      String hex_key,
+      String hex_plaintext,
+      String hex_ciphertext,
+      String hex_tag,
+      String hex_nonce,
+      String hex_associated_data)
+      throws Exception {
+
+    byte[] key = Hex.fromStringCondensed(hex_key);
+    byte[] plaintext = Hex.fromStringCondensed(hex_plaintext);
+    byte[] nonce = Hex.fromStringCondensed(hex_nonce);
+    byte[] ad = Hex.fromStringCondensed(hex_associated_data);
+
+    ChaCha20Poly1305Encryption chachaEnc = new ChaCha20Poly1305Encryption(key, nonce, ad);
+    byte[] ciphertext = plaintext.clone();
+    chachaEnc.encrypt(ciphertext);
+    byte[] tag = chachaEnc.computeTag();
+    assertEquals(Hex.toStringCondensed(ciphertext), hex_ciphertext);
+    assertEquals(Hex.toStringCondensed(tag), hex_tag);
+
+    ChaCha20Poly1305Decryption chachaDec = new ChaCha20Poly1305Decryption(key, nonce, ad);
+    byte[] decrypted = ciphertext.clone();
+    chachaDec.decrypt(decrypted);
+    assertEquals(Hex.toStringCondensed(decrypted), hex_plaintext);
+    assertEquals(chachaDec.verifyTag(tag), true);
+
+    ChaCha20Poly1305Encryption chachaEnc2 = new ChaCha20Poly1305Encryption(key, nonce, ad);
+    byte[] ciphertextSplit = plaintext.clone();
+    chachaEnc2.encrypt(ciphertextSplit, 0, 1);
+    chachaEnc2.encrypt(ciphertextSplit, 1, plaintext.length - 1);
+    byte[] tag2 = chachaEnc2.computeTag();
+    assertEquals(Hex.toStringCondensed(ciphertextSplit), hex_ciphertext);
+    assertEquals(Hex.toStringCondensed(tag2), hex_tag);
+
+    ChaCha20Poly1305Decryption chachaDec2 = new ChaCha20Poly1305Decryption(key, nonce, ad);
+    byte[] decryptedSplit = ciphertext.clone();
+    chachaDec2.decrypt(decryptedSplit, 0, 1);
+    chachaDec2.decrypt(decryptedSplit, 1, ciphertext.length - 1);
+    assertEquals(Hex.toStringCondensed(decryptedSplit), hex_plaintext);
+    assertEquals(chachaDec2.verifyTag(tag), true);
+
+    byte[] badTag = Arrays.copyOf(tag, tag.length);
+    badTag[0] ^= 1;
+    ChaCha20Poly1305Decryption chachaDec3 = new ChaCha20Poly1305Decryption(key, nonce, ad);
+    chachaDec3.decrypt(ciphertext.clone());
+    assertEquals(chachaDec3.verifyTag(badTag), false);
+  }
+}
@@ -31,6 +31,14 @@ public class CryptographicMacTests extends TestCase {
       byte[] macSplit2 = hmac.finish();
       assertEquals(Hex.toStringCondensed(macSplit2), hexExpectedOutput);
     }
+
+    byte[] expected = Hex.fromStringCondensed(hexExpectedOutput);
+    hmac.update(input);
+    assertTrue(hmac.verify(expected));
+
+    expected[0] ^= 1;
+    hmac.update(input);
+    assertFalse(hmac.verify(expected));
   }
 
   public void testHmacSha1() throws Exception {
@@ -62,4 +70,19 @@ public class CryptographicMacTests extends TestCase {
         "7768617420646f2079612077616e7420666f72206e6f7468696e673f",
         "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
   }
+
+  public void testHmacSha512() throws Exception {
+    // RFC 4231
+    hmacKat(
+        "HmacSha512",
+        "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+        "4869205468657265",
+        "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cdedaa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854");
+
+    hmacKat(
+        "HmacSha512",
+        "4a656665",
+        "7768617420646f2079612077616e7420666f72206e6f7468696e673f",
+        "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737");
+  }
 }
@@ -0,0 +1,73 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.crypto;
+
+import junit.framework.TestCase;
+import org.signal.libsignal.protocol.InvalidKeyException;
+import org.signal.libsignal.protocol.util.Hex;
+
+public class HkdfTests extends TestCase {
+
+  private static final byte[] IKM =
+      Hex.fromStringCondensed("0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b");
+  private static final byte[] SALT = Hex.fromStringCondensed("000102030405060708090a0b0c");
+  private static final byte[] INFO = Hex.fromStringCondensed("f0f1f2f3f4f5f6f7f8f9");
+
+  public void testHkdfSha256() throws Exception {
+    // RFC 5869 appendix A.1
+    Hkdf hkdf = new Hkdf("HkdfSha256");
+
+    byte[] prk = hkdf.extract(SALT, IKM);
+    assertEquals(
+        Hex.toStringCondensed(prk),
+        "077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5");
+
+    String expectedOkm =
+        "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865";
+    assertEquals(Hex.toStringCondensed(hkdf.expand(prk, INFO, 42)), expectedOkm);
+    assertEquals(Hex.toStringCondensed(hkdf.deriveSecrets(SALT, IKM, INFO, 42)), expectedOkm);
+  }
+
+  public void testHkdfSha512() throws Exception {
+    // The inputs from RFC 5869 appendix A.1, which has no SHA-512 outputs of its own.
+    Hkdf hkdf = new Hkdf("HkdfSha512");
+
+    byte[] prk = hkdf.extract(SALT, IKM);
+    assertEquals(
+        Hex.toStringCondensed(prk),
+        "665799823737ded04a88e47e54a5890bb2c3d247c7a4254a8e61350723590a26c36238127d8661b88cf80ef802d57e2f7cebcf1e00e083848be19929c61b4237");
+    assertEquals(
+        Hex.toStringCondensed(hkdf.expand(prk, INFO, 42)),
+        "832390086cda71fb47625bb5ceb168e4c8e26a1a16ed34d9fc7fe92c1481579338da362cb8d9f925d7cb");
+  }
+
+  public void testHkdfRejectsBadLengths() throws Exception {
+    Hkdf hkdf = new Hkdf("HkdfSha256");
+    byte[] prk = hkdf.extract(SALT, IKM);
+
+    hkdf.expand(prk, INFO, 255 * 32);
+    try {
+      hkdf.expand(prk, INFO, 255 * 32 + 1);
+      throw new AssertionError("Overlong output accepted");
+    } catch (IllegalArgumentException e) {
+      /* good */
+    }
+
+    try {
+      hkdf.expand(new byte[16], INFO, 32);
+      throw new AssertionError("Short PRK accepted");
+    } catch (InvalidKeyException e) {
+      /* good */
+    }
+
+    try {
+      new Hkdf("HkdfSha384").extract(SALT, IKM);
+      throw new AssertionError("Unknown algorithm accepted");
+    } catch (IllegalArgumentException e) {
+      /* good */
+    }
+  }
+}
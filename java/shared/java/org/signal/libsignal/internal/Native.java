@@ -129,6 +129,16 @@ public final class Native {
   public static native long Aes256GcmEncryption_New(byte[] key, byte[] nonce, byte[] associatedData) throws Exception;
   public static native void Aes256GcmEncryption_Update(long gcm, byte[] data, int offset, int length);
 
+  public static native void Aes256GcmSivDecryption_Destroy(long handle);
+  public static native byte[] Aes256GcmSivDecryption_Finalize(long gcmSiv, byte[] tag) throws Exception;
+  public static native long Aes256GcmSivDecryption_New(byte[] key, byte[] nonce, byte[] associatedData) throws Exception;
+  public static native void Aes256GcmSivDecryption_Update(long gcmSiv, byte[] data, int offset, int length);
+
+  public static native void Aes256GcmSivEncryption_Destroy(long handle);
+  public static native byte[] Aes256GcmSivEncryption_Finalize(long gcmSiv);
+  public static native long Aes256GcmSivEncryption_New(byte[] key, byte[] nonce, byte[] associatedData) throws Exception;
+  public static native void Aes256GcmSivEncryption_Update(long gcmSiv, byte[] data, int offset, int length);
+
   public static native byte[] Aes256GcmSiv_Decrypt(long aesGcmSiv, byte[] ctext, byte[] nonce, byte[] associatedData) throws Exception;
   public static native void Aes256GcmSiv_Destroy(long handle);
   public static native byte[] Aes256GcmSiv_Encrypt(long aesGcmSivObj, byte[] ptext, byte[] nonce, byte[] associatedData) throws Exception;
@@ -192,6 +202,16 @@ public final class Native {
   public static native CompletableFuture<Long> CdsiLookup_new(long asyncRuntime, long connectionManager, String username, String password, long request);
   public static native byte[] CdsiLookup_token(long lookup);
 
+  public static native void ChaCha20Poly1305Decryption_Destroy(long handle);
+  public static native long ChaCha20Poly1305Decryption_New(byte[] key, byte[] nonce, byte[] associatedData) throws Exception;
+  public static native void ChaCha20Poly1305Decryption_Update(long chacha, byte[] data, int offset, int length);
+  public static native boolean ChaCha20Poly1305Decryption_VerifyTag(long chacha, byte[] tag) throws Exception;
+
+  public static native byte[] ChaCha20Poly1305Encryption_ComputeTag(long chacha);
+  public static native void ChaCha20Poly1305Encryption_Destroy(long handle);
+  public static native long ChaCha20Poly1305Encryption_New(byte[] key, byte[] nonce, byte[] associatedData) throws Exception;
+  public static native void ChaCha20Poly1305Encryption_Update(long chacha, byte[] data, int offset, int length);
+
   public static native CompletableFuture<Object> ChatService_auth_send(long asyncRuntime, long chat, long httpRequest, int timeoutMillis);
   public static native CompletableFuture<Object> ChatService_auth_send_and_debug(long asyncRuntime, long chat, long httpRequest, int timeoutMillis);
   public static native CompletableFuture<Object> ChatService_connect_auth(long asyncRuntime, long chat);
@@ -240,6 +260,7 @@ public final class Native {
   public static native long CryptographicMac_New(String algo, byte[] key) throws Exception;
   public static native void CryptographicMac_Update(long mac, byte[] input);
   public static native void CryptographicMac_UpdateWithOffset(long mac, byte[] input, int offset, int len);
+  public static native boolean CryptographicMac_Verify(long mac, byte[] expected);
 
   public static native long DecryptionErrorMessage_Deserialize(byte[] data) throws Exception;
   public static native void DecryptionErrorMessage_Destroy(long handle);
@@ -326,6 +347,9 @@ public final class Native {
 
   public static native byte[] HKDF_DeriveSecrets(int outputLength, byte[] ikm, byte[] label, byte[] salt) throws Exception;
 
+  public static native byte[] Hkdf_Expand(String algo, byte[] prk, byte[] info, int outputLength) throws Exception;
+  public static native byte[] Hkdf_Extract(String algo, byte[] salt, byte[] ikm) throws Exception;
+
   public static native void HsmEnclaveClient_CompleteHandshake(long cli, byte[] handshakeReceived) throws Exception;
   public static native void HsmEnclaveClient_Destroy(long handle);
   public static native byte[] HsmEnclaveClient_EstablishedRecv(long cli, byte[] receivedCiphertext) throws Exception;
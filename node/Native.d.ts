@@ -213,6 +213,11 @@ export function CreateCallLinkCredential_CheckValidContents(paramsBytes: Buffer)
 export function CreateCallLinkCredential_PresentDeterministic(credentialBytes: Buffer, roomId: Buffer, userId: Buffer, serverParamsBytes: Buffer, callLinkParamsBytes: Buffer, randomness: Buffer): Buffer;
 export function CreateOTP(username: string, secret: Buffer): string;
 export function CreateOTPFromBase64(username: string, secret: string): string;
+export function CryptographicMac_Finalize(mac: Wrapper<CryptographicMac>): Buffer;
+export function CryptographicMac_New(algo: string, key: Buffer): CryptographicMac;
+export function CryptographicMac_Update(mac: Wrapper<CryptographicMac>, input: Buffer): void;
+export function CryptographicMac_UpdateWithOffset(mac: Wrapper<CryptographicMac>, input: Buffer, offset: number, len: number): void;
+export function CryptographicMac_Verify(mac: Wrapper<CryptographicMac>, expected: Buffer): boolean;
 export function DecryptionErrorMessage_Deserialize(data: Buffer): DecryptionErrorMessage;
 export function DecryptionErrorMessage_ExtractFromSerializedContent(bytes: Buffer): DecryptionErrorMessage;
 export function DecryptionErrorMessage_ForOriginalMessage(originalBytes: Buffer, originalType: number, originalTimestamp: Timestamp, originalSenderDeviceId: number): DecryptionErrorMessage;
@@ -263,6 +268,8 @@ export function GroupSendFullToken_Verify(token: Buffer, userIds: Buffer, now: T
 export function GroupSendToken_CheckValidContents(bytes: Buffer): void;
 export function GroupSendToken_ToFullToken(token: Buffer, expiration: Timestamp): Buffer;
 export function HKDF_DeriveSecrets(outputLength: number, ikm: Buffer, label: Buffer | null, salt: Buffer | null): Buffer;
+export function Hkdf_Expand(algo: string, prk: Buffer, info: Buffer, outputLength: number): Buffer;
+export function Hkdf_Extract(algo: string, salt: Buffer, ikm: Buffer): Buffer;
 export function HsmEnclaveClient_CompleteHandshake(cli: Wrapper<HsmEnclaveClient>, handshakeReceived: Buffer): void;
 export function HsmEnclaveClient_EstablishedRecv(cli: Wrapper<HsmEnclaveClient>, receivedCiphertext: Buffer): Buffer;
 export function HsmEnclaveClient_EstablishedSend(cli: Wrapper<HsmEnclaveClient>, plaintextToSend: Buffer): Buffer;
@@ -560,6 +567,7 @@ interface CiphertextMessage { readonly __type: unique symbol; }
 interface ComparableBackup { readonly __type: unique symbol; }
 interface ComparableBackup { readonly __type: unique symbol; }
 interface ConnectionManager { readonly __type: unique symbol; }
+interface CryptographicMac { readonly __type: unique symbol; }
 interface DecryptionErrorMessage { readonly __type: unique symbol; }
 interface ExpiringProfileKeyCredential { readonly __type: unique symbol; }
 interface ExpiringProfileKeyCredentialResponse { readonly __type: unique symbol; }
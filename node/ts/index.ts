@@ -77,6 +77,36 @@ export function hkdf(
   return Native.HKDF_DeriveSecrets(outputLength, keyMaterial, label, salt);
 }
 
+export type HkdfAlgorithm = 'HkdfSha256' | 'HkdfSha512';
+
+/**
+ * Runs the HKDF-Extract step of RFC 5869, returning the pseudorandom key.
+ *
+ * An empty `salt` is the same as not providing one.
+ */
+export function hkdfExtract(
+  algorithm: HkdfAlgorithm,
+  salt: Buffer,
+  inputKeyMaterial: Buffer
+): Buffer {
+  return Native.Hkdf_Extract(algorithm, salt, inputKeyMaterial);
+}
+
+/**
+ * Runs the HKDF-Expand step of RFC 5869.
+ *
+ * Throws if `prk` is shorter than the hash output, or `outputLength` is more than 255 times the
+ * hash output.
+ */
+export function hkdfExpand(
+  algorithm: HkdfAlgorithm,
+  prk: Buffer,
+  info: Buffer,
+  outputLength: number
+): Buffer {
+  return Native.Hkdf_Expand(algorithm, prk, info, outputLength);
+}
+
 export class ScannableFingerprint {
   private readonly scannable: Buffer;
 
@@ -173,6 +203,33 @@ export class Aes256GcmSiv {
   }
 }
 
+export type MacAlgorithm = 'HmacSha1' | 'HmacSha256' | 'HmacSha512';
+
+export class CryptographicMac {
+  readonly _nativeHandle: Native.CryptographicMac;
+
+  private constructor(algorithm: MacAlgorithm, key: Buffer) {
+    this._nativeHandle = Native.CryptographicMac_New(algorithm, key);
+  }
+
+  static new(algorithm: MacAlgorithm, key: Buffer): CryptographicMac {
+    return new CryptographicMac(algorithm, key);
+  }
+
+  update(input: Buffer): void {
+    Native.CryptographicMac_Update(this, input);
+  }
+
+  finish(): Buffer {
+    return Native.CryptographicMac_Finalize(this);
+  }
+
+  /** Finishes the MAC and checks, in constant time, whether it matches `expected`. */
+  verify(expected: Buffer): boolean {
+    return Native.CryptographicMac_Verify(this, expected);
+  }
+}
+
 export class PublicKey {
   readonly _nativeHandle: Native.PublicKey;
 
@@ -336,6 +336,45 @@ describe('SignalClient', () => {
       '3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865'
     );
   });
+  it('HKDF extract and expand', () => {
+    const secret = Buffer.from(
+      '0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B',
+      'hex'
+    );
+    const salt = Buffer.from('000102030405060708090A0B0C', 'hex');
+    const info = Buffer.from('F0F1F2F3F4F5F6F7F8F9', 'hex');
+
+    const prk = SignalClient.hkdfExtract('HkdfSha256', salt, secret);
+    assert.deepEqual(
+      prk.toString('hex'),
+      '077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5'
+    );
+    assert.deepEqual(
+      SignalClient.hkdfExpand('HkdfSha256', prk, info, 42).toString('hex'),
+      '3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865'
+    );
+    assert.throws(() =>
+      SignalClient.hkdfExpand('HkdfSha256', prk, info, 255 * 32 + 1)
+    );
+  });
+  it('HMAC verify', () => {
+    // RFC 4231 test case 2
+    const key = Buffer.from('Jefe');
+    const data = Buffer.from('what do ya want for nothing?');
+    const expected = Buffer.from(
+      '5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843',
+      'hex'
+    );
+
+    const mac = SignalClient.CryptographicMac.new('HmacSha256', key);
+    mac.update(data);
+    assert.isTrue(mac.verify(expected));
+
+    const badMac = SignalClient.CryptographicMac.new('HmacSha256', key);
+    badMac.update(data);
+    expected[0] ^= 1;
+    assert.isFalse(badMac.verify(expected));
+  });
   describe('ServiceId', () => {
     const testingUuid = '8c78cd2a-16ff-427d-83dc-1a5e36ce713d';
 
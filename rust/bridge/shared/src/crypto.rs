@@ -5,21 +5,28 @@
 
 use ::signal_crypto;
 use libsignal_bridge_macros::*;
-use signal_crypto::{Aes256Ctr32, CryptographicHash, CryptographicMac, Error, Result};
+use signal_crypto::{Aes256Ctr32, CryptographicHash, CryptographicMac, Error, Hkdf, Result};
 
 use aes_gcm_siv::aead::generic_array::typenum::Unsigned;
 use aes_gcm_siv::{AeadCore, AeadInPlace, KeyInit};
-use libsignal_bridge_types::crypto::{Aes256GcmDecryption, Aes256GcmEncryption, Aes256GcmSiv};
+use libsignal_bridge_types::crypto::{
+    Aes256GcmDecryption, Aes256GcmEncryption, Aes256GcmSiv, Aes256GcmSivDecryption,
+    Aes256GcmSivEncryption, ChaCha20Poly1305Decryption, ChaCha20Poly1305Encryption,
+};
 
 use crate::support::*;
 use crate::*;
 
 bridge_handle_fns!(CryptographicHash, ffi = false, node = false);
-bridge_handle_fns!(CryptographicMac, ffi = false, node = false);
+bridge_handle_fns!(CryptographicMac, clone = false);
 bridge_handle_fns!(Aes256GcmSiv, clone = false);
 bridge_handle_fns!(Aes256Ctr32, clone = false, node = false);
 bridge_handle_fns!(Aes256GcmEncryption, clone = false, node = false);
 bridge_handle_fns!(Aes256GcmDecryption, clone = false, node = false);
+bridge_handle_fns!(ChaCha20Poly1305Encryption, clone = false, node = false);
+bridge_handle_fns!(ChaCha20Poly1305Decryption, clone = false, node = false);
+bridge_handle_fns!(Aes256GcmSivEncryption, clone = false, node = false);
+bridge_handle_fns!(Aes256GcmSivDecryption, clone = false, node = false);
 
 #[bridge_fn(node = false)]
 fn Aes256Ctr32_New(key: &[u8], nonce: &[u8], initial_ctr: u32) -> Result<Aes256Ctr32> {
@@ -85,6 +92,61 @@ fn Aes256GcmDecryption_VerifyTag(gcm: &mut Aes256GcmDecryption, tag: &[u8]) -> R
     gcm.verify_tag(tag)
 }
 
+#[bridge_fn(node = false)]
+fn ChaCha20Poly1305Encryption_New(
+    key: &[u8],
+    nonce: &[u8],
+    associated_data: &[u8],
+) -> Result<ChaCha20Poly1305Encryption> {
+    ChaCha20Poly1305Encryption::new(key, nonce, associated_data)
+}
+
+#[bridge_fn(node = false)]
+fn ChaCha20Poly1305Encryption_Update(
+    chacha: &mut ChaCha20Poly1305Encryption,
+    data: &mut [u8],
+    offset: u32,
+    length: u32,
+) {
+    let offset = offset as usize;
+    let length = length as usize;
+    chacha.encrypt(&mut data[offset..offset + length]);
+}
+
+#[bridge_fn(node = false)]
+fn ChaCha20Poly1305Encryption_ComputeTag(chacha: &mut ChaCha20Poly1305Encryption) -> Vec<u8> {
+    chacha.compute_tag()
+}
+
+#[bridge_fn(node = false)]
+fn ChaCha20Poly1305Decryption_New(
+    key: &[u8],
+    nonce: &[u8],
+    associated_data: &[u8],
+) -> Result<ChaCha20Poly1305Decryption> {
+    ChaCha20Poly1305Decryption::new(key, nonce, associated_data)
+}
+
+#[bridge_fn(node = false)]
+fn ChaCha20Poly1305Decryption_Update(
+    chacha: &mut ChaCha20Poly1305Decryption,
+    data: &mut [u8],
+    offset: u32,
+    length: u32,
+) {
+    let offset = offset as usize;
+    let length = length as usize;
+    chacha.decrypt(&mut data[offset..offset + length]);
+}
+
+#[bridge_fn(node = false)]
+fn ChaCha20Poly1305Decryption_VerifyTag(
+    chacha: &mut ChaCha20Poly1305Decryption,
+    tag: &[u8],
+) -> Result<bool> {
+    chacha.verify_tag(tag)
+}
+
 #[bridge_fn]
 fn Aes256GcmSiv_New(key: &[u8]) -> Result<Aes256GcmSiv> {
     Ok(Aes256GcmSiv(
@@ -136,6 +198,61 @@ fn Aes256GcmSiv_Decrypt(
     Ok(buf)
 }
 
+#[bridge_fn(node = false)]
+fn Aes256GcmSivEncryption_New(
+    key: &[u8],
+    nonce: &[u8],
+    associated_data: &[u8],
+) -> Result<Aes256GcmSivEncryption> {
+    Aes256GcmSivEncryption::new(key, nonce, associated_data)
+}
+
+#[bridge_fn(node = false)]
+fn Aes256GcmSivEncryption_Update(
+    gcm_siv: &mut Aes256GcmSivEncryption,
+    data: &[u8],
+    offset: u32,
+    length: u32,
+) {
+    let offset = offset as usize;
+    let length = length as usize;
+    gcm_siv.update(&data[offset..offset + length]);
+}
+
+#[bridge_fn(node = false)]
+fn Aes256GcmSivEncryption_Finalize(gcm_siv: &mut Aes256GcmSivEncryption) -> Vec<u8> {
+    gcm_siv.finalize()
+}
+
+#[bridge_fn(node = false)]
+fn Aes256GcmSivDecryption_New(
+    key: &[u8],
+    nonce: &[u8],
+    associated_data: &[u8],
+) -> Result<Aes256GcmSivDecryption> {
+    Aes256GcmSivDecryption::new(key, nonce, associated_data)
+}
+
+#[bridge_fn(node = false)]
+fn Aes256GcmSivDecryption_Update(
+    gcm_siv: &mut Aes256GcmSivDecryption,
+    data: &[u8],
+    offset: u32,
+    length: u32,
+) {
+    let offset = offset as usize;
+    let length = length as usize;
+    gcm_siv.update(&data[offset..offset + length]);
+}
+
+#[bridge_fn(node = false)]
+fn Aes256GcmSivDecryption_Finalize(
+    gcm_siv: &mut Aes256GcmSivDecryption,
+    tag: &[u8],
+) -> Result<Vec<u8>> {
+    gcm_siv.finalize(tag)
+}
+
 #[bridge_fn(ffi = false, node = false)]
 fn CryptographicHash_New(algo: String) -> Result<CryptographicHash> {
     CryptographicHash::new(&algo)
@@ -163,17 +280,17 @@ fn CryptographicHash_Finalize(hash: &mut CryptographicHash) -> Vec<u8> {
     hash.finalize()
 }
 
-#[bridge_fn(ffi = false, node = false)]
+#[bridge_fn]
 fn CryptographicMac_New(algo: String, key: &[u8]) -> Result<CryptographicMac> {
     CryptographicMac::new(&algo, key)
 }
 
-#[bridge_fn(ffi = false, node = false)]
+#[bridge_fn]
 fn CryptographicMac_Update(mac: &mut CryptographicMac, input: &[u8]) {
     mac.update(input)
 }
 
-#[bridge_fn(ffi = false, node = false)]
+#[bridge_fn]
 fn CryptographicMac_UpdateWithOffset(
     mac: &mut CryptographicMac,
     input: &[u8],
@@ -185,7 +302,30 @@ fn CryptographicMac_UpdateWithOffset(
     mac.update(&input[offset..(offset + len)])
 }
 
-#[bridge_fn(ffi = false, node = false)]
+#[bridge_fn]
 fn CryptographicMac_Finalize(mac: &mut CryptographicMac) -> Vec<u8> {
     mac.finalize()
 }
+
+#[bridge_fn]
+fn CryptographicMac_Verify(mac: &mut CryptographicMac, expected: &[u8]) -> bool {
+    mac.verify(expected).is_ok()
+}
+
+#[bridge_fn]
+fn Hkdf_Extract(algo: String, salt: &[u8], ikm: &[u8]) -> Result<Vec<u8>> {
+    Ok(Hkdf::new(&algo)?.extract(salt, ikm))
+}
+
+#[bridge_fn]
+fn Hkdf_Expand(algo: String, prk: &[u8], info: &[u8], output_length: u32) -> Result<Vec<u8>> {
+    let hkdf = Hkdf::new(&algo)?;
+    let output_length = output_length as usize;
+    // Check before allocating, so an unreasonable length is rejected rather than attempted.
+    if output_length > hkdf.max_output_size() {
+        return Err(Error::InvalidInputSize);
+    }
+    let mut output = vec![0; output_length];
+    hkdf.expand(prk, info, &mut output)?;
+    Ok(output)
+}
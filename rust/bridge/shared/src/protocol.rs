@@ -334,7 +334,12 @@ bridge_get!(
 
 #[bridge_fn(ffi = "fingerprint_compare")]
 fn ScannableFingerprint_Compare(fprint1: &[u8], fprint2: &[u8]) -> Result<bool> {
-    ScannableFingerprint::deserialize(fprint1)?.compare(fprint2)
+    match ScannableFingerprint::deserialize(fprint1)?.compare(fprint2)? {
+        FingerprintComparison::VersionMismatch { theirs, ours } => Err(
+            SignalProtocolError::FingerprintVersionMismatch(theirs, ours),
+        ),
+        comparison => Ok(comparison.is_match()),
+    }
 }
 
 #[bridge_fn(ffi = "message_deserialize")]
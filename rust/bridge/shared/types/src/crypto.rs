@@ -52,12 +52,136 @@ impl Aes256GcmDecryption {
     }
 }
 
+pub struct ChaCha20Poly1305Encryption {
+    chacha: Option<signal_crypto::ChaCha20Poly1305Encryption>,
+}
+
+impl ChaCha20Poly1305Encryption {
+    pub fn new(key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Result<Self> {
+        let chacha = signal_crypto::ChaCha20Poly1305Encryption::new(key, nonce, associated_data)?;
+        Ok(Self {
+            chacha: Some(chacha),
+        })
+    }
+
+    pub fn encrypt(&mut self, buf: &mut [u8]) {
+        self.chacha
+            .as_mut()
+            .expect("not yet finalized")
+            .encrypt(buf);
+    }
+
+    pub fn compute_tag(&mut self) -> Vec<u8> {
+        let chacha = self.chacha.take().expect("not yet finalized");
+        chacha.compute_tag().to_vec()
+    }
+}
+
+pub struct ChaCha20Poly1305Decryption {
+    chacha: Option<signal_crypto::ChaCha20Poly1305Decryption>,
+}
+
+impl ChaCha20Poly1305Decryption {
+    pub fn new(key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Result<Self> {
+        let chacha = signal_crypto::ChaCha20Poly1305Decryption::new(key, nonce, associated_data)?;
+        Ok(Self {
+            chacha: Some(chacha),
+        })
+    }
+
+    pub fn decrypt(&mut self, buf: &mut [u8]) {
+        self.chacha
+            .as_mut()
+            .expect("not yet finalized")
+            .decrypt(buf);
+    }
+
+    pub fn verify_tag(&mut self, tag: &[u8]) -> Result<bool> {
+        let chacha = self.chacha.take().expect("not yet finalized");
+        match chacha.verify_tag(tag) {
+            Ok(()) => Ok(true),
+            Err(Error::InvalidTag) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// An incremental AES-256-GCM-SIV encryption.
+///
+/// GCM-SIV can't produce any ciphertext until it has seen the whole message, so this buffers the
+/// entire plaintext in memory until [`Self::finalize`].
+pub struct Aes256GcmSivEncryption {
+    gcm_siv: Option<signal_crypto::Aes256GcmSivEncryption>,
+}
+
+impl Aes256GcmSivEncryption {
+    pub fn new(key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Result<Self> {
+        let gcm_siv = signal_crypto::Aes256GcmSivEncryption::new(key, nonce, associated_data)?;
+        Ok(Self {
+            gcm_siv: Some(gcm_siv),
+        })
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        let output = self
+            .gcm_siv
+            .as_mut()
+            .expect("not yet finalized")
+            .update(buf);
+        debug_assert!(output.is_empty(), "GCM-SIV buffers the whole message");
+    }
+
+    /// Returns the ciphertext followed by the tag.
+    pub fn finalize(&mut self) -> Vec<u8> {
+        let gcm_siv = self.gcm_siv.take().expect("not yet finalized");
+        let (mut ciphertext, tag) = gcm_siv.finalize();
+        ciphertext.extend_from_slice(&tag);
+        ciphertext
+    }
+}
+
+/// An incremental AES-256-GCM-SIV decryption.
+///
+/// The entire ciphertext is buffered in memory, and nothing is decrypted until the tag is checked
+/// in [`Self::finalize`].
+pub struct Aes256GcmSivDecryption {
+    gcm_siv: Option<signal_crypto::Aes256GcmSivDecryption>,
+}
+
+impl Aes256GcmSivDecryption {
+    pub fn new(key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Result<Self> {
+        let gcm_siv = signal_crypto::Aes256GcmSivDecryption::new(key, nonce, associated_data)?;
+        Ok(Self {
+            gcm_siv: Some(gcm_siv),
+        })
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        let output = self
+            .gcm_siv
+            .as_mut()
+            .expect("not yet finalized")
+            .update(buf);
+        debug_assert!(output.is_empty(), "GCM-SIV buffers the whole message");
+    }
+
+    /// Returns the plaintext if `tag` is correct, or [`Error::InvalidTag`] otherwise.
+    pub fn finalize(&mut self, tag: &[u8]) -> Result<Vec<u8>> {
+        let gcm_siv = self.gcm_siv.take().expect("not yet finalized");
+        gcm_siv.finalize(tag)
+    }
+}
+
 // Explicit wrapper for cbindgen purposes.
 pub struct Aes256GcmSiv(pub aes_gcm_siv::Aes256GcmSiv);
 
 bridge_as_handle!(CryptographicHash, mut = true, ffi = false, node = false);
-bridge_as_handle!(CryptographicMac, mut = true, ffi = false, node = false);
+bridge_as_handle!(CryptographicMac, mut = true);
 bridge_as_handle!(Aes256GcmSiv);
 bridge_as_handle!(Aes256Ctr32, mut = true, node = false);
 bridge_as_handle!(Aes256GcmEncryption, mut = true, node = false);
 bridge_as_handle!(Aes256GcmDecryption, mut = true, node = false);
+bridge_as_handle!(ChaCha20Poly1305Encryption, mut = true, node = false);
+bridge_as_handle!(ChaCha20Poly1305Decryption, mut = true, node = false);
+bridge_as_handle!(Aes256GcmSivEncryption, mut = true, node = false);
+bridge_as_handle!(Aes256GcmSivDecryption, mut = true, node = false);
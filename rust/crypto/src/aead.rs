@@ -0,0 +1,91 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use crate::{Error, Result};
+
+/// The size of the authentication tag produced by every [`Aead`] in this crate.
+pub const AEAD_TAG_SIZE: usize = 16;
+
+/// An authenticated encryption algorithm with associated data.
+///
+/// Messages can be processed all at once with [`Aead::encrypt`] and [`Aead::decrypt`], which
+/// append and expect the tag after the ciphertext, or a piece at a time with the
+/// [`AeadEncryption`] and [`AeadDecryption`] returned by [`Aead::new_encryption`] and
+/// [`Aead::new_decryption`], which keep the tag separate.
+pub trait Aead {
+    const KEY_SIZE: usize;
+    const NONCE_SIZE: usize;
+
+    type Encryption: AeadEncryption;
+    type Decryption: AeadDecryption;
+
+    fn new_encryption(key: &[u8], nonce: &[u8], associated_data: &[u8])
+        -> Result<Self::Encryption>;
+
+    fn new_decryption(key: &[u8], nonce: &[u8], associated_data: &[u8])
+        -> Result<Self::Decryption>;
+
+    /// Encrypts `plaintext`, returning the ciphertext followed by the tag.
+    fn encrypt(
+        key: &[u8],
+        nonce: &[u8],
+        associated_data: &[u8],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut encryption = Self::new_encryption(key, nonce, associated_data)?;
+        let mut output = Vec::with_capacity(plaintext.len() + AEAD_TAG_SIZE);
+        output.extend(encryption.update(plaintext));
+        let (rest, tag) = encryption.finalize();
+        output.extend(rest);
+        output.extend(tag);
+        Ok(output)
+    }
+
+    /// Decrypts a ciphertext followed by its tag, as produced by [`Aead::encrypt`].
+    fn decrypt(
+        key: &[u8],
+        nonce: &[u8],
+        associated_data: &[u8],
+        ciphertext_and_tag: &[u8],
+    ) -> Result<Vec<u8>> {
+        let ciphertext_len = ciphertext_and_tag
+            .len()
+            .checked_sub(AEAD_TAG_SIZE)
+            .ok_or(Error::InvalidTag)?;
+        let (ciphertext, tag) = ciphertext_and_tag.split_at(ciphertext_len);
+
+        let mut decryption = Self::new_decryption(key, nonce, associated_data)?;
+        let mut output = decryption.update(ciphertext);
+        output.extend(decryption.finalize(tag)?);
+        Ok(output)
+    }
+}
+
+/// An in-progress [`Aead`] encryption.
+pub trait AeadEncryption {
+    /// Encrypts the next part of the message, returning as much of the ciphertext as is
+    /// available so far.
+    ///
+    /// Some algorithms, like AES-256-GCM-SIV, can't produce any ciphertext until they have seen
+    /// the whole message, in which case this returns nothing.
+    fn update(&mut self, plaintext: &[u8]) -> Vec<u8>;
+
+    /// Returns the rest of the ciphertext along with the tag.
+    fn finalize(self) -> (Vec<u8>, [u8; AEAD_TAG_SIZE]);
+}
+
+/// An in-progress [`Aead`] decryption.
+pub trait AeadDecryption {
+    /// Decrypts the next part of the message, returning as much of the plaintext as is available
+    /// so far.
+    ///
+    /// Plaintext returned here has not been authenticated yet, and must not be used until
+    /// [`AeadDecryption::finalize`] succeeds. Algorithms that can't decrypt before they have seen
+    /// the whole message return nothing.
+    fn update(&mut self, ciphertext: &[u8]) -> Vec<u8>;
+
+    /// Checks `tag`, returning the rest of the plaintext if it matches.
+    fn finalize(self, tag: &[u8]) -> Result<Vec<u8>>;
+}
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
-use crate::{Aes256Ctr32, Error, Result};
+use crate::{Aead, AeadDecryption, AeadEncryption, Aes256Ctr32, Error, Result};
 use aes::cipher::generic_array::GenericArray;
 use aes::cipher::{BlockEncrypt, KeyInit};
 use aes::Aes256;
@@ -188,3 +188,55 @@ impl Aes256GcmDecryption {
         Ok(())
     }
 }
+
+/// AES-256-GCM, with a 12-byte nonce.
+pub enum Aes256Gcm {}
+
+impl Aead for Aes256Gcm {
+    const KEY_SIZE: usize = 32;
+    const NONCE_SIZE: usize = NONCE_SIZE;
+
+    type Encryption = Aes256GcmEncryption;
+    type Decryption = Aes256GcmDecryption;
+
+    fn new_encryption(
+        key: &[u8],
+        nonce: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Aes256GcmEncryption> {
+        Aes256GcmEncryption::new(key, nonce, associated_data)
+    }
+
+    fn new_decryption(
+        key: &[u8],
+        nonce: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Aes256GcmDecryption> {
+        Aes256GcmDecryption::new(key, nonce, associated_data)
+    }
+}
+
+impl AeadEncryption for Aes256GcmEncryption {
+    fn update(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let mut buf = plaintext.to_vec();
+        self.encrypt(&mut buf);
+        buf
+    }
+
+    fn finalize(self) -> (Vec<u8>, [u8; TAG_SIZE]) {
+        (vec![], self.compute_tag())
+    }
+}
+
+impl AeadDecryption for Aes256GcmDecryption {
+    fn update(&mut self, ciphertext: &[u8]) -> Vec<u8> {
+        let mut buf = ciphertext.to_vec();
+        self.decrypt(&mut buf);
+        buf
+    }
+
+    fn finalize(self, tag: &[u8]) -> Result<Vec<u8>> {
+        self.verify_tag(tag)?;
+        Ok(vec![])
+    }
+}
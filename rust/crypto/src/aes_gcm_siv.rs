@@ -0,0 +1,144 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use crate::{Aead, AeadDecryption, AeadEncryption, Error, Result};
+use aes_gcm_siv::{AeadInPlace, KeyInit, Nonce};
+
+pub const TAG_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 12;
+
+fn setup_gcm_siv(key: &[u8], nonce: &[u8]) -> Result<(aes_gcm_siv::Aes256GcmSiv, Nonce)> {
+    if nonce.len() != NONCE_SIZE {
+        return Err(Error::InvalidNonceSize);
+    }
+    let cipher =
+        aes_gcm_siv::Aes256GcmSiv::new_from_slice(key).map_err(|_| Error::InvalidKeySize)?;
+    Ok((cipher, *Nonce::from_slice(nonce)))
+}
+
+/// An AES-256-GCM-SIV encryption.
+///
+/// GCM-SIV derives the counter for encryption from the tag, and so has to see the whole plaintext
+/// before it can produce any ciphertext. This buffers the entire message in memory until it is
+/// finalized, and [`AeadEncryption::update`] always returns nothing.
+pub struct Aes256GcmSivEncryption {
+    cipher: aes_gcm_siv::Aes256GcmSiv,
+    nonce: Nonce,
+    associated_data: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl Aes256GcmSivEncryption {
+    pub const TAG_SIZE: usize = TAG_SIZE;
+    pub const NONCE_SIZE: usize = NONCE_SIZE;
+
+    pub fn new(key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Result<Self> {
+        let (cipher, nonce) = setup_gcm_siv(key, nonce)?;
+        Ok(Self {
+            cipher,
+            nonce,
+            associated_data: associated_data.to_vec(),
+            buf: vec![],
+        })
+    }
+}
+
+/// An AES-256-GCM-SIV decryption.
+///
+/// The entire ciphertext is buffered in memory, and nothing is decrypted until the tag has been
+/// provided and checked, so [`AeadDecryption::update`] always returns nothing.
+pub struct Aes256GcmSivDecryption {
+    cipher: aes_gcm_siv::Aes256GcmSiv,
+    nonce: Nonce,
+    associated_data: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl Aes256GcmSivDecryption {
+    pub const TAG_SIZE: usize = TAG_SIZE;
+    pub const NONCE_SIZE: usize = NONCE_SIZE;
+
+    pub fn new(key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Result<Self> {
+        let (cipher, nonce) = setup_gcm_siv(key, nonce)?;
+        Ok(Self {
+            cipher,
+            nonce,
+            associated_data: associated_data.to_vec(),
+            buf: vec![],
+        })
+    }
+}
+
+/// AES-256-GCM-SIV as specified in RFC 8452, with a 12-byte nonce.
+///
+/// Unlike AES-256-GCM, reusing a nonce only reveals whether two messages (and their associated
+/// data) were identical.
+///
+/// [`Aes256GcmSivEncryption`] and [`Aes256GcmSivDecryption`] accept the message in pieces, but
+/// hold all of it in memory until they are finalized, so they use no less memory than
+/// [`Aead::encrypt`] and [`Aead::decrypt`]. Messages too large to keep in memory should use
+/// AES-256-GCM or ChaCha20-Poly1305 instead.
+pub enum Aes256GcmSiv {}
+
+impl Aead for Aes256GcmSiv {
+    const KEY_SIZE: usize = 32;
+    const NONCE_SIZE: usize = NONCE_SIZE;
+
+    type Encryption = Aes256GcmSivEncryption;
+    type Decryption = Aes256GcmSivDecryption;
+
+    fn new_encryption(
+        key: &[u8],
+        nonce: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Aes256GcmSivEncryption> {
+        Aes256GcmSivEncryption::new(key, nonce, associated_data)
+    }
+
+    fn new_decryption(
+        key: &[u8],
+        nonce: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Aes256GcmSivDecryption> {
+        Aes256GcmSivDecryption::new(key, nonce, associated_data)
+    }
+}
+
+impl AeadEncryption for Aes256GcmSivEncryption {
+    fn update(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        self.buf.extend_from_slice(plaintext);
+        vec![]
+    }
+
+    fn finalize(mut self) -> (Vec<u8>, [u8; TAG_SIZE]) {
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&self.nonce, &self.associated_data, &mut self.buf)
+            .expect("message is not too long for GCM-SIV");
+        (self.buf, tag.into())
+    }
+}
+
+impl AeadDecryption for Aes256GcmSivDecryption {
+    fn update(&mut self, ciphertext: &[u8]) -> Vec<u8> {
+        self.buf.extend_from_slice(ciphertext);
+        vec![]
+    }
+
+    fn finalize(mut self, tag: &[u8]) -> Result<Vec<u8>> {
+        if tag.len() != TAG_SIZE {
+            return Err(Error::InvalidTag);
+        }
+        self.cipher
+            .decrypt_in_place_detached(
+                &self.nonce,
+                &self.associated_data,
+                &mut self.buf,
+                tag.into(),
+            )
+            .map_err(|_| Error::InvalidTag)?;
+        Ok(self.buf)
+    }
+}
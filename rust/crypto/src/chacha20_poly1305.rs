@@ -0,0 +1,214 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use crate::{Aead, AeadDecryption, AeadEncryption, Error, Result};
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::ChaCha20;
+use poly1305::universal_hash::{KeyInit, UniversalHash};
+use poly1305::Poly1305;
+use subtle::ConstantTimeEq;
+
+pub const KEY_SIZE: usize = 32;
+pub const TAG_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 12;
+
+const CHACHA20_BLOCK_SIZE: u64 = 64;
+
+#[derive(Clone)]
+struct Poly1305Mac {
+    mac: Poly1305,
+    msg_buf: [u8; TAG_SIZE],
+    msg_buf_offset: usize,
+    ad_len: usize,
+    msg_len: usize,
+}
+
+impl Poly1305Mac {
+    fn new(key: &poly1305::Key, associated_data: &[u8]) -> Self {
+        let mut mac = Poly1305::new(key);
+
+        mac.update_padded(associated_data);
+
+        Self {
+            mac,
+            msg_buf: [0u8; TAG_SIZE],
+            msg_buf_offset: 0,
+            ad_len: associated_data.len(),
+            msg_len: 0,
+        }
+    }
+
+    fn update(&mut self, mut msg: &[u8]) {
+        self.msg_len += msg.len();
+
+        if self.msg_buf_offset > 0 {
+            let taking = std::cmp::min(msg.len(), TAG_SIZE - self.msg_buf_offset);
+            self.msg_buf[self.msg_buf_offset..self.msg_buf_offset + taking]
+                .copy_from_slice(&msg[..taking]);
+            self.msg_buf_offset += taking;
+            msg = &msg[taking..];
+
+            if self.msg_buf_offset < TAG_SIZE {
+                return;
+            }
+            self.mac.update(&[self.msg_buf.into()]);
+            self.msg_buf_offset = 0;
+        }
+
+        // Whole blocks need no padding, so update_padded is the same as update here.
+        let full_blocks_len = msg.len() - msg.len() % TAG_SIZE;
+        self.mac.update_padded(&msg[..full_blocks_len]);
+
+        let leftover = &msg[full_blocks_len..];
+        self.msg_buf[..leftover.len()].copy_from_slice(leftover);
+        self.msg_buf_offset = leftover.len();
+    }
+
+    fn finalize(mut self) -> [u8; TAG_SIZE] {
+        if self.msg_buf_offset > 0 {
+            self.mac.update_padded(&self.msg_buf[..self.msg_buf_offset]);
+        }
+
+        let mut final_block = [0u8; TAG_SIZE];
+        final_block[..8].copy_from_slice(&(self.ad_len as u64).to_le_bytes());
+        final_block[8..].copy_from_slice(&(self.msg_len as u64).to_le_bytes());
+        self.mac.update(&[final_block.into()]);
+
+        self.mac.finalize().into()
+    }
+}
+
+fn setup_chacha20_poly1305(
+    key: &[u8],
+    nonce: &[u8],
+    associated_data: &[u8],
+) -> Result<(ChaCha20, Poly1305Mac)> {
+    if key.len() != KEY_SIZE {
+        return Err(Error::InvalidKeySize);
+    }
+    if nonce.len() != NONCE_SIZE {
+        return Err(Error::InvalidNonceSize);
+    }
+
+    let mut chacha20 = ChaCha20::new(key.into(), nonce.into());
+
+    // The Poly1305 key is the start of the first block of keystream (RFC 8439 section 2.6).
+    let mut mac_key = poly1305::Key::default();
+    chacha20.apply_keystream(&mut mac_key);
+    let mac = Poly1305Mac::new(&mac_key, associated_data);
+
+    chacha20.seek(CHACHA20_BLOCK_SIZE);
+    Ok((chacha20, mac))
+}
+
+pub struct ChaCha20Poly1305Encryption {
+    chacha20: ChaCha20,
+    mac: Poly1305Mac,
+}
+
+impl ChaCha20Poly1305Encryption {
+    pub const TAG_SIZE: usize = TAG_SIZE;
+    pub const NONCE_SIZE: usize = NONCE_SIZE;
+
+    pub fn new(key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Result<Self> {
+        let (chacha20, mac) = setup_chacha20_poly1305(key, nonce, associated_data)?;
+        Ok(Self { chacha20, mac })
+    }
+
+    pub fn encrypt(&mut self, buf: &mut [u8]) {
+        self.chacha20.apply_keystream(buf);
+        self.mac.update(buf);
+    }
+
+    pub fn compute_tag(self) -> [u8; TAG_SIZE] {
+        self.mac.finalize()
+    }
+}
+
+pub struct ChaCha20Poly1305Decryption {
+    chacha20: ChaCha20,
+    mac: Poly1305Mac,
+}
+
+impl ChaCha20Poly1305Decryption {
+    pub const TAG_SIZE: usize = TAG_SIZE;
+    pub const NONCE_SIZE: usize = NONCE_SIZE;
+
+    pub fn new(key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Result<Self> {
+        let (chacha20, mac) = setup_chacha20_poly1305(key, nonce, associated_data)?;
+        Ok(Self { chacha20, mac })
+    }
+
+    pub fn decrypt(&mut self, buf: &mut [u8]) {
+        self.mac.update(buf);
+        self.chacha20.apply_keystream(buf);
+    }
+
+    pub fn verify_tag(self, tag: &[u8]) -> Result<()> {
+        if tag.len() != TAG_SIZE {
+            return Err(Error::InvalidTag);
+        }
+
+        let computed_tag = self.mac.finalize();
+
+        if !bool::from(tag.ct_eq(&computed_tag)) {
+            return Err(Error::InvalidTag);
+        }
+
+        Ok(())
+    }
+}
+
+/// ChaCha20-Poly1305 as specified in RFC 8439, with a 12-byte nonce.
+pub enum ChaCha20Poly1305 {}
+
+impl Aead for ChaCha20Poly1305 {
+    const KEY_SIZE: usize = KEY_SIZE;
+    const NONCE_SIZE: usize = NONCE_SIZE;
+
+    type Encryption = ChaCha20Poly1305Encryption;
+    type Decryption = ChaCha20Poly1305Decryption;
+
+    fn new_encryption(
+        key: &[u8],
+        nonce: &[u8],
+        associated_data: &[u8],
+    ) -> Result<ChaCha20Poly1305Encryption> {
+        ChaCha20Poly1305Encryption::new(key, nonce, associated_data)
+    }
+
+    fn new_decryption(
+        key: &[u8],
+        nonce: &[u8],
+        associated_data: &[u8],
+    ) -> Result<ChaCha20Poly1305Decryption> {
+        ChaCha20Poly1305Decryption::new(key, nonce, associated_data)
+    }
+}
+
+impl AeadEncryption for ChaCha20Poly1305Encryption {
+    fn update(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let mut buf = plaintext.to_vec();
+        self.encrypt(&mut buf);
+        buf
+    }
+
+    fn finalize(self) -> (Vec<u8>, [u8; TAG_SIZE]) {
+        (vec![], self.compute_tag())
+    }
+}
+
+impl AeadDecryption for ChaCha20Poly1305Decryption {
+    fn update(&mut self, ciphertext: &[u8]) -> Vec<u8> {
+        let mut buf = ciphertext.to_vec();
+        self.decrypt(&mut buf);
+        buf
+    }
+
+    fn finalize(self, tag: &[u8]) -> Result<Vec<u8>> {
+        self.verify_tag(tag)?;
+        Ok(vec![])
+    }
+}
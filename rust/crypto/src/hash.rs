@@ -8,11 +8,14 @@ use crate::{Error, Result};
 use hmac::{Hmac, Mac};
 use sha1::Sha1;
 use sha2::{Digest, Sha256, Sha512};
+use sha3::{Sha3_256, Sha3_512};
+use subtle::ConstantTimeEq;
 
 #[derive(Clone)]
 pub enum CryptographicMac {
     HmacSha256(Hmac<Sha256>),
     HmacSha1(Hmac<Sha1>),
+    HmacSha512(Hmac<Sha512>),
 }
 
 impl CryptographicMac {
@@ -24,6 +27,9 @@ impl CryptographicMac {
             "HMACSha256" | "HmacSha256" => Ok(Self::HmacSha256(
                 Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length"),
             )),
+            "HMACSha512" | "HmacSha512" => Ok(Self::HmacSha512(
+                Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts any key length"),
+            )),
             _ => Err(Error::UnknownAlgorithm("MAC", algo.to_string())),
         }
     }
@@ -32,6 +38,7 @@ impl CryptographicMac {
         match self {
             Self::HmacSha1(sha1) => sha1.update(input),
             Self::HmacSha256(sha256) => sha256.update(input),
+            Self::HmacSha512(sha512) => sha512.update(input),
         }
     }
 
@@ -44,7 +51,18 @@ impl CryptographicMac {
         match self {
             Self::HmacSha1(sha1) => sha1.finalize_reset().into_bytes().to_vec(),
             Self::HmacSha256(sha256) => sha256.finalize_reset().into_bytes().to_vec(),
+            Self::HmacSha512(sha512) => sha512.finalize_reset().into_bytes().to_vec(),
+        }
+    }
+
+    /// Finalizes the MAC like [`Self::finalize`], and checks in constant time that it matches
+    /// `expected`.
+    pub fn verify(&mut self, expected: &[u8]) -> Result<()> {
+        let computed = self.finalize();
+        if !bool::from(computed.ct_eq(expected)) {
+            return Err(Error::InvalidTag);
         }
+        Ok(())
     }
 }
 
@@ -53,6 +71,8 @@ pub enum CryptographicHash {
     Sha1(Sha1),
     Sha256(Sha256),
     Sha512(Sha512),
+    Sha3_256(Sha3_256),
+    Sha3_512(Sha3_512),
 }
 
 impl CryptographicHash {
@@ -61,6 +81,8 @@ impl CryptographicHash {
             "SHA-1" | "SHA1" | "Sha1" => Ok(Self::Sha1(Sha1::new())),
             "SHA-256" | "SHA256" | "Sha256" => Ok(Self::Sha256(Sha256::new())),
             "SHA-512" | "SHA512" | "Sha512" => Ok(Self::Sha512(Sha512::new())),
+            "SHA3-256" | "SHA3_256" | "Sha3_256" => Ok(Self::Sha3_256(Sha3_256::new())),
+            "SHA3-512" | "SHA3_512" | "Sha3_512" => Ok(Self::Sha3_512(Sha3_512::new())),
             _ => Err(Error::UnknownAlgorithm("digest", algo.to_string())),
         }
     }
@@ -70,6 +92,8 @@ impl CryptographicHash {
             Self::Sha1(sha1) => sha1.update(input),
             Self::Sha256(sha256) => sha256.update(input),
             Self::Sha512(sha512) => sha512.update(input),
+            Self::Sha3_256(sha3_256) => sha3_256.update(input),
+            Self::Sha3_512(sha3_512) => sha3_512.update(input),
         }
     }
 
@@ -78,6 +102,8 @@ impl CryptographicHash {
             Self::Sha1(sha1) => sha1.finalize_reset().to_vec(),
             Self::Sha256(sha256) => sha256.finalize_reset().to_vec(),
             Self::Sha512(sha512) => sha512.finalize_reset().to_vec(),
+            Self::Sha3_256(sha3_256) => sha3_256.finalize_reset().to_vec(),
+            Self::Sha3_512(sha3_512) => sha3_512.finalize_reset().to_vec(),
         }
     }
 }
@@ -0,0 +1,69 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use crate::{Error, Result};
+
+use sha2::{Sha256, Sha512};
+
+/// HKDF (RFC 5869), with its extract and expand steps available separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hkdf {
+    Sha256,
+    Sha512,
+}
+
+impl Hkdf {
+    pub fn new(algo: &str) -> Result<Self> {
+        match algo {
+            "HKDFSha256" | "HkdfSha256" => Ok(Self::Sha256),
+            "HKDFSha512" | "HkdfSha512" => Ok(Self::Sha512),
+            _ => Err(Error::UnknownAlgorithm("KDF", algo.to_string())),
+        }
+    }
+
+    /// The size of the pseudorandom key produced by [`Self::extract`].
+    pub fn prk_size(self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha512 => 64,
+        }
+    }
+
+    /// The longest output [`Self::expand`] can produce, 255 times the hash size.
+    pub fn max_output_size(self) -> usize {
+        255 * self.prk_size()
+    }
+
+    /// Runs HKDF-Extract, returning the pseudorandom key.
+    ///
+    /// An empty `salt` is the same as not providing one.
+    pub fn extract(self, salt: &[u8], ikm: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => hkdf::Hkdf::<Sha256>::extract(Some(salt), ikm).0.to_vec(),
+            Self::Sha512 => hkdf::Hkdf::<Sha512>::extract(Some(salt), ikm).0.to_vec(),
+        }
+    }
+
+    /// Runs HKDF-Expand, filling `output`.
+    ///
+    /// Fails if `prk` is shorter than [`Self::prk_size`], or `output` is longer than
+    /// [`Self::max_output_size`].
+    pub fn expand(self, prk: &[u8], info: &[u8], output: &mut [u8]) -> Result<()> {
+        match self {
+            Self::Sha256 => hkdf::Hkdf::<Sha256>::from_prk(prk)
+                .map_err(|_| Error::InvalidKeySize)?
+                .expand(info, output),
+            Self::Sha512 => hkdf::Hkdf::<Sha512>::from_prk(prk)
+                .map_err(|_| Error::InvalidKeySize)?
+                .expand(info, output),
+        }
+        .map_err(|_| Error::InvalidInputSize)
+    }
+
+    /// Runs HKDF-Extract followed by HKDF-Expand.
+    pub fn derive(self, salt: &[u8], ikm: &[u8], info: &[u8], output: &mut [u8]) -> Result<()> {
+        self.expand(&self.extract(salt, ikm), info, output)
+    }
+}
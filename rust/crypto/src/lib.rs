@@ -7,13 +7,23 @@
 
 mod error;
 mod hash;
+mod kdf;
 
+mod aead;
 mod aes_cbc;
 mod aes_ctr;
 mod aes_gcm;
+mod aes_gcm_siv;
+mod chacha20_poly1305;
 
+pub use aead::{Aead, AeadDecryption, AeadEncryption, AEAD_TAG_SIZE};
 pub use aes_cbc::{aes_256_cbc_decrypt, aes_256_cbc_encrypt, DecryptionError, EncryptionError};
 pub use aes_ctr::Aes256Ctr32;
-pub use aes_gcm::{Aes256GcmDecryption, Aes256GcmEncryption};
+pub use aes_gcm::{Aes256Gcm, Aes256GcmDecryption, Aes256GcmEncryption};
+pub use aes_gcm_siv::{Aes256GcmSiv, Aes256GcmSivDecryption, Aes256GcmSivEncryption};
+pub use chacha20_poly1305::{
+    ChaCha20Poly1305, ChaCha20Poly1305Decryption, ChaCha20Poly1305Encryption,
+};
 pub use error::{Error, Result};
 pub use hash::{CryptographicHash, CryptographicMac};
+pub use kdf::Hkdf;
@@ -0,0 +1,242 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use rand::Rng;
+use serde::Deserialize;
+use signal_crypto::{Aead, AeadDecryption, AeadEncryption};
+use std::collections::HashMap;
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug)]
+struct WycheproofTest {
+    #[serde(rename = "tcId")]
+    tc_id: usize,
+    comment: String,
+    key: String,
+    #[serde(rename = "iv")]
+    nonce: String,
+    aad: String,
+    #[serde(rename = "msg")]
+    pt: String,
+    ct: String,
+    tag: String,
+    result: String,
+    flags: Vec<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug)]
+struct WycheproofTestGroup {
+    #[serde(rename = "ivSize")]
+    iv_size: usize,
+    #[serde(rename = "keySize")]
+    key_size: usize,
+    #[serde(rename = "tagSize")]
+    tag_size: usize,
+    #[serde(rename = "type")]
+    typ: String,
+    tests: Vec<WycheproofTest>,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug)]
+struct WycheproofTestSet {
+    algorithm: String,
+    #[serde(rename = "generatorVersion")]
+    generator_version: String,
+    #[serde(rename = "numberOfTests")]
+    number_of_tests: usize,
+    header: Vec<String>,
+    notes: HashMap<String, String>,
+    schema: String,
+    #[serde(rename = "testGroups")]
+    test_groups: Vec<WycheproofTestGroup>,
+}
+
+/// Splits `input` into randomly-sized pieces, including empty ones.
+fn random_split(input: &[u8]) -> Vec<&[u8]> {
+    let mut rng = rand::thread_rng();
+    let mut pieces = vec![];
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (piece, remaining) = rest.split_at(rng.gen_range(0..=rest.len()));
+        pieces.push(piece);
+        rest = remaining;
+    }
+    pieces
+}
+
+fn encrypt_in_pieces<A: Aead>(
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+    pt: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), signal_crypto::Error> {
+    let mut encryption = A::new_encryption(key, nonce, aad)?;
+    let mut ct = vec![];
+    for piece in random_split(pt) {
+        ct.extend(encryption.update(piece));
+    }
+    let (rest, tag) = encryption.finalize();
+    ct.extend(rest);
+    Ok((ct, tag.to_vec()))
+}
+
+fn decrypt_in_pieces<A: Aead>(
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+    ct: &[u8],
+    tag: &[u8],
+) -> Result<Vec<u8>, signal_crypto::Error> {
+    let mut decryption = A::new_decryption(key, nonce, aad)?;
+    let mut pt = vec![];
+    for piece in random_split(ct) {
+        pt.extend(decryption.update(piece));
+    }
+    pt.extend(decryption.finalize(tag)?);
+    Ok(pt)
+}
+
+fn test_kat<A: Aead>(kat: WycheproofTest) -> Result<(), signal_crypto::Error> {
+    let key = hex::decode(kat.key).expect("valid hex");
+    let aad = hex::decode(kat.aad).expect("valid hex");
+    let nonce = hex::decode(kat.nonce).expect("valid hex");
+    let tag = hex::decode(kat.tag).expect("valid hex");
+    let pt = hex::decode(kat.pt).expect("valid hex");
+    let ct = hex::decode(kat.ct).expect("valid hex");
+
+    let ct_and_tag = [ct.as_slice(), tag.as_slice()].concat();
+
+    match kat.result.as_ref() {
+        "valid" => {
+            assert_eq!(
+                hex::encode(A::encrypt(&key, &nonce, &aad, &pt)?),
+                hex::encode(&ct_and_tag),
+                "test {}",
+                kat.tc_id
+            );
+            assert_eq!(
+                hex::encode(A::decrypt(&key, &nonce, &aad, &ct_and_tag)?),
+                hex::encode(&pt),
+                "test {}",
+                kat.tc_id
+            );
+
+            for _ in 0..8 {
+                let (split_ct, split_tag) = encrypt_in_pieces::<A>(&key, &nonce, &aad, &pt)?;
+                assert_eq!(hex::encode(split_ct), hex::encode(&ct));
+                assert_eq!(hex::encode(split_tag), hex::encode(&tag));
+                let split_pt = decrypt_in_pieces::<A>(&key, &nonce, &aad, &ct, &tag)?;
+                assert_eq!(hex::encode(split_pt), hex::encode(&pt));
+            }
+        }
+        "invalid" => {
+            assert_ne!(
+                hex::encode(A::encrypt(&key, &nonce, &aad, &pt)?),
+                hex::encode(&ct_and_tag),
+                "test {}",
+                kat.tc_id
+            );
+            assert!(matches!(
+                A::decrypt(&key, &nonce, &aad, &ct_and_tag),
+                Err(signal_crypto::Error::InvalidTag)
+            ));
+            assert!(matches!(
+                decrypt_in_pieces::<A>(&key, &nonce, &aad, &ct, &tag),
+                Err(signal_crypto::Error::InvalidTag)
+            ));
+        }
+        wut => panic!("unknown result field {}", wut),
+    }
+
+    Ok(())
+}
+
+fn run_kats<A: Aead>(kat_data: &[u8], algorithm: &str) -> Result<(), signal_crypto::Error> {
+    let kats: WycheproofTestSet = serde_json::from_slice(kat_data).expect("Valid JSON");
+
+    assert_eq!(kats.algorithm, algorithm);
+
+    let mut count = 0;
+    for group in kats.test_groups {
+        if group.iv_size == 8 * A::NONCE_SIZE
+            && group.key_size == 8 * A::KEY_SIZE
+            && group.tag_size == 8 * signal_crypto::AEAD_TAG_SIZE
+        {
+            for test in group.tests {
+                test_kat::<A>(test)?;
+                count += 1;
+            }
+        }
+    }
+    assert_ne!(count, 0);
+
+    Ok(())
+}
+
+#[test]
+fn aes_gcm_kats() -> Result<(), signal_crypto::Error> {
+    run_kats::<signal_crypto::Aes256Gcm>(include_bytes!("data/aes_gcm_test.json"), "AES-GCM")
+}
+
+#[test]
+fn aes_gcm_siv_kats() -> Result<(), signal_crypto::Error> {
+    run_kats::<signal_crypto::Aes256GcmSiv>(
+        include_bytes!("data/aes_gcm_siv_test.json"),
+        "AES-GCM-SIV",
+    )
+}
+
+#[test]
+fn chacha20_poly1305_kats() -> Result<(), signal_crypto::Error> {
+    run_kats::<signal_crypto::ChaCha20Poly1305>(
+        include_bytes!("data/chacha20_poly1305_test.json"),
+        "CHACHA20-POLY1305",
+    )
+}
+
+fn check_rejects_bad_parameters<A: Aead>() {
+    let key = vec![0u8; A::KEY_SIZE];
+    let nonce = vec![0u8; A::NONCE_SIZE];
+
+    assert!(matches!(
+        A::encrypt(&key[1..], &nonce, b"", b"message"),
+        Err(signal_crypto::Error::InvalidKeySize)
+    ));
+    assert!(matches!(
+        A::encrypt(&key, &nonce[1..], b"", b"message"),
+        Err(signal_crypto::Error::InvalidNonceSize)
+    ));
+
+    let ct_and_tag = A::encrypt(&key, &nonce, b"", b"message").expect("valid");
+    assert!(matches!(
+        A::decrypt(
+            &key,
+            &nonce,
+            b"",
+            &ct_and_tag[..signal_crypto::AEAD_TAG_SIZE - 1]
+        ),
+        Err(signal_crypto::Error::InvalidTag)
+    ));
+    assert!(matches!(
+        A::decrypt(&key, &nonce, b"other", &ct_and_tag),
+        Err(signal_crypto::Error::InvalidTag)
+    ));
+    let tag = &ct_and_tag[ct_and_tag.len() - signal_crypto::AEAD_TAG_SIZE..];
+    assert!(matches!(
+        A::new_decryption(&key, &nonce, b"")
+            .expect("valid")
+            .finalize(&tag[1..]),
+        Err(signal_crypto::Error::InvalidTag)
+    ));
+}
+
+#[test]
+fn aead_rejects_bad_parameters() {
+    check_rejects_bad_parameters::<signal_crypto::Aes256Gcm>();
+    check_rejects_bad_parameters::<signal_crypto::Aes256GcmSiv>();
+    check_rejects_bad_parameters::<signal_crypto::ChaCha20Poly1305>();
+}
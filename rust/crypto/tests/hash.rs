@@ -0,0 +1,162 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use hex_literal::hex;
+use signal_crypto::{CryptographicHash, CryptographicMac, Hkdf};
+
+#[test]
+fn sha3_smoke_test() -> Result<(), signal_crypto::Error> {
+    let mut sha3_256 = CryptographicHash::new("SHA3-256")?;
+    sha3_256.update(b"a");
+    sha3_256.update(b"bc");
+    assert_eq!(
+        hex::encode(sha3_256.finalize()),
+        "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"
+    );
+
+    let mut sha3_512 = CryptographicHash::new("Sha3_512")?;
+    sha3_512.update(b"abc");
+    assert_eq!(
+        hex::encode(sha3_512.finalize()),
+        "b751850b1a57168a5693cd924b6b096e08f621827444f70d884f5d0240d2712e\
+         10e116e9192af3c91a7ec57647e3934057340b4cf408d5a56592f8274eec53f0"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn hmac_sha512_rfc4231() -> Result<(), signal_crypto::Error> {
+    let expected = hex!(
+        "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554"
+        "9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+    );
+
+    let mut mac = CryptographicMac::new("HmacSha512", b"Jefe")?;
+    mac.update(b"what do ya want ");
+    mac.update(b"for nothing?");
+    assert_eq!(hex::encode(mac.finalize()), hex::encode(expected));
+
+    // finalize resets the MAC, so it can be used again.
+    mac.update(b"what do ya want for nothing?");
+    mac.verify(&expected)?;
+
+    Ok(())
+}
+
+#[test]
+fn mac_verify_rejects_mismatches() -> Result<(), signal_crypto::Error> {
+    let mut mac = CryptographicMac::new("HMACSha256", b"key")?;
+    mac.update(b"message");
+    let good = mac.finalize();
+
+    for bad in [&good[..good.len() - 1], &[], &[0; 32]] {
+        mac.update(b"message");
+        assert!(matches!(
+            mac.verify(bad),
+            Err(signal_crypto::Error::InvalidTag)
+        ));
+    }
+
+    mac.update(b"message");
+    mac.verify(&good)?;
+
+    Ok(())
+}
+
+#[test]
+fn unknown_algorithms() {
+    assert!(matches!(
+        CryptographicHash::new("SHA3-384"),
+        Err(signal_crypto::Error::UnknownAlgorithm("digest", _))
+    ));
+    assert!(matches!(
+        CryptographicMac::new("HmacSha384", b""),
+        Err(signal_crypto::Error::UnknownAlgorithm("MAC", _))
+    ));
+    assert!(matches!(
+        Hkdf::new("HkdfSha1"),
+        Err(signal_crypto::Error::UnknownAlgorithm("KDF", _))
+    ));
+}
+
+#[test]
+fn hkdf_rfc5869() -> Result<(), signal_crypto::Error> {
+    // RFC 5869 appendix A.1
+    check_hkdf(
+        "HkdfSha256",
+        &hex!("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"),
+        &hex!(
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"
+            "34007208d5b887185865"
+        ),
+    )
+}
+
+#[test]
+fn hkdf_sha512_with_rfc5869_inputs() -> Result<(), signal_crypto::Error> {
+    // RFC 5869 only has SHA-256 and SHA-1 vectors. This reuses the inputs from appendix A.1, with
+    // outputs computed independently using Python's hmac module.
+    check_hkdf(
+        "HKDFSha512",
+        &hex!(
+            "665799823737ded04a88e47e54a5890bb2c3d247c7a4254a8e61350723590a26"
+            "c36238127d8661b88cf80ef802d57e2f7cebcf1e00e083848be19929c61b4237"
+        ),
+        &hex!(
+            "832390086cda71fb47625bb5ceb168e4c8e26a1a16ed34d9fc7fe92c14815793"
+            "38da362cb8d9f925d7cb"
+        ),
+    )
+}
+
+/// Checks `algo` against the inputs from RFC 5869 appendix A.1.
+fn check_hkdf(
+    algo: &str,
+    expected_prk: &[u8],
+    expected_okm: &[u8; 42],
+) -> Result<(), signal_crypto::Error> {
+    let ikm = [0x0b; 22];
+    let salt = hex!("000102030405060708090a0b0c");
+    let info = hex!("f0f1f2f3f4f5f6f7f8f9");
+
+    let hkdf = Hkdf::new(algo)?;
+
+    let prk = hkdf.extract(&salt, &ikm);
+    assert_eq!(prk.len(), hkdf.prk_size());
+    assert_eq!(hex::encode(&prk), hex::encode(expected_prk));
+
+    let mut okm = [0; 42];
+    hkdf.expand(&prk, &info, &mut okm)?;
+    assert_eq!(hex::encode(okm), hex::encode(expected_okm));
+
+    let mut okm = [0; 42];
+    hkdf.derive(&salt, &ikm, &info, &mut okm)?;
+    assert_eq!(hex::encode(okm), hex::encode(expected_okm));
+
+    Ok(())
+}
+
+#[test]
+fn hkdf_rejects_bad_lengths() -> Result<(), signal_crypto::Error> {
+    let hkdf = Hkdf::new("HkdfSha256")?;
+    let prk = hkdf.extract(b"", b"ikm");
+
+    let mut okm = [0; 32];
+    assert!(matches!(
+        hkdf.expand(&prk[1..], b"", &mut okm),
+        Err(signal_crypto::Error::InvalidKeySize)
+    ));
+
+    assert_eq!(hkdf.max_output_size(), 255 * 32);
+    let mut too_long = vec![0; hkdf.max_output_size() + 1];
+    assert!(matches!(
+        hkdf.expand(&prk, b"", &mut too_long),
+        Err(signal_crypto::Error::InvalidInputSize)
+    ));
+    hkdf.expand(&prk, b"", &mut too_long[1..])?;
+
+    Ok(())
+}
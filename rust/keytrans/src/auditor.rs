@@ -0,0 +1,397 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Implements the third-party auditor side of a transparency log.
+//!
+//! In [`DeploymentMode::ThirdPartyAuditing`], the operator sends every new log entry to the
+//! auditor as an [`AuditorUpdate`]. The [`Auditor`] checks that each one is a valid change to the
+//! prefix tree, keeps track of the log's root, and signs tree heads that the operator hands out to
+//! clients alongside its own.
+
+use ed25519_dalek::{Signer as _, SigningKey};
+use prost::{DecodeError, Message as _};
+
+use crate::log::LogFrontier;
+use crate::prefix::{evaluate as evaluate_prefix, evaluate_absent, MalformedProof};
+use crate::verify::{leaf_hash, marshal_tree_head_tbs, verify_tree_head_signature};
+use crate::wire::*;
+use crate::{log, DeploymentMode, PublicConfig};
+
+/// The version of the [`Auditor`] serialization format written by this library.
+const AUDITOR_STATE_VERSION: u32 = 1;
+
+#[derive(Debug, displaydoc::Display)]
+pub enum Error {
+    /// Invalid auditor config: {0}
+    InvalidConfig(&'static str),
+    /// Malformed update
+    MalformedUpdate,
+    /// Verification failed: {0}
+    VerificationFailed(String),
+    /// Invalid stored state: {0}
+    InvalidState(String),
+}
+
+impl From<MalformedProof> for Error {
+    fn from(_: MalformedProof) -> Self {
+        Self::MalformedUpdate
+    }
+}
+
+impl From<log::Error> for Error {
+    fn from(err: log::Error) -> Self {
+        Self::VerificationFailed(err.to_string())
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Self {
+        Self::InvalidState(err.to_string())
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Auditor incrementally verifies every entry of a log, without storing the
+/// entries themselves.
+pub struct Auditor {
+    config: PublicConfig,
+    signing_key: SigningKey,
+    log: LogFrontier,
+    prefix_root: [u8; 32],
+}
+
+impl Auditor {
+    /// Creates an auditor for an empty log. `config` must be in
+    /// [`DeploymentMode::ThirdPartyAuditing`] mode with the public half of
+    /// `signing_key` as the auditor key.
+    pub fn new(config: PublicConfig, signing_key: SigningKey) -> Result<Self> {
+        match config.mode {
+            DeploymentMode::ThirdPartyAuditing(key) if key == signing_key.verifying_key() => {}
+            DeploymentMode::ThirdPartyAuditing(_) => {
+                return Err(Error::InvalidConfig(
+                    "auditor key does not match signing key",
+                ))
+            }
+            _ => {
+                return Err(Error::InvalidConfig(
+                    "log is not deployed with third-party auditing",
+                ))
+            }
+        }
+        Ok(Self {
+            config,
+            signing_key,
+            log: LogFrontier::new(),
+            prefix_root: [0; 32],
+        })
+    }
+
+    /// Restores an auditor saved with [`Self::serialize`].
+    ///
+    /// The config and signing key are not part of the saved state, since they
+    /// are fixed for a given log.
+    pub fn deserialize(config: PublicConfig, signing_key: SigningKey, data: &[u8]) -> Result<Self> {
+        let stored = StoredAuditorState::decode(data)?;
+        match stored.version {
+            AUDITOR_STATE_VERSION => {}
+            0 => return Err(Error::InvalidState("missing auditor version".to_string())),
+            version => {
+                return Err(Error::InvalidState(format!(
+                    "unsupported auditor version {version}"
+                )))
+            }
+        }
+
+        let malformed = || Error::InvalidState("malformed hash found".to_string());
+        let full_subtrees = stored
+            .full_subtrees
+            .iter()
+            .map(|hash| hash.as_slice().try_into().map_err(|_| malformed()))
+            .collect::<Result<Vec<_>>>()?;
+        let log = LogFrontier::from_full_subtrees(stored.tree_size, &full_subtrees)
+            .map_err(|err| Error::InvalidState(err.to_string()))?;
+
+        Ok(Self {
+            log,
+            prefix_root: stored.prefix_root.try_into().map_err(|_| malformed())?,
+            ..Self::new(config, signing_key)?
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        StoredAuditorState {
+            version: AUDITOR_STATE_VERSION,
+            tree_size: self.log.size(),
+            prefix_root: self.prefix_root.to_vec(),
+            full_subtrees: self
+                .log
+                .full_subtrees()
+                .iter()
+                .map(|hash| hash.to_vec())
+                .collect(),
+        }
+        .encode_to_vec()
+    }
+
+    /// The number of log entries verified so far.
+    pub fn tree_size(&self) -> u64 {
+        self.log.size()
+    }
+
+    /// The root of the log, as computed from the entries verified so far.
+    pub fn root(&self) -> Result<[u8; 32]> {
+        Ok(self.log.root()?)
+    }
+
+    /// Checks that `update` is a valid change to the prefix tree, and adds it
+    /// to the log. The auditor's state is left unchanged if it isn't.
+    pub fn process(&mut self, update: &AuditorUpdate) -> Result<()> {
+        let index: [u8; 32] = update
+            .index
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::MalformedUpdate)?;
+        let commitment: [u8; 32] = update
+            .commitment
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::MalformedUpdate)?;
+        let pos = self.log.size();
+
+        // Compute the prefix tree root before the update, and the search key's
+        // leaf after it. A new search key starts at version 0 and is first
+        // seen at this entry, otherwise its version goes up by one.
+        let mut proof = PrefixProof {
+            proof: update.proof.clone(),
+            counter: 0,
+        };
+        let (prev_root, first_pos) = match &update.previous {
+            None => (evaluate_absent(&index, &proof.proof)?, pos),
+            Some(previous) => {
+                if previous.pos >= pos {
+                    return Err(Error::VerificationFailed(
+                        "search key starts after the current entry".to_string(),
+                    ));
+                }
+                proof.counter = previous.counter;
+                let prev_root = evaluate_prefix(&index, previous.pos, &proof)?;
+                proof.counter = previous.counter.checked_add(1).ok_or_else(|| {
+                    Error::VerificationFailed("search key version overflowed".to_string())
+                })?;
+                (prev_root, previous.pos)
+            }
+        };
+        if prev_root != self.prefix_root {
+            return Err(Error::VerificationFailed(
+                "prefix tree does not match previous entry".to_string(),
+            ));
+        }
+
+        let prefix_root = evaluate_prefix(&index, first_pos, &proof)?;
+        self.log.append(leaf_hash(&prefix_root, &commitment));
+        self.prefix_root = prefix_root;
+        Ok(())
+    }
+
+    /// Checks the operator's signature on a tree head for the log as the
+    /// auditor currently sees it.
+    pub fn verify_tree_head(&self, tree_head: &TreeHead) -> Result<()> {
+        if tree_head.tree_size != self.tree_size() {
+            return Err(Error::VerificationFailed(
+                "tree head is for a different tree size".to_string(),
+            ));
+        }
+        verify_tree_head_signature(
+            &self.config,
+            tree_head,
+            &self.root()?,
+            &self.config.signature_key,
+        )
+        .map_err(|err| Error::VerificationFailed(err.to_string()))
+    }
+
+    /// Signs a tree head for the log as the auditor currently sees it, as of
+    /// `timestamp` milliseconds since the epoch.
+    pub fn sign_tree_head(&self, timestamp: i64) -> Result<TreeHead> {
+        let tree_size = self.tree_size();
+        let tbs = marshal_tree_head_tbs(tree_size, timestamp, &self.root()?, &self.config)
+            .map_err(|err| Error::VerificationFailed(err.to_string()))?;
+        Ok(TreeHead {
+            tree_size,
+            timestamp,
+            signature: self.signing_key.sign(&tbs).to_vec(),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "test-utils"))]
+mod test {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use assert_matches::assert_matches;
+    use futures_util::FutureExt;
+
+    use super::*;
+    use crate::{verify_search, InMemoryLogStore, Prover, VrfSecretKey};
+
+    fn now() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64
+    }
+
+    fn auditor_key() -> SigningKey {
+        SigningKey::from_bytes(&[3; 32])
+    }
+
+    fn test_prover() -> Prover {
+        let mut prover = Prover::new(
+            DeploymentMode::ThirdPartyAuditing(auditor_key().verifying_key()),
+            SigningKey::from_bytes(&[2; 32]),
+            VrfSecretKey::from([1; 32]),
+            [4; 32],
+        );
+        for i in 0..40 {
+            prover
+                .insert(&format!("key{}", i % 6), format!("value{i}").as_bytes())
+                .unwrap();
+        }
+        prover
+    }
+
+    #[test]
+    fn audit_log() {
+        let mut prover = test_prover();
+        let mut auditor = Auditor::new(prover.public_config(), auditor_key()).unwrap();
+
+        for pos in 0..prover.tree_size() {
+            auditor
+                .process(&prover.auditor_update(pos).unwrap())
+                .unwrap();
+            assert_eq!(auditor.root().unwrap(), prover.root(pos + 1).unwrap());
+        }
+        let operator_head = prover.sign_tree_head(40, now()).unwrap();
+        auditor.verify_tree_head(&operator_head).unwrap();
+
+        // Pick up where we left off after a restart.
+        let mut auditor =
+            Auditor::deserialize(prover.public_config(), auditor_key(), &auditor.serialize())
+                .unwrap();
+        for i in 40..45 {
+            let pos = prover
+                .insert("key0", format!("value{i}").as_bytes())
+                .unwrap();
+            auditor
+                .process(&prover.auditor_update(pos).unwrap())
+                .unwrap();
+        }
+        assert_eq!(auditor.root().unwrap(), prover.root(45).unwrap());
+
+        // Clients accept the auditor's tree head.
+        prover
+            .set_auditor_tree_head(auditor.sign_tree_head(now()).unwrap())
+            .unwrap();
+        prover.insert("key1", b"unaudited").unwrap();
+
+        let mut store = InMemoryLogStore::new(prover.public_config());
+        let req = SearchRequest {
+            search_key: "key1".to_string(),
+            version: None,
+            consistency: None,
+        };
+        let res = prover.search(&req, now()).unwrap();
+        assert!(verify_search(&mut store, &req, &res, false)
+            .now_or_never()
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_updates() {
+        let prover = test_prover();
+        let mut auditor = Auditor::new(prover.public_config(), auditor_key()).unwrap();
+        for pos in 0..20 {
+            auditor
+                .process(&prover.auditor_update(pos).unwrap())
+                .unwrap();
+        }
+        let root = auditor.root().unwrap();
+
+        // Entry 20 updates a search key that is already in the tree.
+        let update = prover.auditor_update(20).unwrap();
+        type Tamper = fn(&mut AuditorUpdate);
+        let tampers: [(&str, Tamper); 5] = [
+            ("index", |update| update.index[0] ^= 1),
+            ("proof", |update| update.proof[255][0] ^= 1),
+            ("new key", |update| update.previous = None),
+            ("counter", |update| {
+                update.previous.as_mut().unwrap().counter += 1
+            }),
+            ("position", |update| {
+                update.previous.as_mut().unwrap().pos = 20
+            }),
+        ];
+        for (name, tamper) in tampers {
+            let mut tampered = update.clone();
+            tamper(&mut tampered);
+            assert!(
+                auditor.process(&tampered).is_err(),
+                "tampered {name} was accepted"
+            );
+            assert_eq!(auditor.root().unwrap(), root);
+        }
+
+        auditor.process(&update).unwrap();
+
+        // A different history produces a different root.
+        let mut update = prover.auditor_update(21).unwrap();
+        update.commitment[0] ^= 1;
+        auditor.process(&update).unwrap();
+        assert_matches!(
+            auditor.verify_tree_head(&prover.sign_tree_head(22, now()).unwrap()),
+            Err(Error::VerificationFailed(_))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let prover = test_prover();
+        assert_matches!(
+            Auditor::new(prover.public_config(), SigningKey::from_bytes(&[4; 32])).err(),
+            Some(Error::InvalidConfig(_))
+        );
+
+        let config = PublicConfig {
+            mode: DeploymentMode::ContactMonitoring,
+            ..prover.public_config()
+        };
+        assert_matches!(
+            Auditor::new(config, auditor_key()).err(),
+            Some(Error::InvalidConfig(_))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let prover = test_prover();
+        let auditor = Auditor::new(prover.public_config(), auditor_key()).unwrap();
+        let restore = |data: &[u8]| {
+            Auditor::deserialize(prover.public_config(), auditor_key(), data).map(|_| ())
+        };
+        assert!(restore(&auditor.serialize()).is_ok());
+
+        let unversioned = StoredAuditorState::default().encode_to_vec();
+        assert_matches!(restore(&unversioned), Err(Error::InvalidState(_)));
+
+        let future = StoredAuditorState {
+            version: AUDITOR_STATE_VERSION + 1,
+            ..Default::default()
+        }
+        .encode_to_vec();
+        assert_matches!(restore(&future), Err(Error::InvalidState(_)));
+    }
+}
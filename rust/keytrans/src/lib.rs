@@ -2,12 +2,16 @@
 // Copyright 2024 Signal Messenger, LLC.
 // SPDX-License-Identifier: AGPL-3.0-only
 //
+mod auditor;
 mod commitments;
 mod guide;
 mod implicit;
 mod left_balanced;
 mod log;
 mod prefix;
+#[cfg(feature = "test-utils")]
+mod prover;
+mod store;
 mod verify;
 mod vrf;
 mod wire;
@@ -18,13 +22,18 @@ use async_trait::async_trait;
 use ed25519_dalek::VerifyingKey as SigPublicKey;
 use prost::{DecodeError, Message};
 
+pub use auditor::{Auditor, Error as AuditorError};
+#[cfg(feature = "test-utils")]
+pub use prover::{Error as ProverError, Prover};
+pub use store::{InMemoryLogStore, PersistentLogStore};
 pub use verify::{
     truncate_search_response, verify_distinguished, verify_monitor, verify_search, verify_update,
 };
-use vrf::PublicKey as VrfPublicKey;
+pub use vrf::{PublicKey as VrfPublicKey, SecretKey as VrfSecretKey};
 pub use wire::{
-    Consistency, FullTreeHead, MonitorKey, MonitorRequest, MonitorResponse, SearchRequest,
-    SearchResponse, TreeHead, UpdateRequest, UpdateResponse, UpdateValue,
+    AuditorLeaf, AuditorTreeHead, AuditorUpdate, Consistency, FullTreeHead, MonitorKey,
+    MonitorRequest, MonitorResponse, SearchRequest, SearchResponse, TreeHead, UpdateRequest,
+    UpdateResponse, UpdateValue,
 };
 
 /// DeploymentMode specifies the way that a transparency log is deployed.
@@ -64,6 +73,7 @@ pub struct PublicConfig {
 
 /// MonitoringData is the structure retained for each key in the KT server being
 /// monitored.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MonitoringData {
     /// The VRF output on the search key.
     pub index: [u8; 32],
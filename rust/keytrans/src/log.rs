@@ -12,8 +12,8 @@ mod math {
     // Log Tree structure is different from the Implicit Binary Search Tree's
     // structure, in that intermediate nodes always have two children.
 
-    pub use crate::left_balanced::level;
-    use crate::left_balanced::{left_step, log2, parent_step, right_step};
+    use crate::left_balanced::{left_step, parent_step, right_step};
+    pub use crate::left_balanced::{level, log2};
 
     // Returns the number of nodes needed to store a tree with n leaves.
     fn node_width(n: u64) -> u64 {
@@ -429,6 +429,160 @@ pub fn verify_consistency_proof(
     }
 }
 
+/// LogFrontier keeps only the full subtrees along the right edge of a Log Tree,
+/// which is enough to append leaves and compute the root.
+pub struct LogFrontier {
+    size: u64,
+    calc: SimpleRootCalculator,
+}
+
+impl LogFrontier {
+    pub fn new() -> Self {
+        Self {
+            size: 0,
+            calc: SimpleRootCalculator::new(),
+        }
+    }
+
+    /// Restores a frontier from the output of [`Self::full_subtrees`].
+    pub fn from_full_subtrees(size: u64, full_subtrees: &[Hash]) -> Result<Self> {
+        if full_subtrees.len() != size.count_ones() as usize {
+            return Err(Error::InvalidInput(
+                "expected one full subtree for each bit of the tree size",
+            ));
+        }
+        let levels = (0..u64::BITS as usize).rev().filter(|i| size >> i & 1 == 1);
+
+        let mut calc = SimpleRootCalculator::new();
+        for (level, value) in levels.zip(full_subtrees) {
+            calc.insert(level, *value);
+        }
+        Ok(Self { size, calc })
+    }
+
+    /// Returns the number of leaves in the tree.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Appends a leaf to the tree.
+    pub fn append(&mut self, value: Hash) {
+        self.calc.insert(0, value);
+        self.size += 1;
+    }
+
+    pub fn root(&self) -> Result<Hash> {
+        self.calc.root()
+    }
+
+    /// Returns the values of the full subtrees, from left to right.
+    pub fn full_subtrees(&self) -> Vec<Hash> {
+        self.calc
+            .chain
+            .iter()
+            .rev()
+            .flatten()
+            .map(|nd| nd.value)
+            .collect()
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+/// LogTree stores every leaf of a Log Tree, so that it can produce the proofs
+/// checked by the functions above for any tree size seen so far.
+pub struct LogTree {
+    /// The values of all full subtrees, indexed by level and then by position
+    /// within that level.
+    levels: Vec<Vec<NodeData>>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl LogTree {
+    pub fn new() -> Self {
+        Self {
+            levels: vec![vec![]],
+        }
+    }
+
+    /// Returns the number of leaves in the tree.
+    pub fn size(&self) -> u64 {
+        self.levels[0].len() as u64
+    }
+
+    /// Appends a leaf to the tree.
+    pub fn append(&mut self, value: Hash) {
+        self.levels[0].push(NodeData { leaf: true, value });
+
+        let mut level = 0;
+        while self.levels[level].len() % 2 == 0 {
+            let nodes = &self.levels[level];
+            let parent = tree_hash(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+            if self.levels.len() == level + 1 {
+                self.levels.push(vec![]);
+            }
+            self.levels[level + 1].push(parent);
+            level += 1;
+        }
+    }
+
+    // Returns the value of the subtree covering the leaves in [start, end),
+    // structured the same way as a tree with end - start leaves.
+    fn subtree(&self, start: u64, end: u64) -> NodeData {
+        let width = end - start;
+        if width.is_power_of_two() && start % width == 0 {
+            let level = width.trailing_zeros() as usize;
+            return self.levels[level][(start / width) as usize].clone();
+        }
+        let k = 1 << math::log2(width - 1);
+        tree_hash(
+            &self.subtree(start, start + k),
+            &self.subtree(start + k, end),
+        )
+    }
+
+    // Returns the value of node x in a tree with n leaves.
+    fn node(&self, x: u64, n: u64) -> Hash {
+        let level = math::level(x);
+        let start = (x >> (level + 1)) << level;
+        let end = (start + (1 << level)).min(n);
+        self.subtree(start, end).value
+    }
+
+    /// Returns the root of the tree when it had n leaves.
+    pub fn root(&self, n: u64) -> Result<Hash> {
+        if n == 0 || n > self.size() {
+            return Err(Error::InvalidInput("tree size is out of range"));
+        }
+        Ok(self.subtree(0, n).value)
+    }
+
+    /// Returns the proof that evaluate_batch_proof expects for the leaves x in
+    /// a tree with n leaves.
+    pub fn batch_proof(&self, x: &[u64], n: u64) -> Result<Vec<Hash>> {
+        if n > self.size() || x.iter().any(|&id| id >= n) {
+            return Err(Error::InvalidInput(
+                "leaf ids can not be larger than tree size",
+            ));
+        }
+        Ok(math::batch_copath(x, n)
+            .into_iter()
+            .map(|id| self.node(id, n))
+            .collect())
+    }
+
+    /// Returns the proof that verify_consistency_proof expects between the
+    /// tree with m leaves and the tree with n leaves.
+    pub fn consistency_proof(&self, m: u64, n: u64) -> Result<Vec<Hash>> {
+        if m == 0 || m >= n || n > self.size() {
+            return Err(Error::InvalidInput("m must be within [0, n)"));
+        }
+        Ok(math::consistency_proof(m, n)
+            .into_iter()
+            .map(|id| self.node(id, n))
+            .collect())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -496,4 +650,51 @@ mod test {
         assert!(verify_consistency_proof(1078, 2000, proof, m_root, m_root).is_err());
         assert!(verify_consistency_proof(1078, 2000, proof, n_root, n_root).is_err());
     }
+
+    #[test]
+    fn test_log_tree_proofs() {
+        let mut tree = LogTree::new();
+        for i in 0..100u32 {
+            let mut value = [0u8; 32];
+            value[..4].copy_from_slice(&i.to_be_bytes());
+            tree.append(value);
+        }
+        let leaf = |i: u64| {
+            let mut value = [0u8; 32];
+            value[..4].copy_from_slice(&(i as u32).to_be_bytes());
+            value
+        };
+
+        for n in 1..=100 {
+            let root = tree.root(n).unwrap();
+            for x in [vec![0], vec![n - 1], vec![0, n / 2, n - 1]] {
+                let mut x = x;
+                x.dedup();
+                let values: Vec<Hash> = x.iter().map(|&i| leaf(i)).collect();
+                let proof = tree.batch_proof(&x, n).unwrap();
+                assert_eq!(evaluate_batch_proof(&x, n, &values, &proof).unwrap(), root);
+            }
+            for m in 1..n {
+                let proof = tree.consistency_proof(m, n).unwrap();
+                let m_root = tree.root(m).unwrap();
+                assert!(verify_consistency_proof(m, n, &proof, m_root, root).is_ok());
+            }
+        }
+
+        let mut frontier = LogFrontier::new();
+        for i in 0..100 {
+            frontier.append(leaf(i));
+            assert_eq!(frontier.root().unwrap(), tree.root(i + 1).unwrap());
+
+            let restored =
+                LogFrontier::from_full_subtrees(i + 1, &frontier.full_subtrees()).unwrap();
+            assert_eq!(restored.root().unwrap(), tree.root(i + 1).unwrap());
+        }
+        assert!(LogFrontier::from_full_subtrees(7, &[[0; 32]; 2]).is_err());
+
+        assert!(tree.root(0).is_err());
+        assert!(tree.root(101).is_err());
+        assert!(tree.batch_proof(&[100], 100).is_err());
+        assert!(tree.consistency_proof(100, 100).is_err());
+    }
 }
@@ -3,6 +3,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 //! Implements the Prefix Tree.
+#[cfg(any(test, feature = "test-utils"))]
+use std::collections::HashMap;
 use std::result::Result;
 
 use crate::wire::PrefixProof as SearchResult;
@@ -64,3 +66,177 @@ fn evaluate_proof(
 pub fn evaluate(key: &[u8; 32], pos: u64, res: &SearchResult) -> Result<[u8; 32], MalformedProof> {
     evaluate_proof(key, &leaf_hash(key, res.counter, pos), &res.proof)
 }
+
+/// The value used in place of an empty subtree.
+const EMPTY: [u8; 32] = [0; 32];
+
+// Returns the root of a tree that doesn't contain `key`, given the siblings of
+// where its leaf would be. Unlike in evaluate_proof, a subtree with no leaves
+// has the value EMPTY rather than the hash of its children.
+pub fn evaluate_absent(key: &[u8; 32], proof: &[Vec<u8>]) -> Result<[u8; 32], MalformedProof> {
+    if proof.len() != 8 * KEY_LENGTH {
+        return Err(MalformedProof);
+    }
+
+    let mut value = EMPTY;
+    for i in 0..proof.len() {
+        let sibling: &[u8; 32] = proof[i].as_slice().try_into().map_err(|_| MalformedProof)?;
+        if value == EMPTY && *sibling == EMPTY {
+            continue;
+        }
+
+        let n = proof.len() - i - 1;
+        let b = key[n / 8] >> (7 - (n % 8)) & 1; // Read n^th bit of key
+
+        value = if b == 0 {
+            parent_hash(&value, sibling)
+        } else {
+            parent_hash(sibling, &value)
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+/// Identifies a node of the prefix tree by its depth and the first `depth` bits
+/// of the keys below it, with all other bits cleared.
+type NodeId = (usize, [u8; 32]);
+
+#[cfg(any(test, feature = "test-utils"))]
+fn node_id(key: &[u8; 32], depth: usize) -> NodeId {
+    let mut prefix = [0u8; KEY_LENGTH];
+    let (full, rest) = (depth / 8, depth % 8);
+    prefix[..full].copy_from_slice(&key[..full]);
+    if rest != 0 {
+        prefix[full] = key[full] & (0xff << (8 - rest));
+    }
+    (depth, prefix)
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+/// PrefixTree keeps every version of a Prefix Tree, so that it can produce
+/// search results for any version seen so far. A new version is created each
+/// time a key is inserted, starting from version 0.
+pub struct PrefixTree {
+    versions: u64,
+    /// For each non-empty node, its values in increasing order of version.
+    nodes: HashMap<NodeId, Vec<(u64, [u8; 32])>>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl PrefixTree {
+    pub fn new() -> Self {
+        Self {
+            versions: 0,
+            nodes: HashMap::new(),
+        }
+    }
+
+    fn get(&self, id: &NodeId, version: u64) -> [u8; 32] {
+        let Some(history) = self.nodes.get(id) else {
+            return EMPTY;
+        };
+        match history.partition_point(|(ver, _)| *ver <= version) {
+            0 => EMPTY,
+            i => history[i - 1].1,
+        }
+    }
+
+    /// Sets the counter of `key`, whose first instance in the log is at `pos`,
+    /// and returns the root of the new version of the tree.
+    pub fn insert(&mut self, key: &[u8; 32], ctr: u32, pos: u64) -> [u8; 32] {
+        let version = self.versions;
+        self.versions += 1;
+
+        let depth = 8 * KEY_LENGTH;
+        let mut value = leaf_hash(key, ctr, pos);
+        self.nodes
+            .entry(node_id(key, depth))
+            .or_default()
+            .push((version, value));
+
+        for n in (0..depth).rev() {
+            let b = key[n / 8] >> (7 - (n % 8)) & 1; // Read n^th bit of key
+            let mut sibling = *key;
+            sibling[n / 8] ^= 1 << (7 - (n % 8));
+            let sibling = self.get(&node_id(&sibling, n + 1), version);
+
+            value = if b == 0 {
+                parent_hash(&value, &sibling)
+            } else {
+                parent_hash(&sibling, &value)
+            };
+            self.nodes
+                .entry(node_id(key, n))
+                .or_default()
+                .push((version, value));
+        }
+        value
+    }
+
+    /// Returns the search result for `key` in the given version of the tree.
+    /// The key must be present in that version.
+    pub fn search(&self, key: &[u8; 32], ctr: u32, version: u64) -> SearchResult {
+        let depth = 8 * KEY_LENGTH;
+        let proof = (0..depth)
+            .rev()
+            .map(|n| {
+                let mut sibling = *key;
+                sibling[n / 8] ^= 1 << (7 - (n % 8));
+                self.get(&node_id(&sibling, n + 1), version).to_vec()
+            })
+            .collect();
+        SearchResult {
+            proof,
+            counter: ctr,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prefix_tree() {
+        let keys: Vec<[u8; 32]> = (0..20u8)
+            .map(|i| {
+                let mut key = [i.wrapping_mul(37); 32];
+                key[31] = i;
+                key
+            })
+            .collect();
+
+        let mut tree = PrefixTree::new();
+        let mut roots = vec![];
+        for (pos, key) in keys.iter().enumerate() {
+            roots.push(tree.insert(key, 0, pos as u64));
+        }
+        roots.push(tree.insert(&keys[3], 1, 3));
+
+        for (version, root) in roots.iter().enumerate() {
+            for (pos, key) in keys.iter().enumerate().take(version + 1) {
+                let ctr = if pos == 3 && version == keys.len() {
+                    1
+                } else {
+                    0
+                };
+                let res = tree.search(key, ctr, version as u64);
+                assert_eq!(evaluate(key, pos as u64, &res).unwrap(), *root);
+            }
+        }
+
+        let extra = [0xAA; 32];
+        for (version, root) in roots.iter().enumerate() {
+            let res = tree.search(&extra, 0, version as u64);
+            assert_eq!(evaluate_absent(&extra, &res.proof).unwrap(), *root);
+        }
+        assert_eq!(
+            evaluate_absent(&extra, &vec![EMPTY.to_vec(); 256]).unwrap(),
+            EMPTY
+        );
+
+        let stale = tree.search(&keys[3], 0, keys.len() as u64);
+        assert_ne!(evaluate(&keys[3], 3, &stale).unwrap(), roots[keys.len()]);
+    }
+}
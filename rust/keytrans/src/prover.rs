@@ -0,0 +1,730 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Implements the operator side of a transparency log.
+//!
+//! [`Prover`] keeps the whole log in memory and answers requests with the same proofs a key
+//! transparency server would, which makes it possible to test clients end-to-end without a
+//! server. Its responses can be modified freely before being handed to the verifier, to check
+//! that misbehaving servers are caught.
+
+use std::collections::{BTreeSet, HashMap};
+
+use ed25519_dalek::{Signer as _, SigningKey};
+use sha2::{Digest as _, Sha256};
+
+use crate::commitments::commit;
+use crate::guide::{InvalidState, ProofGuide};
+use crate::implicit::full_monitoring_path;
+use crate::log::LogTree;
+use crate::prefix::PrefixTree;
+use crate::verify::{leaf_hash, marshal_tree_head_tbs, marshal_update_value};
+use crate::wire::*;
+use crate::{log, vrf, DeploymentMode, PublicConfig};
+
+#[derive(Debug, displaydoc::Display)]
+pub enum Error {
+    /// Search key not found
+    SearchKeyNotFound,
+    /// Version of search key not found
+    VersionNotFound,
+    /// Invalid request: {0}
+    InvalidRequest(String),
+    /// Auditor tree head required but not set
+    AuditorTreeHeadMissing,
+}
+
+impl From<log::Error> for Error {
+    fn from(err: log::Error) -> Self {
+        Self::InvalidRequest(err.to_string())
+    }
+}
+
+impl From<InvalidState> for Error {
+    fn from(err: InvalidState) -> Self {
+        Self::InvalidRequest(err.to_string())
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A single update sequenced in the log.
+struct Entry {
+    search_key: String,
+    commitment: [u8; 32],
+    opening: [u8; 16],
+    value: Vec<u8>,
+}
+
+/// Everything the log knows about a search key.
+struct SearchKeyState {
+    /// The VRF output on the search key, and the proof for it.
+    index: [u8; 32],
+    vrf_proof: [u8; 80],
+    /// The position in the log of each version of the key.
+    versions: Vec<u64>,
+}
+
+impl SearchKeyState {
+    /// The position of the key's first occurrence in the log.
+    fn pos(&self) -> u64 {
+        self.versions[0]
+    }
+
+    /// The version of the key as of the entry at position `id` in the log,
+    /// which must not be before the key's first occurrence.
+    fn counter(&self, id: u64) -> u32 {
+        (self.versions.partition_point(|&pos| pos <= id) - 1) as u32
+    }
+}
+
+/// Prover is an in-memory transparency log that produces the responses
+/// expected by [`crate::verify_search`], [`crate::verify_update`] and
+/// [`crate::verify_monitor`].
+pub struct Prover {
+    mode: DeploymentMode,
+    signing_key: SigningKey,
+    vrf_key: vrf::SecretKey,
+    commitment_key: [u8; 32],
+    log: LogTree,
+    prefix: PrefixTree,
+    entries: Vec<Entry>,
+    search_keys: HashMap<String, SearchKeyState>,
+    auditor_tree_head: Option<TreeHead>,
+}
+
+impl Prover {
+    /// Creates an empty log.
+    ///
+    /// `commitment_key` is the secret the log's commitment openings are
+    /// derived from.
+    pub fn new(
+        mode: DeploymentMode,
+        signing_key: SigningKey,
+        vrf_key: vrf::SecretKey,
+        commitment_key: [u8; 32],
+    ) -> Self {
+        Self {
+            mode,
+            signing_key,
+            vrf_key,
+            commitment_key,
+            log: LogTree::new(),
+            prefix: PrefixTree::new(),
+            entries: vec![],
+            search_keys: HashMap::new(),
+            auditor_tree_head: None,
+        }
+    }
+
+    /// The configuration clients need to verify this log's responses.
+    pub fn public_config(&self) -> PublicConfig {
+        PublicConfig {
+            mode: self.mode,
+            signature_key: self.signing_key.verifying_key(),
+            vrf_key: self.vrf_key.public_key().clone(),
+        }
+    }
+
+    /// The number of entries in the log.
+    pub fn tree_size(&self) -> u64 {
+        self.log.size()
+    }
+
+    /// The root of the log when it had `tree_size` entries.
+    pub fn root(&self, tree_size: u64) -> Result<[u8; 32]> {
+        Ok(self.log.root(tree_size)?)
+    }
+
+    /// Sets the tree head to hand out as the third-party auditor's view of the
+    /// log, which is required in [`DeploymentMode::ThirdPartyAuditing`].
+    pub fn set_auditor_tree_head(&mut self, tree_head: TreeHead) -> Result<()> {
+        if tree_head.tree_size == 0 || tree_head.tree_size > self.tree_size() {
+            return Err(Error::InvalidRequest(
+                "auditor tree head is not for a known tree size".to_string(),
+            ));
+        }
+        self.auditor_tree_head = Some(tree_head);
+        Ok(())
+    }
+
+    /// Signs a tree head for the log at `tree_size` entries, as of `timestamp`
+    /// milliseconds since the epoch.
+    pub fn sign_tree_head(&self, tree_size: u64, timestamp: i64) -> Result<TreeHead> {
+        let root = self.log.root(tree_size)?;
+        let tbs = marshal_tree_head_tbs(tree_size, timestamp, &root, &self.public_config())
+            .map_err(|err| Error::InvalidRequest(err.to_string()))?;
+        Ok(TreeHead {
+            tree_size,
+            timestamp,
+            signature: self.signing_key.sign(&tbs).to_vec(),
+        })
+    }
+
+    /// Sequences a new version of `search_key` without building a response,
+    /// returning its position in the log.
+    pub fn insert(&mut self, search_key: &str, value: &[u8]) -> Result<u64> {
+        if u16::try_from(search_key.len()).is_err() {
+            return Err(Error::InvalidRequest("search key is too long".to_string()));
+        }
+        let data =
+            marshal_update_value(value).map_err(|err| Error::InvalidRequest(err.to_string()))?;
+
+        let pos = self.log.size();
+        let opening = self.opening(pos);
+        let commitment = commit(search_key.as_bytes(), &data, &opening)
+            .try_into()
+            .expect("commitments are 32 bytes");
+
+        let vrf_key = &self.vrf_key;
+        let state = self
+            .search_keys
+            .entry(search_key.to_owned())
+            .or_insert_with(|| {
+                let (vrf_proof, index) = vrf_key.prove(search_key.as_bytes());
+                SearchKeyState {
+                    index,
+                    vrf_proof,
+                    versions: vec![],
+                }
+            });
+        state.versions.push(pos);
+
+        let ctr = state.counter(pos);
+        let prefix_root = self.prefix.insert(&state.index, ctr, state.pos());
+        self.log.append(leaf_hash(&prefix_root, &commitment));
+        self.entries.push(Entry {
+            search_key: search_key.to_owned(),
+            commitment,
+            opening,
+            value: value.to_vec(),
+        });
+
+        Ok(pos)
+    }
+
+    /// Sequences a new version of a search key, responding with a tree head
+    /// signed as of `timestamp` milliseconds since the epoch.
+    pub fn update(&mut self, req: &UpdateRequest, timestamp: i64) -> Result<UpdateResponse> {
+        let pos = self.insert(&req.search_key, &req.value)?;
+        let state = &self.search_keys[&req.search_key];
+        let (search, _) = self.search_proof(state, None)?;
+
+        Ok(UpdateResponse {
+            tree_head: Some(self.full_tree_head(req.consistency.as_ref(), timestamp)?),
+            vrf_proof: state.vrf_proof.to_vec(),
+            search: Some(search),
+            opening: self.entries[pos as usize].opening.to_vec(),
+        })
+    }
+
+    /// Looks up a version of a search key, responding with a tree head signed
+    /// as of `timestamp` milliseconds since the epoch.
+    pub fn search(&self, req: &SearchRequest, timestamp: i64) -> Result<SearchResponse> {
+        let state = self
+            .search_keys
+            .get(&req.search_key)
+            .ok_or(Error::SearchKeyNotFound)?;
+        let (search, result_id) = self.search_proof(state, req.version)?;
+        let entry = &self.entries[result_id as usize];
+
+        Ok(SearchResponse {
+            tree_head: Some(self.full_tree_head(req.consistency.as_ref(), timestamp)?),
+            vrf_proof: state.vrf_proof.to_vec(),
+            search: Some(search),
+            opening: entry.opening.to_vec(),
+            value: Some(UpdateValue {
+                value: entry.value.clone(),
+            }),
+        })
+    }
+
+    /// Proves the monitored entries of each search key, responding with a
+    /// tree head signed as of `timestamp` milliseconds since the epoch.
+    pub fn monitor(&self, req: &MonitorRequest, timestamp: i64) -> Result<MonitorResponse> {
+        let n = self.tree_size();
+        let mut leaves = BTreeSet::new();
+        let mut prove = |key: &MonitorKey| {
+            let state = self
+                .search_keys
+                .get(&key.search_key)
+                .ok_or(Error::SearchKeyNotFound)?;
+            if key.entries.is_empty() || key.entries.iter().any(|&x| x < state.pos() || x >= n) {
+                return Err(Error::InvalidRequest(
+                    "monitored entries are out of range".to_string(),
+                ));
+            }
+            let path = full_monitoring_path(&key.entries, state.pos(), n);
+            leaves.extend(path.iter().copied());
+            Ok(MonitorProof {
+                steps: path.iter().map(|&id| self.proof_step(state, id)).collect(),
+            })
+        };
+
+        let owned_proofs = req
+            .owned_keys
+            .iter()
+            .map(&mut prove)
+            .collect::<Result<_>>()?;
+        let contact_proofs = req
+            .contact_keys
+            .iter()
+            .map(&mut prove)
+            .collect::<Result<_>>()?;
+
+        let inclusion = if leaves.is_empty() {
+            vec![self.log.root(n)?]
+        } else {
+            self.log
+                .batch_proof(&leaves.into_iter().collect::<Vec<_>>(), n)?
+        };
+
+        Ok(MonitorResponse {
+            tree_head: Some(self.full_tree_head(req.consistency.as_ref(), timestamp)?),
+            owned_proofs,
+            contact_proofs,
+            inclusion: inclusion.iter().map(|hash| hash.to_vec()).collect(),
+        })
+    }
+
+    /// Returns what a third-party auditor needs to check the entry at
+    /// position `pos` in the log.
+    pub fn auditor_update(&self, pos: u64) -> Result<AuditorUpdate> {
+        let entry = self
+            .entries
+            .get(pos as usize)
+            .ok_or_else(|| Error::InvalidRequest("position is not in the log".to_string()))?;
+        let state = &self.search_keys[&entry.search_key];
+        let ctr = state.counter(pos);
+        let previous = (ctr > 0).then(|| AuditorLeaf {
+            counter: ctr - 1,
+            pos: state.pos(),
+        });
+
+        Ok(AuditorUpdate {
+            index: state.index.to_vec(),
+            commitment: entry.commitment.to_vec(),
+            proof: self.prefix.search(&state.index, ctr, pos).proof,
+            previous,
+        })
+    }
+
+    /// Derives the commitment opening for the entry at position `pos` from
+    /// the commitment key. This keeps the log deterministic, which makes
+    /// failures easy to reproduce.
+    fn opening(&self, pos: u64) -> [u8; 16] {
+        let hash = Sha256::new()
+            .chain_update(self.commitment_key)
+            .chain_update(pos.to_be_bytes())
+            .finalize();
+        hash[..16].try_into().unwrap()
+    }
+
+    fn proof_step(&self, state: &SearchKeyState, id: u64) -> ProofStep {
+        let ctr = state.counter(id);
+        ProofStep {
+            prefix: Some(self.prefix.search(&state.index, ctr, id)),
+            commitment: self.entries[id as usize].commitment.to_vec(),
+        }
+    }
+
+    /// Returns the search proof for the given version of a key, along with the
+    /// position of that version in the log.
+    fn search_proof(
+        &self,
+        state: &SearchKeyState,
+        version: Option<u32>,
+    ) -> Result<(SearchProof, u64)> {
+        let n = self.tree_size();
+        let mut ids = vec![];
+        let mut steps = vec![];
+
+        let result = ProofGuide::new(version, state.pos(), n).consume(|guide, id| {
+            guide.insert(id, state.counter(id));
+            steps.push(self.proof_step(state, id));
+            ids.push(id);
+            Ok::<(), Error>(())
+        })?;
+        let (_, result_id) = result.ok_or(Error::VersionNotFound)?;
+
+        ids.sort();
+        let inclusion = self.log.batch_proof(&ids, n)?;
+
+        Ok((
+            SearchProof {
+                pos: state.pos(),
+                steps,
+                inclusion: inclusion.iter().map(|hash| hash.to_vec()).collect(),
+            },
+            result_id,
+        ))
+    }
+
+    fn full_tree_head(
+        &self,
+        consistency: Option<&Consistency>,
+        timestamp: i64,
+    ) -> Result<FullTreeHead> {
+        let n = self.tree_size();
+        let mut fth = FullTreeHead {
+            tree_head: Some(self.sign_tree_head(n, timestamp)?),
+            ..Default::default()
+        };
+
+        if let Some(consistency) = consistency {
+            if consistency.last > n {
+                return Err(Error::InvalidRequest(
+                    "last tree size is ahead of the log".to_string(),
+                ));
+            }
+            if (1..n).contains(&consistency.last) {
+                fth.consistency = self.consistency_proof(consistency.last, n)?;
+            }
+            match consistency.distinguished {
+                Some(distinguished) if distinguished > n => {
+                    return Err(Error::InvalidRequest(
+                        "distinguished tree size is ahead of the log".to_string(),
+                    ))
+                }
+                Some(distinguished) if (1..n).contains(&distinguished) => {
+                    fth.distinguished = self.consistency_proof(distinguished, n)?;
+                }
+                _ => {}
+            }
+        }
+
+        if let DeploymentMode::ThirdPartyAuditing(_) = self.mode {
+            let tree_head = self
+                .auditor_tree_head
+                .clone()
+                .ok_or(Error::AuditorTreeHeadMissing)?;
+            let (root_value, consistency) = if tree_head.tree_size < n {
+                (
+                    Some(self.log.root(tree_head.tree_size)?.to_vec()),
+                    self.consistency_proof(tree_head.tree_size, n)?,
+                )
+            } else {
+                (None, vec![])
+            };
+            fth.auditor_tree_head = Some(AuditorTreeHead {
+                tree_head: Some(tree_head),
+                root_value,
+                consistency,
+            });
+        }
+
+        Ok(fth)
+    }
+
+    fn consistency_proof(&self, m: u64, n: u64) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .log
+            .consistency_proof(m, n)?
+            .iter()
+            .map(|hash| hash.to_vec())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use assert_matches::assert_matches;
+    use futures_util::FutureExt;
+
+    use super::*;
+    use crate::{
+        verify_distinguished, verify_monitor, verify_search, verify_update, InMemoryLogStore,
+        LogStore,
+    };
+
+    const VRF_KEY: [u8; 32] = [1; 32];
+    const COMMITMENT_KEY: [u8; 32] = [4; 32];
+
+    fn now() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64
+    }
+
+    fn test_prover(mode: DeploymentMode) -> Prover {
+        let mut prover = Prover::new(
+            mode,
+            SigningKey::from_bytes(&[2; 32]),
+            vrf::SecretKey::from(VRF_KEY),
+            COMMITMENT_KEY,
+        );
+        for i in 0..50 {
+            prover
+                .insert(&format!("key{}", i % 7), format!("value{i}").as_bytes())
+                .unwrap();
+        }
+        prover
+    }
+
+    fn consistency(store: &InMemoryLogStore) -> Option<Consistency> {
+        store
+            .get_last_tree_head()
+            .now_or_never()
+            .unwrap()
+            .unwrap()
+            .map(|(head, _)| Consistency {
+                last: head.tree_size,
+                distinguished: None,
+            })
+    }
+
+    fn search_request(store: &InMemoryLogStore, key: &str, version: Option<u32>) -> SearchRequest {
+        SearchRequest {
+            search_key: key.to_string(),
+            version,
+            consistency: consistency(store),
+        }
+    }
+
+    fn check_search(
+        store: &mut InMemoryLogStore,
+        req: &SearchRequest,
+        res: &SearchResponse,
+    ) -> bool {
+        verify_search(store, req, res, false)
+            .now_or_never()
+            .unwrap()
+            .is_ok()
+    }
+
+    #[test]
+    fn search_and_update() {
+        let mut prover = test_prover(DeploymentMode::ContactMonitoring);
+        let mut store = InMemoryLogStore::new(prover.public_config());
+
+        for i in 0..7 {
+            let key = format!("key{i}");
+            let req = search_request(&store, &key, None);
+            let res = prover.search(&req, now()).unwrap();
+            assert!(check_search(&mut store, &req, &res));
+            let latest = (49 - (49 - i) % 7).to_string();
+            assert_eq!(
+                res.value.unwrap().value,
+                format!("value{latest}").as_bytes()
+            );
+
+            let req = search_request(&store, &key, Some(2));
+            let res = prover.search(&req, now()).unwrap();
+            assert!(check_search(&mut store, &req, &res));
+            assert_eq!(
+                res.value.unwrap().value,
+                format!("value{}", i + 14).as_bytes()
+            );
+        }
+
+        let req = UpdateRequest {
+            search_key: "key3".to_string(),
+            value: b"new value".to_vec(),
+            consistency: consistency(&store),
+        };
+        let res = prover.update(&req, now()).unwrap();
+        assert!(verify_update(&mut store, &req, &res)
+            .now_or_never()
+            .unwrap()
+            .is_ok());
+
+        let req = search_request(&store, "key3", None);
+        let res = prover.search(&req, now()).unwrap();
+        assert!(check_search(&mut store, &req, &res));
+        assert_eq!(res.value.unwrap().value, b"new value");
+
+        assert_matches!(
+            prover.search(&search_request(&store, "unknown", None), now()),
+            Err(Error::SearchKeyNotFound)
+        );
+        assert_matches!(
+            prover.search(&search_request(&store, "key0", Some(100)), now()),
+            Err(Error::VersionNotFound)
+        );
+    }
+
+    #[test]
+    fn monitor() {
+        let mut prover = test_prover(DeploymentMode::ContactMonitoring);
+        let mut store = InMemoryLogStore::new(prover.public_config());
+
+        for key in ["key1", "key2"] {
+            let req = search_request(&store, key, None);
+            let res = prover.search(&req, now()).unwrap();
+            assert!(check_search(&mut store, &req, &res));
+        }
+
+        for i in 0..200 {
+            prover
+                .insert(&format!("key{}", i % 5), b"later value")
+                .unwrap();
+        }
+
+        let monitor_key = |key: &str| MonitorKey {
+            search_key: key.to_string(),
+            entries: store
+                .get_data(key)
+                .now_or_never()
+                .unwrap()
+                .unwrap()
+                .unwrap()
+                .entries(),
+        };
+        let req = MonitorRequest {
+            owned_keys: vec![monitor_key("key1")],
+            contact_keys: vec![monitor_key("key2")],
+            consistency: consistency(&store),
+        };
+        let res = prover.monitor(&req, now()).unwrap();
+        assert!(verify_monitor(&mut store, &req, &res)
+            .now_or_never()
+            .unwrap()
+            .is_ok());
+
+        let req = MonitorRequest {
+            owned_keys: vec![],
+            contact_keys: vec![],
+            consistency: consistency(&store),
+        };
+        let res = prover.monitor(&req, now()).unwrap();
+        assert!(verify_monitor(&mut store, &req, &res)
+            .now_or_never()
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn distinguished() {
+        let prover = test_prover(DeploymentMode::ContactMonitoring);
+        let mut store = InMemoryLogStore::new(prover.public_config());
+
+        let req = SearchRequest {
+            search_key: "key0".to_string(),
+            version: None,
+            consistency: Some(Consistency {
+                last: 0,
+                distinguished: Some(20),
+            }),
+        };
+        let res = prover.search(&req, now()).unwrap();
+        assert!(check_search(&mut store, &req, &res));
+        assert!(verify_distinguished(
+            &mut store,
+            res.tree_head.as_ref().unwrap(),
+            20,
+            prover.root(20).unwrap()
+        )
+        .now_or_never()
+        .unwrap()
+        .is_ok());
+    }
+
+    #[test]
+    fn third_party_auditing() {
+        let auditor_key = SigningKey::from_bytes(&[3; 32]);
+        let mut prover = test_prover(DeploymentMode::ThirdPartyAuditing(
+            auditor_key.verifying_key(),
+        ));
+        let mut store = InMemoryLogStore::new(prover.public_config());
+
+        let req = search_request(&store, "key0", None);
+        assert_matches!(
+            prover.search(&req, now()),
+            Err(Error::AuditorTreeHeadMissing)
+        );
+
+        let timestamp = now();
+        let root = prover.root(40).unwrap();
+        let tbs = marshal_tree_head_tbs(40, timestamp, &root, &prover.public_config()).unwrap();
+        prover
+            .set_auditor_tree_head(TreeHead {
+                tree_size: 40,
+                timestamp,
+                signature: auditor_key.sign(&tbs).to_vec(),
+            })
+            .unwrap();
+
+        let res = prover.search(&req, now()).unwrap();
+        assert!(check_search(&mut store, &req, &res));
+    }
+
+    #[test]
+    fn tampered_search_responses_are_rejected() {
+        let prover = test_prover(DeploymentMode::ContactMonitoring);
+        let store = InMemoryLogStore::new(prover.public_config());
+        let req = search_request(&store, "key4", Some(3));
+        let res = prover.search(&req, now()).unwrap();
+        assert!(check_search(&mut store.clone(), &req, &res));
+
+        type Tamper = fn(&mut SearchResponse);
+        let tampers: [(&str, Tamper); 9] = [
+            ("vrf proof", |res| res.vrf_proof[0] ^= 1),
+            ("value", |res| res.value.as_mut().unwrap().value.push(0)),
+            ("opening", |res| res.opening[0] ^= 1),
+            ("pos", |res| res.search.as_mut().unwrap().pos += 1),
+            ("commitment", |res| {
+                res.search.as_mut().unwrap().steps[0].commitment[0] ^= 1
+            }),
+            ("counter", |res| {
+                let steps = &mut res.search.as_mut().unwrap().steps;
+                steps.last_mut().unwrap().prefix.as_mut().unwrap().counter += 1
+            }),
+            ("prefix proof", |res| {
+                res.search.as_mut().unwrap().steps[1]
+                    .prefix
+                    .as_mut()
+                    .unwrap()
+                    .proof[0][0] ^= 1
+            }),
+            ("missing step", |res| {
+                res.search.as_mut().unwrap().steps.pop();
+            }),
+            ("inclusion proof", |res| {
+                res.search.as_mut().unwrap().inclusion[0][0] ^= 1
+            }),
+        ];
+        for (name, tamper) in tampers {
+            let mut tampered = res.clone();
+            tamper(&mut tampered);
+            assert!(
+                !check_search(&mut store.clone(), &req, &tampered),
+                "tampered {name} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn forked_log_is_rejected() {
+        let prover = test_prover(DeploymentMode::ContactMonitoring);
+        let mut store = InMemoryLogStore::new(prover.public_config());
+        let req = search_request(&store, "key0", None);
+        assert!(check_search(
+            &mut store,
+            &req,
+            &prover.search(&req, now()).unwrap()
+        ));
+
+        // A log with the same keys but a different history.
+        let mut fork = Prover::new(
+            DeploymentMode::ContactMonitoring,
+            SigningKey::from_bytes(&[2; 32]),
+            vrf::SecretKey::from(VRF_KEY),
+            COMMITMENT_KEY,
+        );
+        for i in 0..60 {
+            fork.insert(&format!("key{}", i % 7), b"forked value")
+                .unwrap();
+        }
+        let req = search_request(&store, "key0", None);
+        assert!(!check_search(
+            &mut store,
+            &req,
+            &fork.search(&req, now()).unwrap()
+        ));
+    }
+}
@@ -0,0 +1,292 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Reference implementations of [`LogStore`] and [`SimplifiedLogStore`].
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use prost::Message;
+
+use crate::{
+    wire, LogStore, LogStoreError, MonitoringData, PublicConfig, SimplifiedLogStore, TreeHead,
+};
+
+/// The version of the [`PersistentLogStore`] serialization format written by this library.
+const PERSISTENT_LOG_STORE_VERSION: u32 = 1;
+
+/// A [`LogStore`] that keeps everything in memory, mostly useful for tests.
+#[derive(Clone)]
+pub struct InMemoryLogStore {
+    config: PublicConfig,
+    tree_head: Option<(TreeHead, [u8; 32])>,
+    data: HashMap<String, MonitoringData>,
+}
+
+impl InMemoryLogStore {
+    pub fn new(config: PublicConfig) -> Self {
+        Self {
+            config,
+            tree_head: None,
+            data: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl LogStore for InMemoryLogStore {
+    async fn public_config(&self) -> Result<PublicConfig, LogStoreError> {
+        Ok(self.config.clone())
+    }
+
+    async fn get_last_tree_head(&self) -> Result<Option<(TreeHead, [u8; 32])>, LogStoreError> {
+        Ok(self.tree_head.clone())
+    }
+
+    async fn set_last_tree_head(
+        &mut self,
+        head: TreeHead,
+        root: [u8; 32],
+    ) -> Result<(), LogStoreError> {
+        self.tree_head = Some((head, root));
+        Ok(())
+    }
+
+    async fn get_data(&self, key: &str) -> Result<Option<MonitoringData>, LogStoreError> {
+        Ok(self.data.get(key).cloned())
+    }
+
+    async fn set_data(&mut self, key: &str, data: MonitoringData) -> Result<(), LogStoreError> {
+        self.data.insert(key.to_owned(), data);
+        Ok(())
+    }
+}
+
+/// A [`SimplifiedLogStore`] whose whole contents can be saved with [`Self::serialize`] and
+/// restored with [`Self::deserialize`], so that monitoring can pick up where it left off after a
+/// restart.
+///
+/// The serialized form is a versioned protobuf message. [`Self::deserialize`] only accepts the
+/// versions it has code to read, which is currently just the one [`Self::serialize`] writes, and
+/// rejects anything else, including versions written by a newer library.
+#[derive(Clone)]
+pub struct PersistentLogStore {
+    config: PublicConfig,
+    tree_head: Option<Vec<u8>>,
+    search_keys: HashMap<String, wire::StoredSearchKey>,
+}
+
+impl PersistentLogStore {
+    pub fn new(config: PublicConfig) -> Self {
+        Self {
+            config,
+            tree_head: None,
+            search_keys: HashMap::new(),
+        }
+    }
+
+    /// Restores a store saved with [`Self::serialize`].
+    ///
+    /// The public config is not part of the saved state, since it is fixed for a given log.
+    pub fn deserialize(config: PublicConfig, data: &[u8]) -> Result<Self, LogStoreError> {
+        let stored = wire::StoredLogState::decode(data)?;
+        match stored.version {
+            PERSISTENT_LOG_STORE_VERSION => Ok(Self {
+                config,
+                tree_head: stored.tree_head,
+                search_keys: stored.search_keys,
+            }),
+            0 => Err(LogStoreError("missing log store version".to_string())),
+            version => Err(LogStoreError(format!(
+                "unsupported log store version {version}"
+            ))),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        wire::StoredLogState {
+            version: PERSISTENT_LOG_STORE_VERSION,
+            tree_head: self.tree_head.clone(),
+            search_keys: self.search_keys.clone(),
+        }
+        .encode_to_vec()
+    }
+
+    /// The search keys that need to be monitored once the log has reached `tree_size`, in no
+    /// particular order.
+    pub fn keys_to_monitor(&self, tree_size: u64) -> Vec<String> {
+        self.search_keys
+            .iter()
+            .filter(|(_, stored)| stored.next_monitor <= tree_size)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+
+#[async_trait(?Send)]
+impl SimplifiedLogStore for PersistentLogStore {
+    async fn public_config(&self) -> Result<PublicConfig, LogStoreError> {
+        Ok(self.config.clone())
+    }
+
+    async fn get_raw_tree_head(&self) -> Result<Option<Vec<u8>>, LogStoreError> {
+        Ok(self.tree_head.clone())
+    }
+
+    async fn set_raw_tree_head(&mut self, data: &[u8]) -> Result<(), LogStoreError> {
+        self.tree_head = Some(data.to_vec());
+        Ok(())
+    }
+
+    async fn get_raw_data(&self, key: &str) -> Result<Option<Vec<u8>>, LogStoreError> {
+        Ok(self.search_keys.get(key).map(|stored| stored.data.clone()))
+    }
+
+    async fn set_raw_data(
+        &mut self,
+        key: &str,
+        data: &[u8],
+        next_monitor: u64,
+    ) -> Result<(), LogStoreError> {
+        self.search_keys.insert(
+            key.to_owned(),
+            wire::StoredSearchKey {
+                data: data.to_vec(),
+                next_monitor,
+            },
+        );
+        Ok(())
+    }
+
+    async fn list_keys(&self) -> Result<Vec<String>, LogStoreError> {
+        Ok(self.search_keys.keys().cloned().collect())
+    }
+
+    fn as_log_store(&mut self) -> &mut dyn LogStore {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures_util::FutureExt;
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::{DeploymentMode, SigPublicKey, VrfPublicKey};
+
+    fn test_config() -> PublicConfig {
+        PublicConfig {
+            mode: DeploymentMode::ContactMonitoring,
+            signature_key: SigPublicKey::from_bytes(&hex!(
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+            ))
+            .unwrap(),
+            vrf_key: VrfPublicKey::try_from(hex!(
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+            ))
+            .unwrap(),
+        }
+    }
+
+    fn test_tree_head() -> (TreeHead, [u8; 32]) {
+        let head = TreeHead {
+            tree_size: 1000,
+            timestamp: 1_700_000_000_000,
+            signature: vec![1; 64],
+        };
+        (head, [2; 32])
+    }
+
+    fn test_data(owned: bool) -> MonitoringData {
+        MonitoringData {
+            index: [3; 32],
+            pos: 35,
+            ptrs: HashMap::from([(35, 0), (64, 1)]),
+            owned,
+        }
+    }
+
+    fn exercise(store: &mut dyn LogStore) {
+        async {
+            assert_eq!(store.get_last_tree_head().await.unwrap(), None);
+            assert_eq!(store.get_data("alice").await.unwrap(), None);
+
+            let (head, root) = test_tree_head();
+            store.set_last_tree_head(head.clone(), root).await.unwrap();
+            assert_eq!(
+                store.get_last_tree_head().await.unwrap(),
+                Some((head, root))
+            );
+
+            store.set_data("alice", test_data(true)).await.unwrap();
+            store.set_data("bob", test_data(false)).await.unwrap();
+            assert_eq!(
+                store.get_data("alice").await.unwrap(),
+                Some(test_data(true))
+            );
+            assert_eq!(store.get_data("bob").await.unwrap(), Some(test_data(false)));
+            assert_eq!(store.get_data("carol").await.unwrap(), None);
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    #[test]
+    fn in_memory_store() {
+        exercise(&mut InMemoryLogStore::new(test_config()));
+    }
+
+    #[test]
+    fn persistent_store_round_trip() {
+        let mut store = PersistentLogStore::new(test_config());
+        exercise(store.as_log_store());
+
+        let mut keys = store.list_keys().now_or_never().unwrap().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["alice", "bob"]);
+
+        let next_monitor = test_data(true).next_monitor();
+        assert_eq!(
+            store.keys_to_monitor(next_monitor - 1),
+            Vec::<String>::new()
+        );
+        assert_eq!(store.keys_to_monitor(next_monitor).len(), 2);
+
+        let restored = PersistentLogStore::deserialize(test_config(), &store.serialize()).unwrap();
+        async {
+            assert_eq!(
+                LogStore::get_last_tree_head(&restored).await.unwrap(),
+                Some(test_tree_head())
+            );
+            assert_eq!(
+                LogStore::get_data(&restored, "alice").await.unwrap(),
+                Some(test_data(true))
+            );
+            assert_eq!(
+                LogStore::get_data(&restored, "bob").await.unwrap(),
+                Some(test_data(false))
+            );
+        }
+        .now_or_never()
+        .expect("sync");
+        assert_eq!(restored.keys_to_monitor(next_monitor).len(), 2);
+    }
+
+    #[test]
+    fn persistent_store_rejects_unknown_versions() {
+        let unversioned = wire::StoredLogState::default().encode_to_vec();
+        assert!(PersistentLogStore::deserialize(test_config(), &unversioned).is_err());
+
+        let future = wire::StoredLogState {
+            version: PERSISTENT_LOG_STORE_VERSION + 1,
+            ..Default::default()
+        }
+        .encode_to_vec();
+        assert!(PersistentLogStore::deserialize(test_config(), &future).is_err());
+
+        assert!(PersistentLogStore::deserialize(test_config(), &[0xFF]).is_err());
+    }
+}
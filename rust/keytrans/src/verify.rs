@@ -97,7 +97,7 @@ fn serialize_key(buffer: &mut Vec<u8>, key_material: &[u8], key_kind: &str) {
     buffer.extend_from_slice(key_material);
 }
 
-fn marshal_tree_head_tbs(
+pub fn marshal_tree_head_tbs(
     tree_size: u64,
     timestamp: i64,
     root: &[u8; 32],
@@ -122,7 +122,7 @@ fn marshal_tree_head_tbs(
     Ok(buf)
 }
 
-fn marshal_update_value(value: &[u8]) -> Result<Vec<u8>> {
+pub fn marshal_update_value(value: &[u8]) -> Result<Vec<u8>> {
     let mut buf = vec![];
 
     let length = u32::try_from(value.len()).map_err(|_| Error::ValueTooLong)?;
@@ -133,7 +133,7 @@ fn marshal_update_value(value: &[u8]) -> Result<Vec<u8>> {
 }
 
 /// Returns the hash of the leaf of the transparency tree.
-fn leaf_hash(prefix_root: &[u8; 32], commitment: &[u8; 32]) -> [u8; 32] {
+pub fn leaf_hash(prefix_root: &[u8; 32], commitment: &[u8; 32]) -> [u8; 32] {
     let mut hasher = Sha256::new();
     hasher.update(prefix_root);
     hasher.update(commitment);
@@ -142,7 +142,7 @@ fn leaf_hash(prefix_root: &[u8; 32], commitment: &[u8; 32]) -> [u8; 32] {
 }
 
 /// Checks the signature on the provided transparency tree head using the given key
-fn verify_tree_head_signature(
+pub fn verify_tree_head_signature(
     config: &PublicConfig,
     head: &TreeHead,
     root: &[u8; 32],
@@ -5,8 +5,9 @@
 
 //! Implements ECVRF-EDWARDS25519-SHA512-TAI from RFC 9381.
 use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
-use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::scalar::{clamp_integer, Scalar};
 use curve25519_dalek::traits::VartimeMultiscalarMul;
+use rand::{CryptoRng, Rng};
 use sha2::{Digest as _, Sha512};
 
 const SUITE_ID: u8 = 0x03;
@@ -21,6 +22,8 @@ pub enum Error {
     InvalidCurvePoint,
     /// Invalid VRF proof
     InvalidProof,
+    /// Malformed VRF secret key
+    MalformedSecretKey,
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -138,12 +141,107 @@ impl PublicKey {
     }
 }
 
+/// SecretKey holds a VRF private key, which is used by the log operator to
+/// compute search key indices.
+///
+/// It is serialized as the 32-byte seed it was expanded from, in the same way
+/// as an Ed25519 private key.
+#[derive(Clone)]
+pub struct SecretKey {
+    seed: [u8; 32],
+    scalar: Scalar,
+    nonce_prefix: [u8; 32],
+    public_key: PublicKey,
+}
+
+impl From<[u8; 32]> for SecretKey {
+    /// Expands the secret key the same way as an Ed25519 signing key.
+    fn from(secret_key: [u8; 32]) -> Self {
+        let h = Sha512::digest(secret_key);
+        let scalar = Scalar::from_bytes_mod_order(clamp_integer(h[..32].try_into().unwrap()));
+        let decompressed = EdwardsPoint::mul_base(&scalar);
+        Self {
+            seed: secret_key,
+            scalar,
+            nonce_prefix: h[32..].try_into().unwrap(),
+            public_key: PublicKey {
+                compressed: decompressed.compress().0,
+                decompressed,
+            },
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for SecretKey {
+    type Error = Error;
+
+    fn try_from(secret_key: &[u8]) -> Result<Self> {
+        let seed: [u8; 32] = secret_key.try_into().map_err(|_| Error::MalformedSecretKey)?;
+        Ok(Self::from(seed))
+    }
+}
+
+impl SecretKey {
+    pub fn generate<R: Rng + CryptoRng>(rng: &mut R) -> Self {
+        Self::from(rng.gen::<[u8; 32]>())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.seed
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Computes the VRF proof for message m, returning the proof along with
+    /// the index it hashes to.
+    pub fn prove(&self, m: &[u8]) -> ([u8; 80], [u8; 32]) {
+        // H = encode_to_curve_try_and_increment(pk, m)
+        // Gamma = [x]H
+        let h = encode_to_curve_try_and_increment(&self.public_key.compressed, m);
+        let h_bytes = h.compress().0;
+        let gamma = self.scalar * h;
+        let gamma_bytes = gamma.compress().0;
+
+        // k = nonce_generation(sk, h), as in Section 5.1.6 of RFC 8032.
+        let k = Scalar::from_bytes_mod_order_wide(
+            &Sha512::new()
+                .chain_update(self.nonce_prefix)
+                .chain_update(h_bytes)
+                .finalize()
+                .into(),
+        );
+
+        // c = challenge_generation(Y, H, Gamma, [k]B, [k]H)
+        // s = (k + c*x) mod q
+        let c_bytes = generate_challenge([
+            &self.public_key.compressed,
+            &h_bytes,
+            &gamma_bytes,
+            &EdwardsPoint::mul_base(&k).compress().0,
+            &(k * h).compress().0,
+        ]);
+        let mut c_wide = [0u8; 32];
+        c_wide[..16].copy_from_slice(&c_bytes);
+        let s = k + Scalar::from_bytes_mod_order(c_wide) * self.scalar;
+
+        let mut proof = [0u8; 80];
+        proof[..32].copy_from_slice(&gamma_bytes);
+        proof[32..48].copy_from_slice(&c_bytes);
+        proof[48..].copy_from_slice(s.as_bytes());
+
+        (proof, proof_to_hash(&gamma))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use hex_literal::hex;
 
     struct TestVector {
+        sk: [u8; 32],
         pk: [u8; 32],
         alpha: &'static [u8],
         h: [u8; 32],
@@ -153,6 +251,7 @@ mod tests {
 
     const TEST_VECTORS: [TestVector; 3] = [
         TestVector {
+            sk: hex!("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60"),
             pk: hex!("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"),
             alpha: &hex!(""),
             h: hex!("91bbed02a99461df1ad4c6564a5f5d829d0b90cfc7903e7a5797bd658abf3318"),
@@ -160,6 +259,7 @@ mod tests {
             beta: hex!("90cf1df3b703cce59e2a35b925d411164068269d7b2d29f3301c03dd757876ff"),
         },
         TestVector {
+            sk: hex!("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb"),
             pk: hex!("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c"),
             alpha: &hex!("72"),
             h: hex!("5b659fc3d4e9263fd9a4ed1d022d75eaacc20df5e09f9ea937502396598dc551"),
@@ -167,6 +267,7 @@ mod tests {
             beta: hex!("eb4440665d3891d668e7e0fcaf587f1b4bd7fbfe99d0eb2211ccec90496310eb"),
         },
         TestVector {
+            sk: hex!("c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7"),
             pk: hex!("fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025"),
             alpha: &hex!("af82"),
             h: hex!("bf4339376f5542811de615e3313d2b36f6f53c0acfebb482159711201192576a"),
@@ -194,6 +295,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_prove() {
+        for v in TEST_VECTORS {
+            let sk = SecretKey::from(v.sk);
+            assert_eq!(sk.public_key().as_bytes(), v.pk);
+
+            let (pi, beta) = sk.prove(v.alpha);
+            assert_eq!(pi, v.pi);
+            assert_eq!(beta, v.beta);
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let sk = SecretKey::generate(&mut rand::thread_rng());
+        let restored = SecretKey::try_from(&sk.as_bytes()[..]).unwrap();
+        assert_eq!(restored.public_key().as_bytes(), sk.public_key().as_bytes());
+
+        let (proof, index) = sk.prove(b"alice");
+        let pk =
+            PublicKey::try_from(<[u8; 32]>::try_from(sk.public_key().as_bytes()).unwrap()).unwrap();
+        assert_eq!(pk.proof_to_hash(b"alice", &proof).unwrap(), index);
+
+        assert!(SecretKey::try_from(&[0u8; 31][..]).is_err());
+    }
+
     #[test]
     fn test_proof_to_hash_fails() {
         for v in TEST_VECTORS {
@@ -184,6 +184,12 @@ where
         self.unauth_service.send_and_debug(msg, timeout).await
     }
 
+    /// The authenticated connection, for use with typed clients such as
+    /// [`KeyTransparencyClient`](crate::keytrans::KeyTransparencyClient).
+    pub fn authenticated_service(&self) -> &(impl ChatService + Send + Sync) {
+        &self.auth_service
+    }
+
     pub async fn connect_authenticated(&self) -> Result<DebugInfo, ChatServiceError> {
         self.auth_service.connect_and_debug().await
     }
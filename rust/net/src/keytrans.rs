@@ -0,0 +1,451 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Key transparency lookups over the chat connection.
+//!
+//! The chat server relays key transparency requests to the log unchanged, so the requests and
+//! responses here are the protobuf messages from [`libsignal_keytrans`]. Every response is checked
+//! with the verifier in that crate against the caller's [`LogStore`] before anything from it is
+//! returned.
+//!
+//! This client is only available to Rust callers for now. Exposing it to the apps would need a
+//! bridged [`LogStore`] and error type on each platform, and is out of scope for this module.
+
+use std::time::Duration;
+
+use http::header::CONTENT_TYPE;
+use http::uri::PathAndQuery;
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use libsignal_core::Aci;
+use libsignal_keytrans::{
+    verify_monitor, verify_search, Consistency, LogStore, MonitorKey, MonitorRequest,
+    MonitorResponse, SearchRequest, SearchResponse,
+};
+use libsignal_protocol::IdentityKey;
+use prost::Message;
+
+use crate::cdsi::E164;
+use crate::chat::{ChatService, ChatServiceError, Request};
+
+const SEARCH_PATH: &str = "/v1/key-transparency/search";
+const MONITOR_PATH: &str = "/v1/key-transparency/monitor";
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum Error {
+    /// Chat request failed: {0}
+    ChatService(#[from] ChatServiceError),
+    /// Search key not found in the log
+    NotFound,
+    /// Request failed with status {0}
+    RequestFailed(StatusCode),
+    /// Failed to decode response from the server
+    InvalidResponse,
+    /// Verification failed: {0}
+    VerificationFailed(String),
+    /// Search key has not been looked up before
+    NotMonitored,
+    /// Log store operation failed: {0}
+    Storage(String),
+}
+
+/// A value that can be looked up in the key transparency log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SearchKey {
+    /// Maps to the account's identity key.
+    Aci(Aci),
+    /// Maps to the ACI of the account that has this phone number.
+    E164(E164),
+    /// Maps to the ACI of the account that has a username with this hash.
+    UsernameHash(Vec<u8>),
+}
+
+impl SearchKey {
+    /// The search key as it appears in the log. Each kind of key has its own
+    /// prefix, so they can never collide.
+    fn as_log_key(&self) -> String {
+        match self {
+            Self::Aci(aci) => format!("a{}", aci.service_id_string()),
+            Self::E164(e164) => format!("n{e164}"),
+            Self::UsernameHash(hash) => format!("u{}", hex::encode(hash)),
+        }
+    }
+}
+
+/// Looks up identity keys in the key transparency log, verifying every
+/// response against a [`LogStore`].
+pub struct KeyTransparencyClient<'a, C: ?Sized> {
+    chat: &'a C,
+    timeout: Duration,
+}
+
+impl<'a, C: ChatService + ?Sized> KeyTransparencyClient<'a, C> {
+    pub fn new(chat: &'a C, timeout: Duration) -> Self {
+        Self { chat, timeout }
+    }
+
+    /// Looks up the identity key of `aci`.
+    ///
+    /// If `e164` or `username_hash` are provided, they are looked up too, and
+    /// must belong to `aci` for the lookup to succeed.
+    pub async fn search(
+        &self,
+        store: &mut dyn LogStore,
+        aci: &Aci,
+        e164: Option<E164>,
+        username_hash: Option<&[u8]>,
+    ) -> Result<IdentityKey, Error> {
+        let value = self.search_key(store, &SearchKey::Aci(*aci)).await?;
+        let identity_key = IdentityKey::decode(&value).map_err(|_| {
+            Error::VerificationFailed("log contains an invalid identity key".to_string())
+        })?;
+
+        let aliases = e164
+            .map(SearchKey::E164)
+            .into_iter()
+            .chain(username_hash.map(|hash| SearchKey::UsernameHash(hash.to_vec())));
+        for alias in aliases {
+            if self.search_key(store, &alias).await? != aci.service_id_binary() {
+                return Err(Error::VerificationFailed(format!(
+                    "{} belongs to a different account",
+                    match alias {
+                        SearchKey::Aci(_) => "ACI",
+                        SearchKey::E164(_) => "phone number",
+                        SearchKey::UsernameHash(_) => "username",
+                    }
+                )));
+            }
+        }
+
+        Ok(identity_key)
+    }
+
+    /// Checks that the log still holds what was seen when each of the keys
+    /// was looked up, updating `store` with the new state of the log.
+    ///
+    /// `owned` are the keys belonging to this client; `contacts` are everyone
+    /// else's. Every key must have been looked up with [`Self::search`] first.
+    pub async fn monitor(
+        &self,
+        store: &mut dyn LogStore,
+        owned: &[SearchKey],
+        contacts: &[SearchKey],
+    ) -> Result<(), Error> {
+        let mut owned_keys = vec![];
+        for key in owned {
+            owned_keys.push(monitor_key(store, key).await?);
+        }
+        let mut contact_keys = vec![];
+        for key in contacts {
+            contact_keys.push(monitor_key(store, key).await?);
+        }
+
+        let req = MonitorRequest {
+            owned_keys,
+            contact_keys,
+            consistency: consistency(store).await?,
+        };
+        let res: MonitorResponse = self.send(MONITOR_PATH, &req).await?;
+
+        verify_monitor(store, &req, &res)
+            .await
+            .map_err(|err| Error::VerificationFailed(err.to_string()))
+    }
+
+    /// Looks up the latest value of `key`, returning it once verified.
+    async fn search_key(
+        &self,
+        store: &mut dyn LogStore,
+        key: &SearchKey,
+    ) -> Result<Vec<u8>, Error> {
+        let req = SearchRequest {
+            search_key: key.as_log_key(),
+            version: None,
+            consistency: consistency(store).await?,
+        };
+        let res: SearchResponse = self.send(SEARCH_PATH, &req).await?;
+
+        verify_search(store, &req, &res, false)
+            .await
+            .map_err(|err| Error::VerificationFailed(err.to_string()))?;
+        Ok(res.value.map(|value| value.value).unwrap_or_default())
+    }
+
+    async fn send<Res: Message + Default>(
+        &self,
+        path: &'static str,
+        req: &impl Message,
+    ) -> Result<Res, Error> {
+        let request = Request {
+            method: Method::POST,
+            body: Some(req.encode_to_vec().into_boxed_slice()),
+            headers: HeaderMap::from_iter([(
+                CONTENT_TYPE,
+                HeaderValue::from_static(PROTOBUF_CONTENT_TYPE),
+            )]),
+            path: PathAndQuery::from_static(path),
+        };
+        let response = self.chat.send(request, self.timeout).await?;
+
+        match response.status {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND => return Err(Error::NotFound),
+            status => return Err(Error::RequestFailed(status)),
+        }
+        Res::decode(response.body.as_deref().unwrap_or_default())
+            .map_err(|_| Error::InvalidResponse)
+    }
+}
+
+async fn monitor_key(store: &dyn LogStore, key: &SearchKey) -> Result<MonitorKey, Error> {
+    let search_key = key.as_log_key();
+    let data = store
+        .get_data(&search_key)
+        .await
+        .map_err(|err| Error::Storage(err.to_string()))?
+        .ok_or(Error::NotMonitored)?;
+    Ok(MonitorKey {
+        search_key,
+        entries: data.entries(),
+    })
+}
+
+/// The consistency parameters for a request, based on the last tree head the
+/// client has verified.
+async fn consistency(store: &dyn LogStore) -> Result<Option<Consistency>, Error> {
+    let last = store
+        .get_last_tree_head()
+        .await
+        .map_err(|err| Error::Storage(err.to_string()))?;
+    Ok(last.map(|(tree_head, _)| Consistency {
+        last: tree_head.tree_size,
+        distinguished: None,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use assert_matches::assert_matches;
+    use async_trait::async_trait;
+    use ed25519_dalek::SigningKey;
+    use libsignal_keytrans::{DeploymentMode, InMemoryLogStore, Prover, ProverError, VrfSecretKey};
+    use libsignal_protocol::IdentityKeyPair;
+    use rand::rngs::OsRng;
+
+    use super::*;
+    use crate::chat::Response;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const ACI: Aci = Aci::from_uuid_bytes([0x11; 16]);
+    const OTHER_ACI: Aci = Aci::from_uuid_bytes([0x22; 16]);
+    const USERNAME_HASH: [u8; 32] = [0x33; 32];
+
+    fn e164() -> E164 {
+        "+18005550100".parse().unwrap()
+    }
+
+    /// Answers key transparency requests the way the chat server would,
+    /// from an in-memory log.
+    struct FakeChatServer {
+        prover: Mutex<Prover>,
+    }
+
+    impl FakeChatServer {
+        fn new() -> Self {
+            Self {
+                prover: Mutex::new(Prover::new(
+                    DeploymentMode::ContactMonitoring,
+                    SigningKey::from_bytes(&[2; 32]),
+                    VrfSecretKey::from([1; 32]),
+                    [4; 32],
+                )),
+            }
+        }
+
+        fn insert(&self, key: &SearchKey, value: &[u8]) {
+            self.prover
+                .lock()
+                .unwrap()
+                .insert(&key.as_log_key(), value)
+                .expect("can insert");
+        }
+
+        fn insert_account(&self, aci: &Aci, identity_key: &IdentityKey) {
+            self.insert(&SearchKey::Aci(*aci), &identity_key.serialize());
+        }
+
+        fn store(&self) -> InMemoryLogStore {
+            InMemoryLogStore::new(self.prover.lock().unwrap().public_config())
+        }
+
+        fn respond(&self, msg: &Request) -> Result<Vec<u8>, StatusCode> {
+            let body = msg.body.as_deref().unwrap_or_default();
+            let prover = self.prover.lock().unwrap();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64;
+            let result = match msg.path.path() {
+                SEARCH_PATH => {
+                    let req = SearchRequest::decode(body).map_err(|_| StatusCode::BAD_REQUEST)?;
+                    prover.search(&req, now).map(|res| res.encode_to_vec())
+                }
+                MONITOR_PATH => {
+                    let req = MonitorRequest::decode(body).map_err(|_| StatusCode::BAD_REQUEST)?;
+                    prover.monitor(&req, now).map(|res| res.encode_to_vec())
+                }
+                _ => return Err(StatusCode::NOT_FOUND),
+            };
+            result.map_err(|err| match err {
+                ProverError::SearchKeyNotFound => StatusCode::NOT_FOUND,
+                _ => StatusCode::BAD_REQUEST,
+            })
+        }
+    }
+
+    #[async_trait]
+    impl ChatService for FakeChatServer {
+        async fn send(
+            &self,
+            msg: Request,
+            _timeout: Duration,
+        ) -> Result<Response, ChatServiceError> {
+            let (status, body) = match self.respond(&msg) {
+                Ok(body) => (StatusCode::OK, Some(body.into_boxed_slice())),
+                Err(status) => (status, None),
+            };
+            Ok(Response {
+                status,
+                message: None,
+                body,
+                headers: HeaderMap::new(),
+            })
+        }
+
+        async fn connect(&self) -> Result<(), ChatServiceError> {
+            Ok(())
+        }
+
+        async fn disconnect(&self) {}
+    }
+
+    #[tokio::test]
+    async fn search_returns_verified_identity_key() {
+        let server = FakeChatServer::new();
+        let identity_key = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+        server.insert_account(
+            &OTHER_ACI,
+            IdentityKeyPair::generate(&mut OsRng).identity_key(),
+        );
+        server.insert_account(&ACI, &identity_key);
+        server.insert(&SearchKey::E164(e164()), &ACI.service_id_binary());
+        server.insert(
+            &SearchKey::UsernameHash(USERNAME_HASH.to_vec()),
+            &ACI.service_id_binary(),
+        );
+
+        let client = KeyTransparencyClient::new(&server, TIMEOUT);
+        let mut store = server.store();
+        let found = client
+            .search(&mut store, &ACI, Some(e164()), Some(&USERNAME_HASH))
+            .await
+            .expect("can search");
+        assert_eq!(found, identity_key);
+    }
+
+    #[tokio::test]
+    async fn search_rejects_alias_of_another_account() {
+        let server = FakeChatServer::new();
+        server.insert_account(&ACI, IdentityKeyPair::generate(&mut OsRng).identity_key());
+        server.insert(&SearchKey::E164(e164()), &OTHER_ACI.service_id_binary());
+
+        let client = KeyTransparencyClient::new(&server, TIMEOUT);
+        let mut store = server.store();
+        assert_matches!(
+            client.search(&mut store, &ACI, Some(e164()), None).await,
+            Err(Error::VerificationFailed(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn search_unknown_account() {
+        let server = FakeChatServer::new();
+        server.insert_account(
+            &OTHER_ACI,
+            IdentityKeyPair::generate(&mut OsRng).identity_key(),
+        );
+
+        let client = KeyTransparencyClient::new(&server, TIMEOUT);
+        let mut store = server.store();
+        assert_matches!(
+            client.search(&mut store, &ACI, None, None).await,
+            Err(Error::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn search_rejects_response_from_another_log() {
+        let server = FakeChatServer::new();
+        server.insert_account(&ACI, IdentityKeyPair::generate(&mut OsRng).identity_key());
+
+        let client = KeyTransparencyClient::new(&server, TIMEOUT);
+        let mut store = InMemoryLogStore::new(
+            Prover::new(
+                DeploymentMode::ContactMonitoring,
+                SigningKey::from_bytes(&[3; 32]),
+                VrfSecretKey::from([1; 32]),
+                [4; 32],
+            )
+            .public_config(),
+        );
+        assert_matches!(
+            client.search(&mut store, &ACI, None, None).await,
+            Err(Error::VerificationFailed(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn monitor_after_search() {
+        let server = FakeChatServer::new();
+        let identity_key = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+        server.insert_account(&ACI, &identity_key);
+        server.insert(&SearchKey::E164(e164()), &ACI.service_id_binary());
+
+        let client = KeyTransparencyClient::new(&server, TIMEOUT);
+        let mut store = server.store();
+        client
+            .search(&mut store, &ACI, Some(e164()), None)
+            .await
+            .expect("can search");
+
+        for i in 0..10u8 {
+            server.insert_account(
+                &Aci::from_uuid_bytes([i; 16]),
+                IdentityKeyPair::generate(&mut OsRng).identity_key(),
+            );
+        }
+        client
+            .monitor(
+                &mut store,
+                &[],
+                &[SearchKey::Aci(ACI), SearchKey::E164(e164())],
+            )
+            .await
+            .expect("can monitor");
+
+        let (tree_head, _) = store.get_last_tree_head().await.unwrap().unwrap();
+        assert_eq!(tree_head.tree_size, 12);
+
+        assert_matches!(
+            client
+                .monitor(&mut store, &[], &[SearchKey::Aci(OTHER_ACI)])
+                .await,
+            Err(Error::NotMonitored)
+        );
+    }
+}
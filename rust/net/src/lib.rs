@@ -9,6 +9,7 @@ pub mod chat;
 pub mod enclave;
 pub mod env;
 pub mod infra;
+pub mod keytrans;
 pub mod proto;
 pub mod svr;
 pub mod svr3;
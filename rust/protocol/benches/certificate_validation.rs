@@ -0,0 +1,136 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use libsignal_protocol::*;
+use rand::rngs::OsRng;
+
+const BATCH_SIZES: &[usize] = &[1, 16, 256, 1024];
+
+fn sender_certificates(count: usize, trust_root: &KeyPair) -> Vec<SenderCertificate> {
+    let mut rng = OsRng;
+    let server_key = KeyPair::generate(&mut rng);
+    let server_cert =
+        ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut rng)
+            .expect("valid");
+
+    (0..count)
+        .map(|_| {
+            SenderCertificate::new(
+                "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string(),
+                Some("+14152222222".to_string()),
+                KeyPair::generate(&mut rng).public_key,
+                1.into(),
+                Timestamp::from_epoch_millis(u64::MAX),
+                server_cert.clone(),
+                &server_key.private_key,
+                &mut rng,
+            )
+            .expect("valid")
+        })
+        .collect()
+}
+
+pub fn sender_certificate_validation(c: &mut Criterion) {
+    let mut rng = OsRng;
+    let trust_root = KeyPair::generate(&mut rng);
+    let validation_time = Timestamp::from_epoch_millis(0);
+
+    let mut group = c.benchmark_group("sender certificate validation");
+    for &size in BATCH_SIZES {
+        let certs = sender_certificates(size, &trust_root);
+        group.throughput(Throughput::Elements(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("individual", size), &certs, |b, certs| {
+            b.iter(|| {
+                for cert in certs {
+                    assert!(cert
+                        .validate(&trust_root.public_key, validation_time)
+                        .expect("valid"));
+                }
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("batch", size), &certs, |b, certs| {
+            b.iter(|| {
+                let results = SenderCertificate::validate_batch(
+                    certs,
+                    &trust_root.public_key,
+                    validation_time,
+                    &mut rng,
+                )
+                .expect("valid");
+                assert!(results.into_iter().all(|valid| valid));
+            })
+        });
+    }
+    group.finish();
+}
+
+pub fn signature_batches(c: &mut Criterion) {
+    let mut rng = OsRng;
+
+    let mut group = c.benchmark_group("signature verification");
+    for &size in BATCH_SIZES {
+        let key_pairs: Vec<_> = (0..size).map(|_| KeyPair::generate(&mut rng)).collect();
+        let message = [0x42u8; 200];
+        let signatures: Vec<_> = key_pairs
+            .iter()
+            .map(|key_pair| {
+                key_pair
+                    .calculate_signature(&message, &mut rng)
+                    .expect("valid")
+            })
+            .collect();
+        group.throughput(Throughput::Elements(size as u64));
+
+        group.bench_function(BenchmarkId::new("individual", size), |b| {
+            b.iter(|| {
+                for (key_pair, signature) in key_pairs.iter().zip(&signatures) {
+                    assert!(key_pair
+                        .public_key
+                        .verify_signature(&message, signature)
+                        .expect("valid"));
+                }
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("batch", size), |b| {
+            b.iter(|| {
+                let mut batch = SignatureBatch::with_capacity(size);
+                for (key_pair, signature) in key_pairs.iter().zip(&signatures) {
+                    batch.push(key_pair.public_key, &message, signature);
+                }
+                assert!(batch
+                    .verify(&mut rng)
+                    .expect("valid")
+                    .into_iter()
+                    .all(|valid| valid));
+            })
+        });
+
+        if size == 1 {
+            continue;
+        }
+
+        // One bad signature forces the batch to be split to find it.
+        let mut bad_signatures = signatures.clone();
+        bad_signatures[size / 2] = bad_signatures[size / 2 - 1].clone();
+        group.bench_function(BenchmarkId::new("batch with one invalid", size), |b| {
+            b.iter(|| {
+                let mut batch = SignatureBatch::with_capacity(size);
+                for (key_pair, signature) in key_pairs.iter().zip(&bad_signatures) {
+                    batch.push(key_pair.public_key, &message, signature);
+                }
+                batch.verify(&mut rng).expect("valid")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sender_certificate_validation, signature_batches);
+
+criterion_main!(benches);
@@ -44,6 +44,43 @@ pub fn signatures(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, generation, key_agreement, signatures);
+pub fn vrf_signatures(c: &mut Criterion) {
+    let rng = &mut thread_rng();
+    let alice_key = KeyPair::generate(rng);
+    let mut some_data = [0; 1024];
+    rng.fill(&mut some_data);
+
+    c.bench_function("generate VRF signature", |b| {
+        b.iter(|| {
+            alice_key
+                .private_key
+                .calculate_vrf_signature(&some_data, rng)
+                .unwrap()
+        })
+    });
+
+    let (sig, _output) = alice_key
+        .private_key
+        .calculate_vrf_signature(&some_data, rng)
+        .unwrap();
+
+    c.bench_function("verify VRF signature", |b| {
+        b.iter(|| {
+            alice_key
+                .public_key
+                .verify_vrf_signature(&some_data, &sig)
+                .unwrap()
+                .expect("valid")
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    generation,
+    key_agreement,
+    signatures,
+    vrf_signatures
+);
 
 criterion_main!(benches);
@@ -0,0 +1,805 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Streaming encryption and decryption of attachments.
+//!
+//! An encrypted attachment has the form
+//!
+//! ```text
+//! iv (16 bytes) || AES-256-CBC(padded plaintext, PKCS7) || HMAC-SHA256(iv || ciphertext)
+//! ```
+//!
+//! where the 64-byte attachment key is the AES key followed by the HMAC key, and the plaintext is
+//! padded with zeros up to [`padded_attachment_size`] before encryption. The attachment digest is
+//! the SHA-256 of the entire encrypted attachment.
+//!
+//! The encryptor also computes an [incremental MAC](crate::incremental_mac) of the encrypted
+//! attachment using the HMAC key, which lets the decryptor validate the attachment chunk by chunk
+//! as it is downloaded.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use aes::Aes256;
+use futures_io::{AsyncRead, AsyncWrite};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::incremental_mac::{calculate_chunk_size, Incremental, Validating};
+use crate::{Result, SignalProtocolError};
+
+/// The size of an attachment key: a 32-byte AES key followed by a 32-byte HMAC key.
+pub const ATTACHMENT_KEY_SIZE: usize = 64;
+/// The size of the IV at the start of an encrypted attachment.
+pub const ATTACHMENT_IV_SIZE: usize = 16;
+/// The size of the HMAC at the end of an encrypted attachment.
+pub const ATTACHMENT_MAC_SIZE: usize = 32;
+/// The size of an attachment digest.
+pub const ATTACHMENT_DIGEST_SIZE: usize = 32;
+
+const BLOCK_SIZE: usize = 16;
+const MIN_PADDED_SIZE: u64 = 541;
+const PADDING_CHUNK_SIZE: usize = 8 * 1024;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Zeros to encrypt as padding, shared rather than built on the stack for each chunk.
+static PADDING: [u8; PADDING_CHUNK_SIZE] = [0; PADDING_CHUNK_SIZE];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The size an attachment's plaintext is padded to before encryption.
+///
+/// Sizes are rounded up to the next power of 1.05 (with a minimum of 541 bytes), so that the
+/// encrypted size only reveals the approximate size of the attachment.
+pub fn padded_attachment_size(plaintext_len: u64) -> u64 {
+    // Matches the floating-point computation used by existing clients.
+    let bucket = 1.05f64
+        .powf(((plaintext_len as f64).ln() / 1.05f64.ln()).ceil())
+        .floor() as u64;
+    bucket.max(MIN_PADDED_SIZE).max(plaintext_len)
+}
+
+/// The size of the encrypted attachment for a plaintext of `plaintext_len` bytes.
+pub fn encrypted_attachment_size(plaintext_len: u64) -> u64 {
+    let padded_len = padded_attachment_size(plaintext_len);
+    let block_size = BLOCK_SIZE as u64;
+    ATTACHMENT_IV_SIZE as u64
+        + (padded_len / block_size + 1) * block_size
+        + ATTACHMENT_MAC_SIZE as u64
+}
+
+fn split_key(key: &[u8; ATTACHMENT_KEY_SIZE]) -> (&[u8; 32], &[u8; 32]) {
+    let (aes_key, mac_key) = key.split_at(32);
+    (
+        aes_key.try_into().expect("correct length"),
+        mac_key.try_into().expect("correct length"),
+    )
+}
+
+fn new_mac(mac_key: &[u8; 32]) -> HmacSha256 {
+    HmacSha256::new_from_slice(mac_key).expect("HMAC accepts any key length")
+}
+
+fn chunk_size_for(encrypted_len: u64) -> usize {
+    calculate_chunk_size::<Sha256>(encrypted_len.try_into().unwrap_or(usize::MAX))
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The digests of an encrypted attachment, available once an [`AttachmentEncryptor`] is closed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncryptedAttachmentDigests {
+    /// The SHA-256 digest of the whole encrypted attachment.
+    pub digest: [u8; ATTACHMENT_DIGEST_SIZE],
+    /// The concatenated incremental MACs of the encrypted attachment.
+    pub incremental_mac: Vec<u8>,
+    /// The chunk size used for `incremental_mac`.
+    pub incremental_mac_chunk_size: usize,
+}
+
+enum EncryptorState {
+    Body,
+    Padding { remaining: u64 },
+    Closing,
+    Closed,
+}
+
+/// An [`AsyncWrite`] adapter that encrypts an attachment as it is written.
+///
+/// The plaintext length must be known up front, since it determines the padding. Exactly that
+/// many bytes must be written before the encryptor is closed; closing writes out the padding and
+/// the MAC, then closes the wrapped writer. After that, [`AttachmentEncryptor::digests`] returns
+/// the digests to include in the attachment pointer.
+pub struct AttachmentEncryptor<W> {
+    writer: W,
+    cipher: cbc::Encryptor<Aes256>,
+    mac: HmacSha256,
+    digest: Sha256,
+    incremental: Option<Incremental<HmacSha256>>,
+    incremental_macs: Vec<u8>,
+    incremental_mac_chunk_size: usize,
+    partial_block: Vec<u8>,
+    pending: Vec<u8>,
+    pending_offset: usize,
+    plaintext_len: u64,
+    plaintext_written: u64,
+    state: EncryptorState,
+    digests: Option<EncryptedAttachmentDigests>,
+}
+
+impl<W: AsyncWrite + Unpin> AttachmentEncryptor<W> {
+    /// Creates an encryptor for a plaintext of `plaintext_len` bytes, writing to `writer`.
+    ///
+    /// `iv` must be freshly generated for each attachment.
+    pub fn new(
+        key: &[u8; ATTACHMENT_KEY_SIZE],
+        iv: &[u8; ATTACHMENT_IV_SIZE],
+        plaintext_len: u64,
+        writer: W,
+    ) -> Self {
+        let (aes_key, mac_key) = split_key(key);
+        let chunk_size = chunk_size_for(encrypted_attachment_size(plaintext_len));
+        let mut encryptor = Self {
+            writer,
+            cipher: cbc::Encryptor::new(aes_key.into(), iv.into()),
+            mac: new_mac(mac_key),
+            digest: Sha256::new(),
+            incremental: Some(Incremental::new(new_mac(mac_key), chunk_size)),
+            incremental_macs: vec![],
+            incremental_mac_chunk_size: chunk_size,
+            partial_block: Vec::with_capacity(BLOCK_SIZE),
+            pending: vec![],
+            pending_offset: 0,
+            plaintext_len,
+            plaintext_written: 0,
+            state: EncryptorState::Body,
+            digests: None,
+        };
+        encryptor.emit(iv, true);
+        encryptor
+    }
+
+    /// The digests of the encrypted attachment, once the encryptor has been closed.
+    pub fn digests(&self) -> Option<&EncryptedAttachmentDigests> {
+        self.digests.as_ref()
+    }
+
+    /// Returns the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Queues encrypted bytes for output, adding them to the digests.
+    fn emit(&mut self, bytes: &[u8], include_in_mac: bool) {
+        if include_in_mac {
+            self.mac.update(bytes);
+        }
+        self.digest.update(bytes);
+        let incremental = self
+            .incremental
+            .as_mut()
+            .expect("nothing is emitted after closing");
+        for chunk_mac in incremental.update(bytes) {
+            self.incremental_macs.extend_from_slice(&chunk_mac);
+        }
+        self.pending.extend_from_slice(bytes);
+    }
+
+    fn encrypt(&mut self, mut plaintext: &[u8]) {
+        let mut ciphertext = Vec::with_capacity(plaintext.len() + BLOCK_SIZE);
+
+        if !self.partial_block.is_empty() {
+            let needed = std::cmp::min(BLOCK_SIZE - self.partial_block.len(), plaintext.len());
+            let (head, rest) = plaintext.split_at(needed);
+            self.partial_block.extend_from_slice(head);
+            plaintext = rest;
+            if self.partial_block.len() == BLOCK_SIZE {
+                let mut block = [0; BLOCK_SIZE];
+                block.copy_from_slice(&self.partial_block);
+                self.cipher.encrypt_block_mut((&mut block).into());
+                ciphertext.extend_from_slice(&block);
+                self.partial_block.clear();
+            }
+        }
+
+        let mut blocks = plaintext.chunks_exact(BLOCK_SIZE);
+        for block in &mut blocks {
+            let mut block: [u8; BLOCK_SIZE] = block.try_into().expect("exact chunk");
+            self.cipher.encrypt_block_mut((&mut block).into());
+            ciphertext.extend_from_slice(&block);
+        }
+        self.partial_block.extend_from_slice(blocks.remainder());
+
+        self.emit(&ciphertext, true);
+    }
+
+    /// Encrypts the final (PKCS7-padded) block and queues the MAC.
+    fn finish_encryption(&mut self) {
+        let padding = BLOCK_SIZE - self.partial_block.len();
+        let mut block = [padding as u8; BLOCK_SIZE];
+        block[..self.partial_block.len()].copy_from_slice(&self.partial_block);
+        self.partial_block.clear();
+        self.cipher.encrypt_block_mut((&mut block).into());
+        self.emit(&block, true);
+
+        let mac = self.mac.clone().finalize().into_bytes();
+        self.emit(&mac, false);
+    }
+
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.pending_offset < self.pending.len() {
+            let written = ready!(
+                Pin::new(&mut self.writer).poll_write(cx, &self.pending[self.pending_offset..])
+            )?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending_offset += written;
+        }
+        self.pending.clear();
+        self.pending_offset = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AttachmentEncryptor<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !matches!(this.state, EncryptorState::Body) {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "attachment encryptor is closed",
+            )));
+        }
+
+        ready!(this.poll_write_pending(cx))?;
+
+        let remaining = this.plaintext_len - this.plaintext_written;
+        if buf.len() as u64 > remaining {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "more data written than the declared attachment size",
+            )));
+        }
+
+        this.encrypt(buf);
+        this.plaintext_written += buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_write_pending(cx))?;
+            match this.state {
+                EncryptorState::Body => {
+                    if this.plaintext_written != this.plaintext_len {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "less data written than the declared attachment size",
+                        )));
+                    }
+                    this.state = EncryptorState::Padding {
+                        remaining: padded_attachment_size(this.plaintext_len) - this.plaintext_len,
+                    };
+                }
+                EncryptorState::Padding { remaining: 0 } => {
+                    this.finish_encryption();
+                    this.state = EncryptorState::Closing;
+                }
+                EncryptorState::Padding { remaining } => {
+                    let padding_len = std::cmp::min(remaining, PADDING_CHUNK_SIZE as u64);
+                    this.encrypt(&PADDING[..padding_len as usize]);
+                    this.state = EncryptorState::Padding {
+                        remaining: remaining - padding_len,
+                    };
+                }
+                EncryptorState::Closing => {
+                    ready!(Pin::new(&mut this.writer).poll_close(cx))?;
+                    let incremental = this.incremental.take().expect("only closed once");
+                    this.incremental_macs
+                        .extend_from_slice(&incremental.finalize());
+                    this.digests = Some(EncryptedAttachmentDigests {
+                        digest: this.digest.clone().finalize().into(),
+                        incremental_mac: std::mem::take(&mut this.incremental_macs),
+                        incremental_mac_chunk_size: this.incremental_mac_chunk_size,
+                    });
+                    this.state = EncryptorState::Closed;
+                }
+                EncryptorState::Closed => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+/// An [`AsyncRead`] adapter that decrypts an attachment as it is read.
+///
+/// Reads return at most `plaintext_len` bytes; the rest of the decrypted data is padding. The
+/// attachment's MAC, and its digest if one was provided with [`AttachmentDecryptor::with_digest`],
+/// are only checked when the end of the attachment is reached, and a mismatch is reported as an
+/// [`io::ErrorKind::InvalidData`] error from that read. Unless an incremental MAC was provided
+/// with [`AttachmentDecryptor::with_incremental_mac`], callers must not act on the data they have
+/// read until they reach the end of the stream without error.
+///
+/// With an incremental MAC, data is only returned once the chunk containing it has been
+/// validated, at the cost of buffering up to one chunk.
+pub struct AttachmentDecryptor<R> {
+    reader: R,
+    aes_key: [u8; 32],
+    cipher: Option<cbc::Decryptor<Aes256>>,
+    mac: HmacSha256,
+    digest: Sha256,
+    expected_digest: Option<[u8; ATTACHMENT_DIGEST_SIZE]>,
+    validating: Option<Validating<HmacSha256>>,
+    /// Encrypted bytes that have been read but not yet decrypted.
+    encrypted: Vec<u8>,
+    /// The offset of `encrypted[0]` within the encrypted attachment.
+    encrypted_offset: u64,
+    /// The number of encrypted bytes read from the wrapped reader.
+    total_read: u64,
+    /// The number of encrypted bytes included in `mac`.
+    mac_offset: u64,
+    /// The number of encrypted bytes validated by the incremental MAC.
+    validated: u64,
+    plaintext: Vec<u8>,
+    plaintext_offset: usize,
+    plaintext_remaining: u64,
+    finished: bool,
+    /// Scratch space for reads from `reader`, kept here rather than on the stack.
+    read_buf: Box<[u8]>,
+}
+
+impl<R: AsyncRead + Unpin> AttachmentDecryptor<R> {
+    /// Creates a decryptor for an attachment whose plaintext is `plaintext_len` bytes long, reading
+    /// encrypted data from `reader`.
+    pub fn new(key: &[u8; ATTACHMENT_KEY_SIZE], plaintext_len: u64, reader: R) -> Self {
+        let (aes_key, mac_key) = split_key(key);
+        Self {
+            reader,
+            aes_key: *aes_key,
+            cipher: None,
+            mac: new_mac(mac_key),
+            digest: Sha256::new(),
+            expected_digest: None,
+            validating: None,
+            encrypted: vec![],
+            encrypted_offset: 0,
+            total_read: 0,
+            mac_offset: 0,
+            validated: 0,
+            plaintext: vec![],
+            plaintext_offset: 0,
+            plaintext_remaining: plaintext_len,
+            finished: false,
+            read_buf: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
+        }
+    }
+
+    /// Checks the SHA-256 digest of the encrypted attachment at the end of the stream.
+    pub fn with_digest(mut self, digest: &[u8; ATTACHMENT_DIGEST_SIZE]) -> Self {
+        self.expected_digest = Some(*digest);
+        self
+    }
+
+    /// Validates the attachment chunk by chunk using the incremental MAC produced by
+    /// [`AttachmentEncryptor`].
+    pub fn with_incremental_mac(
+        mut self,
+        chunk_size: usize,
+        incremental_mac: &[u8],
+    ) -> Result<Self> {
+        if chunk_size == 0 {
+            return Err(SignalProtocolError::InvalidArgument(
+                "incremental MAC chunk size must be positive".to_string(),
+            ));
+        }
+        let mac_size = ATTACHMENT_MAC_SIZE;
+        if incremental_mac.is_empty() || incremental_mac.len() % mac_size != 0 {
+            return Err(SignalProtocolError::InvalidArgument(
+                "incremental MAC has invalid length".to_string(),
+            ));
+        }
+        // Nothing has been read yet, so `mac` is still a fresh HMAC keyed with the MAC key.
+        self.validating = Some(
+            Incremental::new(self.mac.clone(), chunk_size)
+                .validating(incremental_mac.chunks_exact(mac_size)),
+        );
+        Ok(self)
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Accepts newly-read encrypted bytes.
+    fn accept(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.digest.update(bytes);
+        if let Some(validating) = &mut self.validating {
+            let validated = validating
+                .update(bytes)
+                .map_err(|_| invalid_data("attachment incremental MAC mismatch"))?;
+            self.validated += validated as u64;
+        }
+        self.encrypted.extend_from_slice(bytes);
+        self.total_read += bytes.len() as u64;
+
+        // Everything but the trailing MAC is covered by the MAC.
+        let mac_end = self.total_read.saturating_sub(ATTACHMENT_MAC_SIZE as u64);
+        self.update_mac(mac_end);
+
+        // Hold back the MAC and the final (padded) block until the end of the stream.
+        let mut decrypt_end = self
+            .total_read
+            .saturating_sub((ATTACHMENT_MAC_SIZE + BLOCK_SIZE) as u64);
+        if self.validating.is_some() {
+            decrypt_end = decrypt_end.min(self.validated);
+        }
+        self.decrypt_until(decrypt_end);
+        Ok(())
+    }
+
+    fn update_mac(&mut self, mac_end: u64) {
+        if mac_end > self.mac_offset {
+            let start = (self.mac_offset - self.encrypted_offset) as usize;
+            let end = (mac_end - self.encrypted_offset) as usize;
+            self.mac.update(&self.encrypted[start..end]);
+            self.mac_offset = mac_end;
+        }
+    }
+
+    /// Decrypts all whole blocks before `decrypt_end`.
+    fn decrypt_until(&mut self, decrypt_end: u64) {
+        if self.cipher.is_none() {
+            if decrypt_end < ATTACHMENT_IV_SIZE as u64 {
+                return;
+            }
+            let iv: [u8; ATTACHMENT_IV_SIZE] = self.encrypted[..ATTACHMENT_IV_SIZE]
+                .try_into()
+                .expect("correct length");
+            self.cipher = Some(cbc::Decryptor::new((&self.aes_key).into(), (&iv).into()));
+            self.consume_encrypted(ATTACHMENT_IV_SIZE);
+        }
+
+        let available = decrypt_end.saturating_sub(self.encrypted_offset) as usize;
+        let block_count = available / BLOCK_SIZE;
+        let cipher = self.cipher.as_mut().expect("initialized above");
+        for block in self.encrypted[..block_count * BLOCK_SIZE].chunks_exact(BLOCK_SIZE) {
+            let mut block: [u8; BLOCK_SIZE] = block.try_into().expect("exact chunk");
+            cipher.decrypt_block_mut((&mut block).into());
+            let keep = std::cmp::min(self.plaintext_remaining, BLOCK_SIZE as u64) as usize;
+            self.plaintext.extend_from_slice(&block[..keep]);
+            self.plaintext_remaining -= keep as u64;
+        }
+        self.consume_encrypted(block_count * BLOCK_SIZE);
+    }
+
+    fn consume_encrypted(&mut self, len: usize) {
+        self.encrypted.drain(..len);
+        self.encrypted_offset += len as u64;
+    }
+
+    /// Validates and decrypts the end of the attachment.
+    fn finish(&mut self) -> io::Result<()> {
+        let min_len = (ATTACHMENT_IV_SIZE + BLOCK_SIZE + ATTACHMENT_MAC_SIZE) as u64;
+        if self.total_read < min_len
+            || (self.total_read - (ATTACHMENT_IV_SIZE + ATTACHMENT_MAC_SIZE) as u64)
+                % BLOCK_SIZE as u64
+                != 0
+        {
+            return Err(invalid_data("encrypted attachment has invalid length"));
+        }
+
+        let their_mac = &self.encrypted[self.encrypted.len() - ATTACHMENT_MAC_SIZE..];
+        let our_mac = self.mac.clone().finalize().into_bytes();
+        if !bool::from(our_mac.ct_eq(their_mac)) {
+            return Err(invalid_data("attachment MAC mismatch"));
+        }
+
+        if let Some(expected_digest) = &self.expected_digest {
+            let digest = self.digest.clone().finalize();
+            if !bool::from(digest.ct_eq(expected_digest)) {
+                return Err(invalid_data("attachment digest mismatch"));
+            }
+        }
+
+        if let Some(validating) = self.validating.take() {
+            validating
+                .finalize()
+                .map_err(|_| invalid_data("attachment incremental MAC mismatch"))?;
+        }
+
+        let mac_start = self.total_read - ATTACHMENT_MAC_SIZE as u64;
+        let last_block_start = mac_start - BLOCK_SIZE as u64;
+        self.decrypt_until(last_block_start);
+
+        let mut last_block: [u8; BLOCK_SIZE] = self.encrypted[..BLOCK_SIZE]
+            .try_into()
+            .expect("correct length");
+        self.cipher
+            .as_mut()
+            .expect("initialized by decrypt_until")
+            .decrypt_block_mut((&mut last_block).into());
+        let padding = last_block[BLOCK_SIZE - 1] as usize;
+        if padding == 0
+            || padding > BLOCK_SIZE
+            || last_block[BLOCK_SIZE - padding..]
+                .iter()
+                .any(|&b| b as usize != padding)
+        {
+            return Err(invalid_data("attachment has invalid padding"));
+        }
+        let unpadded = &last_block[..BLOCK_SIZE - padding];
+        if (unpadded.len() as u64) < self.plaintext_remaining {
+            return Err(invalid_data("attachment is shorter than expected"));
+        }
+        let keep = self.plaintext_remaining as usize;
+        self.plaintext.extend_from_slice(&unpadded[..keep]);
+        self.plaintext_remaining = 0;
+        self.encrypted.clear();
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AttachmentDecryptor<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if this.plaintext_offset < this.plaintext.len() {
+                let available = &this.plaintext[this.plaintext_offset..];
+                let len = std::cmp::min(available.len(), buf.len());
+                buf[..len].copy_from_slice(&available[..len]);
+                this.plaintext_offset += len;
+                if this.plaintext_offset == this.plaintext.len() {
+                    this.plaintext.clear();
+                    this.plaintext_offset = 0;
+                }
+                return Poll::Ready(Ok(len));
+            }
+            if this.finished || buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+
+            let read = ready!(Pin::new(&mut this.reader).poll_read(cx, &mut this.read_buf))?;
+            if read == 0 {
+                this.finish()?;
+            } else {
+                // Moved out while in use, since `accept` needs all of `this`.
+                let read_buf = std::mem::take(&mut this.read_buf);
+                let accepted = this.accept(&read_buf[..read]);
+                this.read_buf = read_buf;
+                accepted?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures_util::io::{AsyncReadExt, AsyncWriteExt, Cursor};
+    use futures_util::FutureExt;
+    use rand::rngs::OsRng;
+    use rand::{Rng, RngCore};
+
+    use super::*;
+
+    fn encrypt(
+        key: &[u8; ATTACHMENT_KEY_SIZE],
+        plaintext: &[u8],
+    ) -> (Vec<u8>, EncryptedAttachmentDigests) {
+        let iv: [u8; ATTACHMENT_IV_SIZE] = OsRng.gen();
+        let mut encryptor = AttachmentEncryptor::new(key, &iv, plaintext.len() as u64, vec![]);
+        async {
+            // Write in uneven pieces to exercise the partial-block handling.
+            for piece in plaintext.chunks(1000) {
+                encryptor.write_all(piece).await.expect("can write");
+            }
+            encryptor.close().await.expect("can close");
+        }
+        .now_or_never()
+        .expect("sync");
+        let digests = encryptor.digests().expect("closed").clone();
+        (encryptor.into_inner(), digests)
+    }
+
+    fn decrypt(decryptor: AttachmentDecryptor<Cursor<&[u8]>>) -> io::Result<Vec<u8>> {
+        let mut decryptor = decryptor;
+        let mut plaintext = vec![];
+        decryptor
+            .read_to_end(&mut plaintext)
+            .now_or_never()
+            .expect("sync")?;
+        Ok(plaintext)
+    }
+
+    fn random_key() -> [u8; ATTACHMENT_KEY_SIZE] {
+        let mut key = [0; ATTACHMENT_KEY_SIZE];
+        OsRng.fill_bytes(&mut key);
+        key
+    }
+
+    fn random_plaintext(len: usize) -> Vec<u8> {
+        let mut plaintext = vec![0; len];
+        OsRng.fill_bytes(&mut plaintext);
+        plaintext
+    }
+
+    #[test]
+    fn padded_sizes() {
+        assert_eq!(padded_attachment_size(0), 541);
+        assert_eq!(padded_attachment_size(1), 541);
+        assert_eq!(padded_attachment_size(541), 541);
+        assert_eq!(padded_attachment_size(542), 568);
+        assert_eq!(padded_attachment_size(1_000_000), 1_041_743);
+        assert_eq!(encrypted_attachment_size(0), 16 + 544 + 32);
+        for len in [0, 15, 16, 1000, 100_000] {
+            assert!(padded_attachment_size(len) >= len);
+            assert_eq!(
+                encrypt(&random_key(), &random_plaintext(len as usize))
+                    .0
+                    .len() as u64,
+                encrypted_attachment_size(len)
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for len in [0, 1, 16, 541, 5000, 300_000] {
+            let key = random_key();
+            let plaintext = random_plaintext(len);
+            let (encrypted, digests) = encrypt(&key, &plaintext);
+            assert_eq!(digests.digest, <[u8; 32]>::from(Sha256::digest(&encrypted)));
+
+            let decryptor = AttachmentDecryptor::new(&key, len as u64, Cursor::new(&encrypted[..]))
+                .with_digest(&digests.digest);
+            assert_eq!(decrypt(decryptor).expect("valid"), plaintext);
+
+            let decryptor = AttachmentDecryptor::new(&key, len as u64, Cursor::new(&encrypted[..]))
+                .with_incremental_mac(digests.incremental_mac_chunk_size, &digests.incremental_mac)
+                .expect("valid incremental MAC");
+            assert_eq!(decrypt(decryptor).expect("valid"), plaintext);
+        }
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let key = random_key();
+        let plaintext = random_plaintext(200_000);
+        let (encrypted, digests) = encrypt(&key, &plaintext);
+
+        for i in [
+            0,
+            20,
+            encrypted.len() / 2,
+            encrypted.len() - 40,
+            encrypted.len() - 1,
+        ] {
+            let mut tampered = encrypted.clone();
+            tampered[i] ^= 0x01;
+            let decryptor =
+                AttachmentDecryptor::new(&key, plaintext.len() as u64, Cursor::new(&tampered[..]));
+            let err = decrypt(decryptor).expect_err("tampered");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+            let decryptor =
+                AttachmentDecryptor::new(&key, plaintext.len() as u64, Cursor::new(&tampered[..]))
+                    .with_incremental_mac(
+                        digests.incremental_mac_chunk_size,
+                        &digests.incremental_mac,
+                    )
+                    .expect("valid incremental MAC");
+            let err = decrypt(decryptor).expect_err("tampered");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let truncated = &encrypted[..encrypted.len() - 16];
+        let decryptor =
+            AttachmentDecryptor::new(&key, plaintext.len() as u64, Cursor::new(truncated));
+        assert!(decrypt(decryptor).is_err());
+    }
+
+    #[test]
+    fn wrong_digest_is_rejected() {
+        let key = random_key();
+        let plaintext = random_plaintext(1000);
+        let (encrypted, mut digests) = encrypt(&key, &plaintext);
+        digests.digest[0] ^= 0x01;
+
+        let decryptor =
+            AttachmentDecryptor::new(&key, plaintext.len() as u64, Cursor::new(&encrypted[..]))
+                .with_digest(&digests.digest);
+        let err = decrypt(decryptor).expect_err("wrong digest");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn incremental_mac_releases_only_validated_data() {
+        let key = random_key();
+        let plaintext = random_plaintext(300_000);
+        let (mut encrypted, digests) = encrypt(&key, &plaintext);
+        let chunk_size = digests.incremental_mac_chunk_size;
+
+        // Corrupt the second chunk; the first chunk's plaintext is still readable.
+        encrypted[chunk_size + 10] ^= 0x01;
+        let mut decryptor =
+            AttachmentDecryptor::new(&key, plaintext.len() as u64, Cursor::new(&encrypted[..]))
+                .with_incremental_mac(chunk_size, &digests.incremental_mac)
+                .expect("valid incremental MAC");
+
+        let mut released = vec![];
+        let err = async {
+            let mut buf = [0; 4096];
+            loop {
+                match decryptor.read(&mut buf).await {
+                    Ok(0) => panic!("should have failed"),
+                    Ok(n) => released.extend_from_slice(&buf[..n]),
+                    Err(e) => return e,
+                }
+            }
+        }
+        .now_or_never()
+        .expect("sync");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(released.len() < chunk_size);
+        assert_eq!(released, plaintext[..released.len()]);
+
+        assert!(matches!(
+            AttachmentDecryptor::new(&key, 0, Cursor::new(&[][..]))
+                .with_incremental_mac(0, &[0; 32]),
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            AttachmentDecryptor::new(&key, 0, Cursor::new(&[][..]))
+                .with_incremental_mac(chunk_size, &[0; 31]),
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn length_mismatches_are_rejected() {
+        let key = random_key();
+        let iv: [u8; ATTACHMENT_IV_SIZE] = OsRng.gen();
+        async {
+            let mut encryptor = AttachmentEncryptor::new(&key, &iv, 10, vec![]);
+            let err = encryptor.write_all(&[0; 11]).await.expect_err("too long");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+            let mut encryptor = AttachmentEncryptor::new(&key, &iv, 10, vec![]);
+            encryptor.write_all(&[0; 9]).await.expect("can write");
+            let err = encryptor.close().await.expect_err("too short");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        .now_or_never()
+        .expect("sync");
+
+        // Claiming a longer plaintext than was encrypted is caught at the end.
+        let plaintext = random_plaintext(1000);
+        let (encrypted, _) = encrypt(&key, &plaintext);
+        let decryptor = AttachmentDecryptor::new(&key, 100_000, Cursor::new(&encrypted[..]));
+        let err = decrypt(decryptor).expect_err("too short");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,244 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Issuing and trusting sealed sender certificates.
+//!
+//! A sealed sender message carries a [`SenderCertificate`], signed by a server key, which in turn
+//! carries a [`ServerCertificate`] signed by a trust root. Servers that issue their own
+//! certificates use a [`SenderCertificateIssuer`] for the first half of that chain, and clients
+//! describe which trust roots and server keys they accept with a [`SealedSenderTrustConfig`].
+//!
+//! Rotating a server key is a matter of generating a new issuer with a fresh key ID, and revoking
+//! the old ID in the trust config once its sender certificates have expired. Rotating a trust root
+//! works the same way: add the new root to the trust config, move issuers over to it, and then
+//! remove the old root.
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use prost::Message;
+use rand::{CryptoRng, Rng};
+
+use crate::sealed_sender::REVOKED_SERVER_CERTIFICATE_KEY_IDS;
+use crate::{
+    proto, DeviceId, KeyPair, PrivateKey, PublicKey, Result, SenderCertificate, ServerCertificate,
+    SignalProtocolError, Timestamp,
+};
+
+/// The trust roots and revoked server keys used to validate sealed sender certificates.
+///
+/// A server certificate is trusted if it is signed by any of the trust roots and its key ID has
+/// not been revoked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SealedSenderTrustConfig {
+    trust_roots: Vec<PublicKey>,
+    revoked_server_key_ids: BTreeSet<u32>,
+}
+
+impl SealedSenderTrustConfig {
+    /// Creates a config trusting `trust_roots`, with the server key IDs that libsignal always
+    /// treats as revoked.
+    pub fn new(trust_roots: Vec<PublicKey>) -> Result<Self> {
+        if trust_roots.is_empty() {
+            return Err(SignalProtocolError::InvalidArgument(
+                "a trust config needs at least one trust root".to_string(),
+            ));
+        }
+        Ok(Self {
+            trust_roots,
+            revoked_server_key_ids: REVOKED_SERVER_CERTIFICATE_KEY_IDS.iter().copied().collect(),
+        })
+    }
+
+    /// Reads back a config from [`Self::serialize`].
+    ///
+    /// The server key IDs that libsignal always treats as revoked are revoked in the result even
+    /// if `data` doesn't list them.
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let pb = proto::sealed_sender::TrustConfig::decode(data)
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        let trust_roots = pb
+            .trust_roots
+            .iter()
+            .map(|root| PublicKey::deserialize(root))
+            .collect::<Result<Vec<_>>>()?;
+        if trust_roots.is_empty() {
+            return Err(SignalProtocolError::InvalidProtobufEncoding);
+        }
+        Ok(Self {
+            trust_roots,
+            revoked_server_key_ids: pb
+                .revoked_server_key_ids
+                .into_iter()
+                .chain(REVOKED_SERVER_CERTIFICATE_KEY_IDS.iter().copied())
+                .collect(),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        proto::sealed_sender::TrustConfig {
+            trust_roots: self
+                .trust_roots
+                .iter()
+                .map(|root| root.serialize().into_vec())
+                .collect(),
+            revoked_server_key_ids: self.revoked_server_key_ids.iter().copied().collect(),
+        }
+        .encode_to_vec()
+    }
+
+    pub fn trust_roots(&self) -> &[PublicKey] {
+        &self.trust_roots
+    }
+
+    /// Adds `trust_root`, if it isn't already trusted.
+    pub fn add_trust_root(&mut self, trust_root: PublicKey) {
+        if !self.trust_roots.contains(&trust_root) {
+            self.trust_roots.push(trust_root);
+        }
+    }
+
+    /// Stops trusting `trust_root`, returning whether it was trusted before.
+    ///
+    /// Fails if `trust_root` is the only trust root left.
+    pub fn remove_trust_root(&mut self, trust_root: &PublicKey) -> Result<bool> {
+        let Some(index) = self.trust_roots.iter().position(|root| root == trust_root) else {
+            return Ok(false);
+        };
+        if self.trust_roots.len() == 1 {
+            return Err(SignalProtocolError::InvalidArgument(
+                "cannot remove the last trust root".to_string(),
+            ));
+        }
+        self.trust_roots.remove(index);
+        Ok(true)
+    }
+
+    pub fn revoked_server_key_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.revoked_server_key_ids.iter().copied()
+    }
+
+    pub fn revoke_server_key_id(&mut self, key_id: u32) {
+        self.revoked_server_key_ids.insert(key_id);
+    }
+
+    pub fn is_server_key_id_revoked(&self, key_id: u32) -> bool {
+        self.revoked_server_key_ids.contains(&key_id)
+    }
+}
+
+/// How long issued sender certificates last, and when clients should ask for a new one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SenderCertificateExpiryPolicy {
+    /// The time from issuing a certificate to its expiration.
+    pub lifetime: Duration,
+    /// How long before expiration a certificate should be replaced.
+    pub renewal_window: Duration,
+}
+
+impl Default for SenderCertificateExpiryPolicy {
+    fn default() -> Self {
+        Self {
+            lifetime: Duration::from_secs(7 * 24 * 60 * 60),
+            renewal_window: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl SenderCertificateExpiryPolicy {
+    pub fn expiration_for(&self, issued_at: Timestamp) -> Timestamp {
+        issued_at.add_millis(duration_millis(self.lifetime))
+    }
+
+    /// Whether `certificate` has expired or will within the renewal window after `now`.
+    pub fn needs_renewal(&self, certificate: &SenderCertificate, now: Timestamp) -> Result<bool> {
+        Ok(now.add_millis(duration_millis(self.renewal_window)) >= certificate.expiration()?)
+    }
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Issues sender certificates signed by a server key, following a
+/// [`SenderCertificateExpiryPolicy`].
+#[derive(Clone)]
+pub struct SenderCertificateIssuer {
+    server_certificate: ServerCertificate,
+    server_key: PrivateKey,
+    expiry_policy: SenderCertificateExpiryPolicy,
+}
+
+impl SenderCertificateIssuer {
+    /// Creates an issuer for an existing server key and its certificate.
+    pub fn new(
+        server_certificate: ServerCertificate,
+        server_key: PrivateKey,
+        expiry_policy: SenderCertificateExpiryPolicy,
+    ) -> Result<Self> {
+        if server_key.public_key()? != server_certificate.public_key()? {
+            return Err(SignalProtocolError::InvalidArgument(
+                "server key does not match server certificate".to_string(),
+            ));
+        }
+        Ok(Self {
+            server_certificate,
+            server_key,
+            expiry_policy,
+        })
+    }
+
+    /// Generates a new server key with ID `key_id` and certifies it with `trust_root`.
+    pub fn generate<R: Rng + CryptoRng>(
+        key_id: u32,
+        trust_root: &PrivateKey,
+        expiry_policy: SenderCertificateExpiryPolicy,
+        rng: &mut R,
+    ) -> Result<Self> {
+        let server_key = KeyPair::generate(rng);
+        let server_certificate =
+            ServerCertificate::new(key_id, server_key.public_key, trust_root, rng)?;
+        Ok(Self {
+            server_certificate,
+            server_key: server_key.private_key,
+            expiry_policy,
+        })
+    }
+
+    pub fn server_certificate(&self) -> &ServerCertificate {
+        &self.server_certificate
+    }
+
+    pub fn server_key(&self) -> &PrivateKey {
+        &self.server_key
+    }
+
+    pub fn expiry_policy(&self) -> SenderCertificateExpiryPolicy {
+        self.expiry_policy
+    }
+
+    /// Issues a certificate for the given sender that expires according to the issuer's
+    /// [`SenderCertificateExpiryPolicy`].
+    pub fn issue<R: Rng + CryptoRng>(
+        &self,
+        sender_uuid: String,
+        sender_e164: Option<String>,
+        key: PublicKey,
+        sender_device_id: DeviceId,
+        issued_at: Timestamp,
+        rng: &mut R,
+    ) -> Result<SenderCertificate> {
+        SenderCertificate::new(
+            sender_uuid,
+            sender_e164,
+            key,
+            sender_device_id,
+            self.expiry_policy.expiration_for(issued_at),
+            self.server_certificate.clone(),
+            &self.server_key,
+            rng,
+        )
+    }
+}
@@ -0,0 +1,79 @@
+//
+// Copyright 2020 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::time::Duration;
+
+use crate::kem;
+
+/// Limits on how much state is kept in session and sender key records, and which KEMs are used
+/// for new sessions.
+///
+/// The defaults are suitable for most clients. Raising them lets a client tolerate more skipped
+/// or out-of-order messages at the cost of larger records; lowering them bounds record size more
+/// tightly. Limits are applied as records are updated, so a record written under larger limits
+/// shrinks the next time it is updated under smaller ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtocolConfig {
+    /// The largest number of messages that may be skipped over within a single chain.
+    pub max_forward_jumps: usize,
+    /// The number of skipped message keys kept per chain, for decrypting out-of-order messages.
+    pub max_message_keys: usize,
+    /// The number of receiving chains kept per session.
+    pub max_receiver_chains: usize,
+    /// The number of archived sessions kept per session record.
+    pub max_archived_states: usize,
+    /// The number of sender key states kept per sender key record.
+    pub max_sender_key_states: usize,
+    /// How long a session may go without a response to its PreKey messages before it is no
+    /// longer used for sending.
+    pub max_unacknowledged_session_age: Duration,
+    /// The KEMs this client is willing to use when starting a session from a pre-key bundle,
+    /// most preferred first.
+    ///
+    /// If a bundle offers pre-keys for several KEMs, the first one listed here is used.
+    pub kem_preference: &'static [kem::KeyType],
+    /// Whether to use the post-quantum ratchet in new sessions.
+    ///
+    /// Sessions started from a pre-key bundle offer the ratchet to the other party, and sessions
+    /// started from a PreKey message use it if the other party offered it. Either way, a session
+    /// only keeps using it as long as both parties do.
+    pub enable_pq_ratchet: bool,
+    /// Whether new sessions are ephemeral.
+    ///
+    /// An ephemeral session keeps no skipped message keys, only its newest receiving chain, and
+    /// is discarded rather than archived when replaced. A message can then only be decrypted if
+    /// it arrives in order, and once it has been decrypted (or skipped over) nothing in the record
+    /// can decrypt it again. This applies to this side of the session only; the other party
+    /// chooses for itself.
+    pub ephemeral_sessions: bool,
+}
+
+impl ProtocolConfig {
+    /// The limits used by the functions that don't take a config.
+    pub const DEFAULT: Self = Self {
+        max_forward_jumps: 25_000,
+        max_message_keys: 2000,
+        max_receiver_chains: 5,
+        max_archived_states: 40,
+        max_sender_key_states: 5,
+        max_unacknowledged_session_age: Duration::from_secs(60 * 60 * 24 * 30),
+        // ML-KEM-1024 is accepted but not yet preferred, until every client can use it.
+        kem_preference: &[
+            kem::KeyType::Kyber1024,
+            #[cfg(feature = "mlkem1024")]
+            kem::KeyType::MLKEM1024,
+            #[cfg(any(feature = "kyber768", test))]
+            kem::KeyType::Kyber768,
+        ],
+        enable_pq_ratchet: false,
+        ephemeral_sessions: false,
+    };
+}
+
+impl Default for ProtocolConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
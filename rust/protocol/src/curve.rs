@@ -10,6 +10,7 @@ use crate::{Result, SignalProtocolError};
 use std::cmp::Ordering;
 
 use std::fmt;
+use std::ops::Range;
 
 use arrayref::array_ref;
 use curve25519_dalek::scalar;
@@ -131,6 +132,27 @@ impl PublicKey {
         }
     }
 
+    /// Checks a VXEdDSA signature from [`PrivateKey::calculate_vrf_signature`], returning the VRF
+    /// output for `message` if the signature is valid.
+    pub fn verify_vrf_signature(
+        &self,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<Option<[u8; curve25519::VRF_OUTPUT_LENGTH]>> {
+        match &self.key {
+            PublicKeyData::DjbPublicKey(pub_key) => {
+                if signature.len() != curve25519::VRF_SIGNATURE_LENGTH {
+                    return Ok(None);
+                }
+                Ok(curve25519::PrivateKey::verify_vrf_signature(
+                    pub_key,
+                    &[message],
+                    array_ref![signature, 0, curve25519::VRF_SIGNATURE_LENGTH],
+                ))
+            }
+        }
+    }
+
     fn key_data(&self) -> &[u8] {
         match &self.key {
             PublicKeyData::DjbPublicKey(ref k) => k.as_ref(),
@@ -204,6 +226,95 @@ impl fmt::Debug for PublicKey {
     }
 }
 
+/// A set of signatures to check together, which is much cheaper than calling
+/// [`PublicKey::verify_signature`] on each one when most of them are valid.
+#[derive(Clone, Debug, Default)]
+pub struct SignatureBatch<'a> {
+    entries: Vec<(PublicKey, &'a [u8], &'a [u8])>,
+}
+
+impl<'a> SignatureBatch<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Adds a signature to check, returning its index in the result of [`Self::verify`].
+    pub fn push(&mut self, key: PublicKey, message: &'a [u8], signature: &'a [u8]) -> usize {
+        self.entries.push((key, message, signature));
+        self.entries.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Checks every signature in the batch, returning whether each one is valid in the order they
+    /// were added.
+    ///
+    /// When the batch as a whole doesn't verify, it is split in half repeatedly to find the
+    /// invalid entries, and single entries are checked with [`PublicKey::verify_signature`]. Each
+    /// result is the same as [`PublicKey::verify_signature`] would give for that entry alone.
+    pub fn verify<R: CryptoRng + Rng>(&self, csprng: &mut R) -> Result<Vec<bool>> {
+        let mut results = vec![false; self.entries.len()];
+        self.verify_range(0..self.entries.len(), &mut results, csprng)?;
+        Ok(results)
+    }
+
+    fn verify_range<R: CryptoRng + Rng>(
+        &self,
+        range: Range<usize>,
+        results: &mut [bool],
+        csprng: &mut R,
+    ) -> Result<()> {
+        match range.len() {
+            0 => {}
+            1 => {
+                let (key, message, signature) = &self.entries[range.start];
+                results[range.start] = key.verify_signature(message, signature)?;
+            }
+            len => {
+                if Self::verify_all(&self.entries[range.clone()], csprng) {
+                    results[range].fill(true);
+                } else {
+                    let mid = range.start + len / 2;
+                    self.verify_range(range.start..mid, results, csprng)?;
+                    self.verify_range(mid..range.end, results, csprng)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_all<R: CryptoRng + Rng>(
+        entries: &[(PublicKey, &'a [u8], &'a [u8])],
+        csprng: &mut R,
+    ) -> bool {
+        let mut signatures = Vec::with_capacity(entries.len());
+        for (key, message, signature) in entries {
+            let PublicKeyData::DjbPublicKey(pub_key) = &key.key;
+            if signature.len() != curve25519::SIGNATURE_LENGTH {
+                return false;
+            }
+            signatures.push((
+                pub_key,
+                std::slice::from_ref(message),
+                array_ref![signature, 0, curve25519::SIGNATURE_LENGTH],
+            ));
+        }
+        curve25519::PrivateKey::verify_signature_batch(csprng, &signatures)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum PrivateKeyData {
     DjbPrivateKey([u8; curve25519::PRIVATE_KEY_LENGTH]),
@@ -272,6 +383,26 @@ impl PrivateKey {
         }
     }
 
+    /// Calculates a VXEdDSA signature over `message`, returning it along with the VRF output it
+    /// proves.
+    ///
+    /// The signature is randomized, but the VRF output depends only on the key and the message, and
+    /// can't be predicted without the private key. Anyone with the public key can check the output
+    /// with [`PublicKey::verify_vrf_signature`].
+    pub fn calculate_vrf_signature<R: CryptoRng + Rng>(
+        &self,
+        message: &[u8],
+        csprng: &mut R,
+    ) -> Result<(Box<[u8]>, [u8; curve25519::VRF_OUTPUT_LENGTH])> {
+        match self.key {
+            PrivateKeyData::DjbPrivateKey(k) => {
+                let private_key = curve25519::PrivateKey::from(k);
+                let (signature, output) = private_key.calculate_vrf_signature(csprng, &[message]);
+                Ok((Box::new(signature), output))
+            }
+        }
+    }
+
     pub fn calculate_agreement(&self, their_key: &PublicKey) -> Result<Box<[u8]>> {
         match (self.key, their_key.key) {
             (PrivateKeyData::DjbPrivateKey(priv_key), PublicKeyData::DjbPublicKey(pub_key)) => {
@@ -425,4 +556,41 @@ mod tests {
         assert_eq!(&serialized_public[..], &extra_space_decode?.serialize()[..]);
         Ok(())
     }
+
+    #[test]
+    fn test_signature_batch() -> Result<()> {
+        let mut csprng = OsRng;
+        let key_pairs: Vec<_> = (0..20).map(|_| KeyPair::generate(&mut csprng)).collect();
+        let messages: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; 100]).collect();
+        let mut signatures = key_pairs
+            .iter()
+            .zip(&messages)
+            .map(|(key_pair, message)| key_pair.calculate_signature(message, &mut csprng))
+            .collect::<Result<Vec<_>>>()?;
+        signatures[3] = signatures[3][..10].into();
+        signatures[11][0] ^= 0x01;
+        signatures[12] = signatures[13].clone();
+
+        let mut batch = SignatureBatch::new();
+        assert!(batch.verify(&mut csprng)?.is_empty());
+        for ((key_pair, message), signature) in key_pairs.iter().zip(&messages).zip(&signatures) {
+            batch.push(key_pair.public_key, message, signature);
+        }
+        assert_eq!(batch.len(), 20);
+
+        let expected: Vec<bool> = (0..20).map(|i| ![3, 11, 12].contains(&i)).collect();
+        assert_eq!(batch.verify(&mut csprng)?, expected);
+
+        let mut valid_batch = SignatureBatch::with_capacity(2);
+        assert_eq!(
+            valid_batch.push(key_pairs[0].public_key, &messages[0], &signatures[0]),
+            0
+        );
+        assert_eq!(
+            valid_batch.push(key_pairs[1].public_key, &messages[1], &signatures[1]),
+            1
+        );
+        assert_eq!(valid_batch.verify(&mut csprng)?, vec![true, true]);
+        Ok(())
+    }
 }
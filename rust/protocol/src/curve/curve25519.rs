@@ -3,20 +3,60 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
-use curve25519_dalek::constants::ED25519_BASEPOINT_TABLE;
-use curve25519_dalek::edwards::EdwardsPoint;
+use arrayref::array_ref;
+use curve25519_dalek::constants::{ED25519_BASEPOINT_POINT, ED25519_BASEPOINT_TABLE};
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
 use curve25519_dalek::montgomery::MontgomeryPoint;
 use curve25519_dalek::scalar;
 use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::{IsIdentity, VartimeMultiscalarMul};
 use rand::{CryptoRng, Rng};
 use sha2::{Digest, Sha512};
-use subtle::ConstantTimeEq;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
 use x25519_dalek::{PublicKey, StaticSecret};
 
 const AGREEMENT_LENGTH: usize = 32;
 pub const PRIVATE_KEY_LENGTH: usize = 32;
 pub const PUBLIC_KEY_LENGTH: usize = 32;
 pub const SIGNATURE_LENGTH: usize = 64;
+pub const VRF_SIGNATURE_LENGTH: usize = 96;
+pub const VRF_OUTPUT_LENGTH: usize = 32;
+
+/// The domain separation prefix for `hash_i` in the XEdDSA paper, 2^256 - 1 - i in little-endian.
+fn hash_prefix(i: u8) -> [u8; 32] {
+    let mut prefix = [0xFFu8; 32];
+    prefix[0] -= i;
+    prefix
+}
+
+/// `hash_to_point` from the XEdDSA paper, over the Edwards public key `A` and `message`.
+fn hash_to_point(cap_a: &CompressedEdwardsY, message: &[&[u8]]) -> EdwardsPoint {
+    let mut input = hash_prefix(2).to_vec();
+    input.extend_from_slice(cap_a.as_bytes());
+    for message_piece in message {
+        input.extend_from_slice(message_piece);
+    }
+    // This is deprecated because it isn't a standard hash-to-curve, but it is exactly the
+    // Elligator 2 mapping (with Z = 2), sign bit, and cofactor clearing that XEdDSA specifies.
+    #[allow(deprecated)]
+    EdwardsPoint::nonspec_map_to_curve::<Sha512>(&input)
+}
+
+fn vrf_output(cap_v: &EdwardsPoint) -> [u8; VRF_OUTPUT_LENGTH] {
+    let mut hash = Sha512::new();
+    hash.update(hash_prefix(5));
+    hash.update(cap_v.mul_by_cofactor().compress().as_bytes());
+    let digest = hash.finalize();
+    *array_ref![digest, 0, VRF_OUTPUT_LENGTH]
+}
+
+/// Whether `bytes` encode a field element less than 2^255 - 19.
+fn is_canonical_field_element(bytes: &[u8; 32]) -> bool {
+    let mut p = [0xFFu8; 32];
+    p[0] = 0xED;
+    p[31] = 0x7F;
+    bytes.iter().rev().lt(p.iter().rev())
+}
 
 #[derive(Clone)]
 pub struct PrivateKey {
@@ -148,6 +188,184 @@ impl PrivateKey {
         bool::from(cap_r_check.as_bytes().ct_eq(&cap_r))
     }
 
+    /// Checks several signatures from [`Self::calculate_signature`] at once, returning whether
+    /// all of them are valid.
+    ///
+    /// Each signature contributes `s*B - h*A - R` to a random linear combination, which is then
+    /// evaluated with a single multiscalar multiplication. Like [`Self::verify_signature`], the
+    /// check has no cofactor, and a random combination of small-order points is too likely to
+    /// cancel out, so any signature whose `R` isn't canonically encoded or whose `R` or `A` has a
+    /// small-order component makes the whole batch fail. [`Self::verify_signature`] may still
+    /// accept such a signature, so check the signatures one at a time when this returns `false`.
+    pub fn verify_signature_batch<R>(
+        csprng: &mut R,
+        signatures: &[(&[u8; PUBLIC_KEY_LENGTH], &[&[u8]], &[u8; SIGNATURE_LENGTH])],
+    ) -> bool
+    where
+        R: CryptoRng + Rng,
+    {
+        let mut basepoint_scalar = Scalar::ZERO;
+        let mut scalars = Vec::with_capacity(2 * signatures.len() + 1);
+        let mut points = Vec::with_capacity(2 * signatures.len() + 1);
+
+        for (their_public_key, message, signature) in signatures {
+            let mont_point = MontgomeryPoint(**their_public_key);
+            let Some(ed_pub_key_point) =
+                mont_point.to_edwards((signature[SIGNATURE_LENGTH - 1] & 0b1000_0000_u8) >> 7)
+            else {
+                return false;
+            };
+            let cap_a = ed_pub_key_point.compress();
+            let cap_r = array_ref![signature, 0, 32];
+            let Some(cap_r_point) = CompressedEdwardsY(*cap_r).decompress() else {
+                return false;
+            };
+            if cap_r_point.compress().as_bytes() != cap_r
+                || !cap_r_point.is_torsion_free()
+                || !ed_pub_key_point.is_torsion_free()
+            {
+                return false;
+            }
+            let mut s = *array_ref![signature, 32, 32];
+            s[31] &= 0b0111_1111_u8;
+            if (s[31] & 0b1110_0000_u8) != 0 {
+                return false;
+            }
+
+            let mut hash = Sha512::new();
+            hash.update(&cap_r[..]);
+            hash.update(cap_a.as_bytes());
+            for message_piece in *message {
+                hash.update(message_piece);
+            }
+            let h = Scalar::from_hash(hash);
+
+            // 128 bits is enough to make an accidental cancellation as unlikely as a forgery.
+            let mut z_bytes = [0u8; 32];
+            csprng.fill_bytes(&mut z_bytes[..16]);
+            let z = Scalar::from_bytes_mod_order(z_bytes);
+
+            basepoint_scalar += z * Scalar::from_bytes_mod_order(s);
+            scalars.push(-z);
+            points.push(cap_r_point);
+            scalars.push(-(z * h));
+            points.push(ed_pub_key_point);
+        }
+
+        scalars.push(basepoint_scalar);
+        points.push(ED25519_BASEPOINT_POINT);
+
+        EdwardsPoint::vartime_multiscalar_mul(scalars, points).is_identity()
+    }
+
+    /// Calculates a VXEdDSA signature using the X25519 private key directly, returning it along
+    /// with the VRF output it proves.
+    ///
+    /// Refer to <https://signal.org/docs/specifications/xeddsa/#vxeddsa> for more details. Unlike
+    /// [`Self::calculate_signature`], this follows the paper exactly.
+    pub fn calculate_vrf_signature<R>(
+        &self,
+        csprng: &mut R,
+        message: &[&[u8]],
+    ) -> ([u8; VRF_SIGNATURE_LENGTH], [u8; VRF_OUTPUT_LENGTH])
+    where
+        R: CryptoRng + Rng,
+    {
+        let mut random_bytes = [0u8; 64];
+        csprng.fill_bytes(&mut random_bytes);
+
+        // calculate_key_pair: use whichever of k and -k gives a public key with a sign bit of 0.
+        let k = Scalar::from_bytes_mod_order(self.secret.to_bytes());
+        let mut cap_a = (&k * ED25519_BASEPOINT_TABLE).compress();
+        let sign_bit = Choice::from(cap_a.as_bytes()[31] >> 7);
+        let a = Scalar::conditional_select(&k, &-k, sign_bit);
+        cap_a.0[31] &= 0b0111_1111_u8;
+
+        let cap_b_v = hash_to_point(&cap_a, message);
+        let cap_v_point = a * cap_b_v;
+        let cap_v = cap_v_point.compress();
+
+        let mut hash3 = Sha512::new();
+        hash3.update(hash_prefix(3));
+        hash3.update(a.as_bytes());
+        hash3.update(cap_v.as_bytes());
+        hash3.update(&random_bytes[..]);
+        let r = Scalar::from_hash(hash3);
+
+        let cap_r = (&r * ED25519_BASEPOINT_TABLE).compress();
+        let cap_r_v = (r * cap_b_v).compress();
+
+        let mut hash4 = Sha512::new();
+        hash4.update(hash_prefix(4));
+        hash4.update(cap_a.as_bytes());
+        hash4.update(cap_v.as_bytes());
+        hash4.update(cap_r.as_bytes());
+        hash4.update(cap_r_v.as_bytes());
+        for message_piece in message {
+            hash4.update(message_piece);
+        }
+        let h = Scalar::from_hash(hash4);
+        let s = r + h * a;
+
+        let mut signature = [0u8; VRF_SIGNATURE_LENGTH];
+        signature[..32].copy_from_slice(cap_v.as_bytes());
+        signature[32..64].copy_from_slice(h.as_bytes());
+        signature[64..].copy_from_slice(s.as_bytes());
+        (signature, vrf_output(&cap_v_point))
+    }
+
+    /// Checks a signature from [`Self::calculate_vrf_signature`], returning the VRF output if it
+    /// is valid.
+    pub fn verify_vrf_signature(
+        their_public_key: &[u8; PUBLIC_KEY_LENGTH],
+        message: &[&[u8]],
+        signature: &[u8; VRF_SIGNATURE_LENGTH],
+    ) -> Option<[u8; VRF_OUTPUT_LENGTH]> {
+        let cap_v_bytes = array_ref![signature, 0, 32];
+        let h_bytes = array_ref![signature, 32, 32];
+        let s_bytes = array_ref![signature, 64, 32];
+
+        let mut cap_v_y = *cap_v_bytes;
+        cap_v_y[31] &= 0b0111_1111_u8;
+        if !is_canonical_field_element(their_public_key)
+            || !is_canonical_field_element(&cap_v_y)
+            || (h_bytes[31] & 0b1110_0000_u8) != 0
+            || (s_bytes[31] & 0b1110_0000_u8) != 0
+        {
+            return None;
+        }
+
+        let cap_a_point = MontgomeryPoint(*their_public_key).to_edwards(0)?;
+        let cap_a = cap_a_point.compress();
+        let cap_v_point = CompressedEdwardsY(*cap_v_bytes).decompress()?;
+        let cap_b_v = hash_to_point(&cap_a, message);
+        if cap_a_point.is_small_order() || cap_v_point.is_small_order() || cap_b_v.is_identity() {
+            return None;
+        }
+
+        let h = Scalar::from_bytes_mod_order(*h_bytes);
+        let s = Scalar::from_bytes_mod_order(*s_bytes);
+        let cap_r =
+            EdwardsPoint::vartime_double_scalar_mul_basepoint(&h, &-cap_a_point, &s).compress();
+        let cap_r_v = (s * cap_b_v - h * cap_v_point).compress();
+
+        let mut hash4 = Sha512::new();
+        hash4.update(hash_prefix(4));
+        hash4.update(cap_a.as_bytes());
+        hash4.update(cap_v_bytes);
+        hash4.update(cap_r.as_bytes());
+        hash4.update(cap_r_v.as_bytes());
+        for message_piece in message {
+            hash4.update(message_piece);
+        }
+        let h_check = Scalar::from_hash(hash4);
+
+        if !bool::from(h_check.as_bytes().ct_eq(h_bytes)) {
+            return None;
+        }
+        Some(vrf_output(&cap_v_point))
+    }
+
     pub fn derive_public_key_bytes(&self) -> [u8; PUBLIC_KEY_LENGTH] {
         *PublicKey::from(&self.secret).as_bytes()
     }
@@ -166,6 +384,7 @@ impl From<[u8; PRIVATE_KEY_LENGTH]> for PrivateKey {
 
 #[cfg(test)]
 mod tests {
+    use curve25519_dalek::constants::EIGHT_TORSION;
     use rand::rngs::OsRng;
     use rand::RngCore;
 
@@ -303,4 +522,109 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_signature_batch() {
+        let mut csprng = OsRng;
+        let keys: Vec<PrivateKey> = (0..16).map(|_| PrivateKey::new(&mut csprng)).collect();
+        let public_keys: Vec<_> = keys.iter().map(|k| k.derive_public_key_bytes()).collect();
+        let messages: Vec<[u8; 64]> = (0..16)
+            .map(|_| {
+                let mut message = [0u8; 64];
+                csprng.fill_bytes(&mut message);
+                message
+            })
+            .collect();
+        let mut signatures: Vec<_> = keys
+            .iter()
+            .zip(&messages)
+            .map(|(key, message)| key.calculate_signature(&mut csprng, &[message]))
+            .collect();
+
+        let batch = |signatures: &[[u8; SIGNATURE_LENGTH]]| {
+            let message_parts: Vec<[&[u8]; 1]> = messages.iter().map(|m| [&m[..]]).collect();
+            let entries: Vec<_> = public_keys
+                .iter()
+                .zip(&message_parts)
+                .zip(signatures)
+                .map(|((key, message), signature)| (key, &message[..], signature))
+                .collect();
+            PrivateKey::verify_signature_batch(&mut OsRng, &entries)
+        };
+
+        assert!(batch(&signatures), "batch check failed");
+        assert!(PrivateKey::verify_signature_batch(&mut csprng, &[]));
+
+        for i in [0, 31, 32, 62, 63] {
+            signatures[5][i] ^= 0x01u8;
+            assert!(
+                !batch(&signatures),
+                "batch check passed when it should not have"
+            );
+            signatures[5][i] ^= 0x01u8;
+        }
+
+        signatures.swap(2, 3);
+        assert!(
+            !batch(&signatures),
+            "batch check passed when it should not have"
+        );
+    }
+
+    /// Signs `message` like [`PrivateKey::calculate_signature`], but adds `torsion` to `R`.
+    fn sign_with_torsioned_nonce(
+        key: &PrivateKey,
+        message: &[u8],
+        torsion: EdwardsPoint,
+    ) -> [u8; SIGNATURE_LENGTH] {
+        let a = Scalar::from_bytes_mod_order(key.secret.to_bytes());
+        let cap_a = (&a * ED25519_BASEPOINT_TABLE).compress();
+        let mut random_bytes = [0u8; 64];
+        OsRng.fill_bytes(&mut random_bytes);
+        let r = Scalar::from_bytes_mod_order_wide(&random_bytes);
+        let cap_r = (&r * ED25519_BASEPOINT_TABLE + torsion).compress();
+
+        let mut hash = Sha512::new();
+        hash.update(cap_r.as_bytes());
+        hash.update(cap_a.as_bytes());
+        hash.update(message);
+        let h = Scalar::from_hash(hash);
+        let s = (h * a) + r;
+
+        let mut result = [0u8; SIGNATURE_LENGTH];
+        result[..32].copy_from_slice(cap_r.as_bytes());
+        result[32..].copy_from_slice(s.as_bytes());
+        result[SIGNATURE_LENGTH - 1] |= cap_a.as_bytes()[31] & 0b1000_0000_u8;
+        result
+    }
+
+    #[test]
+    fn test_signature_batch_rejects_torsioned_nonce() {
+        let mut csprng = OsRng;
+        let key = PrivateKey::new(&mut csprng);
+        let public_key = key.derive_public_key_bytes();
+        let message = b"torsion";
+        let valid_signature = key.calculate_signature(&mut csprng, &[message]);
+
+        for torsion in &EIGHT_TORSION[1..] {
+            let signature = sign_with_torsioned_nonce(&key, message, *torsion);
+            // s*B - h*A - R is -torsion, so a cofactored check would accept this signature.
+            assert!(!PrivateKey::verify_signature(
+                &public_key,
+                &[message],
+                &signature
+            ));
+            assert!(!PrivateKey::verify_signature_batch(
+                &mut csprng,
+                &[(&public_key, &[message], &signature)]
+            ));
+            assert!(!PrivateKey::verify_signature_batch(
+                &mut csprng,
+                &[
+                    (&public_key, &[message], &valid_signature),
+                    (&public_key, &[message], &signature),
+                ]
+            ));
+        }
+    }
 }
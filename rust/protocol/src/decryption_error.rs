@@ -0,0 +1,108 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use uuid::Uuid;
+
+use crate::group_cipher::distribution_message_for_record;
+use crate::{
+    DecryptionErrorMessage, ProtocolAddress, Result, SenderKeyDistributionMessage, SenderKeyStore,
+    SessionStore, SignalProtocolError,
+};
+
+/// What the application must do to recover from a [`DecryptionErrorMessage`], as decided by
+/// [`handle_decryption_error_message`].
+///
+/// Every action other than [`NotForThisDevice`](Self::NotForThisDevice) ends with resending the
+/// message identified by [`DecryptionErrorMessage::timestamp`].
+#[derive(Debug, Clone)]
+pub enum DecryptionErrorAction {
+    /// The failed message was sent by another of the local user's devices, so there is nothing
+    /// for this one to do.
+    NotForThisDevice,
+    /// The session the message was sent on has been archived. Fetch and process a new pre-key
+    /// bundle for the device that reported the error, then resend the message.
+    ResendWithNewSession,
+    /// The session has moved on since the failed message was sent. Resend the message on the
+    /// current session.
+    Resend,
+    /// The failed message was encrypted with a sender key. Send this distribution message to the
+    /// device that reported the error, then resend the message.
+    ResendWithSenderKeyDistribution(SenderKeyDistributionMessage),
+}
+
+/// Applies the standard recovery for a [`DecryptionErrorMessage`] received from
+/// `remote_address`, reporting a message that `local_address` sent.
+///
+/// If the error names the ratchet key of the current session with `remote_address`, that session
+/// is archived, since the other device evidently can't use it. Errors about sender key messages
+/// carry no ratchet key; for those, `distribution_id` must identify the distribution the failed
+/// message was sent to, and the local sender key for it is distributed again.
+pub async fn handle_decryption_error_message(
+    error_message: &DecryptionErrorMessage,
+    remote_address: &ProtocolAddress,
+    local_address: &ProtocolAddress,
+    distribution_id: Option<Uuid>,
+    session_store: &mut dyn SessionStore,
+    sender_key_store: &mut dyn SenderKeyStore,
+) -> Result<DecryptionErrorAction> {
+    if error_message.device_id() != u32::from(local_address.device_id()) {
+        log::info!(
+            "Ignoring decryption error from {} for a message sent by device {}",
+            remote_address,
+            error_message.device_id()
+        );
+        return Ok(DecryptionErrorAction::NotForThisDevice);
+    }
+
+    let Some(ratchet_key) = error_message.ratchet_key() else {
+        let distribution_id = distribution_id.ok_or_else(|| {
+            SignalProtocolError::InvalidArgument(
+                "a distribution ID is required for errors about sender key messages".to_string(),
+            )
+        })?;
+        let record = sender_key_store
+            .load_sender_key(local_address, distribution_id)
+            .await?
+            .ok_or(SignalProtocolError::NoSenderKeyState { distribution_id })?;
+        log::info!(
+            "{} could not decrypt a sender key message for distribution {}; redistributing",
+            remote_address,
+            distribution_id
+        );
+        return Ok(DecryptionErrorAction::ResendWithSenderKeyDistribution(
+            distribution_message_for_record(&record, distribution_id)?,
+        ));
+    };
+
+    let Some(mut session_record) = session_store
+        .load_session(remote_address)
+        .await?
+        .filter(|record| record.session_state().is_some())
+    else {
+        log::info!(
+            "{} could not decrypt a message, but there is no current session with it",
+            remote_address
+        );
+        return Ok(DecryptionErrorAction::ResendWithNewSession);
+    };
+
+    if !session_record.current_ratchet_key_matches(ratchet_key)? {
+        log::info!(
+            "{} could not decrypt a message from a session that has since moved on",
+            remote_address
+        );
+        return Ok(DecryptionErrorAction::Resend);
+    }
+
+    log::info!(
+        "{} could not decrypt a message from the current session; archiving it",
+        remote_address
+    );
+    session_record.archive_current_state()?;
+    session_store
+        .store_session(remote_address, &session_record)
+        .await?;
+    Ok(DecryptionErrorAction::ResendWithNewSession)
+}
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
-use crate::{proto, IdentityKey, Result, SignalProtocolError};
+use crate::{proto, Aci, IdentityKey, Result, SignalProtocolError};
 use prost::Message;
 use sha2::digest::Digest;
 use sha2::Sha512;
@@ -60,11 +60,48 @@ impl DisplayableFingerprint {
     }
 }
 
+/// A key transparency tree head that a [`ScannableFingerprint`] was made against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FingerprintTreeHead {
+    pub tree_size: u64,
+    pub root_hash: [u8; 32],
+}
+
+/// The result of [`ScannableFingerprint::compare`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FingerprintComparison {
+    /// Both sides have the same identities, and the same tree head if any.
+    Match,
+    /// The scanned fingerprint uses a different fingerprint version.
+    VersionMismatch { theirs: u32, ours: u32 },
+    /// The scanned fingerprint was made for a different pair of identities.
+    IdentityMismatch,
+    /// The identities match, but the two sides have seen different sizes of the key transparency
+    /// log (or only one side has seen it at all).
+    ///
+    /// The side with the older (or missing) tree head should update and compare again.
+    StaleTreeHead {
+        theirs: Option<FingerprintTreeHead>,
+        ours: Option<FingerprintTreeHead>,
+    },
+    /// The identities match, but the two sides have different tree heads for the same log size.
+    ///
+    /// This means the key transparency log has presented different views to the two sides.
+    TreeHeadConflict,
+}
+
+impl FingerprintComparison {
+    pub fn is_match(&self) -> bool {
+        matches!(self, Self::Match)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ScannableFingerprint {
     version: u32,
     local_fingerprint: Vec<u8>,
     remote_fingerprint: Vec<u8>,
+    tree_head: Option<FingerprintTreeHead>,
 }
 
 impl ScannableFingerprint {
@@ -73,6 +110,7 @@ impl ScannableFingerprint {
             version,
             local_fingerprint: local_fprint[..32].to_vec(),
             remote_fingerprint: remote_fprint[..32].to_vec(),
+            tree_head: None,
         }
     }
 
@@ -94,6 +132,11 @@ impl ScannableFingerprint {
                 .ok_or(SignalProtocolError::FingerprintParsingError)?
                 .content
                 .ok_or(SignalProtocolError::FingerprintParsingError)?,
+            tree_head: fingerprint
+                .tree_head
+                .as_ref()
+                .map(tree_head_from_proto)
+                .transpose()?,
         })
     }
 
@@ -106,22 +149,34 @@ impl ScannableFingerprint {
             remote_fingerprint: Some(proto::fingerprint::LogicalFingerprint {
                 content: Some(self.remote_fingerprint.to_owned()),
             }),
+            tree_head: self.tree_head.map(|tree_head| {
+                proto::fingerprint::KeyTransparencyTreeHead {
+                    tree_size: Some(tree_head.tree_size),
+                    root_hash: Some(tree_head.root_hash.to_vec()),
+                }
+            }),
         };
 
         Ok(combined_fingerprints.encode_to_vec())
     }
 
-    pub fn compare(&self, combined: &[u8]) -> Result<bool> {
+    /// The key transparency tree head this fingerprint was made against, if any.
+    pub fn tree_head(&self) -> Option<FingerprintTreeHead> {
+        self.tree_head
+    }
+
+    /// Compares this fingerprint with one scanned from the other party.
+    pub fn compare(&self, combined: &[u8]) -> Result<FingerprintComparison> {
         let combined = proto::fingerprint::CombinedFingerprints::decode(combined)
             .map_err(|_| SignalProtocolError::FingerprintParsingError)?;
 
         let their_version = combined.version.unwrap_or(0);
 
         if their_version != self.version {
-            return Err(SignalProtocolError::FingerprintVersionMismatch(
-                their_version,
-                self.version,
-            ));
+            return Ok(FingerprintComparison::VersionMismatch {
+                theirs: their_version,
+                ours: self.version,
+            });
         }
 
         let same1 = combined
@@ -141,10 +196,41 @@ impl ScannableFingerprint {
             .ok_or(SignalProtocolError::FingerprintParsingError)?
             .ct_eq(&self.local_fingerprint);
 
-        Ok(same1.into() && same2.into())
+        if !bool::from(same1 & same2) {
+            return Ok(FingerprintComparison::IdentityMismatch);
+        }
+
+        let their_tree_head = combined
+            .tree_head
+            .as_ref()
+            .map(tree_head_from_proto)
+            .transpose()?;
+
+        Ok(match (their_tree_head, self.tree_head) {
+            (theirs, ours) if theirs == ours => FingerprintComparison::Match,
+            (Some(theirs), Some(ours)) if theirs.tree_size == ours.tree_size => {
+                FingerprintComparison::TreeHeadConflict
+            }
+            (theirs, ours) => FingerprintComparison::StaleTreeHead { theirs, ours },
+        })
     }
 }
 
+fn tree_head_from_proto(
+    tree_head: &proto::fingerprint::KeyTransparencyTreeHead,
+) -> Result<FingerprintTreeHead> {
+    Ok(FingerprintTreeHead {
+        tree_size: tree_head
+            .tree_size
+            .ok_or(SignalProtocolError::FingerprintParsingError)?,
+        root_hash: tree_head
+            .root_hash
+            .as_deref()
+            .and_then(|hash| hash.try_into().ok())
+            .ok_or(SignalProtocolError::FingerprintParsingError)?,
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct Fingerprint {
     pub display: DisplayableFingerprint,
@@ -152,8 +238,12 @@ pub struct Fingerprint {
 }
 
 impl Fingerprint {
+    /// The scannable fingerprint version produced by [`Fingerprint::new_v3`].
+    pub const ACI_VERSION: u32 = 3;
+
     fn get_fingerprint(
         iterations: u32,
+        hash_version: [u8; 2],
         local_id: &[u8],
         local_key: &IdentityKey,
     ) -> Result<Vec<u8>> {
@@ -164,14 +254,13 @@ impl Fingerprint {
             )));
         }
 
-        let fingerprint_version = [0u8, 0u8]; // 0x0000
         let key_bytes = local_key.serialize();
 
         let mut sha512 = Sha512::new();
 
         // iteration=0
         // Explicitly pass a slice to avoid generating multiple versions of update().
-        sha512.update(&fingerprint_version[..]);
+        sha512.update(&hash_version[..]);
         sha512.update(&key_bytes);
         sha512.update(local_id);
         sha512.update(&key_bytes);
@@ -188,6 +277,11 @@ impl Fingerprint {
         Ok(buf.to_vec())
     }
 
+    /// Creates a version 1 or 2 fingerprint from caller-chosen stable IDs.
+    ///
+    /// Any other version is rejected. In particular, a scannable fingerprint with
+    /// [`Self::ACI_VERSION`] must come from [`Fingerprint::new_v3`]; anything else would be
+    /// compared against a real v3 fingerprint and report a spurious identity mismatch.
     pub fn new(
         version: u32,
         iterations: u32,
@@ -196,8 +290,25 @@ impl Fingerprint {
         remote_id: &[u8],
         remote_key: &IdentityKey,
     ) -> Result<Fingerprint> {
-        let local_fingerprint = Fingerprint::get_fingerprint(iterations, local_id, local_key)?;
-        let remote_fingerprint = Fingerprint::get_fingerprint(iterations, remote_id, remote_key)?;
+        if version == Self::ACI_VERSION {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "fingerprint version {} must be created with Fingerprint::new_v3",
+                version
+            )));
+        }
+        if !(1..=2).contains(&version) {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "unsupported fingerprint version {}",
+                version
+            )));
+        }
+
+        // Versions 1 and 2 only differ in the scannable version number.
+        let hash_version = [0u8, 0u8]; // 0x0000
+        let local_fingerprint =
+            Fingerprint::get_fingerprint(iterations, hash_version, local_id, local_key)?;
+        let remote_fingerprint =
+            Fingerprint::get_fingerprint(iterations, hash_version, remote_id, remote_key)?;
 
         Ok(Fingerprint {
             display: DisplayableFingerprint::new(&local_fingerprint, &remote_fingerprint)?,
@@ -205,6 +316,46 @@ impl Fingerprint {
         })
     }
 
+    /// Creates a version 3 fingerprint, which identifies both parties by their ACIs rather than a
+    /// caller-chosen stable ID.
+    ///
+    /// If `tree_head` is provided, it is included in the scannable fingerprint so that
+    /// [`ScannableFingerprint::compare`] can detect when the two parties have verified the
+    /// identities against different states of the key transparency log. It does not affect the
+    /// displayed digits.
+    pub fn new_v3(
+        iterations: u32,
+        local_aci: Aci,
+        local_key: &IdentityKey,
+        remote_aci: Aci,
+        remote_key: &IdentityKey,
+        tree_head: Option<FingerprintTreeHead>,
+    ) -> Result<Fingerprint> {
+        // Distinct from versions 1 and 2, so a 16-byte stable ID can't collide with an ACI.
+        let hash_version = [0u8, 3u8]; // 0x0003
+        let local_fingerprint = Fingerprint::get_fingerprint(
+            iterations,
+            hash_version,
+            &local_aci.service_id_binary(),
+            local_key,
+        )?;
+        let remote_fingerprint = Fingerprint::get_fingerprint(
+            iterations,
+            hash_version,
+            &remote_aci.service_id_binary(),
+            remote_key,
+        )?;
+
+        let mut scannable =
+            ScannableFingerprint::new(Self::ACI_VERSION, &local_fingerprint, &remote_fingerprint);
+        scannable.tree_head = tree_head;
+
+        Ok(Fingerprint {
+            display: DisplayableFingerprint::new(&local_fingerprint, &remote_fingerprint)?,
+            scannable,
+        })
+    }
+
     pub fn display_string(&self) -> Result<String> {
         Ok(format!("{}", self.display))
     }
@@ -393,18 +544,22 @@ mod test {
 
         assert!(a_fprint
             .scannable
-            .compare(&b_fprint.scannable.serialize()?)?);
+            .compare(&b_fprint.scannable.serialize()?)?
+            .is_match());
         assert!(b_fprint
             .scannable
-            .compare(&a_fprint.scannable.serialize()?)?);
+            .compare(&a_fprint.scannable.serialize()?)?
+            .is_match());
 
         // Java is missing this test
         assert!(!a_fprint
             .scannable
-            .compare(&a_fprint.scannable.serialize()?)?);
+            .compare(&a_fprint.scannable.serialize()?)?
+            .is_match());
         assert!(!b_fprint
             .scannable
-            .compare(&b_fprint.scannable.serialize()?)?);
+            .compare(&b_fprint.scannable.serialize()?)?
+            .is_match());
 
         Ok(())
     }
@@ -450,10 +605,12 @@ mod test {
 
         assert!(!a_fprint
             .scannable
-            .compare(&b_fprint.scannable.serialize()?)?);
+            .compare(&b_fprint.scannable.serialize()?)?
+            .is_match());
         assert!(!b_fprint
             .scannable
-            .compare(&a_fprint.scannable.serialize()?)?);
+            .compare(&a_fprint.scannable.serialize()?)?
+            .is_match());
 
         Ok(())
     }
@@ -497,10 +654,12 @@ mod test {
 
         assert!(!a_fprint
             .scannable
-            .compare(&b_fprint.scannable.serialize()?)?);
+            .compare(&b_fprint.scannable.serialize()?)?
+            .is_match());
         assert!(!b_fprint
             .scannable
-            .compare(&a_fprint.scannable.serialize()?)?);
+            .compare(&a_fprint.scannable.serialize()?)?
+            .is_match());
 
         Ok(())
     }
@@ -544,4 +703,209 @@ mod test {
 
         Ok(())
     }
+
+    const ALICE_ACI: &str = "9d0652a3-dcc3-4d11-975f-74d61598733f";
+    const BOB_ACI: &str = "796abedb-ca4e-4f18-8803-1fde5b921f9f";
+
+    const DISPLAYABLE_FINGERPRINT_V3: &str =
+        "351791649536009920036691394896443054084492711299108877039312";
+    const ALICE_SCANNABLE_FINGERPRINT_V3 : &str = "080312220a20fce7593ef1c31ebec28c830bf923875a206f6cd67da2cd80820fc3ad88d01d6c1a220a20ceae7dae4b17a55459ef014228c749aeff87b00338755b01210d6cf55f50f81e";
+    const BOB_SCANNABLE_FINGERPRINT_V3   : &str = "080312220a20ceae7dae4b17a55459ef014228c749aeff87b00338755b01210d6cf55f50f81e1a220a20fce7593ef1c31ebec28c830bf923875a206f6cd67da2cd80820fc3ad88d01d6c";
+
+    fn aci(s: &str) -> Aci {
+        Aci::parse_from_service_id_string(s).expect("valid")
+    }
+
+    #[test]
+    fn fingerprint_test_v3() -> Result<()> {
+        let a_key = IdentityKey::decode(ALICE_IDENTITY)?;
+        let b_key = IdentityKey::decode(BOB_IDENTITY)?;
+
+        let iterations = 5200;
+
+        let a_fprint = Fingerprint::new_v3(
+            iterations,
+            aci(ALICE_ACI),
+            &a_key,
+            aci(BOB_ACI),
+            &b_key,
+            None,
+        )?;
+        let b_fprint = Fingerprint::new_v3(
+            iterations,
+            aci(BOB_ACI),
+            &b_key,
+            aci(ALICE_ACI),
+            &a_key,
+            None,
+        )?;
+
+        assert_eq!(
+            hex::encode(a_fprint.scannable.serialize()?),
+            ALICE_SCANNABLE_FINGERPRINT_V3
+        );
+        assert_eq!(
+            hex::encode(b_fprint.scannable.serialize()?),
+            BOB_SCANNABLE_FINGERPRINT_V3
+        );
+
+        assert_eq!(format!("{}", a_fprint.display), DISPLAYABLE_FINGERPRINT_V3);
+        assert_eq!(format!("{}", b_fprint.display), DISPLAYABLE_FINGERPRINT_V3);
+
+        assert_eq!(
+            a_fprint
+                .scannable
+                .compare(&b_fprint.scannable.serialize()?)?,
+            FingerprintComparison::Match
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_v3_tree_heads() -> Result<()> {
+        use crate::IdentityKeyPair;
+        use rand::rngs::OsRng;
+
+        let a_key = IdentityKey::decode(ALICE_IDENTITY)?;
+        let b_key = IdentityKey::decode(BOB_IDENTITY)?;
+
+        let iterations = 1024;
+        let head = |tree_size, hash_byte| FingerprintTreeHead {
+            tree_size,
+            root_hash: [hash_byte; 32],
+        };
+        let a_fprint = |tree_head| {
+            Fingerprint::new_v3(
+                iterations,
+                aci(ALICE_ACI),
+                &a_key,
+                aci(BOB_ACI),
+                &b_key,
+                tree_head,
+            )
+        };
+        let b_serialized = |tree_head| {
+            Fingerprint::new_v3(
+                iterations,
+                aci(BOB_ACI),
+                &b_key,
+                aci(ALICE_ACI),
+                &a_key,
+                tree_head,
+            )?
+            .scannable
+            .serialize()
+        };
+
+        let a_with_head = a_fprint(Some(head(10, 1)))?;
+        assert_eq!(a_with_head.scannable.tree_head(), Some(head(10, 1)));
+        assert_eq!(
+            ScannableFingerprint::deserialize(&a_with_head.scannable.serialize()?)?.tree_head(),
+            Some(head(10, 1))
+        );
+
+        // The tree head doesn't change the displayed digits.
+        assert_eq!(
+            format!("{}", a_with_head.display),
+            format!("{}", a_fprint(None)?.display)
+        );
+
+        assert_eq!(
+            a_with_head
+                .scannable
+                .compare(&b_serialized(Some(head(10, 1)))?)?,
+            FingerprintComparison::Match
+        );
+        assert_eq!(
+            a_with_head
+                .scannable
+                .compare(&b_serialized(Some(head(12, 2)))?)?,
+            FingerprintComparison::StaleTreeHead {
+                theirs: Some(head(12, 2)),
+                ours: Some(head(10, 1)),
+            }
+        );
+        assert_eq!(
+            a_with_head.scannable.compare(&b_serialized(None)?)?,
+            FingerprintComparison::StaleTreeHead {
+                theirs: None,
+                ours: Some(head(10, 1)),
+            }
+        );
+        assert_eq!(
+            a_with_head
+                .scannable
+                .compare(&b_serialized(Some(head(10, 2)))?)?,
+            FingerprintComparison::TreeHeadConflict
+        );
+
+        // Identity problems are reported before tree head problems.
+        let m_key = *IdentityKeyPair::generate(&mut OsRng).identity_key(); // mitm
+        let mitm_serialized = Fingerprint::new_v3(
+            iterations,
+            aci(BOB_ACI),
+            &m_key,
+            aci(ALICE_ACI),
+            &a_key,
+            Some(head(12, 2)),
+        )?
+        .scannable
+        .serialize()?;
+        assert_eq!(
+            a_with_head.scannable.compare(&mitm_serialized)?,
+            FingerprintComparison::IdentityMismatch
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_v3_differs_from_v2() -> Result<()> {
+        let a_key = IdentityKey::decode(ALICE_IDENTITY)?;
+        let b_key = IdentityKey::decode(BOB_IDENTITY)?;
+
+        let iterations = 1024;
+
+        let a_aci = aci(ALICE_ACI);
+        let b_aci = aci(BOB_ACI);
+
+        // Even with the ACI bytes as the stable ID.
+        let a_fprint_v2 = Fingerprint::new(
+            2,
+            iterations,
+            &a_aci.service_id_binary(),
+            &a_key,
+            &b_aci.service_id_binary(),
+            &b_key,
+        )?;
+        let b_fprint_v3 = Fingerprint::new_v3(iterations, b_aci, &b_key, a_aci, &a_key, None)?;
+
+        assert_ne!(
+            format!("{}", a_fprint_v2.display),
+            format!("{}", b_fprint_v3.display)
+        );
+        assert_eq!(
+            a_fprint_v2
+                .scannable
+                .compare(&b_fprint_v3.scannable.serialize()?)?,
+            FingerprintComparison::VersionMismatch { theirs: 3, ours: 2 }
+        );
+
+        for version in [0, Fingerprint::ACI_VERSION, 4, u32::MAX] {
+            assert!(matches!(
+                Fingerprint::new(
+                    version,
+                    iterations,
+                    &a_aci.service_id_binary(),
+                    &a_key,
+                    &b_aci.service_id_binary(),
+                    &b_key,
+                ),
+                Err(SignalProtocolError::InvalidArgument(_))
+            ));
+        }
+
+        Ok(())
+    }
 }
@@ -3,14 +3,17 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use std::time::{Duration, SystemTime};
+
 use rand::{CryptoRng, Rng};
 use uuid::Uuid;
 
 use crate::protocol::SENDERKEY_MESSAGE_CURRENT_VERSION;
 use crate::sender_keys::{SenderKeyState, SenderMessageKey};
 use crate::{
-    consts, CiphertextMessageType, KeyPair, ProtocolAddress, Result, SenderKeyDistributionMessage,
-    SenderKeyMessage, SenderKeyRecord, SenderKeyStore, SignalProtocolError,
+    CiphertextMessageType, KeyPair, ProtocolAddress, ProtocolConfig, Result,
+    SenderKeyDistributionMessage, SenderKeyMessage, SenderKeyRecord, SenderKeyStore,
+    SignalProtocolError,
 };
 
 pub async fn group_encrypt<R: Rng + CryptoRng>(
@@ -72,6 +75,7 @@ fn get_sender_key(
     state: &mut SenderKeyState,
     iteration: u32,
     distribution_id: Uuid,
+    config: &ProtocolConfig,
 ) -> Result<SenderMessageKey> {
     let sender_chain_key = state
         .sender_chain_key()
@@ -95,11 +99,11 @@ fn get_sender_key(
     }
 
     let jump = (iteration - current_iteration) as usize;
-    if jump > consts::MAX_FORWARD_JUMPS {
+    if jump > config.max_forward_jumps {
         log::error!(
             "SenderKey distribution {} Exceeded future message limit: {}, current iteration: {})",
             distribution_id,
-            consts::MAX_FORWARD_JUMPS,
+            config.max_forward_jumps,
             current_iteration
         );
         return Err(SignalProtocolError::InvalidMessage(
@@ -111,7 +115,7 @@ fn get_sender_key(
     let mut sender_chain_key = sender_chain_key;
 
     while sender_chain_key.iteration() < iteration {
-        state.add_sender_message_key(&sender_chain_key.sender_message_key());
+        state.add_sender_message_key(&sender_chain_key.sender_message_key(), config);
         sender_chain_key = sender_chain_key.next();
     }
 
@@ -123,6 +127,22 @@ pub async fn group_decrypt(
     skm_bytes: &[u8],
     sender_key_store: &mut dyn SenderKeyStore,
     sender: &ProtocolAddress,
+) -> Result<Vec<u8>> {
+    group_decrypt_with_config(
+        skm_bytes,
+        sender_key_store,
+        sender,
+        &ProtocolConfig::DEFAULT,
+    )
+    .await
+}
+
+/// Like [`group_decrypt`], but with the limits on skipped messages given by `config`.
+pub async fn group_decrypt_with_config(
+    skm_bytes: &[u8],
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    config: &ProtocolConfig,
 ) -> Result<Vec<u8>> {
     let skm = SenderKeyMessage::try_from(skm_bytes)?;
 
@@ -161,7 +181,7 @@ pub async fn group_decrypt(
         return Err(SignalProtocolError::SignatureValidationFailed);
     }
 
-    let sender_key = get_sender_key(sender_key_state, skm.iteration(), distribution_id)?;
+    let sender_key = get_sender_key(sender_key_state, skm.iteration(), distribution_id, config)?;
 
     let plaintext = match signal_crypto::aes_256_cbc_decrypt(
         skm.ciphertext(),
@@ -198,6 +218,23 @@ pub async fn process_sender_key_distribution_message(
     sender: &ProtocolAddress,
     skdm: &SenderKeyDistributionMessage,
     sender_key_store: &mut dyn SenderKeyStore,
+) -> Result<()> {
+    process_sender_key_distribution_message_with_config(
+        sender,
+        skdm,
+        sender_key_store,
+        &ProtocolConfig::DEFAULT,
+    )
+    .await
+}
+
+/// Like [`process_sender_key_distribution_message`], but with the limit on stored sender key
+/// states given by `config`.
+pub async fn process_sender_key_distribution_message_with_config(
+    sender: &ProtocolAddress,
+    skdm: &SenderKeyDistributionMessage,
+    sender_key_store: &mut dyn SenderKeyStore,
+    config: &ProtocolConfig,
 ) -> Result<()> {
     let distribution_id = skdm.distribution_id()?;
     log::info!(
@@ -219,6 +256,7 @@ pub async fn process_sender_key_distribution_message(
         skdm.chain_key()?,
         *skdm.signing_key()?,
         None,
+        config,
     );
     sender_key_store
         .store_sender_key(sender, distribution_id, &sender_key_record)
@@ -231,6 +269,25 @@ pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
     distribution_id: Uuid,
     sender_key_store: &mut dyn SenderKeyStore,
     csprng: &mut R,
+) -> Result<SenderKeyDistributionMessage> {
+    create_sender_key_distribution_message_at(
+        sender,
+        distribution_id,
+        sender_key_store,
+        SystemTime::now(),
+        csprng,
+    )
+    .await
+}
+
+/// Like [`create_sender_key_distribution_message`], but records `now` as the creation time of a
+/// new sender key, for [`SenderKeyRotationPolicy`].
+pub async fn create_sender_key_distribution_message_at<R: Rng + CryptoRng>(
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    sender_key_store: &mut dyn SenderKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
 ) -> Result<SenderKeyDistributionMessage> {
     let sender_key_record = sender_key_store
         .load_sender_key(sender, distribution_id)
@@ -239,26 +296,7 @@ pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
     let sender_key_record = match sender_key_record {
         Some(record) => record,
         None => {
-            // libsignal-protocol-java uses 31-bit integers for sender key chain IDs
-            let chain_id = (csprng.gen::<u32>()) >> 1;
-            log::info!(
-                "Creating SenderKey for distribution {} with chain ID {}",
-                distribution_id,
-                chain_id
-            );
-
-            let iteration = 0;
-            let sender_key: [u8; 32] = csprng.gen();
-            let signing_key = KeyPair::generate(csprng);
-            let mut record = SenderKeyRecord::new_empty();
-            record.add_sender_key_state(
-                SENDERKEY_MESSAGE_CURRENT_VERSION,
-                chain_id,
-                iteration,
-                &sender_key,
-                signing_key.public_key,
-                Some(signing_key.private_key),
-            );
+            let record = new_own_sender_key_record(distribution_id, None, now, csprng);
             sender_key_store
                 .store_sender_key(sender, distribution_id, &record)
                 .await?;
@@ -266,7 +304,180 @@ pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
         }
     };
 
-    let state = sender_key_record
+    distribution_message_for_record(&sender_key_record, distribution_id)
+}
+
+/// When a sender should replace its own sender key for a distribution.
+///
+/// The default policy never rotates on its own; a rotation still happens after
+/// [`mark_sender_key_for_rotation`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SenderKeyRotationPolicy {
+    /// Rotate once this many messages have been sent with the current sender key.
+    pub max_messages: Option<u32>,
+    /// Rotate once the current sender key is this old.
+    pub max_age: Option<Duration>,
+}
+
+/// Requests that the local sender key for `distribution_id` be replaced the next time
+/// [`rotate_sender_key_if_needed`] is called.
+///
+/// Call this when a member is removed from the group, so that they can't decrypt later messages.
+/// Does nothing if there is no sender key for `distribution_id` yet.
+pub async fn mark_sender_key_for_rotation(
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    sender_key_store: &mut dyn SenderKeyStore,
+) -> Result<()> {
+    let Some(mut record) = sender_key_store
+        .load_sender_key(sender, distribution_id)
+        .await?
+    else {
+        return Ok(());
+    };
+
+    record
+        .sender_key_state_mut()
+        .map_err(|_| SignalProtocolError::InvalidSenderKeySession { distribution_id })?
+        .request_rotation();
+
+    sender_key_store
+        .store_sender_key(sender, distribution_id, &record)
+        .await
+}
+
+/// Replaces the local sender key for `distribution_id` if `policy` says it is due, or if it has
+/// been marked with [`mark_sender_key_for_rotation`].
+///
+/// Call this before [`group_encrypt`]. If a new sender key was created, the returned
+/// [`SenderKeyDistributionMessage`] must be delivered to the remaining group members before any
+/// message encrypted with it; messages encrypted with the old sender key can still be decrypted
+/// by recipients that have it.
+///
+/// Returns `None` if there is no sender key for `distribution_id` yet; use
+/// [`create_sender_key_distribution_message`] to create one. Sender keys created before rotation
+/// support have no recorded creation time, so their age is counted from the first call.
+pub async fn rotate_sender_key_if_needed<R: Rng + CryptoRng>(
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    sender_key_store: &mut dyn SenderKeyStore,
+    policy: &SenderKeyRotationPolicy,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<Option<SenderKeyDistributionMessage>> {
+    let Some(mut record) = sender_key_store
+        .load_sender_key(sender, distribution_id)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let state = record
+        .sender_key_state_mut()
+        .map_err(|_| SignalProtocolError::InvalidSenderKeySession { distribution_id })?;
+    let sent_messages = state
+        .sender_chain_key()
+        .ok_or(SignalProtocolError::InvalidSenderKeySession { distribution_id })?
+        .iteration();
+    let rotation_requested = state.rotation_requested();
+
+    let created_at = match state.created_at() {
+        Some(created_at) => created_at,
+        None => {
+            state.set_created_at(now);
+            sender_key_store
+                .store_sender_key(sender, distribution_id, &record)
+                .await?;
+            now
+        }
+    };
+
+    let expired = policy
+        .max_age
+        .is_some_and(|max_age| created_at + max_age <= now);
+    let exhausted = policy
+        .max_messages
+        .is_some_and(|max_messages| sent_messages >= max_messages);
+
+    if !rotation_requested && !expired && !exhausted {
+        return Ok(None);
+    }
+
+    rotate_sender_key(sender, distribution_id, sender_key_store, now, csprng)
+        .await
+        .map(Some)
+}
+
+/// Unconditionally replaces the local sender key for `distribution_id`, returning the
+/// [`SenderKeyDistributionMessage`] for the new one.
+///
+/// See [`rotate_sender_key_if_needed`].
+pub async fn rotate_sender_key<R: Rng + CryptoRng>(
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    sender_key_store: &mut dyn SenderKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<SenderKeyDistributionMessage> {
+    let previous_chain_id = sender_key_store
+        .load_sender_key(sender, distribution_id)
+        .await?
+        .and_then(|record| record.sender_key_state().ok().map(|state| state.chain_id()));
+
+    // Our old states are not kept; we will never send with them again.
+    let record = new_own_sender_key_record(distribution_id, previous_chain_id, now, csprng);
+    sender_key_store
+        .store_sender_key(sender, distribution_id, &record)
+        .await?;
+
+    distribution_message_for_record(&record, distribution_id)
+}
+
+fn new_own_sender_key_record<R: Rng + CryptoRng>(
+    distribution_id: Uuid,
+    previous_chain_id: Option<u32>,
+    now: SystemTime,
+    csprng: &mut R,
+) -> SenderKeyRecord {
+    let chain_id = loop {
+        // libsignal-protocol-java uses 31-bit integers for sender key chain IDs
+        let chain_id = (csprng.gen::<u32>()) >> 1;
+        if Some(chain_id) != previous_chain_id {
+            break chain_id;
+        }
+    };
+    log::info!(
+        "Creating SenderKey for distribution {} with chain ID {}",
+        distribution_id,
+        chain_id
+    );
+
+    let iteration = 0;
+    let sender_key: [u8; 32] = csprng.gen();
+    let signing_key = KeyPair::generate(csprng);
+    let mut record = SenderKeyRecord::new_empty();
+    record.add_sender_key_state(
+        SENDERKEY_MESSAGE_CURRENT_VERSION,
+        chain_id,
+        iteration,
+        &sender_key,
+        signing_key.public_key,
+        Some(signing_key.private_key),
+        // A new record only has the one state.
+        &ProtocolConfig::DEFAULT,
+    );
+    record
+        .sender_key_state_mut()
+        .expect("just added")
+        .set_created_at(now);
+    record
+}
+
+pub(crate) fn distribution_message_for_record(
+    record: &SenderKeyRecord,
+    distribution_id: Uuid,
+) -> Result<SenderKeyDistributionMessage> {
+    let state = record
         .sender_key_state()
         .map_err(|_| SignalProtocolError::InvalidSenderKeySession { distribution_id })?;
     let sender_chain_key = state
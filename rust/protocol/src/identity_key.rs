@@ -13,9 +13,11 @@ use rand::{CryptoRng, Rng};
 
 use prost::Message;
 
-// Used for domain separation between alternate-identity signatures and other key-to-key signatures.
+// Used for domain separation between alternate-identity or succession signatures and other
+// key-to-key signatures.
 const ALTERNATE_IDENTITY_SIGNATURE_PREFIX_1: &[u8] = &[0xFF; 32];
 const ALTERNATE_IDENTITY_SIGNATURE_PREFIX_2: &[u8] = b"Signal_PNI_Signature";
+const IDENTITY_SUCCESSION_SIGNATURE_PREFIX_2: &[u8] = b"Signal_Identity_Succession";
 
 /// A public key that represents the identity of a user.
 ///
@@ -142,6 +144,44 @@ impl IdentityKeyPair {
         result.into_boxed_slice()
     }
 
+    /// Generate a new identity to replace this one, along with a statement signed by this identity
+    /// that lets peers accept the new one.
+    ///
+    /// Publish the new identity together with the returned [`IdentityKeySuccession`]; peers check
+    /// it with [`IdentityKeyStore::save_identity_with_succession`].
+    ///
+    /// [`IdentityKeyStore::save_identity_with_succession`]: crate::IdentityKeyStore::save_identity_with_succession
+    pub fn rotate<R: Rng + CryptoRng>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(IdentityKeyPair, IdentityKeySuccession)> {
+        let new_identity = Self::generate(rng);
+        let succession = self.sign_succession(new_identity.identity_key(), rng)?;
+        Ok((new_identity, succession))
+    }
+
+    /// Generate a statement that `new_identity` replaces `self`.
+    pub fn sign_succession<R: Rng + CryptoRng>(
+        &self,
+        new_identity: &IdentityKey,
+        rng: &mut R,
+    ) -> Result<IdentityKeySuccession> {
+        let signature = self.private_key.calculate_signature_for_multipart_message(
+            &[
+                ALTERNATE_IDENTITY_SIGNATURE_PREFIX_1,
+                IDENTITY_SUCCESSION_SIGNATURE_PREFIX_2,
+                &self.identity_key.serialize(),
+                &new_identity.serialize(),
+            ],
+            rng,
+        )?;
+        Ok(IdentityKeySuccession {
+            old_identity: self.identity_key,
+            new_identity: *new_identity,
+            signature,
+        })
+    }
+
     /// Generate a signature claiming that `other` represents the same user as `self`.
     pub fn sign_alternate_identity<R: Rng + CryptoRng>(
         &self,
@@ -196,6 +236,90 @@ impl From<IdentityKeyPair> for KeyPair {
     }
 }
 
+/// A statement, signed by an old identity, that a new identity replaces it.
+///
+/// Produced by [`IdentityKeyPair::rotate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityKeySuccession {
+    old_identity: IdentityKey,
+    new_identity: IdentityKey,
+    signature: Box<[u8]>,
+}
+
+impl IdentityKeySuccession {
+    /// Return the identity being replaced, which signed this statement.
+    #[inline]
+    pub fn old_identity(&self) -> &IdentityKey {
+        &self.old_identity
+    }
+
+    /// Return the identity replacing [`Self::old_identity`].
+    #[inline]
+    pub fn new_identity(&self) -> &IdentityKey {
+        &self.new_identity
+    }
+
+    /// Return the signature by [`Self::old_identity`].
+    #[inline]
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    /// Check that [`Self::old_identity`] signed this statement.
+    ///
+    /// This says nothing about whether the old identity should be trusted; callers must also check
+    /// that it is the identity they already know for the other user.
+    pub fn verify(&self) -> Result<bool> {
+        self.old_identity
+            .public_key
+            .verify_signature_for_multipart_message(
+                &[
+                    ALTERNATE_IDENTITY_SIGNATURE_PREFIX_1,
+                    IDENTITY_SUCCESSION_SIGNATURE_PREFIX_2,
+                    &self.old_identity.serialize(),
+                    &self.new_identity.serialize(),
+                ],
+                &self.signature,
+            )
+    }
+
+    /// Return a byte slice which can later be deserialized with [`Self::try_from`].
+    pub fn serialize(&self) -> Box<[u8]> {
+        proto::wire::IdentityKeySuccession {
+            old_identity_key: Some(self.old_identity.serialize().into_vec()),
+            new_identity_key: Some(self.new_identity.serialize().into_vec()),
+            signature: Some(self.signature.to_vec()),
+        }
+        .encode_to_vec()
+        .into_boxed_slice()
+    }
+}
+
+impl TryFrom<&[u8]> for IdentityKeySuccession {
+    type Error = SignalProtocolError;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let structure = proto::wire::IdentityKeySuccession::decode(value)
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        Ok(Self {
+            old_identity: IdentityKey::decode(
+                &structure
+                    .old_identity_key
+                    .ok_or(SignalProtocolError::InvalidProtobufEncoding)?,
+            )?,
+            new_identity: IdentityKey::decode(
+                &structure
+                    .new_identity_key
+                    .ok_or(SignalProtocolError::InvalidProtobufEncoding)?,
+            )?,
+            signature: structure
+                .signature
+                .ok_or(SignalProtocolError::InvalidProtobufEncoding)?
+                .into_boxed_slice(),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -262,4 +386,48 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_identity_succession() -> Result<()> {
+        let old = IdentityKeyPair::generate(&mut OsRng);
+        let (new, succession) = old.rotate(&mut OsRng)?;
+        assert_eq!(succession.old_identity(), old.identity_key());
+        assert_eq!(succession.new_identity(), new.identity_key());
+        assert!(succession.verify()?);
+
+        let deserialized = IdentityKeySuccession::try_from(&succession.serialize()[..])?;
+        assert_eq!(deserialized, succession);
+        assert!(deserialized.verify()?);
+
+        // Signed by the wrong key, or for a different new key.
+        let unrelated = IdentityKeyPair::generate(&mut OsRng);
+        let forged = IdentityKeySuccession {
+            old_identity: *unrelated.identity_key(),
+            ..succession.clone()
+        };
+        assert!(!forged.verify()?);
+        let redirected = IdentityKeySuccession {
+            new_identity: *unrelated.identity_key(),
+            ..succession.clone()
+        };
+        assert!(!redirected.verify()?);
+
+        // Not interchangeable with an alternate identity signature.
+        let alternate_signature = old.sign_alternate_identity(new.identity_key(), &mut OsRng)?;
+        assert!(!IdentityKeySuccession {
+            signature: alternate_signature.clone(),
+            ..succession.clone()
+        }
+        .verify()?);
+        assert!(!old
+            .identity_key()
+            .verify_alternate_identity(new.identity_key(), succession.signature())?);
+
+        assert!(matches!(
+            IdentityKeySuccession::try_from(&succession.serialize()[1..]),
+            Err(_)
+        ));
+
+        Ok(())
+    }
 }
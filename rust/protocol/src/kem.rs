@@ -157,7 +157,7 @@ pub enum KeyType {
 }
 
 impl KeyType {
-    fn value(&self) -> u8 {
+    pub(crate) fn value(&self) -> u8 {
         match self {
             #[cfg(any(feature = "kyber768", test))]
             KeyType::Kyber768 => 0x07,
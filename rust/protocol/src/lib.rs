@@ -22,15 +22,19 @@
 // https://doc.rust-lang.org/rustdoc/what-to-include.html for background.
 // #![warn(missing_docs)]
 
-mod consts;
+pub mod attachment;
+mod certificate_authority;
+mod config;
 mod crypto;
 mod curve;
+mod decryption_error;
 pub mod error;
 mod fingerprint;
 mod group_cipher;
 mod identity_key;
 pub mod incremental_mac;
 pub mod kem;
+mod prekey_manager;
 mod proto;
 mod protocol;
 mod ratchet;
@@ -39,6 +43,7 @@ mod sender_keys;
 mod session;
 mod session_cipher;
 mod state;
+mod state_export;
 mod storage;
 mod timestamp;
 mod utils;
@@ -49,14 +54,28 @@ pub use libsignal_core::{
     Aci, DeviceId, Pni, ProtocolAddress, ServiceId, ServiceIdFixedWidthBinaryBytes, ServiceIdKind,
 };
 
-pub use curve::{KeyPair, PrivateKey, PublicKey};
+pub use certificate_authority::{
+    SealedSenderTrustConfig, SenderCertificateExpiryPolicy, SenderCertificateIssuer,
+};
+pub use config::ProtocolConfig;
+pub use curve::{KeyPair, PrivateKey, PublicKey, SignatureBatch};
+pub use decryption_error::{handle_decryption_error_message, DecryptionErrorAction};
 pub use error::SignalProtocolError;
-pub use fingerprint::{DisplayableFingerprint, Fingerprint, ScannableFingerprint};
+pub use fingerprint::{
+    DisplayableFingerprint, Fingerprint, FingerprintComparison, FingerprintTreeHead,
+    ScannableFingerprint,
+};
 pub use group_cipher::{
-    create_sender_key_distribution_message, group_decrypt, group_encrypt,
-    process_sender_key_distribution_message,
+    create_sender_key_distribution_message, create_sender_key_distribution_message_at,
+    group_decrypt, group_decrypt_with_config, group_encrypt, mark_sender_key_for_rotation,
+    process_sender_key_distribution_message, process_sender_key_distribution_message_with_config,
+    rotate_sender_key, rotate_sender_key_if_needed, SenderKeyRotationPolicy,
+};
+pub use identity_key::{IdentityKey, IdentityKeyPair, IdentityKeySuccession};
+pub use prekey_manager::{
+    KyberPreKeyUpload, PreKeyCounts, PreKeyMaintenance, PreKeyManager, PreKeyManagerConfig,
+    PreKeyManagerState, PreKeyUploadBatch, SignedPreKeyUpload,
 };
-pub use identity_key::{IdentityKey, IdentityKeyPair};
 pub use protocol::{
     extract_decryption_error_message_from_serialized_content, CiphertextMessage,
     CiphertextMessageType, DecryptionErrorMessage, KyberPayload, PlaintextContent,
@@ -67,23 +86,42 @@ pub use ratchet::{
     BobSignalProtocolParameters,
 };
 pub use sealed_sender::{
-    sealed_sender_decrypt, sealed_sender_decrypt_to_usmc, sealed_sender_encrypt,
-    sealed_sender_encrypt_from_usmc, sealed_sender_multi_recipient_encrypt, ContentHint,
-    SealedSenderDecryptionResult, SealedSenderV2SentMessage, SealedSenderV2SentMessageRecipient,
-    SenderCertificate, ServerCertificate, UnidentifiedSenderMessageContent,
+    sealed_sender_decrypt, sealed_sender_decrypt_to_usmc, sealed_sender_decrypt_with_trust_config,
+    sealed_sender_encrypt, sealed_sender_encrypt_from_usmc, sealed_sender_multi_recipient_encrypt,
+    sealed_sender_multi_recipient_encrypt_partial, ContentHint, SealedSenderDecryptionResult,
+    SealedSenderExcludedDestination, SealedSenderExclusionReason,
+    SealedSenderMultiRecipientMessage, SealedSenderV2SentMessage,
+    SealedSenderV2SentMessageRecipient, SenderCertificate, ServerCertificate,
+    UnidentifiedSenderMessageContent,
 };
 pub use sender_keys::SenderKeyRecord;
-pub use session::{process_prekey, process_prekey_bundle};
+pub use session::{
+    process_prekey, process_prekey_bundle, process_prekey_bundle_with_config,
+    process_prekey_with_config,
+};
 pub use session_cipher::{
-    message_decrypt, message_decrypt_prekey, message_decrypt_signal, message_encrypt,
+    message_decrypt, message_decrypt_prekey, message_decrypt_prekey_with_config,
+    message_decrypt_signal, message_decrypt_signal_with_config, message_decrypt_with_config,
+    message_encrypt, message_encrypt_with_config,
 };
 pub use state::{
-    GenericSignedPreKey, KyberPreKeyId, KyberPreKeyRecord, PreKeyBundle, PreKeyBundleContent,
-    PreKeyId, PreKeyRecord, SessionRecord, SignedPreKeyId, SignedPreKeyRecord,
+    ChainDescription, GenericSignedPreKey, KyberPreKeyId, KyberPreKeyRecord,
+    PendingPreKeyDescription, PreKeyBundle, PreKeyBundleContent, PreKeyId, PreKeyRecord,
+    SessionRecord, SessionRecordDescription, SessionStateDescription, SignedPreKeyId,
+    SignedPreKeyRecord,
+};
+pub use state_export::{
+    export_protocol_state, import_protocol_state, ProtocolStateImportSummary, STATE_EXPORT_KEY_SIZE,
+};
+pub use storage::{
+    Direction, IdentityChange, IdentityKeyStore, InMemIdentityKeyStore, InMemKyberPreKeyStore,
+    InMemPreKeyStore, InMemSenderKeyStore, InMemSessionStore, InMemSignalProtocolStore,
+    InMemSignedPreKeyStore, KyberPreKeyStore, PreKeyStore, ProtocolStore, SenderKeyStore,
+    SessionStore, SignedPreKeyStore, TransactionalProtocolStore,
 };
+#[cfg(feature = "fs-store")]
 pub use storage::{
-    Direction, IdentityKeyStore, InMemIdentityKeyStore, InMemKyberPreKeyStore, InMemPreKeyStore,
-    InMemSenderKeyStore, InMemSessionStore, InMemSignalProtocolStore, InMemSignedPreKeyStore,
-    KyberPreKeyStore, PreKeyStore, ProtocolStore, SenderKeyStore, SessionStore, SignedPreKeyStore,
+    FsIdentityKeyStore, FsKyberPreKeyStore, FsPreKeyStore, FsSenderKeyStore, FsSessionStore,
+    FsSignalProtocolStore, FsSignedPreKeyStore,
 };
 pub use timestamp::Timestamp;
@@ -0,0 +1,764 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Policy for generating, rotating, and retiring this client's pre-keys.
+//!
+//! Clients publish three kinds of pre-keys to the server:
+//!
+//! - one-time EC and Kyber pre-keys, which are each handed out at most once and must be
+//!   replenished as they are consumed;
+//! - a signed EC pre-key, used when the one-time EC pre-keys have run out;
+//! - a "last resort" signed Kyber pre-key, used when the one-time Kyber pre-keys have run out.
+//!
+//! The signed keys are rotated periodically, but the previous keys must remain available locally
+//! for a while, since peers may have fetched them just before the rotation. The same goes for
+//! one-time keys once the server has replaced them with a new batch. [PreKeyManager] implements
+//! this policy on top of the existing stores; the caller is responsible for uploading the resulting
+//! [PreKeyUploadBatch] and for persisting the manager's [PreKeyManagerState].
+
+use std::time::{Duration, SystemTime};
+
+use prost::Message;
+use rand::{CryptoRng, Rng};
+
+use crate::proto::storage::{pre_key_manager_state_structure, PreKeyManagerStateStructure};
+use crate::state::GenericSignedPreKey;
+use crate::{
+    kem, IdentityKeyPair, IdentityKeyStore, KeyPair, KyberPreKeyId, KyberPreKeyRecord,
+    KyberPreKeyStore, PreKeyId, PreKeyRecord, PreKeyStore, PublicKey, Result, SignalProtocolError,
+    SignedPreKeyId, SignedPreKeyRecord, SignedPreKeyStore, Timestamp,
+};
+
+/// The largest pre-key ID handed out by [PreKeyManager].
+///
+/// The service only accepts 24-bit pre-key IDs.
+const MAX_PRE_KEY_ID: u32 = 0xFF_FFFF;
+
+/// Tunable parameters for a [PreKeyManager].
+#[derive(Clone, Debug)]
+pub struct PreKeyManagerConfig {
+    /// How many one-time EC pre-keys to generate when replenishing them.
+    pub pre_key_batch_size: u32,
+    /// Replenish one-time EC pre-keys when fewer than this many remain on the server.
+    pub pre_key_low_water_mark: u32,
+    /// How many one-time Kyber pre-keys to generate when replenishing them.
+    pub kyber_pre_key_batch_size: u32,
+    /// Replenish one-time Kyber pre-keys when fewer than this many remain on the server.
+    pub kyber_pre_key_low_water_mark: u32,
+    /// How long a signed EC pre-key is used before it is replaced.
+    pub signed_pre_key_rotation_interval: Duration,
+    /// How long a last-resort Kyber pre-key is used before it is replaced.
+    pub last_resort_kyber_pre_key_rotation_interval: Duration,
+    /// How long a replaced key is kept for decrypting late messages.
+    pub retired_key_grace_period: Duration,
+    /// The KEM used for newly generated Kyber pre-keys.
+    pub kyber_key_type: kem::KeyType,
+}
+
+impl Default for PreKeyManagerConfig {
+    fn default() -> Self {
+        const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+        Self {
+            pre_key_batch_size: 100,
+            pre_key_low_water_mark: 10,
+            kyber_pre_key_batch_size: 100,
+            kyber_pre_key_low_water_mark: 10,
+            signed_pre_key_rotation_interval: DAY * 2,
+            last_resort_kyber_pre_key_rotation_interval: DAY * 2,
+            retired_key_grace_period: DAY * 30,
+            kyber_key_type: kem::KeyType::Kyber1024,
+        }
+    }
+}
+
+/// The number of unused one-time pre-keys the server reports it still has for this device.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PreKeyCounts {
+    /// Unused one-time EC pre-keys.
+    pub pre_keys: u32,
+    /// Unused one-time Kyber pre-keys, not counting the last-resort key.
+    pub kyber_pre_keys: u32,
+}
+
+/// The public half of a newly generated signed pre-key, ready to be uploaded.
+#[derive(Clone, Debug)]
+pub struct SignedPreKeyUpload {
+    /// The ID the key was saved under locally.
+    pub id: SignedPreKeyId,
+    /// The key to publish.
+    pub public_key: PublicKey,
+    /// The public key's signature by the identity key.
+    pub signature: Vec<u8>,
+}
+
+/// The public half of a newly generated Kyber pre-key, ready to be uploaded.
+#[derive(Clone)]
+pub struct KyberPreKeyUpload {
+    /// The ID the key was saved under locally.
+    pub id: KyberPreKeyId,
+    /// The key to publish.
+    pub public_key: kem::PublicKey,
+    /// The public key's signature by the identity key.
+    pub signature: Vec<u8>,
+}
+
+/// Newly generated public keys that should be uploaded to the server.
+///
+/// The corresponding private keys have already been saved to the local stores.
+#[derive(Clone, Default)]
+pub struct PreKeyUploadBatch {
+    /// A replacement signed pre-key, if it was due for rotation.
+    pub signed_pre_key: Option<SignedPreKeyUpload>,
+    /// A replacement last-resort Kyber pre-key, if it was due for rotation.
+    pub last_resort_kyber_pre_key: Option<KyberPreKeyUpload>,
+    /// New one-time EC pre-keys.
+    pub pre_keys: Vec<(PreKeyId, PublicKey)>,
+    /// New one-time Kyber pre-keys.
+    pub kyber_pre_keys: Vec<KyberPreKeyUpload>,
+}
+
+impl PreKeyUploadBatch {
+    /// Returns true if there is nothing to upload.
+    pub fn is_empty(&self) -> bool {
+        self.signed_pre_key.is_none()
+            && self.last_resort_kyber_pre_key.is_none()
+            && self.pre_keys.is_empty()
+            && self.kyber_pre_keys.is_empty()
+    }
+}
+
+/// The outcome of [PreKeyManager::refresh].
+#[derive(Clone, Default)]
+pub struct PreKeyMaintenance {
+    /// Keys to upload to the server.
+    pub upload: PreKeyUploadBatch,
+    /// Retired signed pre-keys whose grace period has ended, which should be deleted locally.
+    pub expired_signed_pre_keys: Vec<SignedPreKeyId>,
+}
+
+/// The persistent bookkeeping for a [PreKeyManager].
+///
+/// This should be saved after each successful upload of a [PreKeyUploadBatch]. If the upload
+/// fails, reload the previously saved state instead; the unpublished keys in the local stores will
+/// then be overwritten by the next attempt.
+#[derive(Clone, Debug)]
+pub struct PreKeyManagerState {
+    state: PreKeyManagerStateStructure,
+}
+
+impl PreKeyManagerState {
+    /// Creates state for a device with no pre-keys yet, starting from random IDs.
+    pub fn new<R: Rng + CryptoRng>(csprng: &mut R) -> Self {
+        Self {
+            state: PreKeyManagerStateStructure {
+                next_pre_key_id: csprng.gen_range(1..=MAX_PRE_KEY_ID),
+                next_signed_pre_key_id: csprng.gen_range(1..=MAX_PRE_KEY_ID),
+                next_kyber_pre_key_id: csprng.gen_range(1..=MAX_PRE_KEY_ID),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Loads state previously produced by [Self::serialize].
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        Ok(Self {
+            state: PreKeyManagerStateStructure::decode(data)
+                .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?,
+        })
+    }
+
+    /// Encodes the state for persistent storage.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        Ok(self.state.encode_to_vec())
+    }
+
+    /// The signed pre-key currently being published, if any.
+    pub fn signed_pre_key_id(&self) -> Option<SignedPreKeyId> {
+        self.state.signed_pre_key.as_ref().map(|key| key.id.into())
+    }
+
+    /// The last-resort Kyber pre-key currently being published, if any.
+    pub fn last_resort_kyber_pre_key_id(&self) -> Option<KyberPreKeyId> {
+        self.state
+            .last_resort_kyber_pre_key
+            .as_ref()
+            .map(|key| key.id.into())
+    }
+}
+
+/// Decides when to generate, rotate, and retire pre-keys.
+///
+/// One-time pre-keys are replenished whenever the server's supply falls below a low-water mark.
+/// The signed pre-key and last-resort Kyber pre-key are replaced on a schedule, with the old ones
+/// kept for a grace period since peers may have fetched them just before the rotation.
+#[derive(Clone, Debug)]
+pub struct PreKeyManager {
+    config: PreKeyManagerConfig,
+    state: PreKeyManagerState,
+}
+
+impl PreKeyManager {
+    /// Creates a manager that applies `config`, resuming from a previously persisted `state`.
+    pub fn new(config: PreKeyManagerConfig, state: PreKeyManagerState) -> Self {
+        Self { config, state }
+    }
+
+    /// The parameters this manager was created with.
+    pub fn config(&self) -> &PreKeyManagerConfig {
+        &self.config
+    }
+
+    /// The state to persist once the latest [PreKeyUploadBatch] has been uploaded.
+    pub fn state(&self) -> &PreKeyManagerState {
+        &self.state
+    }
+
+    /// Brings this device's pre-keys up to date.
+    ///
+    /// `counts` is the number of unused one-time pre-keys the server reports. New keys are saved
+    /// to the given stores and returned for upload. Retired one-time and Kyber pre-keys past their
+    /// grace period are deleted from the stores, which covers one-time keys that were consumed in
+    /// the meantime; retired signed pre-keys are returned for the caller to delete, since the store
+    /// traits do not support deletion of signed pre-keys.
+    pub async fn refresh<R: Rng + CryptoRng>(
+        &mut self,
+        identity_store: &dyn IdentityKeyStore,
+        pre_key_store: &mut dyn PreKeyStore,
+        signed_pre_key_store: &mut dyn SignedPreKeyStore,
+        kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+        counts: PreKeyCounts,
+        now: SystemTime,
+        csprng: &mut R,
+    ) -> Result<PreKeyMaintenance> {
+        let now = to_timestamp(now);
+        let identity_key_pair = identity_store.get_identity_key_pair().await?;
+        let signing_key = identity_key_pair.private_key();
+        let state = &mut self.state.state;
+        let mut result = PreKeyMaintenance::default();
+
+        if needs_rotation(
+            state.signed_pre_key.as_ref(),
+            self.config.signed_pre_key_rotation_interval,
+            now,
+        ) {
+            let id = allocate_id(&mut state.next_signed_pre_key_id);
+            let key_pair = KeyPair::generate(csprng);
+            let signature =
+                signing_key.calculate_signature(&key_pair.public_key.serialize(), csprng)?;
+            let record = SignedPreKeyRecord::new(id.into(), now, &key_pair, &signature);
+            signed_pre_key_store
+                .save_signed_pre_key(id.into(), &record)
+                .await?;
+            retire(
+                &mut state.retired_signed_pre_keys,
+                &mut state.signed_pre_key,
+                now,
+            );
+            state.signed_pre_key = Some(active_key(id, now));
+            result.upload.signed_pre_key = Some(SignedPreKeyUpload {
+                id: id.into(),
+                public_key: key_pair.public_key,
+                signature: signature.into_vec(),
+            });
+        }
+
+        if needs_rotation(
+            state.last_resort_kyber_pre_key.as_ref(),
+            self.config.last_resort_kyber_pre_key_rotation_interval,
+            now,
+        ) {
+            let id = allocate_id(&mut state.next_kyber_pre_key_id);
+            let upload = generate_kyber_pre_key(
+                id,
+                self.config.kyber_key_type,
+                kyber_pre_key_store,
+                &identity_key_pair,
+                now,
+                csprng,
+            )
+            .await?;
+            retire(
+                &mut state.retired_last_resort_kyber_pre_keys,
+                &mut state.last_resort_kyber_pre_key,
+                now,
+            );
+            state.last_resort_kyber_pre_key = Some(active_key(id, now));
+            result.upload.last_resort_kyber_pre_key = Some(upload);
+        }
+
+        if counts.pre_keys < self.config.pre_key_low_water_mark {
+            retire_all(&mut state.retired_pre_keys, &mut state.pre_keys, now);
+            for _ in 0..self.config.pre_key_batch_size {
+                let id = allocate_id(&mut state.next_pre_key_id).into();
+                let key_pair = KeyPair::generate(csprng);
+                pre_key_store
+                    .save_pre_key(id, &PreKeyRecord::new(id, &key_pair))
+                    .await?;
+                state.pre_keys.push(id.into());
+                result.upload.pre_keys.push((id, key_pair.public_key));
+            }
+        }
+
+        if counts.kyber_pre_keys < self.config.kyber_pre_key_low_water_mark {
+            retire_all(
+                &mut state.retired_kyber_pre_keys,
+                &mut state.kyber_pre_keys,
+                now,
+            );
+            for _ in 0..self.config.kyber_pre_key_batch_size {
+                let id = allocate_id(&mut state.next_kyber_pre_key_id);
+                let upload = generate_kyber_pre_key(
+                    id,
+                    self.config.kyber_key_type,
+                    kyber_pre_key_store,
+                    &identity_key_pair,
+                    now,
+                    csprng,
+                )
+                .await?;
+                state.kyber_pre_keys.push(id);
+                result.upload.kyber_pre_keys.push(upload);
+            }
+        }
+
+        let grace_period = self.config.retired_key_grace_period;
+        result.expired_signed_pre_keys =
+            remove_expired(&mut state.retired_signed_pre_keys, grace_period, now)
+                .map(SignedPreKeyId::from)
+                .collect();
+        for id in remove_expired(&mut state.retired_pre_keys, grace_period, now) {
+            pre_key_store.remove_pre_key(id.into()).await?;
+        }
+        let expired_kyber_pre_keys =
+            remove_expired(&mut state.retired_kyber_pre_keys, grace_period, now).chain(
+                remove_expired(
+                    &mut state.retired_last_resort_kyber_pre_keys,
+                    grace_period,
+                    now,
+                ),
+            );
+        for id in expired_kyber_pre_keys {
+            kyber_pre_key_store.remove_kyber_pre_key(id.into()).await?;
+        }
+
+        Ok(result)
+    }
+}
+
+async fn generate_kyber_pre_key<R: Rng + CryptoRng>(
+    id: u32,
+    key_type: kem::KeyType,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    identity_key_pair: &IdentityKeyPair,
+    now: Timestamp,
+    csprng: &mut R,
+) -> Result<KyberPreKeyUpload> {
+    let key_pair = kem::KeyPair::generate(key_type);
+    let signature = identity_key_pair
+        .private_key()
+        .calculate_signature(&key_pair.public_key.serialize(), csprng)?;
+    let record = KyberPreKeyRecord::new(id.into(), now, &key_pair, &signature);
+    kyber_pre_key_store
+        .save_kyber_pre_key(id.into(), &record)
+        .await?;
+    Ok(KyberPreKeyUpload {
+        id: id.into(),
+        public_key: key_pair.public_key,
+        signature: signature.into_vec(),
+    })
+}
+
+fn to_timestamp(time: SystemTime) -> Timestamp {
+    let millis = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    Timestamp::from_epoch_millis(millis.try_into().unwrap_or(u64::MAX))
+}
+
+fn has_elapsed(since: u64, period: Duration, now: Timestamp) -> bool {
+    let period_millis: u64 = period.as_millis().try_into().unwrap_or(u64::MAX);
+    now.epoch_millis().saturating_sub(since) >= period_millis
+}
+
+fn needs_rotation(
+    current: Option<&pre_key_manager_state_structure::ActiveKey>,
+    interval: Duration,
+    now: Timestamp,
+) -> bool {
+    match current {
+        None => true,
+        Some(key) => has_elapsed(key.created_at, interval, now),
+    }
+}
+
+/// Returns the next ID in sequence, wrapping around within the range accepted by the service.
+fn allocate_id(next_id: &mut u32) -> u32 {
+    let id = (*next_id).clamp(1, MAX_PRE_KEY_ID);
+    *next_id = if id == MAX_PRE_KEY_ID { 1 } else { id + 1 };
+    id
+}
+
+fn active_key(id: u32, now: Timestamp) -> pre_key_manager_state_structure::ActiveKey {
+    pre_key_manager_state_structure::ActiveKey {
+        id,
+        created_at: now.epoch_millis(),
+    }
+}
+
+fn retire(
+    retired: &mut Vec<pre_key_manager_state_structure::RetiredKey>,
+    current: &mut Option<pre_key_manager_state_structure::ActiveKey>,
+    now: Timestamp,
+) {
+    if let Some(key) = current.take() {
+        retired.push(pre_key_manager_state_structure::RetiredKey {
+            id: key.id,
+            retired_at: now.epoch_millis(),
+        });
+    }
+}
+
+fn retire_all(
+    retired: &mut Vec<pre_key_manager_state_structure::RetiredKey>,
+    current: &mut Vec<u32>,
+    now: Timestamp,
+) {
+    retired.extend(
+        current
+            .drain(..)
+            .map(|id| pre_key_manager_state_structure::RetiredKey {
+                id,
+                retired_at: now.epoch_millis(),
+            }),
+    );
+}
+
+fn remove_expired(
+    retired: &mut Vec<pre_key_manager_state_structure::RetiredKey>,
+    grace_period: Duration,
+    now: Timestamp,
+) -> impl Iterator<Item = u32> {
+    let (expired, kept) = std::mem::take(retired)
+        .into_iter()
+        .partition::<Vec<_>, _>(|key| has_elapsed(key.retired_at, grace_period, now));
+    *retired = kept;
+    expired.into_iter().map(|key| key.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use rand::rngs::OsRng;
+
+    use super::*;
+    use crate::InMemSignalProtocolStore;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn refresh(
+        manager: &mut PreKeyManager,
+        store: &mut InMemSignalProtocolStore,
+        counts: PreKeyCounts,
+        now: SystemTime,
+    ) -> PreKeyMaintenance {
+        manager
+            .refresh(
+                &store.identity_store,
+                &mut store.pre_key_store,
+                &mut store.signed_pre_key_store,
+                &mut store.kyber_pre_key_store,
+                counts,
+                now,
+                &mut OsRng,
+            )
+            .now_or_never()
+            .expect("sync")
+            .expect("success")
+    }
+
+    fn has_pre_key(store: &InMemSignalProtocolStore, id: PreKeyId) -> bool {
+        store.get_pre_key(id).now_or_never().expect("sync").is_ok()
+    }
+
+    fn has_kyber_pre_key(store: &InMemSignalProtocolStore, id: KyberPreKeyId) -> bool {
+        store
+            .get_kyber_pre_key(id)
+            .now_or_never()
+            .expect("sync")
+            .is_ok()
+    }
+
+    fn test_config() -> PreKeyManagerConfig {
+        PreKeyManagerConfig {
+            pre_key_batch_size: 5,
+            kyber_pre_key_batch_size: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn initial_refresh_generates_everything() {
+        let mut store =
+            InMemSignalProtocolStore::new(IdentityKeyPair::generate(&mut OsRng), 1).expect("valid");
+        let mut manager = PreKeyManager::new(test_config(), PreKeyManagerState::new(&mut OsRng));
+
+        let result = refresh(
+            &mut manager,
+            &mut store,
+            PreKeyCounts::default(),
+            SystemTime::now(),
+        );
+        let upload = result.upload;
+        assert_eq!(upload.pre_keys.len(), 5);
+        assert_eq!(upload.kyber_pre_keys.len(), 3);
+        assert!(result.expired_signed_pre_keys.is_empty());
+
+        let signed = upload.signed_pre_key.expect("generated");
+        assert_eq!(manager.state().signed_pre_key_id(), Some(signed.id));
+        let identity_key = *store
+            .get_identity_key_pair()
+            .now_or_never()
+            .expect("sync")
+            .expect("present")
+            .identity_key();
+        assert!(identity_key
+            .public_key()
+            .verify_signature(&signed.public_key.serialize(), &signed.signature)
+            .expect("valid"));
+
+        let last_resort = upload.last_resort_kyber_pre_key.expect("generated");
+        assert_eq!(
+            manager.state().last_resort_kyber_pre_key_id(),
+            Some(last_resort.id)
+        );
+
+        // Everything uploaded has been saved locally.
+        for (id, public_key) in &upload.pre_keys {
+            let record = store
+                .get_pre_key(*id)
+                .now_or_never()
+                .expect("sync")
+                .expect("saved");
+            assert_eq!(&record.public_key().expect("valid"), public_key);
+        }
+        for kyber in upload.kyber_pre_keys.iter().chain([&last_resort]) {
+            assert!(store
+                .get_kyber_pre_key(kyber.id)
+                .now_or_never()
+                .expect("sync")
+                .is_ok());
+        }
+        assert!(store
+            .get_signed_pre_key(signed.id)
+            .now_or_never()
+            .expect("sync")
+            .is_ok());
+    }
+
+    #[test]
+    fn one_time_keys_replenished_below_low_water_mark() {
+        let mut store =
+            InMemSignalProtocolStore::new(IdentityKeyPair::generate(&mut OsRng), 1).expect("valid");
+        let config = test_config();
+        let mut manager = PreKeyManager::new(config.clone(), PreKeyManagerState::new(&mut OsRng));
+        let now = SystemTime::now();
+        let first = refresh(&mut manager, &mut store, PreKeyCounts::default(), now);
+
+        let plenty = PreKeyCounts {
+            pre_keys: config.pre_key_low_water_mark,
+            kyber_pre_keys: config.kyber_pre_key_low_water_mark,
+        };
+        assert!(refresh(&mut manager, &mut store, plenty, now)
+            .upload
+            .is_empty());
+
+        let low_ec = PreKeyCounts {
+            pre_keys: config.pre_key_low_water_mark - 1,
+            ..plenty
+        };
+        let second = refresh(&mut manager, &mut store, low_ec, now).upload;
+        assert_eq!(second.pre_keys.len(), 5);
+        assert!(second.kyber_pre_keys.is_empty());
+        assert!(second.signed_pre_key.is_none());
+
+        // IDs continue on from the previous batch.
+        let first_ids = first.upload.pre_keys.iter().map(|(id, _)| u32::from(*id));
+        let second_ids = second.pre_keys.iter().map(|(id, _)| u32::from(*id));
+        let all_ids: Vec<u32> = first_ids.chain(second_ids).collect();
+        for pair in all_ids.windows(2) {
+            assert_eq!(
+                pair[1],
+                if pair[0] == MAX_PRE_KEY_ID {
+                    1
+                } else {
+                    pair[0] + 1
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn signed_keys_rotate_and_expire() {
+        let mut store =
+            InMemSignalProtocolStore::new(IdentityKeyPair::generate(&mut OsRng), 1).expect("valid");
+        let config = PreKeyManagerConfig {
+            signed_pre_key_rotation_interval: 48 * HOUR,
+            last_resort_kyber_pre_key_rotation_interval: 72 * HOUR,
+            retired_key_grace_period: 12 * HOUR,
+            ..test_config()
+        };
+        let plenty = PreKeyCounts {
+            pre_keys: config.pre_key_low_water_mark,
+            kyber_pre_keys: config.kyber_pre_key_low_water_mark,
+        };
+        let mut manager = PreKeyManager::new(config, PreKeyManagerState::new(&mut OsRng));
+        let start = SystemTime::now();
+
+        let initial = refresh(&mut manager, &mut store, plenty, start).upload;
+        let first_signed = initial.signed_pre_key.expect("generated").id;
+        let first_kyber = initial.last_resort_kyber_pre_key.expect("generated").id;
+
+        let not_yet = refresh(&mut manager, &mut store, plenty, start + 47 * HOUR);
+        assert!(not_yet.upload.is_empty());
+
+        let rotated = refresh(&mut manager, &mut store, plenty, start + 48 * HOUR);
+        let second_signed = rotated.upload.signed_pre_key.expect("rotated").id;
+        assert_ne!(second_signed, first_signed);
+        assert!(rotated.upload.last_resort_kyber_pre_key.is_none());
+        assert!(rotated.expired_signed_pre_keys.is_empty());
+
+        // The old signed pre-key is kept until the grace period ends.
+        let within_grace = refresh(&mut manager, &mut store, plenty, start + 59 * HOUR);
+        assert!(within_grace.expired_signed_pre_keys.is_empty());
+
+        // Round-tripping the state preserves the retired keys.
+        let state = PreKeyManagerState::deserialize(&manager.state().serialize().expect("valid"))
+            .expect("valid");
+        let mut manager = PreKeyManager::new(manager.config().clone(), state);
+
+        let expired = refresh(&mut manager, &mut store, plenty, start + 60 * HOUR);
+        assert!(expired.upload.is_empty());
+        assert_eq!(expired.expired_signed_pre_keys, vec![first_signed]);
+
+        let rotated = refresh(&mut manager, &mut store, plenty, start + 72 * HOUR);
+        assert!(rotated.upload.signed_pre_key.is_none());
+        let second_kyber = rotated
+            .upload
+            .last_resort_kyber_pre_key
+            .expect("rotated")
+            .id;
+        assert_ne!(second_kyber, first_kyber);
+
+        let within_grace = refresh(&mut manager, &mut store, plenty, start + 83 * HOUR);
+        assert!(within_grace.upload.is_empty());
+        assert!(has_kyber_pre_key(&store, first_kyber));
+
+        // Expired last-resort keys are deleted by the manager itself.
+        let expired = refresh(&mut manager, &mut store, plenty, start + 84 * HOUR);
+        assert!(expired.upload.is_empty());
+        assert!(expired.expired_signed_pre_keys.is_empty());
+        assert!(!has_kyber_pre_key(&store, first_kyber));
+        assert!(has_kyber_pre_key(&store, second_kyber));
+
+        assert_eq!(manager.state().signed_pre_key_id(), Some(second_signed));
+        assert_eq!(
+            manager.state().last_resort_kyber_pre_key_id(),
+            Some(second_kyber)
+        );
+    }
+
+    #[test]
+    fn replaced_one_time_keys_are_deleted() {
+        let mut store =
+            InMemSignalProtocolStore::new(IdentityKeyPair::generate(&mut OsRng), 1).expect("valid");
+        let config = PreKeyManagerConfig {
+            retired_key_grace_period: 12 * HOUR,
+            ..test_config()
+        };
+        let plenty = PreKeyCounts {
+            pre_keys: config.pre_key_low_water_mark,
+            kyber_pre_keys: config.kyber_pre_key_low_water_mark,
+        };
+        let mut manager = PreKeyManager::new(config, PreKeyManagerState::new(&mut OsRng));
+        let start = SystemTime::now();
+
+        let first = refresh(&mut manager, &mut store, PreKeyCounts::default(), start).upload;
+        let first_pre_keys: Vec<PreKeyId> = first.pre_keys.iter().map(|(id, _)| *id).collect();
+        let first_kyber_pre_keys: Vec<KyberPreKeyId> =
+            first.kyber_pre_keys.iter().map(|key| key.id).collect();
+
+        // Consume a key of each kind, the way decrypting a PreKeySignalMessage would.
+        store
+            .remove_pre_key(first_pre_keys[0])
+            .now_or_never()
+            .expect("sync")
+            .expect("success");
+        store
+            .mark_kyber_pre_key_used(first_kyber_pre_keys[0])
+            .now_or_never()
+            .expect("sync")
+            .expect("success");
+
+        // Uploading a new batch replaces the old one on the server, but the old keys are kept for
+        // the grace period.
+        let second = refresh(
+            &mut manager,
+            &mut store,
+            PreKeyCounts::default(),
+            start + HOUR,
+        );
+        assert_eq!(second.upload.pre_keys.len(), 5);
+        assert_eq!(second.upload.kyber_pre_keys.len(), 3);
+        let within_grace = refresh(&mut manager, &mut store, plenty, start + 12 * HOUR);
+        assert!(within_grace.upload.is_empty());
+        assert!(first_pre_keys[1..]
+            .iter()
+            .all(|id| has_pre_key(&store, *id)));
+        assert!(first_kyber_pre_keys
+            .iter()
+            .all(|id| has_kyber_pre_key(&store, *id)));
+
+        // Round-tripping the state preserves the retired keys.
+        let state = PreKeyManagerState::deserialize(&manager.state().serialize().expect("valid"))
+            .expect("valid");
+        let mut manager = PreKeyManager::new(manager.config().clone(), state);
+
+        refresh(&mut manager, &mut store, plenty, start + 13 * HOUR);
+        assert!(!first_pre_keys.iter().any(|id| has_pre_key(&store, *id)));
+        assert!(!first_kyber_pre_keys
+            .iter()
+            .any(|id| has_kyber_pre_key(&store, *id)));
+
+        // The current batch and the last-resort key are untouched.
+        assert!(second
+            .upload
+            .pre_keys
+            .iter()
+            .all(|(id, _)| has_pre_key(&store, *id)));
+        assert!(second
+            .upload
+            .kyber_pre_keys
+            .iter()
+            .all(|key| has_kyber_pre_key(&store, key.id)));
+        let last_resort = manager
+            .state()
+            .last_resort_kyber_pre_key_id()
+            .expect("present");
+        assert!(has_kyber_pre_key(&store, last_resort));
+    }
+
+    #[test]
+    fn ids_wrap_around() {
+        let mut next = MAX_PRE_KEY_ID;
+        assert_eq!(allocate_id(&mut next), MAX_PRE_KEY_ID);
+        assert_eq!(allocate_id(&mut next), 1);
+        assert_eq!(allocate_id(&mut next), 2);
+
+        // A zeroed (default) state still starts from a valid ID.
+        let mut next = 0;
+        assert_eq!(allocate_id(&mut next), 1);
+    }
+}
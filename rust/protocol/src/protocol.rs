@@ -65,6 +65,7 @@ pub struct SignalMessage {
     #[allow(dead_code)]
     previous_counter: u32,
     ciphertext: Box<[u8]>,
+    pq_ratchet: Option<proto::wire::PqRatchetMessage>,
     serialized: Box<[u8]>,
 }
 
@@ -80,12 +81,38 @@ impl SignalMessage {
         ciphertext: &[u8],
         sender_identity_key: &IdentityKey,
         receiver_identity_key: &IdentityKey,
+    ) -> Result<Self> {
+        Self::new_with_pq_ratchet(
+            message_version,
+            mac_key,
+            sender_ratchet_key,
+            counter,
+            previous_counter,
+            ciphertext,
+            None,
+            sender_identity_key,
+            receiver_identity_key,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new_with_pq_ratchet(
+        message_version: u8,
+        mac_key: &[u8],
+        sender_ratchet_key: PublicKey,
+        counter: u32,
+        previous_counter: u32,
+        ciphertext: &[u8],
+        pq_ratchet: Option<proto::wire::PqRatchetMessage>,
+        sender_identity_key: &IdentityKey,
+        receiver_identity_key: &IdentityKey,
     ) -> Result<Self> {
         let message = proto::wire::SignalMessage {
             ratchet_key: Some(sender_ratchet_key.serialize().into_vec()),
             counter: Some(counter),
             previous_counter: Some(previous_counter),
             ciphertext: Some(Vec::<u8>::from(ciphertext)),
+            pq_ratchet: pq_ratchet.clone(),
         };
         let mut serialized = Vec::with_capacity(1 + message.encoded_len() + Self::MAC_LENGTH);
         serialized.push(((message_version & 0xF) << 4) | CIPHERTEXT_MESSAGE_CURRENT_VERSION);
@@ -106,6 +133,7 @@ impl SignalMessage {
             counter,
             previous_counter,
             ciphertext: ciphertext.into(),
+            pq_ratchet,
             serialized,
         })
     }
@@ -135,6 +163,11 @@ impl SignalMessage {
         &self.ciphertext
     }
 
+    #[inline]
+    pub(crate) fn pq_ratchet(&self) -> Option<&proto::wire::PqRatchetMessage> {
+        self.pq_ratchet.as_ref()
+    }
+
     pub fn verify_mac(
         &self,
         sender_identity_key: &IdentityKey,
@@ -229,6 +262,7 @@ impl TryFrom<&[u8]> for SignalMessage {
             counter,
             previous_counter,
             ciphertext,
+            pq_ratchet: proto_structure.pq_ratchet,
             serialized: Box::from(value),
         })
     }
@@ -5,6 +5,7 @@
 
 mod keys;
 mod params;
+pub(crate) mod pq;
 
 pub(crate) use self::keys::{ChainKey, MessageKeys, RootKey};
 pub use self::params::{AliceSignalProtocolParameters, BobSignalProtocolParameters};
@@ -104,6 +105,11 @@ pub(crate) fn initialize_alice_session<R: Rng + CryptoRng>(
     if let Some(kyber_ciphertext) = kyber_ciphertext {
         session.set_kyber_ciphertext(kyber_ciphertext);
     }
+    if let Some(kyber_public) = parameters.their_kyber_pre_key() {
+        session.set_kem_key_type(kyber_public.key_type());
+    }
+
+    session.set_ephemeral(parameters.ephemeral());
 
     Ok(session)
 }
@@ -163,7 +169,7 @@ pub(crate) fn initialize_bob_session(
 
     let (root_key, chain_key) = derive_keys(has_kyber, &secrets);
 
-    let session = SessionState::new(
+    let mut session = SessionState::new(
         message_version(has_kyber),
         local_identity,
         parameters.their_identity_key(),
@@ -172,6 +178,12 @@ pub(crate) fn initialize_bob_session(
     )
     .with_sender_chain(parameters.our_ratchet_key_pair(), &chain_key);
 
+    if let Some(key_pair) = parameters.our_kyber_pre_key_pair() {
+        session.set_kem_key_type(key_pair.public_key.key_type());
+    }
+
+    session.set_ephemeral(parameters.ephemeral());
+
     Ok(session)
 }
 
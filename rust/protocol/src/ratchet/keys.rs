@@ -138,6 +138,14 @@ impl RootKey {
             },
         ))
     }
+
+    pub(crate) fn mix_post_quantum_secret(self, secret: &[u8]) -> RootKey {
+        let mut key = [0; 32];
+        hkdf::Hkdf::<sha2::Sha256>::new(Some(&self.key), secret)
+            .expand(b"WhisperPQRatchet", &mut key)
+            .expect("valid output length");
+        RootKey { key }
+    }
 }
 
 impl fmt::Display for RootKey {
@@ -14,6 +14,8 @@ pub struct AliceSignalProtocolParameters {
     their_one_time_pre_key: Option<PublicKey>,
     their_ratchet_key: PublicKey,
     their_kyber_pre_key: Option<kem::PublicKey>,
+
+    ephemeral: bool,
 }
 
 impl AliceSignalProtocolParameters {
@@ -32,6 +34,7 @@ impl AliceSignalProtocolParameters {
             their_one_time_pre_key: None,
             their_ratchet_key,
             their_kyber_pre_key: None,
+            ephemeral: false,
         }
     }
 
@@ -53,6 +56,23 @@ impl AliceSignalProtocolParameters {
         self
     }
 
+    /// Makes the new session ephemeral; see [`SessionRecord::is_ephemeral`].
+    ///
+    /// [`SessionRecord::is_ephemeral`]: crate::SessionRecord::is_ephemeral
+    pub fn set_ephemeral(&mut self, ephemeral: bool) {
+        self.ephemeral = ephemeral;
+    }
+
+    pub fn with_ephemeral(mut self, ephemeral: bool) -> Self {
+        self.set_ephemeral(ephemeral);
+        self
+    }
+
+    #[inline]
+    pub fn ephemeral(&self) -> bool {
+        self.ephemeral
+    }
+
     #[inline]
     pub fn our_identity_key_pair(&self) -> &IdentityKeyPair {
         &self.our_identity_key_pair
@@ -100,6 +120,8 @@ pub struct BobSignalProtocolParameters<'a> {
     their_identity_key: IdentityKey,
     their_base_key: PublicKey,
     their_kyber_ciphertext: Option<&'a kem::SerializedCiphertext>,
+
+    ephemeral: bool,
 }
 
 impl<'a> BobSignalProtocolParameters<'a> {
@@ -122,9 +144,27 @@ impl<'a> BobSignalProtocolParameters<'a> {
             their_identity_key,
             their_base_key,
             their_kyber_ciphertext,
+            ephemeral: false,
         }
     }
 
+    /// Makes the new session ephemeral; see [`SessionRecord::is_ephemeral`].
+    ///
+    /// [`SessionRecord::is_ephemeral`]: crate::SessionRecord::is_ephemeral
+    pub fn set_ephemeral(&mut self, ephemeral: bool) {
+        self.ephemeral = ephemeral;
+    }
+
+    pub fn with_ephemeral(mut self, ephemeral: bool) -> Self {
+        self.set_ephemeral(ephemeral);
+        self
+    }
+
+    #[inline]
+    pub fn ephemeral(&self) -> bool {
+        self.ephemeral
+    }
+
     #[inline]
     pub fn our_identity_key_pair(&self) -> &IdentityKeyPair {
         &self.our_identity_key_pair
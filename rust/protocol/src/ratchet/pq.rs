@@ -0,0 +1,293 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! The post-quantum ratchet, which periodically mixes a KEM shared secret into a session's root
+//! key so that the session regains post-quantum security after a compromise.
+//!
+//! The ratchet proceeds in epochs. In each epoch one party, the holder, generates a KEM key pair
+//! and sends the public key to the other party a chunk at a time, one chunk per message, so that
+//! no single message has to carry a whole key. The other party encapsulates a shared secret to
+//! the key once it has every chunk and sends the ciphertext back the same way. When the holder has
+//! decapsulated the secret, it mixes it into the root key the next time it starts a sending
+//! chain, and marks every message on that chain with the epoch. The other party mixes in the same
+//! secret when it creates the matching receiving chain, and becomes the holder for the next epoch.
+//!
+//! Every message of a session using the ratchet carries a [`PqRatchetMessage`], even when there is
+//! no chunk to send. A session stops using the ratchet as soon as it receives a message without
+//! one, which is how peers that don't support it are detected.
+//!
+//! # Protocol
+//!
+//! Each message carries three things: the sender's current `epoch`, the `mixed_epoch` of the
+//! sending chain it was encrypted on (0 if no secret was mixed in when that chain was created),
+//! and optionally one chunk of a public key or ciphertext, with its index and the total count.
+//!
+//! - The session's initiator starts as the holder of epoch 1. The responder becomes the
+//!   encapsulator of epoch 1 when it first sees a public key chunk.
+//! - The holder of epoch `e` sends public key chunks until it has received the whole ciphertext.
+//!   The encapsulator of epoch `e` collects public key chunks, encapsulates once it has them all,
+//!   and then sends ciphertext chunks. Chunks are resent round-robin, so dropped messages only
+//!   delay an epoch. Chunks for any other epoch are ignored, so late messages are harmless.
+//! - Once the holder has decapsulated, the next sending chain it creates mixes the secret into the
+//!   root key and is marked with `mixed_epoch = e`. The holder then becomes the encapsulator of
+//!   epoch `e + 1`. Every later chain is unmarked until the next secret is ready.
+//! - When the encapsulator creates the receiving chain for a message marked with `e`, it mixes in
+//!   its own copy of the secret and becomes the holder of epoch `e + 1`.
+//!
+//! Because a new sending chain is only created after receiving the other party's newest chain,
+//! both parties create their chains in the same order even when individual messages are dropped
+//! or reordered, including across epoch boundaries: a message on an older chain uses a receiving
+//! chain that already exists, and never consumes a secret.
+//!
+//! A marked chain for which we have no secret (no ratchet state, or a different epoch) therefore
+//! means our state has diverged from the other party's, e.g. after restoring an old session from a
+//! backup. Rather than rejecting the message up front, the chain is derived without a secret and
+//! the ratchet is turned off for the session. If the other party did mix in a secret, the
+//! message's MAC check fails and nothing is saved, the same as any other message from a diverged
+//! session. The usual recovery then applies: the app reports the decryption failure and a new
+//! session is started.
+//!
+//! The KEM key pairs and encapsulations use the system randomness of the KEM implementation, like
+//! [`kem::KeyPair::generate`]; they cannot be driven by a caller-provided RNG.
+
+use crate::proto::storage::PqRatchetStructure;
+use crate::proto::wire::PqRatchetMessage;
+use crate::{kem, Result};
+
+pub(crate) const PQ_RATCHET_VERSION: u32 = 1;
+
+/// The largest piece of a public key or ciphertext sent in a single message.
+const CHUNK_SIZE: usize = 256;
+/// Chunks claiming to be part of a larger key or ciphertext are ignored.
+const MAX_CHUNK_COUNT: u32 = 64;
+
+/// Starts a new epoch in which we are the holder.
+pub(crate) fn new_holder(epoch: u32, key_type: kem::KeyType) -> PqRatchetStructure {
+    let key_pair = kem::KeyPair::generate(key_type);
+    PqRatchetStructure {
+        version: PQ_RATCHET_VERSION,
+        epoch,
+        kem_key_type: key_type.value().into(),
+        kem_public: key_pair.public_key.serialize().into_vec(),
+        kem_private: key_pair.secret_key.serialize().into_vec(),
+        ..Default::default()
+    }
+}
+
+/// Starts a new epoch in which the other party is the holder.
+fn new_encapsulator(epoch: u32, kem_key_type: u32) -> PqRatchetStructure {
+    PqRatchetStructure {
+        version: PQ_RATCHET_VERSION,
+        epoch,
+        kem_key_type,
+        ..Default::default()
+    }
+}
+
+fn is_holder(state: &PqRatchetStructure) -> bool {
+    !state.kem_private.is_empty()
+}
+
+/// The number of epochs whose secrets have been mixed into the root key.
+pub(crate) fn completed_epochs(state: &PqRatchetStructure) -> u32 {
+    state.epoch.saturating_sub(1)
+}
+
+/// Produces the post-quantum ratchet part of an outgoing message.
+///
+/// Each call sends the next chunk of whatever we are currently sending, cycling back to the start
+/// so that chunks lost in transit are eventually resent.
+pub(crate) fn outgoing_message(state: &mut PqRatchetStructure) -> PqRatchetMessage {
+    let mut message = PqRatchetMessage {
+        version: Some(state.version),
+        mixed_epoch: Some(state.sending_chain_epoch),
+        epoch: Some(state.epoch),
+        ..Default::default()
+    };
+
+    let (data, is_public_key) = if is_holder(state) && state.pending_secret.is_empty() {
+        (&state.kem_public, true)
+    } else if !state.ciphertext.is_empty() {
+        (&state.ciphertext, false)
+    } else {
+        return message;
+    };
+
+    let chunk_count = data.len().div_ceil(CHUNK_SIZE);
+    let index = state.next_chunk as usize % chunk_count;
+    let chunk = data
+        .chunks(CHUNK_SIZE)
+        .nth(index)
+        .expect("in range")
+        .to_vec();
+    if is_public_key {
+        message.public_key_chunk = Some(chunk);
+    } else {
+        message.ciphertext_chunk = Some(chunk);
+    }
+    message.chunk_index = Some(index as u32);
+    message.chunk_count = Some(chunk_count as u32);
+
+    state.next_chunk = ((index + 1) % chunk_count) as u32;
+    message
+}
+
+/// Updates the ratchet with the post-quantum ratchet part of an authenticated incoming message.
+///
+/// Returns the new state, or `None` if the session should not (or no longer) use the ratchet.
+/// `enabled` controls whether a session not yet using the ratchet starts to when the other party
+/// offers it.
+pub(crate) fn process_incoming(
+    state: Option<PqRatchetStructure>,
+    message: Option<&PqRatchetMessage>,
+    enabled: bool,
+) -> Option<PqRatchetStructure> {
+    let message = match message {
+        Some(message) if message.version == Some(PQ_RATCHET_VERSION) => message,
+        _ => {
+            if state.is_some() {
+                log::info!("other party does not support the post-quantum ratchet; turning it off");
+            }
+            return None;
+        }
+    };
+
+    let mut state = match state {
+        Some(state) => state,
+        None if enabled && message.public_key_chunk.is_some() => {
+            new_encapsulator(message.epoch(), 0)
+        }
+        None => return None,
+    };
+
+    if message.epoch() != state.epoch {
+        // A late message from an earlier epoch.
+        return Some(state);
+    }
+
+    let result = if is_holder(&state) {
+        match &message.ciphertext_chunk {
+            Some(chunk) if state.pending_secret.is_empty() => {
+                receive_ciphertext_chunk(&mut state, message, chunk)
+            }
+            _ => Ok(()),
+        }
+    } else {
+        match &message.public_key_chunk {
+            Some(chunk) if state.ciphertext.is_empty() => {
+                receive_public_key_chunk(&mut state, message, chunk)
+            }
+            _ => Ok(()),
+        }
+    };
+
+    match result {
+        Ok(()) => Some(state),
+        Err(e) => {
+            log::warn!(
+                "invalid post-quantum ratchet data in epoch {}; turning it off: {}",
+                state.epoch,
+                e
+            );
+            None
+        }
+    }
+}
+
+fn receive_public_key_chunk(
+    state: &mut PqRatchetStructure,
+    message: &PqRatchetMessage,
+    chunk: &[u8],
+) -> Result<()> {
+    let Some(public_key) = add_chunk(state, message, chunk) else {
+        return Ok(());
+    };
+    let public_key = kem::PublicKey::deserialize(&public_key)?;
+    let (secret, ciphertext) = public_key.encapsulate();
+    state.kem_key_type = public_key.key_type().value().into();
+    state.pending_secret = secret.into_vec();
+    state.ciphertext = ciphertext.into_vec();
+    state.next_chunk = 0;
+    Ok(())
+}
+
+fn receive_ciphertext_chunk(
+    state: &mut PqRatchetStructure,
+    message: &PqRatchetMessage,
+    chunk: &[u8],
+) -> Result<()> {
+    let Some(ciphertext) = add_chunk(state, message, chunk) else {
+        return Ok(());
+    };
+    let secret_key = kem::SecretKey::deserialize(&state.kem_private)?;
+    state.pending_secret = secret_key
+        .decapsulate(&ciphertext.into_boxed_slice())?
+        .into_vec();
+    Ok(())
+}
+
+/// Records a chunk, returning the reassembled data once every chunk has arrived.
+fn add_chunk(
+    state: &mut PqRatchetStructure,
+    message: &PqRatchetMessage,
+    chunk: &[u8],
+) -> Option<Vec<u8>> {
+    let index = message.chunk_index();
+    let count = message.chunk_count();
+    if count == 0 || count > MAX_CHUNK_COUNT || index >= count || chunk.len() > CHUNK_SIZE {
+        return None;
+    }
+
+    if state.received_chunks.len() != count as usize {
+        state.received_chunks = vec![vec![]; count as usize];
+    }
+    state.received_chunks[index as usize] = chunk.to_vec();
+
+    if state.received_chunks.iter().any(Vec::is_empty) {
+        return None;
+    }
+    Some(std::mem::take(&mut state.received_chunks).concat())
+}
+
+/// Takes the secret to mix into the root key for a new sending chain, if there is one.
+///
+/// Once the secret is taken, the other party becomes the holder for the next epoch.
+pub(crate) fn take_secret_for_sending_chain(state: &mut PqRatchetStructure) -> Option<Vec<u8>> {
+    if !is_holder(state) || state.pending_secret.is_empty() {
+        state.sending_chain_epoch = 0;
+        return None;
+    }
+    let secret = std::mem::take(&mut state.pending_secret);
+    let epoch = state.epoch;
+    *state = new_encapsulator(epoch + 1, state.kem_key_type);
+    state.sending_chain_epoch = epoch;
+    Some(secret)
+}
+
+/// Takes the secret the other party mixed into the root key when it created the chain of a
+/// message marked with `mixed_epoch`.
+///
+/// Once the secret is taken, we become the holder for the next epoch. Returns an error if we
+/// don't have the secret, which means the session has diverged (see the module docs).
+pub(crate) fn take_secret_for_receiving_chain(
+    state: Option<&mut PqRatchetStructure>,
+    mixed_epoch: u32,
+) -> std::result::Result<Option<Vec<u8>>, &'static str> {
+    if mixed_epoch == 0 {
+        return Ok(None);
+    }
+    let state = state.ok_or("post-quantum ratchet is not in use")?;
+    if is_holder(state) || state.epoch != mixed_epoch || state.pending_secret.is_empty() {
+        return Err("unexpected post-quantum ratchet epoch");
+    }
+    let key_type = u8::try_from(state.kem_key_type)
+        .ok()
+        .and_then(|key_type| kem::KeyType::try_from(key_type).ok())
+        .ok_or("unsupported post-quantum ratchet KEM")?;
+
+    let secret = std::mem::take(&mut state.pending_secret);
+    *state = new_holder(mixed_epoch + 1, key_type);
+    Ok(Some(secret))
+}
@@ -6,8 +6,9 @@
 use crate::{
     message_encrypt, Aci, CiphertextMessageType, DeviceId, Direction, IdentityKey, IdentityKeyPair,
     IdentityKeyStore, KeyPair, KyberPreKeyStore, PreKeySignalMessage, PreKeyStore, PrivateKey,
-    ProtocolAddress, PublicKey, Result, ServiceId, ServiceIdFixedWidthBinaryBytes, SessionRecord,
-    SessionStore, SignalMessage, SignalProtocolError, SignedPreKeyStore, Timestamp,
+    ProtocolAddress, PublicKey, Result, SealedSenderTrustConfig, ServiceId,
+    ServiceIdFixedWidthBinaryBytes, SessionRecord, SessionStore, SignalMessage,
+    SignalProtocolError, SignatureBatch, SignedPreKeyStore, Timestamp,
 };
 
 use crate::{crypto, curve, proto, session_cipher};
@@ -23,6 +24,7 @@ use subtle::ConstantTimeEq;
 
 use proto::sealed_sender::unidentified_sender_message::message::Type as ProtoMessageType;
 
+use std::collections::HashMap;
 use std::ops::Range;
 use std::time::SystemTime;
 
@@ -43,7 +45,7 @@ been revoked. If one ever does, add its key ID here.
 If a production server certificate is ever generated which collides
 with this test certificate ID, Bad Things will happen.
 */
-const REVOKED_SERVER_CERTIFICATE_KEY_IDS: &[u32] = &[0xDEADC357];
+pub(crate) const REVOKED_SERVER_CERTIFICATE_KEY_IDS: &[u32] = &[0xDEADC357];
 
 // Valid registration IDs fit in 14 bits.
 // TODO: move this into a RegistrationId strong type.
@@ -126,14 +128,129 @@ impl ServerCertificate {
     }
 
     pub fn validate(&self, trust_root: &PublicKey) -> Result<bool> {
-        if REVOKED_SERVER_CERTIFICATE_KEY_IDS.contains(&self.key_id()?) {
+        if self.is_revoked() {
+            return Ok(false);
+        }
+        trust_root.verify_signature(&self.certificate, &self.signature)
+    }
+
+    /// Like [`Self::validate`], but accepts a signature from any of `trust_config`'s trust roots
+    /// and uses its set of revoked key IDs.
+    pub fn validate_with_trust_config(
+        &self,
+        trust_config: &SealedSenderTrustConfig,
+    ) -> Result<bool> {
+        if trust_config.is_server_key_id_revoked(self.key_id) {
             log::error!(
                 "received server certificate with revoked ID {:x}",
-                self.key_id()?
+                self.key_id
             );
             return Ok(false);
         }
-        trust_root.verify_signature(&self.certificate, &self.signature)
+        for trust_root in trust_config.trust_roots() {
+            if trust_root.verify_signature(&self.certificate, &self.signature)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Validates each of `certificates` as if by [`Self::validate`], but checks their signatures
+    /// as a single [`SignatureBatch`].
+    pub fn validate_batch<R: Rng + CryptoRng>(
+        certificates: &[ServerCertificate],
+        trust_root: &PublicKey,
+        csprng: &mut R,
+    ) -> Result<Vec<bool>> {
+        let mut batch = SignatureBatch::with_capacity(certificates.len());
+        let signature_indexes = certificates
+            .iter()
+            .map(|cert| {
+                (!cert.is_revoked())
+                    .then(|| batch.push(*trust_root, &cert.certificate, &cert.signature))
+            })
+            .collect_vec();
+        let signatures_valid = batch.verify(csprng)?;
+
+        Ok(signature_indexes
+            .into_iter()
+            .map(|index| index.is_some_and(|i| signatures_valid[i]))
+            .collect())
+    }
+
+    /// Validates each of `certificates` as if by [`Self::validate_with_trust_config`], but checks
+    /// their signatures as one [`SignatureBatch`] per trust root.
+    ///
+    /// Each batch only includes the certificates that weren't signed by an earlier trust root.
+    pub fn validate_batch_with_trust_config<R: Rng + CryptoRng>(
+        certificates: &[ServerCertificate],
+        trust_config: &SealedSenderTrustConfig,
+        csprng: &mut R,
+    ) -> Result<Vec<bool>> {
+        Self::validate_refs_with_trust_config(
+            &certificates.iter().collect_vec(),
+            trust_config,
+            csprng,
+        )
+    }
+
+    fn validate_refs_with_trust_config<R: Rng + CryptoRng>(
+        certificates: &[&ServerCertificate],
+        trust_config: &SealedSenderTrustConfig,
+        csprng: &mut R,
+    ) -> Result<Vec<bool>> {
+        let mut results = vec![false; certificates.len()];
+        let mut unverified = certificates
+            .iter()
+            .enumerate()
+            .filter(|(_, cert)| {
+                let revoked = trust_config.is_server_key_id_revoked(cert.key_id);
+                if revoked {
+                    log::error!(
+                        "received server certificate with revoked ID {:x}",
+                        cert.key_id
+                    );
+                }
+                !revoked
+            })
+            .map(|(i, _)| i)
+            .collect_vec();
+
+        for trust_root in trust_config.trust_roots() {
+            if unverified.is_empty() {
+                break;
+            }
+            let mut batch = SignatureBatch::with_capacity(unverified.len());
+            for &i in &unverified {
+                batch.push(
+                    *trust_root,
+                    &certificates[i].certificate,
+                    &certificates[i].signature,
+                );
+            }
+            let signatures_valid = batch.verify(csprng)?;
+            unverified = unverified
+                .into_iter()
+                .zip(signatures_valid)
+                .filter_map(|(i, valid)| {
+                    results[i] = valid;
+                    (!valid).then_some(i)
+                })
+                .collect();
+        }
+
+        Ok(results)
+    }
+
+    fn is_revoked(&self) -> bool {
+        if REVOKED_SERVER_CERTIFICATE_KEY_IDS.contains(&self.key_id) {
+            log::error!(
+                "received server certificate with revoked ID {:x}",
+                self.key_id
+            );
+            return true;
+        }
+        false
     }
 
     pub fn key_id(&self) -> Result<u32> {
@@ -265,32 +382,144 @@ impl SenderCertificate {
     }
 
     pub fn validate(&self, trust_root: &PublicKey, validation_time: Timestamp) -> Result<bool> {
-        if !self.signer.validate(trust_root)? {
+        self.validate_signed_by(self.signer.validate(trust_root)?, validation_time)
+    }
+
+    /// Like [`Self::validate`], but checks the server certificate with
+    /// [`ServerCertificate::validate_with_trust_config`].
+    pub fn validate_with_trust_config(
+        &self,
+        trust_config: &SealedSenderTrustConfig,
+        validation_time: Timestamp,
+    ) -> Result<bool> {
+        self.validate_signed_by(
+            self.signer.validate_with_trust_config(trust_config)?,
+            validation_time,
+        )
+    }
+
+    fn validate_signed_by(&self, signer_valid: bool, validation_time: Timestamp) -> Result<bool> {
+        let signature_valid = signer_valid
+            && self
+                .signer
+                .public_key()?
+                .verify_signature(&self.certificate, &self.signature)?;
+        Ok(self.validity(signer_valid, signature_valid, validation_time))
+    }
+
+    /// Validates each of `certificates` as if by [`Self::validate`], but checks all of their
+    /// signatures as a single [`SignatureBatch`].
+    ///
+    /// A server certificate shared by several sender certificates is only checked once.
+    pub fn validate_batch<R: Rng + CryptoRng>(
+        certificates: &[SenderCertificate],
+        trust_root: &PublicKey,
+        validation_time: Timestamp,
+        csprng: &mut R,
+    ) -> Result<Vec<bool>> {
+        let mut batch = SignatureBatch::with_capacity(2 * certificates.len());
+        let mut signer_indexes: HashMap<&[u8], Option<usize>> = HashMap::new();
+        let signature_indexes = certificates
+            .iter()
+            .map(|cert| {
+                let signer = &cert.signer;
+                let signer_index = *signer_indexes.entry(&signer.serialized).or_insert_with(|| {
+                    (!signer.is_revoked())
+                        .then(|| batch.push(*trust_root, &signer.certificate, &signer.signature))
+                });
+                let index = batch.push(signer.key, &cert.certificate, &cert.signature);
+                (signer_index, index)
+            })
+            .collect_vec();
+        let signatures_valid = batch.verify(csprng)?;
+
+        Ok(certificates
+            .iter()
+            .zip(signature_indexes)
+            .map(|(cert, (signer_index, index))| {
+                cert.validity(
+                    signer_index.is_some_and(|i| signatures_valid[i]),
+                    signatures_valid[index],
+                    validation_time,
+                )
+            })
+            .collect())
+    }
+
+    /// Validates each of `certificates` as if by [`Self::validate_with_trust_config`], checking
+    /// their server certificates with [`ServerCertificate::validate_batch_with_trust_config`] and
+    /// the rest of their signatures as a single [`SignatureBatch`].
+    ///
+    /// A server certificate shared by several sender certificates is only checked once.
+    pub fn validate_batch_with_trust_config<R: Rng + CryptoRng>(
+        certificates: &[SenderCertificate],
+        trust_config: &SealedSenderTrustConfig,
+        validation_time: Timestamp,
+        csprng: &mut R,
+    ) -> Result<Vec<bool>> {
+        let mut signers = Vec::new();
+        let mut signer_indexes: HashMap<&[u8], usize> = HashMap::new();
+        let cert_signer_indexes = certificates
+            .iter()
+            .map(|cert| {
+                *signer_indexes
+                    .entry(&cert.signer.serialized)
+                    .or_insert_with(|| {
+                        signers.push(&cert.signer);
+                        signers.len() - 1
+                    })
+            })
+            .collect_vec();
+        let signers_valid =
+            ServerCertificate::validate_refs_with_trust_config(&signers, trust_config, csprng)?;
+
+        let mut batch = SignatureBatch::with_capacity(certificates.len());
+        for cert in certificates {
+            batch.push(cert.signer.key, &cert.certificate, &cert.signature);
+        }
+        let signatures_valid = batch.verify(csprng)?;
+
+        Ok(certificates
+            .iter()
+            .zip(cert_signer_indexes)
+            .zip(signatures_valid)
+            .map(|((cert, signer_index), signature_valid)| {
+                cert.validity(
+                    signers_valid[signer_index],
+                    signature_valid,
+                    validation_time,
+                )
+            })
+            .collect())
+    }
+
+    /// Whether this certificate is valid, given whether its server certificate and its own
+    /// signature are.
+    fn validity(
+        &self,
+        signer_valid: bool,
+        signature_valid: bool,
+        validation_time: Timestamp,
+    ) -> bool {
+        if !signer_valid {
             log::error!(
                 "sender certificate contained server certificate that wasn't signed by trust root"
             );
-            return Ok(false);
+            return false;
         }
-
-        if !self
-            .signer
-            .public_key()?
-            .verify_signature(&self.certificate, &self.signature)?
-        {
+        if !signature_valid {
             log::error!("sender certificate not signed by server");
-            return Ok(false);
+            return false;
         }
-
         if validation_time > self.expiration {
             log::error!(
                 "sender certificate is expired (expiration: {}, validation_time: {})",
                 self.expiration.epoch_millis(),
                 validation_time.epoch_millis()
             );
-            return Ok(false);
+            return false;
         }
-
-        Ok(true)
+        true
     }
 
     pub fn signer(&self) -> Result<&ServerCertificate> {
@@ -1500,6 +1729,166 @@ where
     Ok(serialized)
 }
 
+/// Why a destination was left out by [`sealed_sender_multi_recipient_encrypt_partial`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SealedSenderExclusionReason {
+    /// No session was provided for the destination.
+    MissingSession,
+    /// The destination's session has no current state, e.g. because it was archived.
+    StaleSession,
+    /// The destination's session has a registration ID that doesn't fit in 14 bits.
+    InvalidRegistrationId(u32),
+    /// The destination's name is not a ServiceId, or its device ID is out of range.
+    InvalidAddress,
+    /// There is no identity key stored for the destination.
+    MissingIdentity,
+    /// The identity key stored for the destination is not trusted for sending.
+    UntrustedIdentity,
+}
+
+/// A destination left out by [`sealed_sender_multi_recipient_encrypt_partial`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SealedSenderExcludedDestination {
+    /// The destination, as passed in.
+    pub address: ProtocolAddress,
+    /// Why the destination could not be included.
+    pub reason: SealedSenderExclusionReason,
+}
+
+/// The result of [`sealed_sender_multi_recipient_encrypt_partial`].
+#[derive(Clone, Debug)]
+pub struct SealedSenderMultiRecipientMessage {
+    /// The SSv2 SentMessage for all destinations that were not excluded.
+    ///
+    /// See [`sealed_sender_multi_recipient_encrypt`] for the format.
+    pub serialized: Vec<u8>,
+    /// The destinations that were left out, in the order they were given.
+    pub excluded: Vec<SealedSenderExcludedDestination>,
+}
+
+/// Like [`sealed_sender_multi_recipient_encrypt`], but leaves out destinations that can't be
+/// encrypted to instead of failing the whole message.
+///
+/// `destination_sessions` may contain `None` for destinations with no session. A destination is
+/// excluded if its session is missing or has no current state, or if its recipient has no
+/// identity key or one that `identity_store` does not trust for sending. Problems with a
+/// recipient's identity exclude all of that recipient's devices. The caller can fetch new pre-key
+/// bundles for the excluded destinations and send to them separately.
+///
+/// Recipients with no remaining devices are not included in the message at all (not even as
+/// `excluded_recipients`). Errors from `identity_store` still fail the whole call.
+pub async fn sealed_sender_multi_recipient_encrypt_partial<
+    R: Rng + CryptoRng,
+    X: IntoIterator<Item = ServiceId>,
+>(
+    destinations: &[&ProtocolAddress],
+    destination_sessions: &[Option<&SessionRecord>],
+    excluded_recipients: X,
+    usmc: &UnidentifiedSenderMessageContent,
+    identity_store: &dyn IdentityKeyStore,
+    rng: &mut R,
+) -> Result<SealedSenderMultiRecipientMessage>
+where
+    X::IntoIter: ExactSizeIterator,
+{
+    if destinations.len() != destination_sessions.len() {
+        return Err(SignalProtocolError::InvalidArgument(
+            "must have the same number of destination sessions as addresses".to_string(),
+        ));
+    }
+
+    let mut recipient_problems: HashMap<&str, Option<SealedSenderExclusionReason>> = HashMap::new();
+    let mut included_destinations = Vec::with_capacity(destinations.len());
+    let mut included_sessions = Vec::with_capacity(destinations.len());
+    let mut excluded = vec![];
+
+    for (&destination, &session) in destinations.iter().zip(destination_sessions) {
+        let recipient_problem = match recipient_problems.get(destination.name()) {
+            Some(problem) => *problem,
+            None => {
+                let problem = check_multi_recipient_identity(destination, identity_store).await?;
+                recipient_problems.insert(destination.name(), problem);
+                problem
+            }
+        };
+
+        match recipient_problem.or_else(|| check_multi_recipient_session(destination, session)) {
+            Some(reason) => {
+                log::warn!(
+                    "excluding {} from multi-recipient sealed sender message: {:?}",
+                    destination,
+                    reason
+                );
+                excluded.push(SealedSenderExcludedDestination {
+                    address: destination.clone(),
+                    reason,
+                });
+            }
+            None => {
+                included_destinations.push(destination);
+                included_sessions.push(session.expect("checked above"));
+            }
+        }
+    }
+
+    let serialized = sealed_sender_multi_recipient_encrypt_impl(
+        &included_destinations,
+        &included_sessions,
+        excluded_recipients,
+        usmc,
+        identity_store,
+        rng,
+    )
+    .await?;
+
+    Ok(SealedSenderMultiRecipientMessage {
+        serialized,
+        excluded,
+    })
+}
+
+async fn check_multi_recipient_identity(
+    destination: &ProtocolAddress,
+    identity_store: &dyn IdentityKeyStore,
+) -> Result<Option<SealedSenderExclusionReason>> {
+    if ServiceId::parse_from_service_id_string(destination.name()).is_none() {
+        return Ok(Some(SealedSenderExclusionReason::InvalidAddress));
+    }
+    let Some(their_identity) = identity_store.get_identity(destination).await? else {
+        return Ok(Some(SealedSenderExclusionReason::MissingIdentity));
+    };
+    if !identity_store
+        .is_trusted_identity(destination, &their_identity, Direction::Sending)
+        .await?
+    {
+        return Ok(Some(SealedSenderExclusionReason::UntrustedIdentity));
+    }
+    Ok(None)
+}
+
+/// Mirrors the per-device checks in `sealed_sender_multi_recipient_encrypt_impl`.
+fn check_multi_recipient_session(
+    destination: &ProtocolAddress,
+    session: Option<&SessionRecord>,
+) -> Option<SealedSenderExclusionReason> {
+    let device_id: u32 = destination.device_id().into();
+    if device_id == 0 || device_id > MAX_VALID_DEVICE_ID {
+        return Some(SealedSenderExclusionReason::InvalidAddress);
+    }
+    let Some(session) = session else {
+        return Some(SealedSenderExclusionReason::MissingSession);
+    };
+    let Ok(their_registration_id) = session.remote_registration_id() else {
+        return Some(SealedSenderExclusionReason::StaleSession);
+    };
+    if their_registration_id & u32::from(VALID_REGISTRATION_ID_MASK) != their_registration_id {
+        return Some(SealedSenderExclusionReason::InvalidRegistrationId(
+            their_registration_id,
+        ));
+    }
+    None
+}
+
 /// Represents a single recipient in an SSv2 SentMessage.
 ///
 /// See [`SealedSenderV2SentMessage`].
@@ -1924,10 +2313,45 @@ pub async fn sealed_sender_decrypt(
     pre_key_store: &mut dyn PreKeyStore,
     signed_pre_key_store: &dyn SignedPreKeyStore,
     kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+) -> Result<SealedSenderDecryptionResult> {
+    sealed_sender_decrypt_with_trust_config(
+        ciphertext,
+        &SealedSenderTrustConfig::new(vec![*trust_root])?,
+        timestamp,
+        local_e164,
+        local_uuid,
+        local_device_id,
+        identity_store,
+        session_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+    )
+    .await
+}
+
+/// Like [`sealed_sender_decrypt`], but validates the sender certificate against a
+/// [`SealedSenderTrustConfig`] rather than a single trust root.
+#[allow(clippy::too_many_arguments)]
+pub async fn sealed_sender_decrypt_with_trust_config(
+    ciphertext: &[u8],
+    trust_config: &SealedSenderTrustConfig,
+    timestamp: Timestamp,
+    local_e164: Option<String>,
+    local_uuid: String,
+    local_device_id: DeviceId,
+    identity_store: &mut dyn IdentityKeyStore,
+    session_store: &mut dyn SessionStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
 ) -> Result<SealedSenderDecryptionResult> {
     let usmc = sealed_sender_decrypt_to_usmc(ciphertext, identity_store).await?;
 
-    if !usmc.sender()?.validate(trust_root, timestamp)? {
+    if !usmc
+        .sender()?
+        .validate_with_trust_config(trust_config, timestamp)?
+    {
         return Err(SignalProtocolError::InvalidSealedSenderMessage(
             "trust root validation failed".to_string(),
         ));
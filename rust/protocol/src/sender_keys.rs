@@ -4,13 +4,14 @@
 //
 
 use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
 
 use itertools::Itertools;
 use prost::Message;
 
 use crate::crypto::hmac_sha256;
 use crate::proto::storage as storage_proto;
-use crate::{consts, PrivateKey, PublicKey, SignalProtocolError};
+use crate::{PrivateKey, ProtocolConfig, PublicKey, SignalProtocolError};
 
 /// A distinct error type to keep from accidentally propagating deserialization errors.
 #[derive(Debug)]
@@ -151,6 +152,8 @@ impl SenderKeyState {
                 },
             ),
             sender_message_keys: vec![],
+            created_at: 0,
+            rotation_requested: false,
         };
 
         Self { state }
@@ -201,16 +204,47 @@ impl SenderKeyState {
         }
     }
 
+    pub(crate) fn created_at(&self) -> Option<SystemTime> {
+        match self.state.created_at {
+            0 => None,
+            millis => Some(SystemTime::UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+
+    pub(crate) fn set_created_at(&mut self, created_at: SystemTime) {
+        self.state.created_at = created_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            .try_into()
+            .unwrap_or(u64::MAX);
+    }
+
+    pub(crate) fn rotation_requested(&self) -> bool {
+        self.state.rotation_requested
+    }
+
+    pub(crate) fn request_rotation(&mut self) {
+        self.state.rotation_requested = true;
+    }
+
     pub(crate) fn as_protobuf(&self) -> storage_proto::SenderKeyStateStructure {
         self.state.clone()
     }
 
-    pub(crate) fn add_sender_message_key(&mut self, sender_message_key: &SenderMessageKey) {
+    pub(crate) fn add_sender_message_key(
+        &mut self,
+        sender_message_key: &SenderMessageKey,
+        config: &ProtocolConfig,
+    ) {
         self.state
             .sender_message_keys
             .push(sender_message_key.as_protobuf());
-        while self.state.sender_message_keys.len() > consts::MAX_MESSAGE_KEYS {
-            self.state.sender_message_keys.remove(0);
+        let message_key_count = self.state.sender_message_keys.len();
+        if message_key_count > config.max_message_keys {
+            self.state
+                .sender_message_keys
+                .drain(..message_key_count - config.max_message_keys);
         }
     }
 
@@ -237,7 +271,7 @@ pub struct SenderKeyRecord {
 impl SenderKeyRecord {
     pub(crate) fn new_empty() -> Self {
         Self {
-            states: VecDeque::with_capacity(consts::MAX_SENDER_KEY_STATES),
+            states: VecDeque::with_capacity(ProtocolConfig::DEFAULT.max_sender_key_states),
         }
     }
 
@@ -292,6 +326,7 @@ impl SenderKeyRecord {
         chain_key: &[u8],
         signature_key: PublicKey,
         signature_private_key: Option<PrivateKey>,
+        config: &ProtocolConfig,
     ) {
         let existing_state = self.remove_state(chain_id, signature_key);
 
@@ -314,11 +349,8 @@ impl SenderKeyRecord {
             Some(state) => state,
         };
 
-        while self.states.len() >= consts::MAX_SENDER_KEY_STATES {
-            self.states.pop_back();
-        }
-
         self.states.push_front(state);
+        self.states.truncate(config.max_sender_key_states);
     }
 
     /// Remove the state with the matching `chain_id` and `signature_key`.
@@ -389,8 +421,15 @@ mod sender_key_record_add_sender_key_state_tests {
         /// method under test in this module.
         fn add_sender_key_state_record(&mut self, record_key: (PublicKey, u32), chain_key: &[u8]) {
             let (public_key, chain_id) = record_key;
-            self.sender_key_record
-                .add_sender_key_state(1, chain_id, 1, chain_key, public_key, None);
+            self.sender_key_record.add_sender_key_state(
+                1,
+                chain_id,
+                1,
+                chain_key,
+                public_key,
+                None,
+                &ProtocolConfig::DEFAULT,
+            );
         }
 
         fn assert_number_of_states(&self, expected: usize) {
@@ -490,7 +529,7 @@ mod sender_key_record_add_sender_key_state_tests {
     fn when_exceed_maximum_states_then_oldest_is_ejected() {
         assert_eq!(
             5,
-            consts::MAX_SENDER_KEY_STATES,
+            ProtocolConfig::DEFAULT.max_sender_key_states,
             "Test written to expect this limit"
         );
 
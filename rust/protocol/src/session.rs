@@ -7,8 +7,8 @@ use std::time::SystemTime;
 
 use crate::{
     kem, Direction, IdentityKeyStore, KeyPair, KyberPreKeyId, KyberPreKeyStore, PreKeyBundle,
-    PreKeyId, PreKeySignalMessage, PreKeyStore, ProtocolAddress, Result, SessionRecord,
-    SessionStore, SignalProtocolError, SignedPreKeyStore,
+    PreKeyId, PreKeySignalMessage, PreKeyStore, ProtocolAddress, ProtocolConfig, Result,
+    SessionRecord, SessionStore, SignalProtocolError, SignedPreKeyStore,
 };
 
 use crate::ratchet;
@@ -39,6 +39,31 @@ pub async fn process_prekey(
     pre_key_store: &dyn PreKeyStore,
     signed_prekey_store: &dyn SignedPreKeyStore,
     kyber_prekey_store: &dyn KyberPreKeyStore,
+) -> Result<PreKeysUsed> {
+    process_prekey_with_config(
+        message,
+        remote_address,
+        session_record,
+        identity_store,
+        pre_key_store,
+        signed_prekey_store,
+        kyber_prekey_store,
+        &ProtocolConfig::DEFAULT,
+    )
+    .await
+}
+
+/// Like [`process_prekey`], but archives the previous session according to `config`.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_prekey_with_config(
+    message: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_record: &mut SessionRecord,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &dyn PreKeyStore,
+    signed_prekey_store: &dyn SignedPreKeyStore,
+    kyber_prekey_store: &dyn KyberPreKeyStore,
+    config: &ProtocolConfig,
 ) -> Result<PreKeysUsed> {
     let their_identity_key = message.identity_key();
 
@@ -59,6 +84,7 @@ pub async fn process_prekey(
         kyber_prekey_store,
         pre_key_store,
         identity_store,
+        config,
     )
     .await?;
 
@@ -77,6 +103,7 @@ async fn process_prekey_impl(
     kyber_prekey_store: &dyn KyberPreKeyStore,
     pre_key_store: &dyn PreKeyStore,
     identity_store: &dyn IdentityKeyStore,
+    config: &ProtocolConfig,
 ) -> Result<PreKeysUsed> {
     if session_record.has_session_state(
         message.message_version() as u32,
@@ -115,7 +142,7 @@ async fn process_prekey_impl(
         None
     };
 
-    let parameters = BobSignalProtocolParameters::new(
+    let mut parameters = BobSignalProtocolParameters::new(
         identity_store.get_identity_key_pair().await?,
         our_signed_pre_key_pair, // signed pre key
         our_one_time_pre_key_pair,
@@ -125,13 +152,14 @@ async fn process_prekey_impl(
         *message.base_key(),
         message.kyber_ciphertext(),
     );
+    parameters.set_ephemeral(config.ephemeral_sessions);
 
     let mut new_session = ratchet::initialize_bob_session(&parameters)?;
 
     new_session.set_local_registration_id(identity_store.get_local_registration_id().await?);
     new_session.set_remote_registration_id(message.registration_id());
 
-    session_record.promote_state(new_session);
+    session_record.promote_state(new_session, config);
 
     let pre_keys_used = PreKeysUsed {
         pre_key_id: message.pre_key_id(),
@@ -141,12 +169,37 @@ async fn process_prekey_impl(
 }
 
 pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    bundle: &PreKeyBundle,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<()> {
+    process_prekey_bundle_with_config(
+        remote_address,
+        session_store,
+        identity_store,
+        bundle,
+        now,
+        csprng,
+        &ProtocolConfig::DEFAULT,
+    )
+    .await
+}
+
+/// Like [`process_prekey_bundle`], but archives the previous session according to `config`.
+///
+/// If the bundle offers Kyber pre-keys for more than one KEM, the one used is chosen by
+/// `config.kem_preference`.
+pub async fn process_prekey_bundle_with_config<R: Rng + CryptoRng>(
     remote_address: &ProtocolAddress,
     session_store: &mut dyn SessionStore,
     identity_store: &mut dyn IdentityKeyStore,
     bundle: &PreKeyBundle,
     now: SystemTime,
     mut csprng: &mut R,
+    config: &ProtocolConfig,
 ) -> Result<()> {
     let their_identity_key = bundle.identity_key()?;
 
@@ -166,16 +219,15 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
         return Err(SignalProtocolError::SignatureValidationFailed);
     }
 
-    if let Some(kyber_public) = bundle.kyber_pre_key_public()? {
-        if !their_identity_key.public_key().verify_signature(
-            kyber_public.serialize().as_ref(),
-            bundle
-                .kyber_pre_key_signature()?
-                .expect("signature must be present"),
-        )? {
+    for (_, kyber_public, kyber_signature) in bundle.kyber_pre_keys() {
+        if !their_identity_key
+            .public_key()
+            .verify_signature(kyber_public.serialize().as_ref(), kyber_signature)?
+        {
             return Err(SignalProtocolError::SignatureValidationFailed);
         }
     }
+    let kyber_pre_key = bundle.select_kyber_pre_key(config.kem_preference)?;
 
     let mut session_record = session_store
         .load_session(remote_address)
@@ -200,12 +252,22 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
         parameters.set_their_one_time_pre_key(key);
     }
 
-    if let Some(key) = bundle.kyber_pre_key_public()? {
+    if let Some((_, key)) = kyber_pre_key {
         parameters.set_their_kyber_pre_key(key);
     }
 
+    parameters.set_ephemeral(config.ephemeral_sessions);
+
     let mut session = ratchet::initialize_alice_session(&parameters, csprng)?;
 
+    if config.enable_pq_ratchet {
+        let key_type = match kyber_pre_key {
+            Some((_, key)) => key.key_type(),
+            None => kem::KeyType::Kyber1024,
+        };
+        session.set_pq_ratchet(Some(ratchet::pq::new_holder(1, key_type)));
+    }
+
     log::info!(
         "set_unacknowledged_pre_key_message for: {} with preKeyId: {}",
         remote_address,
@@ -219,7 +281,7 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
         now,
     );
 
-    if let Some(kyber_pre_key_id) = bundle.kyber_pre_key_id()? {
+    if let Some((kyber_pre_key_id, _)) = kyber_pre_key {
         session.set_unacknowledged_kyber_pre_key_id(kyber_pre_key_id);
     }
 
@@ -230,7 +292,7 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
         .save_identity(remote_address, their_identity_key)
         .await?;
 
-    session_record.promote_state(session);
+    session_record.promote_state(session, config);
 
     session_store
         .store_session(remote_address, &session_record)
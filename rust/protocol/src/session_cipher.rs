@@ -7,13 +7,13 @@ use std::time::SystemTime;
 
 use rand::{CryptoRng, Rng};
 
-use crate::consts::{MAX_FORWARD_JUMPS, MAX_UNACKNOWLEDGED_SESSION_AGE};
-use crate::ratchet::{ChainKey, MessageKeys};
+use crate::ratchet::{pq, ChainKey, MessageKeys};
 use crate::state::{InvalidSessionError, SessionState};
 use crate::{
     session, CiphertextMessage, CiphertextMessageType, Direction, IdentityKeyStore, KeyPair,
-    KyberPayload, KyberPreKeyStore, PreKeySignalMessage, PreKeyStore, ProtocolAddress, PublicKey,
-    Result, SessionRecord, SessionStore, SignalMessage, SignalProtocolError, SignedPreKeyStore,
+    KyberPayload, KyberPreKeyStore, PreKeySignalMessage, PreKeyStore, ProtocolAddress,
+    ProtocolConfig, PublicKey, Result, SessionRecord, SessionStore, SignalMessage,
+    SignalProtocolError, SignedPreKeyStore,
 };
 
 pub async fn message_encrypt(
@@ -22,6 +22,27 @@ pub async fn message_encrypt(
     session_store: &mut dyn SessionStore,
     identity_store: &mut dyn IdentityKeyStore,
     now: SystemTime,
+) -> Result<CiphertextMessage> {
+    message_encrypt_with_config(
+        ptext,
+        remote_address,
+        session_store,
+        identity_store,
+        now,
+        &ProtocolConfig::DEFAULT,
+    )
+    .await
+}
+
+/// Like [`message_encrypt`], but refuses to use unacknowledged sessions older than `config`
+/// allows.
+pub async fn message_encrypt_with_config(
+    ptext: &[u8],
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    now: SystemTime,
+    config: &ProtocolConfig,
 ) -> Result<CiphertextMessage> {
     let mut session_record = session_store
         .load_session(remote_address)
@@ -54,13 +75,15 @@ pub async fn message_encrypt(
                 SignalProtocolError::InvalidSessionStructure("invalid sender chain message keys")
             })?;
 
+    let pq_ratchet = session_state.pq_ratchet_mut().map(pq::outgoing_message);
+
     let message = if let Some(items) = session_state.unacknowledged_pre_key_message_items()? {
         let timestamp_as_unix_time = items
             .timestamp()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
-        if items.timestamp() + MAX_UNACKNOWLEDGED_SESSION_AGE < now {
+        if items.timestamp() + config.max_unacknowledged_session_age < now {
             log::warn!(
                 "stale unacknowledged session for {} (created at {})",
                 remote_address,
@@ -80,13 +103,14 @@ pub async fn message_encrypt(
             timestamp_as_unix_time,
         );
 
-        let message = SignalMessage::new(
+        let message = SignalMessage::new_with_pq_ratchet(
             session_version,
             message_keys.mac_key(),
             sender_ephemeral,
             chain_key.index(),
             previous_counter,
             &ctext,
+            pq_ratchet,
             &local_identity_key,
             &their_identity_key,
         )?;
@@ -107,13 +131,14 @@ pub async fn message_encrypt(
             message,
         )?)
     } else {
-        CiphertextMessage::SignalMessage(SignalMessage::new(
+        CiphertextMessage::SignalMessage(SignalMessage::new_with_pq_ratchet(
             session_version,
             message_keys.mac_key(),
             sender_ephemeral,
             chain_key.index(),
             previous_counter,
             &ctext,
+            pq_ratchet,
             &local_identity_key,
             &their_identity_key,
         )?)
@@ -160,13 +185,49 @@ pub async fn message_decrypt<R: Rng + CryptoRng>(
     signed_pre_key_store: &dyn SignedPreKeyStore,
     kyber_pre_key_store: &mut dyn KyberPreKeyStore,
     csprng: &mut R,
+) -> Result<Vec<u8>> {
+    message_decrypt_with_config(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        csprng,
+        &ProtocolConfig::DEFAULT,
+    )
+    .await
+}
+
+/// Like [`message_decrypt`], but with the limits on skipped messages and stored state given by
+/// `config`.
+#[allow(clippy::too_many_arguments)]
+pub async fn message_decrypt_with_config<R: Rng + CryptoRng>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    csprng: &mut R,
+    config: &ProtocolConfig,
 ) -> Result<Vec<u8>> {
     match ciphertext {
         CiphertextMessage::SignalMessage(m) => {
-            message_decrypt_signal(m, remote_address, session_store, identity_store, csprng).await
+            message_decrypt_signal_with_config(
+                m,
+                remote_address,
+                session_store,
+                identity_store,
+                csprng,
+                config,
+            )
+            .await
         }
         CiphertextMessage::PreKeySignalMessage(m) => {
-            message_decrypt_prekey(
+            message_decrypt_prekey_with_config(
                 m,
                 remote_address,
                 session_store,
@@ -175,6 +236,7 @@ pub async fn message_decrypt<R: Rng + CryptoRng>(
                 signed_pre_key_store,
                 kyber_pre_key_store,
                 csprng,
+                config,
             )
             .await
         }
@@ -195,6 +257,34 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
     signed_pre_key_store: &dyn SignedPreKeyStore,
     kyber_pre_key_store: &mut dyn KyberPreKeyStore,
     csprng: &mut R,
+) -> Result<Vec<u8>> {
+    message_decrypt_prekey_with_config(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        csprng,
+        &ProtocolConfig::DEFAULT,
+    )
+    .await
+}
+
+/// Like [`message_decrypt_prekey`], but with the limits on skipped messages and stored state
+/// given by `config`.
+#[allow(clippy::too_many_arguments)]
+pub async fn message_decrypt_prekey_with_config<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    csprng: &mut R,
+    config: &ProtocolConfig,
 ) -> Result<Vec<u8>> {
     let mut session_record = session_store
         .load_session(remote_address)
@@ -202,7 +292,7 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
         .unwrap_or_else(SessionRecord::new_fresh);
 
     // Make sure we log the session state if we fail to process the pre-key.
-    let pre_key_used_or_err = session::process_prekey(
+    let pre_key_used_or_err = session::process_prekey_with_config(
         ciphertext,
         remote_address,
         &mut session_record,
@@ -210,6 +300,7 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
         pre_key_store,
         signed_pre_key_store,
         kyber_pre_key_store,
+        config,
     )
     .await;
 
@@ -237,6 +328,7 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
         ciphertext.message(),
         CiphertextMessageType::PreKey,
         csprng,
+        config,
     )?;
 
     session_store
@@ -262,6 +354,27 @@ pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
     session_store: &mut dyn SessionStore,
     identity_store: &mut dyn IdentityKeyStore,
     csprng: &mut R,
+) -> Result<Vec<u8>> {
+    message_decrypt_signal_with_config(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        csprng,
+        &ProtocolConfig::DEFAULT,
+    )
+    .await
+}
+
+/// Like [`message_decrypt_signal`], but with the limits on skipped messages and stored state
+/// given by `config`.
+pub async fn message_decrypt_signal_with_config<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    csprng: &mut R,
+    config: &ProtocolConfig,
 ) -> Result<Vec<u8>> {
     let mut session_record = session_store
         .load_session(remote_address)
@@ -274,6 +387,7 @@ pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
         ciphertext,
         CiphertextMessageType::Whisper,
         csprng,
+        config,
     )?;
 
     // Why are we performing this check after decryption instead of before?
@@ -411,6 +525,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
     ciphertext: &SignalMessage,
     original_message_type: CiphertextMessageType,
     csprng: &mut R,
+    config: &ProtocolConfig,
 ) -> Result<Vec<u8>> {
     debug_assert!(matches!(
         original_message_type,
@@ -447,6 +562,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
             original_message_type,
             remote_address,
             csprng,
+            config,
         );
 
         match result {
@@ -485,6 +601,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
             original_message_type,
             remote_address,
             csprng,
+            config,
         );
 
         match result {
@@ -511,7 +628,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
     }
 
     if let Some((ptext, idx, updated_session)) = updated_session {
-        record.promote_old_session(idx, updated_session);
+        record.promote_old_session(idx, updated_session, config);
         Ok(ptext)
     } else {
         let previous_state_count = || record.previous_session_states().len();
@@ -564,6 +681,7 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
     original_message_type: CiphertextMessageType,
     remote_address: &ProtocolAddress,
     csprng: &mut R,
+    config: &ProtocolConfig,
 ) -> Result<Vec<u8>> {
     // Check for a completely empty or invalid session state before we do anything else.
     let _ = state.root_key().map_err(|_| {
@@ -582,7 +700,17 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
 
     let their_ephemeral = ciphertext.sender_ratchet_key();
     let counter = ciphertext.counter();
-    let chain_key = get_or_create_chain_key(state, their_ephemeral, remote_address, csprng)?;
+    let chain_key = get_or_create_chain_key(
+        state,
+        their_ephemeral,
+        ciphertext
+            .pq_ratchet()
+            .map_or(0, |pq_ratchet| pq_ratchet.mixed_epoch()),
+        remote_address,
+        original_message_type,
+        csprng,
+        config,
+    )?;
     let message_keys = get_or_create_message_key(
         state,
         their_ephemeral,
@@ -590,6 +718,7 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
         original_message_type,
         &chain_key,
         counter,
+        config,
     )?;
 
     let their_identity_key =
@@ -639,14 +768,30 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
 
     state.clear_unacknowledged_pre_key_message();
 
+    let pq_ratchet = pq::process_incoming(
+        state.take_pq_ratchet(),
+        ciphertext.pq_ratchet(),
+        config.enable_pq_ratchet,
+    );
+    state.set_pq_ratchet(pq_ratchet);
+
     Ok(ptext)
 }
 
+/// Returns the receiving chain for `their_ephemeral`, creating it and a new sending chain if this
+/// is the first message on the chain.
+///
+/// `pq_mixed_epoch` is the post-quantum ratchet epoch the sender mixed into the root key when it
+/// created the chain, or 0 if none.
+#[allow(clippy::too_many_arguments)]
 fn get_or_create_chain_key<R: Rng + CryptoRng>(
     state: &mut SessionState,
     their_ephemeral: &PublicKey,
+    pq_mixed_epoch: u32,
     remote_address: &ProtocolAddress,
+    original_message_type: CiphertextMessageType,
     csprng: &mut R,
+    config: &ProtocolConfig,
 ) -> Result<ChainKey> {
     if let Some(chain) = state.get_receiver_chain_key(their_ephemeral)? {
         log::debug!("{} has existing receiver chain.", remote_address);
@@ -655,16 +800,43 @@ fn get_or_create_chain_key<R: Rng + CryptoRng>(
 
     log::info!("{} creating new chains.", remote_address);
 
-    let root_key = state.root_key()?;
+    let mut root_key = state.root_key()?;
+    let their_pq_secret =
+        match pq::take_secret_for_receiving_chain(state.pq_ratchet_mut(), pq_mixed_epoch) {
+            Ok(secret) => secret,
+            Err(e) => {
+                // Derive the chain classically and let the MAC check decide; see the pq module
+                // docs for when this can happen.
+                log::warn!(
+                    "{}: {} for {:?} message; turning the post-quantum ratchet off",
+                    remote_address,
+                    e,
+                    original_message_type,
+                );
+                state.set_pq_ratchet(None);
+                None
+            }
+        };
+    if let Some(secret) = their_pq_secret {
+        root_key = root_key.mix_post_quantum_secret(&secret);
+    }
+
     let our_ephemeral = state.sender_ratchet_private_key()?;
     let receiver_chain = root_key.create_chain(their_ephemeral, &our_ephemeral)?;
+
+    let mut root_key = receiver_chain.0;
+    if let Some(secret) = state
+        .pq_ratchet_mut()
+        .and_then(pq::take_secret_for_sending_chain)
+    {
+        root_key = root_key.mix_post_quantum_secret(&secret);
+    }
+
     let our_new_ephemeral = KeyPair::generate(csprng);
-    let sender_chain = receiver_chain
-        .0
-        .create_chain(their_ephemeral, &our_new_ephemeral.private_key)?;
+    let sender_chain = root_key.create_chain(their_ephemeral, &our_new_ephemeral.private_key)?;
 
     state.set_root_key(&sender_chain.0);
-    state.add_receiver_chain(their_ephemeral, &receiver_chain.1);
+    state.add_receiver_chain(their_ephemeral, &receiver_chain.1, config);
 
     let current_index = state.get_sender_chain_key()?.index();
     let previous_index = if current_index > 0 {
@@ -685,6 +857,7 @@ fn get_or_create_message_key(
     original_message_type: CiphertextMessageType,
     chain_key: &ChainKey,
     counter: u32,
+    config: &ProtocolConfig,
 ) -> Result<MessageKeys> {
     let chain_index = chain_key.index();
 
@@ -706,7 +879,7 @@ fn get_or_create_message_key(
 
     let jump = (counter - chain_index) as usize;
 
-    if jump > MAX_FORWARD_JUMPS {
+    if jump > config.max_forward_jumps {
         if state.session_with_self()? {
             log::info!(
                 "{} Jumping ahead {} messages (index: {}, counter: {})",
@@ -719,7 +892,7 @@ fn get_or_create_message_key(
             log::error!(
                 "{} Exceeded future message limit: {}, index: {}, counter: {})",
                 remote_address,
-                MAX_FORWARD_JUMPS,
+                config.max_forward_jumps,
                 chain_index,
                 counter
             );
@@ -733,8 +906,12 @@ fn get_or_create_message_key(
     let mut chain_key = chain_key.clone();
 
     while chain_key.index() < counter {
-        let message_keys = chain_key.message_keys();
-        state.set_message_keys(their_ephemeral, &message_keys)?;
+        // Ephemeral sessions don't keep keys for skipped messages, so those can never be
+        // decrypted once a later message has been.
+        if !state.ephemeral() {
+            let message_keys = chain_key.message_keys();
+            state.set_message_keys(their_ephemeral, &message_keys, config)?;
+        }
         chain_key = chain_key.next_chain_key();
     }
 
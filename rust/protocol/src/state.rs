@@ -12,6 +12,9 @@ mod signed_prekey;
 pub use bundle::{PreKeyBundle, PreKeyBundleContent};
 pub use kyber_prekey::{KyberPreKeyId, KyberPreKeyRecord};
 pub use prekey::{PreKeyId, PreKeyRecord};
-pub use session::SessionRecord;
+pub use session::{
+    ChainDescription, PendingPreKeyDescription, SessionRecord, SessionRecordDescription,
+    SessionStateDescription,
+};
 pub(crate) use session::{InvalidSessionError, SessionState};
 pub use signed_prekey::{GenericSignedPreKey, SignedPreKeyId, SignedPreKeyRecord};
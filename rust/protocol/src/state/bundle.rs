@@ -57,10 +57,15 @@ pub struct PreKeyBundleContent {
     pub kyber_pre_key_id: Option<KyberPreKeyId>,
     pub kyber_pre_key_public: Option<kem::PublicKey>,
     pub kyber_pre_key_signature: Option<Vec<u8>>,
+    // Pre-keys for other KEMs, offered alongside the kyber_pre_key_* one, as
+    // (ID, public key, signature).
+    pub additional_kyber_pre_keys: Vec<(KyberPreKeyId, kem::PublicKey, Vec<u8>)>,
 }
 
 impl From<PreKeyBundle> for PreKeyBundleContent {
     fn from(bundle: PreKeyBundle) -> Self {
+        let mut kyber_pre_keys = bundle.kyber_pre_keys.into_iter();
+        let kyber_pre_key = kyber_pre_keys.next();
         Self {
             registration_id: Some(bundle.registration_id),
             device_id: Some(bundle.device_id),
@@ -70,15 +75,12 @@ impl From<PreKeyBundle> for PreKeyBundleContent {
             ec_pre_key_public: Some(bundle.ec_signed_pre_key.public_key),
             ec_pre_key_signature: Some(bundle.ec_signed_pre_key.signature),
             identity_key: Some(bundle.identity_key),
-            kyber_pre_key_id: bundle.kyber_pre_key.as_ref().map(|kyber| kyber.id),
-            kyber_pre_key_public: bundle
-                .kyber_pre_key
-                .as_ref()
-                .map(|kyber| kyber.public_key.clone()),
-            kyber_pre_key_signature: bundle
-                .kyber_pre_key
-                .as_ref()
-                .map(|kyber| kyber.signature.clone()),
+            kyber_pre_key_id: kyber_pre_key.as_ref().map(|kyber| kyber.id),
+            kyber_pre_key_public: kyber_pre_key.as_ref().map(|kyber| kyber.public_key.clone()),
+            kyber_pre_key_signature: kyber_pre_key.map(|kyber| kyber.signature),
+            additional_kyber_pre_keys: kyber_pre_keys
+                .map(|kyber| (kyber.id, kyber.public_key, kyber.signature))
+                .collect(),
         }
     }
 }
@@ -126,6 +128,9 @@ impl TryFrom<PreKeyBundleContent> for PreKeyBundle {
         ) {
             bundle = bundle.with_kyber_pre_key(kyber_id, kyber_public, kyber_sig);
         }
+        for (kyber_id, kyber_public, kyber_sig) in content.additional_kyber_pre_keys {
+            bundle = bundle.with_kyber_pre_key(kyber_id, kyber_public, kyber_sig);
+        }
         Ok(bundle)
     }
 }
@@ -138,9 +143,10 @@ pub struct PreKeyBundle {
     pre_key_public: Option<PublicKey>,
     ec_signed_pre_key: SignedPreKey,
     identity_key: IdentityKey,
-    // Optional to support older clients
-    // TODO: remove optionality once the transition is over
-    kyber_pre_key: Option<KyberPreKey>,
+    // At most one per KEM, in the order they were added.
+    // May be empty to support older clients
+    // TODO: require at least one once the transition is over
+    kyber_pre_keys: Vec<KyberPreKey>,
 }
 
 impl PreKeyBundle {
@@ -171,17 +177,30 @@ impl PreKeyBundle {
             pre_key_public,
             ec_signed_pre_key,
             identity_key,
-            kyber_pre_key: None,
+            kyber_pre_keys: vec![],
         })
     }
 
+    /// Adds a Kyber pre-key to the bundle.
+    ///
+    /// A bundle can offer one pre-key for each KEM, letting the sender pick the one it prefers.
+    /// Adding a key of a type the bundle already has replaces the existing key.
     pub fn with_kyber_pre_key(
         mut self,
         pre_key_id: KyberPreKeyId,
         public_key: kem::PublicKey,
         signature: Vec<u8>,
     ) -> Self {
-        self.kyber_pre_key = Some(KyberPreKey::new(pre_key_id, public_key, signature));
+        let key_type = public_key.key_type();
+        let pre_key = KyberPreKey::new(pre_key_id, public_key, signature);
+        match self
+            .kyber_pre_keys
+            .iter_mut()
+            .find(|existing| existing.public_key.key_type() == key_type)
+        {
+            Some(existing) => *existing = pre_key,
+            None => self.kyber_pre_keys.push(pre_key),
+        }
         self
     }
 
@@ -218,27 +237,69 @@ impl PreKeyBundle {
     }
 
     pub fn has_kyber_pre_key(&self) -> bool {
-        self.kyber_pre_key.is_some()
+        !self.kyber_pre_keys.is_empty()
     }
 
+    // The kyber_pre_key_* accessors describe the first Kyber pre-key added to the bundle.
+
     pub fn kyber_pre_key_id(&self) -> Result<Option<KyberPreKeyId>> {
-        Ok(self.kyber_pre_key.as_ref().map(|pre_key| pre_key.id))
+        Ok(self.kyber_pre_keys.first().map(|pre_key| pre_key.id))
     }
 
     pub fn kyber_pre_key_public(&self) -> Result<Option<&kem::PublicKey>> {
         Ok(self
-            .kyber_pre_key
-            .as_ref()
+            .kyber_pre_keys
+            .first()
             .map(|pre_key| &pre_key.public_key))
     }
 
     pub fn kyber_pre_key_signature(&self) -> Result<Option<&[u8]>> {
         Ok(self
-            .kyber_pre_key
-            .as_ref()
+            .kyber_pre_keys
+            .first()
             .map(|pre_key| pre_key.signature.as_ref()))
     }
 
+    /// All the Kyber pre-keys offered by this bundle, as (ID, public key, signature).
+    pub fn kyber_pre_keys(
+        &self,
+    ) -> impl ExactSizeIterator<Item = (KyberPreKeyId, &kem::PublicKey, &[u8])> {
+        self.kyber_pre_keys.iter().map(|pre_key| {
+            (
+                pre_key.id,
+                &pre_key.public_key,
+                pre_key.signature.as_slice(),
+            )
+        })
+    }
+
+    /// Picks the Kyber pre-key to use for a new session: the first type in `preference` that this
+    /// bundle offers.
+    ///
+    /// Returns `None` if the bundle has no Kyber pre-keys, and an error if it only has pre-keys
+    /// for types not in `preference`.
+    pub fn select_kyber_pre_key(
+        &self,
+        preference: &[kem::KeyType],
+    ) -> Result<Option<(KyberPreKeyId, &kem::PublicKey)>> {
+        if self.kyber_pre_keys.is_empty() {
+            return Ok(None);
+        }
+        preference
+            .iter()
+            .find_map(|key_type| {
+                self.kyber_pre_keys
+                    .iter()
+                    .find(|pre_key| pre_key.public_key.key_type() == *key_type)
+            })
+            .map(|pre_key| Some((pre_key.id, &pre_key.public_key)))
+            .ok_or_else(|| {
+                SignalProtocolError::InvalidArgument(
+                    "bundle has no Kyber pre-key of a supported type".to_string(),
+                )
+            })
+    }
+
     pub fn modify<F>(self, modify: F) -> Result<Self>
     where
         F: FnOnce(&mut PreKeyBundleContent),
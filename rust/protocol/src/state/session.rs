@@ -9,11 +9,14 @@ use std::time::{Duration, SystemTime};
 use prost::Message;
 use subtle::ConstantTimeEq;
 
-use crate::ratchet::{ChainKey, MessageKeys, RootKey};
-use crate::{kem, IdentityKey, KeyPair, PrivateKey, PublicKey, SignalProtocolError};
-
-use crate::consts;
-use crate::proto::storage::{session_structure, RecordStructure, SessionStructure};
+use crate::ratchet::{pq, ChainKey, MessageKeys, RootKey};
+use crate::{
+    kem, IdentityKey, KeyPair, PrivateKey, ProtocolConfig, PublicKey, SignalProtocolError,
+};
+
+use crate::proto::storage::{
+    session_structure, PqRatchetStructure, RecordStructure, SessionStructure,
+};
 use crate::state::{KyberPreKeyId, PreKeyId, SignedPreKeyId};
 
 /// A distinct error type to keep from accidentally propagating deserialization errors.
@@ -119,6 +122,9 @@ impl SessionState {
                 remote_registration_id: 0,
                 local_registration_id: 0,
                 alice_base_key: alice_base_key.serialize().into_vec(),
+                kem_key_type: 0,
+                pq_ratchet: None,
+                ephemeral: false,
             },
         }
     }
@@ -211,14 +217,18 @@ impl SessionState {
         }
     }
 
-    pub fn has_usable_sender_chain(&self, now: SystemTime) -> Result<bool, InvalidSessionError> {
+    pub(crate) fn has_usable_sender_chain(
+        &self,
+        now: SystemTime,
+        config: &ProtocolConfig,
+    ) -> Result<bool, InvalidSessionError> {
         if self.session.sender_chain.is_none() {
             return Ok(false);
         }
         if let Some(pending_pre_key) = &self.session.pending_pre_key {
             let creation_timestamp =
                 SystemTime::UNIX_EPOCH + Duration::from_secs(pending_pre_key.timestamp);
-            if creation_timestamp + consts::MAX_UNACKNOWLEDGED_SESSION_AGE < now {
+            if creation_timestamp + config.max_unacknowledged_session_age < now {
                 return Ok(false);
             }
         }
@@ -273,7 +283,12 @@ impl SessionState {
         }
     }
 
-    pub(crate) fn add_receiver_chain(&mut self, sender: &PublicKey, chain_key: &ChainKey) {
+    pub(crate) fn add_receiver_chain(
+        &mut self,
+        sender: &PublicKey,
+        chain_key: &ChainKey,
+        config: &ProtocolConfig,
+    ) {
         let chain_key = session_structure::chain::ChainKey {
             index: chain_key.index(),
             key: chain_key.key().to_vec(),
@@ -288,19 +303,31 @@ impl SessionState {
 
         self.session.receiver_chains.push(chain);
 
-        if self.session.receiver_chains.len() > consts::MAX_RECEIVER_CHAINS {
+        // Ephemeral sessions only keep the newest chain, so that nothing sent on an older one can
+        // still be decrypted.
+        let max_receiver_chains = if self.ephemeral() {
+            1
+        } else {
+            config.max_receiver_chains
+        };
+
+        let receiver_chain_count = self.session.receiver_chains.len();
+        if receiver_chain_count > max_receiver_chains {
             log::info!(
                 "Trimming excessive receiver_chain for session with base key {}, chain count: {}",
                 self.sender_ratchet_key_for_logging()
                     .unwrap_or_else(|e| format!("<error: {}>", e.0)),
-                self.session.receiver_chains.len()
+                receiver_chain_count
             );
-            self.session.receiver_chains.remove(0);
+            self.session
+                .receiver_chains
+                .drain(..receiver_chain_count - max_receiver_chains);
         }
     }
 
     pub(crate) fn with_receiver_chain(mut self, sender: &PublicKey, chain_key: &ChainKey) -> Self {
-        self.add_receiver_chain(sender, chain_key);
+        // A brand new session has no other receiver chains, so the limits don't matter.
+        self.add_receiver_chain(sender, chain_key, &ProtocolConfig::DEFAULT);
         self
     }
 
@@ -415,6 +442,7 @@ impl SessionState {
         &mut self,
         sender: &PublicKey,
         message_keys: &MessageKeys,
+        config: &ProtocolConfig,
     ) -> Result<(), InvalidSessionError> {
         let new_keys = session_structure::chain::MessageKey {
             cipher_key: message_keys.cipher_key().to_vec(),
@@ -429,9 +457,7 @@ impl SessionState {
         let mut updated_chain = chain_and_index.0;
         updated_chain.message_keys.insert(0, new_keys);
 
-        if updated_chain.message_keys.len() > consts::MAX_MESSAGE_KEYS {
-            updated_chain.message_keys.pop();
-        }
+        updated_chain.message_keys.truncate(config.max_message_keys);
 
         self.session.receiver_chains[chain_and_index.1] = updated_chain;
 
@@ -531,6 +557,9 @@ impl SessionState {
             remote_registration_id: _remote_registration_id,
             local_registration_id: _local_registration_id,
             alice_base_key: _alice_base_key,
+            kem_key_type: _kem_key_type,
+            pq_ratchet: _pq_ratchet,
+            ephemeral: _ephemeral,
         } = &self.session;
         // ####### IMPORTANT #######
         // Don't forget to clean up new pending fields.
@@ -555,6 +584,76 @@ impl SessionState {
         self.session.local_registration_id
     }
 
+    pub(crate) fn describe(&self) -> Result<SessionStateDescription, InvalidSessionError> {
+        let sender_chain = self
+            .session
+            .sender_chain
+            .as_ref()
+            .map(ChainDescription::new)
+            .transpose()?;
+        let receiver_chains = self
+            .session
+            .receiver_chains
+            .iter()
+            .map(ChainDescription::new)
+            .collect::<Result<_, _>>()?;
+        let pending_pre_key =
+            self.unacknowledged_pre_key_message_items()?
+                .map(|items| PendingPreKeyDescription {
+                    pre_key_id: items.pre_key_id(),
+                    signed_pre_key_id: items.signed_pre_key_id(),
+                    kyber_pre_key_id: items.kyber_pre_key_id(),
+                    timestamp: items.timestamp(),
+                });
+
+        Ok(SessionStateDescription {
+            version: self.session_version()?,
+            previous_counter: self.previous_counter(),
+            sender_chain,
+            receiver_chains,
+            pending_pre_key,
+        })
+    }
+
+    pub(crate) fn set_kem_key_type(&mut self, key_type: kem::KeyType) {
+        self.session.kem_key_type = key_type.value().into();
+    }
+
+    pub(crate) fn kem_key_type(&self) -> Result<Option<kem::KeyType>, SignalProtocolError> {
+        match self.session.kem_key_type {
+            0 => Ok(None),
+            value => {
+                let value =
+                    u8::try_from(value).map_err(|_| InvalidSessionError("invalid KEM key type"))?;
+                Ok(Some(kem::KeyType::try_from(value)?))
+            }
+        }
+    }
+
+    pub(crate) fn set_ephemeral(&mut self, ephemeral: bool) {
+        self.session.ephemeral = ephemeral;
+    }
+
+    pub(crate) fn ephemeral(&self) -> bool {
+        self.session.ephemeral
+    }
+
+    pub(crate) fn pq_ratchet_mut(&mut self) -> Option<&mut PqRatchetStructure> {
+        self.session.pq_ratchet.as_mut()
+    }
+
+    pub(crate) fn take_pq_ratchet(&mut self) -> Option<PqRatchetStructure> {
+        self.session.pq_ratchet.take()
+    }
+
+    pub(crate) fn set_pq_ratchet(&mut self, pq_ratchet: Option<PqRatchetStructure>) {
+        self.session.pq_ratchet = pq_ratchet;
+    }
+
+    pub(crate) fn pq_ratchet_completed_epochs(&self) -> Option<u32> {
+        self.session.pq_ratchet.as_ref().map(pq::completed_epochs)
+    }
+
     pub(crate) fn get_kyber_ciphertext(&self) -> Option<&Vec<u8>> {
         self.session
             .pending_kyber_pre_key
@@ -665,27 +764,30 @@ impl SessionRecord {
         &mut self,
         old_session: usize,
         updated_session: SessionState,
+        config: &ProtocolConfig,
     ) {
         self.previous_sessions.remove(old_session);
-        self.promote_state(updated_session)
+        self.promote_state(updated_session, config)
     }
 
-    pub(crate) fn promote_state(&mut self, new_state: SessionState) {
-        self.archive_current_state_inner();
+    pub(crate) fn promote_state(&mut self, new_state: SessionState, config: &ProtocolConfig) {
+        self.archive_current_state_inner(config);
         self.current_session = Some(new_state);
     }
 
     // A non-fallible version of archive_current_state.
     //
     // Returns `true` if there was a session to archive, `false` if not.
-    fn archive_current_state_inner(&mut self) -> bool {
+    fn archive_current_state_inner(&mut self, config: &ProtocolConfig) -> bool {
         if let Some(mut current_session) = self.current_session.take() {
-            if self.previous_sessions.len() >= consts::ARCHIVED_STATES_MAX_LENGTH {
-                self.previous_sessions.pop();
+            if current_session.ephemeral() {
+                log::info!("Discarding ephemeral session instead of archiving it");
+                return true;
             }
             current_session.clear_unacknowledged_pre_key_message();
             self.previous_sessions
                 .insert(0, current_session.session.encode_to_vec());
+            self.previous_sessions.truncate(config.max_archived_states);
             true
         } else {
             false
@@ -693,7 +795,7 @@ impl SessionRecord {
     }
 
     pub fn archive_current_state(&mut self) -> Result<(), SignalProtocolError> {
-        if !self.archive_current_state_inner() {
+        if !self.archive_current_state_inner(&ProtocolConfig::DEFAULT) {
             log::info!("Skipping archive, current session state is fresh");
         }
         Ok(())
@@ -765,8 +867,18 @@ impl SessionRecord {
     }
 
     pub fn has_usable_sender_chain(&self, now: SystemTime) -> Result<bool, SignalProtocolError> {
+        self.has_usable_sender_chain_with_config(now, &ProtocolConfig::DEFAULT)
+    }
+
+    /// Like [`Self::has_usable_sender_chain`], but with the given limit on the age of sessions
+    /// that have not been acknowledged.
+    pub fn has_usable_sender_chain_with_config(
+        &self,
+        now: SystemTime,
+        config: &ProtocolConfig,
+    ) -> Result<bool, SignalProtocolError> {
         match &self.current_session {
-            Some(session) => Ok(session.has_usable_sender_chain(now)?),
+            Some(session) => Ok(session.has_usable_sender_chain(now, config)?),
             None => Ok(false),
         }
     }
@@ -818,6 +930,57 @@ impl SessionRecord {
         }
     }
 
+    /// Produces a summary of this record suitable for logging when diagnosing decryption failures.
+    ///
+    /// The summary includes no key material beyond public ratchet keys, which are already logged
+    /// when decryption fails.
+    pub fn describe(&self) -> Result<SessionRecordDescription, SignalProtocolError> {
+        Ok(SessionRecordDescription {
+            current_session: self
+                .current_session
+                .as_ref()
+                .map(SessionState::describe)
+                .transpose()?,
+            previous_sessions: self
+                .previous_session_states()
+                .map(|state| state?.describe())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// The KEM used to set up the current session, or `None` if it was set up without one.
+    ///
+    /// Sessions set up before this was recorded also report `None`.
+    pub fn kem_key_type(&self) -> Result<Option<kem::KeyType>, SignalProtocolError> {
+        self.session_state()
+            .ok_or_else(|| {
+                SignalProtocolError::InvalidState("kem_key_type", "No current session".into())
+            })?
+            .kem_key_type()
+    }
+
+    /// Whether the current session is ephemeral, keeping nothing that could decrypt a message
+    /// after the first attempt to decrypt it.
+    pub fn is_ephemeral(&self) -> Result<bool, SignalProtocolError> {
+        Ok(self
+            .session_state()
+            .ok_or_else(|| {
+                SignalProtocolError::InvalidState("is_ephemeral", "No current session".into())
+            })?
+            .ephemeral())
+    }
+
+    /// The number of post-quantum ratchet epochs mixed into the current session's root key, or
+    /// `None` if the session does not use the post-quantum ratchet.
+    pub fn pq_ratchet_epochs(&self) -> Result<Option<u32>, SignalProtocolError> {
+        Ok(self
+            .session_state()
+            .ok_or_else(|| {
+                SignalProtocolError::InvalidState("pq_ratchet_epochs", "No current session".into())
+            })?
+            .pq_ratchet_completed_epochs())
+    }
+
     pub fn get_kyber_ciphertext(&self) -> Result<Option<&Vec<u8>>, SignalProtocolError> {
         Ok(self
             .session_state()
@@ -830,3 +993,64 @@ impl SessionRecord {
             .get_kyber_ciphertext())
     }
 }
+
+/// A log-safe snapshot of a [SessionRecord], produced by [SessionRecord::describe].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionRecordDescription {
+    /// The session used for sending, if any.
+    pub current_session: Option<SessionStateDescription>,
+    /// Archived sessions still tried when decrypting, most recent first.
+    pub previous_sessions: Vec<SessionStateDescription>,
+}
+
+/// A log-safe snapshot of a single session within a [SessionRecord].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionStateDescription {
+    /// The message version used by this session.
+    pub version: u32,
+    /// The number of messages sent on the previous sending chain.
+    pub previous_counter: u32,
+    /// The current sending chain, if one has been set up.
+    pub sender_chain: Option<ChainDescription>,
+    /// Receiving chains, most recent first.
+    pub receiver_chains: Vec<ChainDescription>,
+    /// Set if the other party has not yet responded to this session's PreKey messages.
+    pub pending_pre_key: Option<PendingPreKeyDescription>,
+}
+
+/// A log-safe snapshot of a sending or receiving chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainDescription {
+    /// The hex-encoded public ratchet key identifying this chain.
+    pub ratchet_key: String,
+    /// The index of the next message key in the chain, if the chain key is present.
+    pub index: Option<u32>,
+    /// The number of skipped message keys cached for out-of-order messages.
+    pub cached_message_keys: usize,
+}
+
+impl ChainDescription {
+    fn new(chain: &session_structure::Chain) -> Result<Self, InvalidSessionError> {
+        let ratchet_key = PublicKey::deserialize(&chain.sender_ratchet_key)
+            .map_err(|_| InvalidSessionError("invalid chain ratchet key"))?;
+        Ok(Self {
+            ratchet_key: hex::encode(
+                ratchet_key
+                    .public_key_bytes()
+                    .expect("no invalid public keys"),
+            ),
+            index: chain.chain_key.as_ref().map(|chain_key| chain_key.index),
+            cached_message_keys: chain.message_keys.len(),
+        })
+    }
+}
+
+/// The pre-keys used to set up a session that has not been acknowledged by the other party.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingPreKeyDescription {
+    pub pre_key_id: Option<PreKeyId>,
+    pub signed_pre_key_id: SignedPreKeyId,
+    pub kyber_pre_key_id: Option<KyberPreKeyId>,
+    /// When the session was set up.
+    pub timestamp: SystemTime,
+}
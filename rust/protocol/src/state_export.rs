@@ -0,0 +1,310 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Moving sessions, sender keys, and identity trust decisions between stores.
+//!
+//! An export is a protobuf listing the records, encrypted with AES-256-GCM under a key chosen by
+//! the caller:
+//!
+//! ```text
+//! version (1 byte) || nonce (12 bytes) || ciphertext || tag (16 bytes)
+//! ```
+//!
+//! The version byte is included as associated data.
+
+use std::collections::BTreeMap;
+
+use prost::Message;
+use rand::{CryptoRng, Rng};
+use signal_crypto::{Aes256GcmDecryption, Aes256GcmEncryption};
+use uuid::Uuid;
+
+use crate::proto::storage::{protocol_state_export_structure, ProtocolStateExportStructure};
+use crate::{
+    IdentityKey, IdentityKeyStore, ProtocolAddress, Result, SenderKeyRecord, SenderKeyStore,
+    SessionRecord, SessionStore, SignalProtocolError,
+};
+
+const EXPORT_FORMAT_VERSION: u8 = 1;
+
+/// The size of the key passed to [`export_protocol_state`] and [`import_protocol_state`].
+pub const STATE_EXPORT_KEY_SIZE: usize = 32;
+
+/// The result of a successful [`import_protocol_state`].
+///
+/// Records already present in the destination are never overwritten; they are listed here
+/// instead.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProtocolStateImportSummary {
+    /// The number of sessions added to the destination.
+    pub imported_sessions: usize,
+    /// The number of identities added to the destination.
+    pub imported_identities: usize,
+    /// The number of sender keys added to the destination.
+    pub imported_sender_keys: usize,
+    /// Addresses whose session was skipped because the destination already had one.
+    pub existing_sessions: Vec<ProtocolAddress>,
+    /// Addresses whose identity differs from the one already trusted by the destination.
+    ///
+    /// Neither the identity nor the session for these addresses was imported.
+    pub conflicting_identities: Vec<ProtocolAddress>,
+    /// Sender keys skipped because the destination already had one.
+    pub existing_sender_keys: Vec<(ProtocolAddress, Uuid)>,
+}
+
+/// Exports the sessions and identities for `addresses`, and the sender keys for `sender_keys`,
+/// encrypted under `key`.
+///
+/// Addresses with no stored session, identity, or sender key are skipped.
+#[allow(clippy::too_many_arguments)]
+pub async fn export_protocol_state<R: Rng + CryptoRng>(
+    key: &[u8; STATE_EXPORT_KEY_SIZE],
+    addresses: &[ProtocolAddress],
+    sender_keys: &[(ProtocolAddress, Uuid)],
+    session_store: &dyn SessionStore,
+    identity_store: &dyn IdentityKeyStore,
+    sender_key_store: &mut dyn SenderKeyStore,
+    csprng: &mut R,
+) -> Result<Vec<u8>> {
+    let mut export = ProtocolStateExportStructure {
+        local_identity_key: identity_store
+            .get_identity_key_pair()
+            .await?
+            .identity_key()
+            .serialize()
+            .into_vec(),
+        ..Default::default()
+    };
+
+    for address in addresses {
+        if let Some(record) = session_store.load_session(address).await? {
+            export
+                .sessions
+                .push(protocol_state_export_structure::Session {
+                    name: address.name().to_owned(),
+                    device_id: address.device_id().into(),
+                    record: record.serialize()?,
+                });
+        }
+        if let Some(identity) = identity_store.get_identity(address).await? {
+            export
+                .identities
+                .push(protocol_state_export_structure::Identity {
+                    name: address.name().to_owned(),
+                    device_id: address.device_id().into(),
+                    identity_key: identity.serialize().into_vec(),
+                });
+        }
+    }
+
+    for (sender, distribution_id) in sender_keys {
+        if let Some(record) = sender_key_store
+            .load_sender_key(sender, *distribution_id)
+            .await?
+        {
+            export
+                .sender_keys
+                .push(protocol_state_export_structure::SenderKey {
+                    name: sender.name().to_owned(),
+                    device_id: sender.device_id().into(),
+                    distribution_id: distribution_id.as_bytes().to_vec(),
+                    record: record.serialize()?,
+                });
+        }
+    }
+
+    let nonce: [u8; Aes256GcmEncryption::NONCE_SIZE] = csprng.gen();
+    let mut gcm = Aes256GcmEncryption::new(key, &nonce, &[EXPORT_FORMAT_VERSION])
+        .expect("key and nonce sizes are valid");
+
+    let mut ciphertext = export.encode_to_vec();
+    gcm.encrypt(&mut ciphertext);
+    let tag = gcm.compute_tag();
+
+    let mut result =
+        Vec::with_capacity(1 + nonce.len() + ciphertext.len() + Aes256GcmEncryption::TAG_SIZE);
+    result.push(EXPORT_FORMAT_VERSION);
+    result.extend_from_slice(&nonce);
+    result.extend_from_slice(&ciphertext);
+    result.extend_from_slice(&tag);
+    Ok(result)
+}
+
+/// Decrypts an export produced by [`export_protocol_state`] and adds its contents to the given
+/// stores.
+///
+/// The whole export is validated before anything is written: it must decrypt under `key`, every
+/// record must parse, and it must have been made for the same local identity as
+/// `identity_store`'s. Existing records in the destination are left alone.
+///
+/// Any identity in the export that differs from one the destination already has is reported in
+/// [`ProtocolStateImportSummary::conflicting_identities`] rather than imported, and so is any
+/// session whose remote identity differs from the destination's. Records are processed in
+/// address order, so the summary's lists are deterministic.
+pub async fn import_protocol_state(
+    key: &[u8; STATE_EXPORT_KEY_SIZE],
+    exported: &[u8],
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    sender_key_store: &mut dyn SenderKeyStore,
+) -> Result<ProtocolStateImportSummary> {
+    let export = decrypt_export(key, exported)?;
+
+    let local_identity_key = identity_store
+        .get_identity_key_pair()
+        .await?
+        .identity_key()
+        .serialize();
+    if export.local_identity_key != *local_identity_key {
+        return Err(SignalProtocolError::InvalidState(
+            "import_protocol_state",
+            "export was made for a different local identity".to_string(),
+        ));
+    }
+
+    let identities = export
+        .identities
+        .into_iter()
+        .map(|identity| {
+            Ok((
+                ProtocolAddress::new(identity.name, identity.device_id.into()),
+                IdentityKey::decode(&identity.identity_key)?,
+            ))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    let sessions = export
+        .sessions
+        .into_iter()
+        .map(|session| {
+            let address = ProtocolAddress::new(session.name, session.device_id.into());
+            let record = SessionRecord::deserialize(&session.record)?;
+            let mut remote_identity_key = None;
+            if let Some(state) = record.session_state() {
+                if state.local_identity_key_bytes()? != *local_identity_key {
+                    return Err(SignalProtocolError::InvalidState(
+                        "import_protocol_state",
+                        "exported session belongs to a different local identity".to_string(),
+                    ));
+                }
+                remote_identity_key = state.remote_identity_key()?;
+                if let Some(identity) = identities.get(&address) {
+                    if remote_identity_key.as_ref() != Some(identity) {
+                        return Err(SignalProtocolError::InvalidState(
+                            "import_protocol_state",
+                            "exported session does not match exported identity".to_string(),
+                        ));
+                    }
+                }
+            }
+            Ok((address, (record, remote_identity_key)))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    let sender_keys = export
+        .sender_keys
+        .into_iter()
+        .map(|sender_key| {
+            let distribution_id = Uuid::from_slice(&sender_key.distribution_id)
+                .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+            Ok((
+                (
+                    ProtocolAddress::new(sender_key.name, sender_key.device_id.into()),
+                    distribution_id,
+                ),
+                SenderKeyRecord::deserialize(&sender_key.record)?,
+            ))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    let mut summary = ProtocolStateImportSummary::default();
+
+    for (address, identity) in &identities {
+        match identity_store.get_identity(address).await? {
+            None => {
+                identity_store.save_identity(address, identity).await?;
+                summary.imported_identities += 1;
+            }
+            Some(existing) if existing == *identity => {}
+            Some(_) => summary.conflicting_identities.push(address.clone()),
+        }
+    }
+
+    for (address, (record, remote_identity_key)) in sessions {
+        if summary.conflicting_identities.contains(&address) {
+            continue;
+        }
+        // The export may carry a session without the matching identity; it still must not
+        // contradict the identity the destination already trusts.
+        if let Some(remote_identity_key) = remote_identity_key {
+            match identity_store.get_identity(&address).await? {
+                Some(existing) if existing != remote_identity_key => {
+                    summary.conflicting_identities.push(address);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        if session_store.load_session(&address).await?.is_some() {
+            summary.existing_sessions.push(address);
+            continue;
+        }
+        session_store.store_session(&address, &record).await?;
+        summary.imported_sessions += 1;
+    }
+
+    for ((sender, distribution_id), record) in sender_keys {
+        if sender_key_store
+            .load_sender_key(&sender, distribution_id)
+            .await?
+            .is_some()
+        {
+            summary.existing_sender_keys.push((sender, distribution_id));
+            continue;
+        }
+        sender_key_store
+            .store_sender_key(&sender, distribution_id, &record)
+            .await?;
+        summary.imported_sender_keys += 1;
+    }
+
+    Ok(summary)
+}
+
+fn decrypt_export(
+    key: &[u8; STATE_EXPORT_KEY_SIZE],
+    exported: &[u8],
+) -> Result<ProtocolStateExportStructure> {
+    let (&version, rest) = exported
+        .split_first()
+        .ok_or_else(|| SignalProtocolError::InvalidArgument("empty state export".to_string()))?;
+    if version != EXPORT_FORMAT_VERSION {
+        return Err(SignalProtocolError::InvalidArgument(format!(
+            "unsupported state export version {}",
+            version
+        )));
+    }
+    if rest.len() < Aes256GcmDecryption::NONCE_SIZE + Aes256GcmDecryption::TAG_SIZE {
+        return Err(SignalProtocolError::InvalidArgument(
+            "state export is truncated".to_string(),
+        ));
+    }
+
+    let (nonce, rest) = rest.split_at(Aes256GcmDecryption::NONCE_SIZE);
+    let (ciphertext, tag) = rest.split_at(rest.len() - Aes256GcmDecryption::TAG_SIZE);
+
+    let mut gcm =
+        Aes256GcmDecryption::new(key, nonce, &[version]).expect("key and nonce sizes are valid");
+    let mut plaintext = ciphertext.to_vec();
+    gcm.decrypt(&mut plaintext);
+    gcm.verify_tag(tag).map_err(|_| {
+        SignalProtocolError::InvalidArgument(
+            "state export could not be authenticated with the given key".to_string(),
+        )
+    })?;
+
+    ProtocolStateExportStructure::decode(plaintext.as_slice())
+        .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)
+}
@@ -4,17 +4,27 @@
 //
 
 //! Interfaces in [traits] and reference implementations in [inmem] for various mutable stores.
+//!
+//! With the `fs-store` feature, [fs] provides implementations that persist to a local directory.
 
 #![warn(missing_docs)]
 
+#[cfg(feature = "fs-store")]
+mod fs;
 mod inmem;
 mod traits;
 
+#[cfg(feature = "fs-store")]
+pub use fs::{
+    FsIdentityKeyStore, FsKyberPreKeyStore, FsPreKeyStore, FsSenderKeyStore, FsSessionStore,
+    FsSignalProtocolStore, FsSignedPreKeyStore,
+};
+
 pub use inmem::{
     InMemIdentityKeyStore, InMemKyberPreKeyStore, InMemPreKeyStore, InMemSenderKeyStore,
     InMemSessionStore, InMemSignalProtocolStore, InMemSignedPreKeyStore,
 };
 pub use traits::{
-    Direction, IdentityKeyStore, KyberPreKeyStore, PreKeyStore, ProtocolStore, SenderKeyStore,
-    SessionStore, SignedPreKeyStore,
+    Direction, IdentityChange, IdentityKeyStore, KyberPreKeyStore, PreKeyStore, ProtocolStore,
+    SenderKeyStore, SessionStore, SignedPreKeyStore, TransactionalProtocolStore,
 };
@@ -0,0 +1,1064 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Implementations for stores defined in [super::traits], persisted to a local directory.
+//!
+//! Every record is kept in its own file, serialized with the record's usual `serialize` method.
+//! Individual writes are crash-safe: new contents are written to a temporary file, flushed to
+//! disk, and then moved over the old file. Transactions (see
+//! [traits::TransactionalProtocolStore]) are buffered in memory and committed through a journal
+//! file, which is replayed on the next [FsSignalProtocolStore::open] if the process dies partway
+//! through applying it.
+//!
+//! The layout of the directory is an implementation detail and may change.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use prost::Message;
+use uuid::Uuid;
+
+use crate::proto::storage::{
+    fs_store_journal_structure, FsStoreIdentityStructure, FsStoreJournalStructure,
+};
+use crate::state::GenericSignedPreKey;
+use crate::storage::traits;
+use crate::{
+    IdentityKey, IdentityKeyPair, KyberPreKeyId, KyberPreKeyRecord, PreKeyId, PreKeyRecord,
+    ProtocolAddress, Result, SenderKeyRecord, SessionRecord, SignalProtocolError, SignedPreKeyId,
+    SignedPreKeyRecord,
+};
+
+const IDENTITY_FILE: &str = "identity";
+const JOURNAL_FILE: &str = "journal";
+const TEMPORARY_SUFFIX: &str = ".tmp";
+
+const IDENTITIES_DIR: &str = "identities";
+const PRE_KEYS_DIR: &str = "pre_keys";
+const SIGNED_PRE_KEYS_DIR: &str = "signed_pre_keys";
+const KYBER_PRE_KEYS_DIR: &str = "kyber_pre_keys";
+const SESSIONS_DIR: &str = "sessions";
+const SENDER_KEYS_DIR: &str = "sender_keys";
+
+const ALL_DIRS: [&str; 6] = [
+    IDENTITIES_DIR,
+    PRE_KEYS_DIR,
+    SIGNED_PRE_KEYS_DIR,
+    KYBER_PRE_KEYS_DIR,
+    SESSIONS_DIR,
+    SENDER_KEYS_DIR,
+];
+
+/// An I/O failure, stripped down to something that can be carried in a [SignalProtocolError].
+///
+/// Deliberately does not include any paths, since those are derived from user addresses.
+#[derive(Debug, thiserror::Error)]
+#[error("I/O error: {0}")]
+struct FsStoreError(io::ErrorKind);
+
+fn io_error(method: &'static str) -> impl FnOnce(io::Error) -> SignalProtocolError {
+    move |e| SignalProtocolError::for_application_callback(method)(FsStoreError(e.kind()))
+}
+
+/// Pending writes for a transaction, keyed by path relative to the store root.
+///
+/// `None` means the file should be removed.
+type PendingWrites = BTreeMap<String, Option<Vec<u8>>>;
+
+/// State shared between all the stores that make up a [FsSignalProtocolStore].
+struct StoreDirectory {
+    root: PathBuf,
+    transaction: Mutex<Option<PendingWrites>>,
+}
+
+impl StoreDirectory {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            transaction: Mutex::new(None),
+        }
+    }
+
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        if let Some(pending) = self.lock_transaction().as_ref() {
+            if let Some(contents) = pending.get(name) {
+                return Ok(contents.clone());
+            }
+        }
+        match fs::read(self.root.join(name)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, name: String, contents: Vec<u8>) -> io::Result<()> {
+        if let Some(pending) = self.lock_transaction().as_mut() {
+            pending.insert(name, Some(contents));
+            return Ok(());
+        }
+        write_atomically(&self.root.join(name), &contents)
+    }
+
+    fn remove(&self, name: String) -> io::Result<()> {
+        if let Some(pending) = self.lock_transaction().as_mut() {
+            pending.insert(name, None);
+            return Ok(());
+        }
+        remove_durably(&self.root.join(name))
+    }
+
+    /// Lists the records in `dir`, including any changes pending in a transaction.
+    fn list(&self, dir: &str) -> io::Result<BTreeSet<String>> {
+        let mut names = BTreeSet::new();
+        for entry in fs::read_dir(self.root.join(dir))? {
+            // Anything that isn't valid UTF-8 wasn't written by us.
+            if let Ok(name) = entry?.file_name().into_string() {
+                if !name.ends_with(TEMPORARY_SUFFIX) {
+                    names.insert(name);
+                }
+            }
+        }
+        if let Some(pending) = self.lock_transaction().as_ref() {
+            let prefix = format!("{}/", dir);
+            for (name, contents) in pending {
+                if let Some(name) = name.strip_prefix(&prefix) {
+                    match contents {
+                        Some(_) => names.insert(name.to_owned()),
+                        None => names.remove(name),
+                    };
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Lists the ids of the pre-keys in `dir`, in increasing order.
+    fn list_ids<Id: From<u32> + Ord>(&self, dir: &str, method: &'static str) -> Result<Vec<Id>> {
+        let mut ids = self
+            .list(dir)
+            .map_err(io_error(method))?
+            .into_iter()
+            .filter_map(|name| name.parse::<u32>().ok().map(Id::from))
+            .collect::<Vec<_>>();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn lock_transaction(&self) -> std::sync::MutexGuard<'_, Option<PendingWrites>> {
+        self.transaction
+            .lock()
+            .expect("not poisoned; no panics while holding the lock")
+    }
+
+    fn begin_transaction(&self) -> Result<()> {
+        let mut transaction = self.lock_transaction();
+        if transaction.is_some() {
+            return Err(SignalProtocolError::InvalidState(
+                "begin_transaction",
+                "a transaction is already in progress".to_string(),
+            ));
+        }
+        *transaction = Some(PendingWrites::new());
+        Ok(())
+    }
+
+    fn take_transaction(&self, method: &'static str) -> Result<PendingWrites> {
+        self.lock_transaction().take().ok_or_else(|| {
+            SignalProtocolError::InvalidState(method, "no transaction in progress".to_string())
+        })
+    }
+
+    fn commit(&self, pending: PendingWrites) -> io::Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let journal = FsStoreJournalStructure {
+            entries: pending
+                .into_iter()
+                .map(|(name, contents)| fs_store_journal_structure::Entry { name, contents })
+                .collect(),
+        };
+        // Once the journal is on disk the transaction is committed, even if we crash while
+        // applying it.
+        let journal_path = self.root.join(JOURNAL_FILE);
+        write_atomically(&journal_path, &journal.encode_to_vec())?;
+        self.apply_journal(journal)?;
+        remove_durably(&journal_path)
+    }
+
+    fn apply_journal(&self, journal: FsStoreJournalStructure) -> io::Result<()> {
+        // A damaged journal must not be able to write outside the store.
+        if !journal
+            .entries
+            .iter()
+            .all(|entry| is_record_name(&entry.name))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "journal entry is not a record in the store",
+            ));
+        }
+        for entry in journal.entries {
+            let path = self.root.join(entry.name);
+            match entry.contents {
+                Some(contents) => write_atomically(&path, &contents)?,
+                None => remove_durably(&path)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Finishes applying a transaction that was committed but not fully applied.
+    fn recover(&self) -> Result<()> {
+        let journal_path = self.root.join(JOURNAL_FILE);
+        let journal = match fs::read(&journal_path) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(io_error("open")(e)),
+        };
+        let journal = FsStoreJournalStructure::decode(journal.as_slice())
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        log::info!(
+            "replaying store journal with {} entries",
+            journal.entries.len()
+        );
+        self.apply_journal(journal).map_err(io_error("open"))?;
+        remove_durably(&journal_path).map_err(io_error("open"))
+    }
+}
+
+/// Whether `name` is a record path this store could have produced: one of the record directories
+/// followed by a single file name made up of the characters our file names use.
+fn is_record_name(name: &str) -> bool {
+    let Some((dir, file)) = name.split_once('/') else {
+        return false;
+    };
+    ALL_DIRS.contains(&dir)
+        && !file.is_empty()
+        && file != "."
+        && file != ".."
+        && !file.ends_with(TEMPORARY_SUFFIX)
+        && file
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-')
+}
+
+/// Replaces the contents of `path` such that a crash leaves either the old or the new contents.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(TEMPORARY_SUFFIX);
+    let temporary_path = PathBuf::from(temporary_path);
+
+    let mut file = File::create(&temporary_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temporary_path, path)?;
+    sync_parent_dir(path)
+}
+
+fn remove_durably(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => sync_parent_dir(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    // Directories can only be opened (and thus synced) like this on Unix-like platforms.
+    if cfg!(unix) {
+        if let Some(parent) = path.parent() {
+            File::open(parent)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+/// Produces a file name for `address` that is safe on every platform.
+fn address_file_name(address: &ProtocolAddress) -> String {
+    format!("{}.{}", hex::encode(address.name()), address.device_id())
+}
+
+/// Persistent implementation of [traits::IdentityKeyStore].
+///
+/// Like [InMemIdentityKeyStore](super::InMemIdentityKeyStore), trusts any identity on first use.
+#[derive(Clone)]
+pub struct FsIdentityKeyStore {
+    directory: Arc<StoreDirectory>,
+    key_pair: IdentityKeyPair,
+    registration_id: u32,
+}
+
+impl FsIdentityKeyStore {
+    fn file_name(address: &ProtocolAddress) -> String {
+        format!("{}/{}", IDENTITIES_DIR, address_file_name(address))
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityKeyStore for FsIdentityKeyStore {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair> {
+        Ok(self.key_pair)
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32> {
+        Ok(self.registration_id)
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<bool> {
+        let existing = self.get_identity(address).await?;
+        if existing.as_ref() == Some(identity) {
+            return Ok(false); // same key
+        }
+        self.directory
+            .write(Self::file_name(address), identity.serialize().into_vec())
+            .map_err(io_error("save_identity"))?;
+        Ok(existing.is_some())
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        _direction: traits::Direction,
+    ) -> Result<bool> {
+        match self.get_identity(address).await? {
+            None => Ok(true), // first use
+            Some(k) => Ok(&k == identity),
+        }
+    }
+
+    async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
+        self.directory
+            .read(&Self::file_name(address))
+            .map_err(io_error("get_identity"))?
+            .map(|bytes| IdentityKey::decode(&bytes))
+            .transpose()
+    }
+}
+
+/// Persistent implementation of [traits::PreKeyStore].
+#[derive(Clone)]
+pub struct FsPreKeyStore {
+    directory: Arc<StoreDirectory>,
+}
+
+impl FsPreKeyStore {
+    fn file_name(id: PreKeyId) -> String {
+        format!("{}/{}", PRE_KEYS_DIR, id)
+    }
+
+    /// Returns all registered pre-key ids, in increasing order.
+    pub fn all_pre_key_ids(&self) -> Result<Vec<PreKeyId>> {
+        self.directory.list_ids(PRE_KEYS_DIR, "all_pre_key_ids")
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::PreKeyStore for FsPreKeyStore {
+    async fn get_pre_key(&self, id: PreKeyId) -> Result<PreKeyRecord> {
+        let bytes = self
+            .directory
+            .read(&Self::file_name(id))
+            .map_err(io_error("get_pre_key"))?
+            .ok_or(SignalProtocolError::InvalidPreKeyId)?;
+        PreKeyRecord::deserialize(&bytes)
+    }
+
+    async fn save_pre_key(&mut self, id: PreKeyId, record: &PreKeyRecord) -> Result<()> {
+        self.directory
+            .write(Self::file_name(id), record.serialize()?)
+            .map_err(io_error("save_pre_key"))
+    }
+
+    async fn remove_pre_key(&mut self, id: PreKeyId) -> Result<()> {
+        self.directory
+            .remove(Self::file_name(id))
+            .map_err(io_error("remove_pre_key"))
+    }
+}
+
+/// Persistent implementation of [traits::SignedPreKeyStore].
+#[derive(Clone)]
+pub struct FsSignedPreKeyStore {
+    directory: Arc<StoreDirectory>,
+}
+
+impl FsSignedPreKeyStore {
+    fn file_name(id: SignedPreKeyId) -> String {
+        format!("{}/{}", SIGNED_PRE_KEYS_DIR, id)
+    }
+
+    /// Returns all registered signed pre-key ids, in increasing order.
+    pub fn all_signed_pre_key_ids(&self) -> Result<Vec<SignedPreKeyId>> {
+        self.directory
+            .list_ids(SIGNED_PRE_KEYS_DIR, "all_signed_pre_key_ids")
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SignedPreKeyStore for FsSignedPreKeyStore {
+    async fn get_signed_pre_key(&self, id: SignedPreKeyId) -> Result<SignedPreKeyRecord> {
+        let bytes = self
+            .directory
+            .read(&Self::file_name(id))
+            .map_err(io_error("get_signed_pre_key"))?
+            .ok_or(SignalProtocolError::InvalidSignedPreKeyId)?;
+        SignedPreKeyRecord::deserialize(&bytes)
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<()> {
+        self.directory
+            .write(Self::file_name(id), record.serialize()?)
+            .map_err(io_error("save_signed_pre_key"))
+    }
+}
+
+/// Persistent implementation of [traits::KyberPreKeyStore].
+#[derive(Clone)]
+pub struct FsKyberPreKeyStore {
+    directory: Arc<StoreDirectory>,
+}
+
+impl FsKyberPreKeyStore {
+    fn file_name(id: KyberPreKeyId) -> String {
+        format!("{}/{}", KYBER_PRE_KEYS_DIR, id)
+    }
+
+    /// The marker recording that the key has been used. It isn't a number, so it doesn't show up
+    /// in [`Self::all_kyber_pre_key_ids`].
+    fn used_file_name(id: KyberPreKeyId) -> String {
+        format!("{}/{}.used", KYBER_PRE_KEYS_DIR, id)
+    }
+
+    /// Returns all registered Kyber pre-key ids, in increasing order.
+    pub fn all_kyber_pre_key_ids(&self) -> Result<Vec<KyberPreKeyId>> {
+        self.directory
+            .list_ids(KYBER_PRE_KEYS_DIR, "all_kyber_pre_key_ids")
+    }
+
+    /// Whether the Kyber pre-key `id` has been marked as used since it was last saved.
+    pub fn is_kyber_pre_key_used(&self, id: KyberPreKeyId) -> Result<bool> {
+        Ok(self
+            .directory
+            .read(&Self::used_file_name(id))
+            .map_err(io_error("is_kyber_pre_key_used"))?
+            .is_some())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::KyberPreKeyStore for FsKyberPreKeyStore {
+    async fn get_kyber_pre_key(&self, kyber_prekey_id: KyberPreKeyId) -> Result<KyberPreKeyRecord> {
+        let bytes = self
+            .directory
+            .read(&Self::file_name(kyber_prekey_id))
+            .map_err(io_error("get_kyber_pre_key"))?
+            .ok_or(SignalProtocolError::InvalidKyberPreKeyId)?;
+        KyberPreKeyRecord::deserialize(&bytes)
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<()> {
+        self.directory
+            .write(Self::file_name(kyber_prekey_id), record.serialize()?)
+            .map_err(io_error("save_kyber_pre_key"))?;
+        self.directory
+            .remove(Self::used_file_name(kyber_prekey_id))
+            .map_err(io_error("save_kyber_pre_key"))
+    }
+
+    async fn mark_kyber_pre_key_used(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        self.directory
+            .write(Self::used_file_name(kyber_prekey_id), vec![])
+            .map_err(io_error("mark_kyber_pre_key_used"))
+    }
+
+    async fn remove_kyber_pre_key(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        self.directory
+            .remove(Self::file_name(kyber_prekey_id))
+            .map_err(io_error("remove_kyber_pre_key"))?;
+        self.directory
+            .remove(Self::used_file_name(kyber_prekey_id))
+            .map_err(io_error("remove_kyber_pre_key"))
+    }
+}
+
+/// Persistent implementation of [traits::SessionStore].
+#[derive(Clone)]
+pub struct FsSessionStore {
+    directory: Arc<StoreDirectory>,
+}
+
+impl FsSessionStore {
+    fn file_name(address: &ProtocolAddress) -> String {
+        format!("{}/{}", SESSIONS_DIR, address_file_name(address))
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SessionStore for FsSessionStore {
+    async fn load_session(&self, address: &ProtocolAddress) -> Result<Option<SessionRecord>> {
+        self.directory
+            .read(&Self::file_name(address))
+            .map_err(io_error("load_session"))?
+            .map(|bytes| SessionRecord::deserialize(&bytes))
+            .transpose()
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<()> {
+        self.directory
+            .write(Self::file_name(address), record.serialize()?)
+            .map_err(io_error("store_session"))
+    }
+}
+
+/// Persistent implementation of [traits::SenderKeyStore].
+#[derive(Clone)]
+pub struct FsSenderKeyStore {
+    directory: Arc<StoreDirectory>,
+}
+
+impl FsSenderKeyStore {
+    fn file_name(sender: &ProtocolAddress, distribution_id: Uuid) -> String {
+        format!(
+            "{}/{}.{}",
+            SENDER_KEYS_DIR,
+            address_file_name(sender),
+            distribution_id.as_hyphenated()
+        )
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SenderKeyStore for FsSenderKeyStore {
+    async fn store_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+        record: &SenderKeyRecord,
+    ) -> Result<()> {
+        self.directory
+            .write(
+                Self::file_name(sender, distribution_id),
+                record.serialize()?,
+            )
+            .map_err(io_error("store_sender_key"))
+    }
+
+    async fn load_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+    ) -> Result<Option<SenderKeyRecord>> {
+        self.directory
+            .read(&Self::file_name(sender, distribution_id))
+            .map_err(io_error("load_sender_key"))?
+            .map(|bytes| SenderKeyRecord::deserialize(&bytes))
+            .transpose()
+    }
+}
+
+/// Persistent implementation of [traits::ProtocolStore], backed by a directory on disk.
+///
+/// Like [InMemSignalProtocolStore](super::InMemSignalProtocolStore), the individual stores are
+/// exposed as fields so they can be passed separately to the message functions. They share a
+/// single underlying directory, so a transaction started on this object covers all of them.
+#[allow(missing_docs)]
+#[derive(Clone)]
+pub struct FsSignalProtocolStore {
+    pub session_store: FsSessionStore,
+    pub pre_key_store: FsPreKeyStore,
+    pub signed_pre_key_store: FsSignedPreKeyStore,
+    pub kyber_pre_key_store: FsKyberPreKeyStore,
+    pub identity_store: FsIdentityKeyStore,
+    pub sender_key_store: FsSenderKeyStore,
+    directory: Arc<StoreDirectory>,
+}
+
+impl FsSignalProtocolStore {
+    /// Create a new store in `path`, representing the given identity `key_pair` along with the
+    /// separate randomly chosen `registration_id`.
+    ///
+    /// `path` is created if it does not exist; it is an error if it already contains a store.
+    pub fn create(
+        path: impl Into<PathBuf>,
+        key_pair: IdentityKeyPair,
+        registration_id: u32,
+    ) -> Result<Self> {
+        let root = path.into();
+        for dir in ALL_DIRS {
+            fs::create_dir_all(root.join(dir)).map_err(io_error("create"))?;
+        }
+
+        let identity_path = root.join(IDENTITY_FILE);
+        if identity_path.exists() {
+            return Err(SignalProtocolError::InvalidState(
+                "create",
+                "directory already contains a store".to_string(),
+            ));
+        }
+        let identity = FsStoreIdentityStructure {
+            identity_key_pair: key_pair.serialize().into_vec(),
+            registration_id,
+        };
+        write_atomically(&identity_path, &identity.encode_to_vec()).map_err(io_error("create"))?;
+
+        Ok(Self::with_directory(
+            StoreDirectory::new(root),
+            key_pair,
+            registration_id,
+        ))
+    }
+
+    /// Open a store previously created with [`Self::create`].
+    ///
+    /// If the process previously stopped partway through committing a transaction, the
+    /// transaction is completed first.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let directory = StoreDirectory::new(path.into());
+        let identity = fs::read(directory.root.join(IDENTITY_FILE)).map_err(io_error("open"))?;
+        let identity = FsStoreIdentityStructure::decode(identity.as_slice())
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        let key_pair = IdentityKeyPair::try_from(identity.identity_key_pair.as_slice())?;
+
+        directory.recover()?;
+
+        Ok(Self::with_directory(
+            directory,
+            key_pair,
+            identity.registration_id,
+        ))
+    }
+
+    fn with_directory(
+        directory: StoreDirectory,
+        key_pair: IdentityKeyPair,
+        registration_id: u32,
+    ) -> Self {
+        let directory = Arc::new(directory);
+        Self {
+            session_store: FsSessionStore {
+                directory: directory.clone(),
+            },
+            pre_key_store: FsPreKeyStore {
+                directory: directory.clone(),
+            },
+            signed_pre_key_store: FsSignedPreKeyStore {
+                directory: directory.clone(),
+            },
+            kyber_pre_key_store: FsKyberPreKeyStore {
+                directory: directory.clone(),
+            },
+            identity_store: FsIdentityKeyStore {
+                directory: directory.clone(),
+                key_pair,
+                registration_id,
+            },
+            sender_key_store: FsSenderKeyStore {
+                directory: directory.clone(),
+            },
+            directory,
+        }
+    }
+
+    /// The directory this store was opened from.
+    pub fn path(&self) -> &Path {
+        &self.directory.root
+    }
+
+    /// Returns all registered pre-key ids, in increasing order.
+    pub fn all_pre_key_ids(&self) -> Result<Vec<PreKeyId>> {
+        self.pre_key_store.all_pre_key_ids()
+    }
+
+    /// Returns all registered signed pre-key ids, in increasing order.
+    pub fn all_signed_pre_key_ids(&self) -> Result<Vec<SignedPreKeyId>> {
+        self.signed_pre_key_store.all_signed_pre_key_ids()
+    }
+
+    /// Returns all registered Kyber pre-key ids, in increasing order.
+    pub fn all_kyber_pre_key_ids(&self) -> Result<Vec<KyberPreKeyId>> {
+        self.kyber_pre_key_store.all_kyber_pre_key_ids()
+    }
+
+    /// Whether the Kyber pre-key `id` has been marked as used since it was last saved.
+    pub fn is_kyber_pre_key_used(&self, id: KyberPreKeyId) -> Result<bool> {
+        self.kyber_pre_key_store.is_kyber_pre_key_used(id)
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityKeyStore for FsSignalProtocolStore {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair> {
+        self.identity_store.get_identity_key_pair().await
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32> {
+        self.identity_store.get_local_registration_id().await
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<bool> {
+        self.identity_store.save_identity(address, identity).await
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        direction: traits::Direction,
+    ) -> Result<bool> {
+        self.identity_store
+            .is_trusted_identity(address, identity, direction)
+            .await
+    }
+
+    async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
+        self.identity_store.get_identity(address).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::PreKeyStore for FsSignalProtocolStore {
+    async fn get_pre_key(&self, id: PreKeyId) -> Result<PreKeyRecord> {
+        self.pre_key_store.get_pre_key(id).await
+    }
+
+    async fn save_pre_key(&mut self, id: PreKeyId, record: &PreKeyRecord) -> Result<()> {
+        self.pre_key_store.save_pre_key(id, record).await
+    }
+
+    async fn remove_pre_key(&mut self, id: PreKeyId) -> Result<()> {
+        self.pre_key_store.remove_pre_key(id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SignedPreKeyStore for FsSignalProtocolStore {
+    async fn get_signed_pre_key(&self, id: SignedPreKeyId) -> Result<SignedPreKeyRecord> {
+        self.signed_pre_key_store.get_signed_pre_key(id).await
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<()> {
+        self.signed_pre_key_store
+            .save_signed_pre_key(id, record)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::KyberPreKeyStore for FsSignalProtocolStore {
+    async fn get_kyber_pre_key(&self, kyber_prekey_id: KyberPreKeyId) -> Result<KyberPreKeyRecord> {
+        self.kyber_pre_key_store
+            .get_kyber_pre_key(kyber_prekey_id)
+            .await
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<()> {
+        self.kyber_pre_key_store
+            .save_kyber_pre_key(kyber_prekey_id, record)
+            .await
+    }
+
+    async fn mark_kyber_pre_key_used(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        self.kyber_pre_key_store
+            .mark_kyber_pre_key_used(kyber_prekey_id)
+            .await
+    }
+
+    async fn remove_kyber_pre_key(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        self.kyber_pre_key_store
+            .remove_kyber_pre_key(kyber_prekey_id)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SessionStore for FsSignalProtocolStore {
+    async fn load_session(&self, address: &ProtocolAddress) -> Result<Option<SessionRecord>> {
+        self.session_store.load_session(address).await
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<()> {
+        self.session_store.store_session(address, record).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SenderKeyStore for FsSignalProtocolStore {
+    async fn store_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+        record: &SenderKeyRecord,
+    ) -> Result<()> {
+        self.sender_key_store
+            .store_sender_key(sender, distribution_id, record)
+            .await
+    }
+
+    async fn load_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+    ) -> Result<Option<SenderKeyRecord>> {
+        self.sender_key_store
+            .load_sender_key(sender, distribution_id)
+            .await
+    }
+}
+
+impl traits::ProtocolStore for FsSignalProtocolStore {}
+
+#[async_trait(?Send)]
+impl traits::TransactionalProtocolStore for FsSignalProtocolStore {
+    async fn begin_transaction(&mut self) -> Result<()> {
+        self.directory.begin_transaction()
+    }
+
+    async fn commit_transaction(&mut self) -> Result<()> {
+        let pending = self.directory.take_transaction("commit_transaction")?;
+        self.directory
+            .commit(pending)
+            .map_err(io_error("commit_transaction"))
+    }
+
+    async fn rollback_transaction(&mut self) -> Result<()> {
+        self.directory
+            .take_transaction("rollback_transaction")
+            .map(|_pending| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use rand::rngs::OsRng;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        KeyPair, KyberPreKeyStore as _, PreKeyStore as _, TransactionalProtocolStore as _,
+    };
+
+    fn pre_key(id: u32) -> PreKeyRecord {
+        PreKeyRecord::new(id.into(), &KeyPair::generate(&mut OsRng))
+    }
+
+    #[test]
+    fn reopen_preserves_identity() {
+        let dir = TempDir::new().expect("can create temporary directory");
+        let key_pair = IdentityKeyPair::generate(&mut OsRng);
+        drop(FsSignalProtocolStore::create(dir.path(), key_pair, 1234).expect("can create"));
+
+        let store = FsSignalProtocolStore::open(dir.path()).expect("can open");
+        assert_eq!(
+            store.identity_store.key_pair.serialize(),
+            key_pair.serialize()
+        );
+        assert_eq!(store.identity_store.registration_id, 1234);
+
+        assert!(matches!(
+            FsSignalProtocolStore::create(dir.path(), key_pair, 1234),
+            Err(SignalProtocolError::InvalidState("create", _))
+        ));
+    }
+
+    #[test]
+    fn committed_journal_is_replayed_on_open() {
+        let dir = TempDir::new().expect("can create temporary directory");
+        let mut store =
+            FsSignalProtocolStore::create(dir.path(), IdentityKeyPair::generate(&mut OsRng), 1)
+                .expect("can create");
+        store
+            .save_pre_key(1.into(), &pre_key(1))
+            .now_or_never()
+            .expect("sync")
+            .expect("can save");
+
+        // Simulate a crash right after the journal was written.
+        let journal = FsStoreJournalStructure {
+            entries: vec![
+                fs_store_journal_structure::Entry {
+                    name: FsPreKeyStore::file_name(1.into()),
+                    contents: None,
+                },
+                fs_store_journal_structure::Entry {
+                    name: FsPreKeyStore::file_name(2.into()),
+                    contents: Some(pre_key(2).serialize().expect("can serialize")),
+                },
+            ],
+        };
+        write_atomically(&dir.path().join(JOURNAL_FILE), &journal.encode_to_vec())
+            .expect("can write journal");
+        drop(store);
+
+        let store = FsSignalProtocolStore::open(dir.path()).expect("can open");
+        assert!(!dir.path().join(JOURNAL_FILE).exists());
+        assert!(matches!(
+            store.get_pre_key(1.into()).now_or_never().expect("sync"),
+            Err(SignalProtocolError::InvalidPreKeyId)
+        ));
+        assert!(store
+            .get_pre_key(2.into())
+            .now_or_never()
+            .expect("sync")
+            .is_ok());
+    }
+
+    #[test]
+    fn journal_entries_outside_the_store_are_rejected() {
+        let parent = TempDir::new().expect("can create temporary directory");
+        let dir = parent.path().join("store");
+        drop(
+            FsSignalProtocolStore::create(&dir, IdentityKeyPair::generate(&mut OsRng), 1)
+                .expect("can create"),
+        );
+
+        for name in [
+            "../escaped",
+            "pre_keys/../../escaped",
+            "pre_keys/",
+            "pre_keys/..",
+            "unknown/1",
+            "identity",
+            "/tmp/escaped",
+        ] {
+            let journal = FsStoreJournalStructure {
+                entries: vec![fs_store_journal_structure::Entry {
+                    name: name.to_owned(),
+                    contents: Some(b"contents".to_vec()),
+                }],
+            };
+            write_atomically(&dir.join(JOURNAL_FILE), &journal.encode_to_vec())
+                .expect("can write journal");
+            assert!(
+                FsSignalProtocolStore::open(&dir).is_err(),
+                "accepted journal entry {name}"
+            );
+        }
+        assert!(!parent.path().join("escaped").exists());
+        assert_ne!(
+            fs::read(dir.join(IDENTITY_FILE)).expect("still there"),
+            b"contents"
+        );
+    }
+
+    #[test]
+    fn kyber_pre_key_used_state_persists() {
+        let dir = TempDir::new().expect("can create temporary directory");
+        let mut store =
+            FsSignalProtocolStore::create(dir.path(), IdentityKeyPair::generate(&mut OsRng), 1)
+                .expect("can create");
+        let identity_key = store.identity_store.key_pair;
+        let record = KyberPreKeyRecord::generate(
+            crate::kem::KeyType::Kyber1024,
+            1.into(),
+            identity_key.private_key(),
+        )
+        .expect("can generate");
+
+        async {
+            store.save_kyber_pre_key(1.into(), &record).await?;
+            assert!(!store.is_kyber_pre_key_used(1.into())?);
+            store.mark_kyber_pre_key_used(1.into()).await?;
+            assert!(store.is_kyber_pre_key_used(1.into())?);
+            assert_eq!(store.all_kyber_pre_key_ids()?, [1.into()]);
+            Ok::<_, SignalProtocolError>(())
+        }
+        .now_or_never()
+        .expect("sync")
+        .expect("success");
+        drop(store);
+
+        let mut store = FsSignalProtocolStore::open(dir.path()).expect("can open");
+        assert!(store.is_kyber_pre_key_used(1.into()).expect("can read"));
+
+        // Saving a new record under the same id starts it out unused.
+        store
+            .save_kyber_pre_key(1.into(), &record)
+            .now_or_never()
+            .expect("sync")
+            .expect("can save");
+        assert!(!store.is_kyber_pre_key_used(1.into()).expect("can read"));
+
+        // Removing a key also removes its used marker.
+        async {
+            store.mark_kyber_pre_key_used(1.into()).await?;
+            store.remove_kyber_pre_key(1.into()).await?;
+            assert!(store.get_kyber_pre_key(1.into()).await.is_err());
+            assert!(!store.is_kyber_pre_key_used(1.into())?);
+            Ok::<_, SignalProtocolError>(())
+        }
+        .now_or_never()
+        .expect("sync")
+        .expect("success");
+    }
+
+    #[test]
+    fn uncommitted_transaction_is_not_persisted() {
+        let dir = TempDir::new().expect("can create temporary directory");
+        let mut store =
+            FsSignalProtocolStore::create(dir.path(), IdentityKeyPair::generate(&mut OsRng), 1)
+                .expect("can create");
+
+        async {
+            store.begin_transaction().await?;
+            store.save_pre_key(1.into(), &pre_key(1)).await?;
+            // Visible within the transaction...
+            assert!(store.get_pre_key(1.into()).await.is_ok());
+            Ok::<_, SignalProtocolError>(())
+        }
+        .now_or_never()
+        .expect("sync")
+        .expect("success");
+        // ...but not on disk.
+        drop(store);
+
+        let store = FsSignalProtocolStore::open(dir.path()).expect("can open");
+        assert!(matches!(
+            store.get_pre_key(1.into()).now_or_never().expect("sync"),
+            Err(SignalProtocolError::InvalidPreKeyId)
+        ));
+    }
+}
@@ -16,7 +16,7 @@ use crate::{
 
 use async_trait::async_trait;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
 /// Reference implementation of [traits::IdentityKeyStore].
@@ -198,6 +198,7 @@ impl traits::SignedPreKeyStore for InMemSignedPreKeyStore {
 #[derive(Clone)]
 pub struct InMemKyberPreKeyStore {
     kyber_pre_keys: HashMap<KyberPreKeyId, KyberPreKeyRecord>,
+    used_kyber_pre_keys: HashSet<KyberPreKeyId>,
 }
 
 impl InMemKyberPreKeyStore {
@@ -205,6 +206,7 @@ impl InMemKyberPreKeyStore {
     pub fn new() -> Self {
         Self {
             kyber_pre_keys: HashMap::new(),
+            used_kyber_pre_keys: HashSet::new(),
         }
     }
 
@@ -212,6 +214,11 @@ impl InMemKyberPreKeyStore {
     pub fn all_kyber_pre_key_ids(&self) -> impl Iterator<Item = &KyberPreKeyId> {
         self.kyber_pre_keys.keys()
     }
+
+    /// Whether the Kyber pre-key `id` has been marked as used since it was last saved.
+    pub fn is_kyber_pre_key_used(&self, id: KyberPreKeyId) -> bool {
+        self.used_kyber_pre_keys.contains(&id)
+    }
 }
 
 impl Default for InMemKyberPreKeyStore {
@@ -237,10 +244,18 @@ impl traits::KyberPreKeyStore for InMemKyberPreKeyStore {
     ) -> Result<()> {
         self.kyber_pre_keys
             .insert(kyber_prekey_id, record.to_owned());
+        self.used_kyber_pre_keys.remove(&kyber_prekey_id);
         Ok(())
     }
 
-    async fn mark_kyber_pre_key_used(&mut self, _kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+    async fn mark_kyber_pre_key_used(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        self.used_kyber_pre_keys.insert(kyber_prekey_id);
+        Ok(())
+    }
+
+    async fn remove_kyber_pre_key(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        self.kyber_pre_keys.remove(&kyber_prekey_id);
+        self.used_kyber_pre_keys.remove(&kyber_prekey_id);
         Ok(())
     }
 }
@@ -355,6 +370,9 @@ impl traits::SenderKeyStore for InMemSenderKeyStore {
 }
 
 /// Reference implementation of [traits::ProtocolStore].
+///
+/// Also implements [traits::TransactionalProtocolStore] by snapshotting every contained store when
+/// a transaction begins.
 #[allow(missing_docs)]
 #[derive(Clone)]
 pub struct InMemSignalProtocolStore {
@@ -364,6 +382,18 @@ pub struct InMemSignalProtocolStore {
     pub kyber_pre_key_store: InMemKyberPreKeyStore,
     pub identity_store: InMemIdentityKeyStore,
     pub sender_key_store: InMemSenderKeyStore,
+    transaction_snapshot: Option<Box<InMemStoreSnapshot>>,
+}
+
+/// The state of an [InMemSignalProtocolStore] at the start of a transaction.
+#[derive(Clone)]
+struct InMemStoreSnapshot {
+    session_store: InMemSessionStore,
+    pre_key_store: InMemPreKeyStore,
+    signed_pre_key_store: InMemSignedPreKeyStore,
+    kyber_pre_key_store: InMemKyberPreKeyStore,
+    identity_store: InMemIdentityKeyStore,
+    sender_key_store: InMemSenderKeyStore,
 }
 
 impl InMemSignalProtocolStore {
@@ -377,6 +407,7 @@ impl InMemSignalProtocolStore {
             kyber_pre_key_store: InMemKyberPreKeyStore::new(),
             identity_store: InMemIdentityKeyStore::new(key_pair, registration_id),
             sender_key_store: InMemSenderKeyStore::new(),
+            transaction_snapshot: None,
         })
     }
 
@@ -394,6 +425,11 @@ impl InMemSignalProtocolStore {
     pub fn all_kyber_pre_key_ids(&self) -> impl Iterator<Item = &KyberPreKeyId> {
         self.kyber_pre_key_store.all_kyber_pre_key_ids()
     }
+
+    /// Whether the Kyber pre-key `id` has been marked as used since it was last saved.
+    pub fn is_kyber_pre_key_used(&self, id: KyberPreKeyId) -> bool {
+        self.kyber_pre_key_store.is_kyber_pre_key_used(id)
+    }
 }
 
 #[async_trait(?Send)]
@@ -485,6 +521,12 @@ impl traits::KyberPreKeyStore for InMemSignalProtocolStore {
             .mark_kyber_pre_key_used(kyber_prekey_id)
             .await
     }
+
+    async fn remove_kyber_pre_key(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        self.kyber_pre_key_store
+            .remove_kyber_pre_key(kyber_prekey_id)
+            .await
+    }
 }
 
 #[async_trait(?Send)]
@@ -527,3 +569,60 @@ impl traits::SenderKeyStore for InMemSignalProtocolStore {
 }
 
 impl traits::ProtocolStore for InMemSignalProtocolStore {}
+
+#[async_trait(?Send)]
+impl traits::TransactionalProtocolStore for InMemSignalProtocolStore {
+    async fn begin_transaction(&mut self) -> Result<()> {
+        if self.transaction_snapshot.is_some() {
+            return Err(SignalProtocolError::InvalidState(
+                "begin_transaction",
+                "a transaction is already in progress".to_string(),
+            ));
+        }
+        self.transaction_snapshot = Some(Box::new(InMemStoreSnapshot {
+            session_store: self.session_store.clone(),
+            pre_key_store: self.pre_key_store.clone(),
+            signed_pre_key_store: self.signed_pre_key_store.clone(),
+            kyber_pre_key_store: self.kyber_pre_key_store.clone(),
+            identity_store: self.identity_store.clone(),
+            sender_key_store: self.sender_key_store.clone(),
+        }));
+        Ok(())
+    }
+
+    async fn commit_transaction(&mut self) -> Result<()> {
+        // Everything is already applied in memory; just forget how to undo it.
+        self.transaction_snapshot
+            .take()
+            .ok_or_else(|| {
+                SignalProtocolError::InvalidState(
+                    "commit_transaction",
+                    "no transaction in progress".to_string(),
+                )
+            })
+            .map(|_snapshot| ())
+    }
+
+    async fn rollback_transaction(&mut self) -> Result<()> {
+        let InMemStoreSnapshot {
+            session_store,
+            pre_key_store,
+            signed_pre_key_store,
+            kyber_pre_key_store,
+            identity_store,
+            sender_key_store,
+        } = *self.transaction_snapshot.take().ok_or_else(|| {
+            SignalProtocolError::InvalidState(
+                "rollback_transaction",
+                "no transaction in progress".to_string(),
+            )
+        })?;
+        self.session_store = session_store;
+        self.pre_key_store = pre_key_store;
+        self.signed_pre_key_store = signed_pre_key_store;
+        self.kyber_pre_key_store = kyber_pre_key_store;
+        self.identity_store = identity_store;
+        self.sender_key_store = sender_key_store;
+        Ok(())
+    }
+}
@@ -14,7 +14,9 @@ use crate::state::{
     KyberPreKeyId, KyberPreKeyRecord, PreKeyId, PreKeyRecord, SessionRecord, SignedPreKeyId,
     SignedPreKeyRecord,
 };
-use crate::{IdentityKey, IdentityKeyPair, ProtocolAddress};
+use crate::{
+    IdentityKey, IdentityKeyPair, IdentityKeySuccession, ProtocolAddress, SignalProtocolError,
+};
 
 // TODO: consider moving this enum into utils.rs?
 /// Each Signal message can be considered to have exactly two participants, a sender and receiver.
@@ -29,6 +31,32 @@ pub enum Direction {
     Receiving,
 }
 
+/// How saving an identity with [IdentityKeyStore::save_identity_reporting_change] or
+/// [IdentityKeyStore::save_identity_with_succession] changed the store.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IdentityChange {
+    /// The identity was not known before, or was already the stored one.
+    NewOrUnchanged,
+    /// The identity replaced a different one, which should be treated as a safety number change.
+    ReplacedExisting,
+    /// The identity replaced a different one that signed an [IdentityKeySuccession] for it.
+    ///
+    /// The other user rotated their identity key deliberately, so this does not need the scrutiny
+    /// of [IdentityChange::ReplacedExisting].
+    RotatedWithProof,
+}
+
+impl IdentityChange {
+    /// Converts the result of a store that only reports whether an existing identity was replaced.
+    pub fn from_changed(changed: bool) -> Self {
+        if changed {
+            Self::ReplacedExisting
+        } else {
+            Self::NewOrUnchanged
+        }
+    }
+}
+
 /// Interface defining the identity store, which may be in-memory, on-disk, etc.
 ///
 /// Signal clients usually use the identity store in a [TOFU] manner, but this is not required.
@@ -60,6 +88,51 @@ pub trait IdentityKeyStore {
         identity: &IdentityKey,
     ) -> Result<bool>;
 
+    /// Like [Self::save_identity], but reports the result as an [IdentityChange].
+    async fn save_identity_reporting_change(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<IdentityChange> {
+        Ok(IdentityChange::from_changed(
+            self.save_identity(address, identity).await?,
+        ))
+    }
+
+    /// Record the new identity from `succession`, which must be signed by the identity currently
+    /// stored for `address`. The new identity is then considered "trusted".
+    ///
+    /// Returns [IdentityChange::RotatedWithProof] if the stored identity was replaced, and
+    /// [IdentityChange::NewOrUnchanged] if the new identity was already stored or no identity was
+    /// known for `address` yet. Fails with [SignalProtocolError::SignatureValidationFailed] if
+    /// the succession is not validly signed, and with [SignalProtocolError::UntrustedIdentity] if
+    /// it rotates away from an identity other than the stored one; the store is left unchanged in
+    /// either case.
+    async fn save_identity_with_succession(
+        &mut self,
+        address: &ProtocolAddress,
+        succession: &IdentityKeySuccession,
+    ) -> Result<IdentityChange> {
+        if !succession.verify()? {
+            return Err(SignalProtocolError::SignatureValidationFailed);
+        }
+        match self.get_identity(address).await? {
+            None => {
+                self.save_identity_reporting_change(address, succession.new_identity())
+                    .await
+            }
+            Some(current) if current == *succession.new_identity() => {
+                Ok(IdentityChange::NewOrUnchanged)
+            }
+            Some(current) if current == *succession.old_identity() => {
+                self.save_identity(address, succession.new_identity())
+                    .await?;
+                Ok(IdentityChange::RotatedWithProof)
+            }
+            Some(_) => Err(SignalProtocolError::UntrustedIdentity(address.clone())),
+        }
+    }
+
     /// Return whether an identity is trusted for the role specified by `direction`.
     async fn is_trusted_identity(
         &self,
@@ -120,6 +193,17 @@ pub trait KyberPreKeyStore {
     /// Mark the entry for `kyber_prekey_id` as "used".
     /// This would mean different things for one-time and last-resort Kyber keys.
     async fn mark_kyber_pre_key_used(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()>;
+
+    /// Remove the entry for `kyber_prekey_id`.
+    ///
+    /// Only needed by [crate::PreKeyManager], which deletes Kyber pre-keys once they can no longer
+    /// be used; stores that are not used with it need not implement this.
+    async fn remove_kyber_pre_key(&mut self, _kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        Err(SignalProtocolError::InvalidState(
+            "remove_kyber_pre_key",
+            "this store does not support removing Kyber pre-keys".to_owned(),
+        ))
+    }
 }
 
 /// Interface for a Signal client instance to store a session associated with another particular
@@ -167,3 +251,34 @@ pub trait ProtocolStore:
     SessionStore + PreKeyStore + SignedPreKeyStore + KyberPreKeyStore + IdentityKeyStore
 {
 }
+
+/// A [ProtocolStore] that can group several mutations into a single atomic update.
+///
+/// Processing one message can touch several stores: for example, [message_decrypt_prekey] may call
+/// [IdentityKeyStore::save_identity], [SessionStore::store_session],
+/// [PreKeyStore::remove_pre_key], and [KyberPreKeyStore::mark_kyber_pre_key_used] in turn. If the
+/// caller brackets the operation with [begin_transaction](Self::begin_transaction) and
+/// [commit_transaction](Self::commit_transaction) (or
+/// [rollback_transaction](Self::rollback_transaction) on error), either all of those updates are
+/// applied or none of them are.
+///
+/// Transactions do not nest; beginning a transaction while one is already in progress is an
+/// error.
+///
+/// [message_decrypt_prekey]: crate::message_decrypt_prekey
+#[async_trait(?Send)]
+pub trait TransactionalProtocolStore: ProtocolStore {
+    /// Start a new transaction.
+    ///
+    /// Until the transaction is committed, none of the mutations made through the store
+    /// interfaces need to be durable.
+    async fn begin_transaction(&mut self) -> Result<()>;
+
+    /// Atomically apply every mutation made since the last call to
+    /// [begin_transaction](Self::begin_transaction).
+    async fn commit_transaction(&mut self) -> Result<()>;
+
+    /// Discard every mutation made since the last call to
+    /// [begin_transaction](Self::begin_transaction).
+    async fn rollback_transaction(&mut self) -> Result<()>;
+}
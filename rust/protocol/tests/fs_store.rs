@@ -0,0 +1,280 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Runs the session scenarios against [FsSignalProtocolStore], plus checks that state survives
+//! reopening the store.
+
+#[path = "session/scenarios.rs"]
+mod scenarios;
+mod support;
+
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+
+use std::time::SystemTime;
+use support::*;
+
+type Store = FsSignalProtocolStore;
+type TestResult = Result<(), SignalProtocolError>;
+
+fn reopen(store: Store) -> Result<Store, SignalProtocolError> {
+    let path = store.path().to_owned();
+    drop(store);
+    Store::open(path)
+}
+
+/// Sets up a session from Alice to Bob and returns the first message, not yet decrypted.
+async fn start_session(
+    alice_store: &mut Store,
+    bob_store: &mut Store,
+    bob_address: &ProtocolAddress,
+    original_message: &str,
+) -> Result<CiphertextMessage, SignalProtocolError> {
+    let mut csprng = OsRng;
+    let bob_pre_key_bundle = create_pre_key_bundle(bob_store, &mut csprng).await?;
+
+    process_prekey_bundle(
+        bob_address,
+        &mut alice_store.session_store,
+        &mut alice_store.identity_store,
+        &bob_pre_key_bundle,
+        SystemTime::now(),
+        &mut csprng,
+    )
+    .await?;
+
+    let outgoing_message = encrypt(alice_store, bob_address, original_message).await?;
+    assert_eq!(
+        outgoing_message.message_type(),
+        CiphertextMessageType::PreKey
+    );
+    Ok(outgoing_message)
+}
+
+#[test]
+fn test_basic_prekey_persists_across_reopen() -> TestResult {
+    async {
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store: Store = test_protocol_store();
+        let mut bob_store: Store = test_protocol_store();
+
+        let original_message = "L'homme est condamné à être libre";
+        let outgoing_message = start_session(
+            &mut alice_store,
+            &mut bob_store,
+            &bob_address,
+            original_message,
+        )
+        .await?;
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &outgoing_message).await?,
+            original_message.as_bytes()
+        );
+
+        let bob_identity = *bob_store.get_identity_key_pair().await?.identity_key();
+        let bob_registration_id = bob_store.get_local_registration_id().await?;
+        let mut alice_store = reopen(alice_store)?;
+        let mut bob_store = reopen(bob_store)?;
+
+        assert_eq!(
+            *bob_store.get_identity_key_pair().await?.identity_key(),
+            bob_identity
+        );
+        assert_eq!(
+            bob_store.get_local_registration_id().await?,
+            bob_registration_id
+        );
+        assert_eq!(
+            alice_store.get_identity(&bob_address).await?,
+            Some(bob_identity)
+        );
+        assert_eq!(
+            alice_store.session_version(&bob_address)?,
+            KYBER_AWARE_MESSAGE_VERSION
+        );
+
+        let bob_response = "Who watches the watchers?";
+        let bob_outgoing = encrypt(&mut bob_store, &alice_address, bob_response).await?;
+        assert_eq!(bob_outgoing.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &bob_outgoing).await?,
+            bob_response.as_bytes()
+        );
+
+        // The one-time pre-key was consumed, so replaying the first message must fail.
+        assert!(decrypt(&mut bob_store, &alice_address, &outgoing_message)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_shuffled_interaction_across_reopen() -> TestResult {
+    async {
+        let mut csprng = OsRng;
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), 1.into());
+
+        let mut alice_store: Store = test_protocol_store();
+        let mut bob_store: Store = test_protocol_store();
+
+        let original_message = "smert ze smert";
+        let outgoing_message = start_session(
+            &mut alice_store,
+            &mut bob_store,
+            &bob_address,
+            original_message,
+        )
+        .await?;
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &outgoing_message).await?,
+            original_message.as_bytes()
+        );
+
+        const ALICE_MESSAGE_COUNT: usize = 50;
+        let mut alice_messages = Vec::with_capacity(ALICE_MESSAGE_COUNT);
+        for i in 0..ALICE_MESSAGE_COUNT {
+            let ptext = format!("смерть за смерть {}", i);
+            let ctext = encrypt(&mut alice_store, &bob_address, &ptext).await?;
+            alice_messages.push((ptext, ctext));
+        }
+        alice_messages.shuffle(&mut csprng);
+
+        // Receive half of the messages, then restart Bob; the skipped message keys for the
+        // other half must have been persisted.
+        let (first_half, second_half) = alice_messages.split_at(ALICE_MESSAGE_COUNT / 2);
+        for (ptext, ctext) in first_half {
+            assert_eq!(
+                decrypt(&mut bob_store, &alice_address, ctext).await?,
+                ptext.as_bytes()
+            );
+        }
+        let mut bob_store = reopen(bob_store)?;
+        for (ptext, ctext) in second_half {
+            assert_eq!(
+                decrypt(&mut bob_store, &alice_address, ctext).await?,
+                ptext.as_bytes()
+            );
+        }
+
+        let bob_response = "Ничто не истинно, все разрешено";
+        let bob_outgoing = encrypt(&mut bob_store, &alice_address, bob_response).await?;
+        let mut alice_store = reopen(alice_store)?;
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &bob_outgoing).await?,
+            bob_response.as_bytes()
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_transaction_persists_only_when_committed() -> TestResult {
+    async {
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store: Store = test_protocol_store();
+        let mut bob_store: Store = test_protocol_store();
+
+        let original_message = "Tout est permis";
+        let outgoing_message = start_session(
+            &mut alice_store,
+            &mut bob_store,
+            &bob_address,
+            original_message,
+        )
+        .await?;
+
+        bob_store.begin_transaction().await?;
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &outgoing_message).await?,
+            original_message.as_bytes()
+        );
+        let mut bob_store = reopen(bob_store)?;
+        assert!(bob_store.load_session(&alice_address).await?.is_none());
+
+        bob_store.begin_transaction().await?;
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &outgoing_message).await?,
+            original_message.as_bytes()
+        );
+        bob_store.commit_transaction().await?;
+        let bob_store = reopen(bob_store)?;
+        assert!(bob_store.load_session(&alice_address).await?.is_some());
+        assert!(bob_store.get_identity(&alice_address).await?.is_some());
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_group_sender_key_persists_across_reopen() -> TestResult {
+    async {
+        let mut csprng = OsRng;
+
+        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1.into());
+        let distribution_id = uuid::Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store: Store = test_protocol_store();
+        let mut bob_store: Store = test_protocol_store();
+
+        let sent_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        let recv_distribution_message =
+            SenderKeyDistributionMessage::try_from(sent_distribution_message.serialized())?;
+        process_sender_key_distribution_message(
+            &sender_address,
+            &recv_distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        let mut alice_store = reopen(alice_store)?;
+        let mut bob_store = reopen(bob_store)?;
+
+        let alice_ciphertext = group_encrypt(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "space camp?".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+        let bob_plaintext = group_decrypt(
+            alice_ciphertext.serialized(),
+            &mut bob_store,
+            &sender_address,
+        )
+        .await?;
+        assert_eq!(
+            String::from_utf8(bob_plaintext).expect("valid utf8"),
+            "space camp?"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -11,7 +11,7 @@ use rand::rngs::OsRng;
 use rand::seq::SliceRandom;
 use rand::Rng;
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use support::*;
 use uuid::Uuid;
 
@@ -141,6 +141,125 @@ fn group_basic_encrypt_decrypt() -> Result<(), SignalProtocolError> {
     .expect("sync")
 }
 
+#[test]
+fn group_decryption_error_redistributes_sender_key() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng;
+
+        let alice_address = ProtocolAddress::new("+14159999111".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14159999222".to_owned(), 1.into());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        // Bob never receives this.
+        let _lost_distribution_message = create_sender_key_distribution_message(
+            &alice_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+
+        let alice_ciphertext = group_encrypt(
+            &mut alice_store,
+            &alice_address,
+            distribution_id,
+            "space camp?".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+        assert!(matches!(
+            group_decrypt(
+                alice_ciphertext.serialized(),
+                &mut bob_store,
+                &alice_address
+            )
+            .await,
+            Err(SignalProtocolError::NoSenderKeyState { .. })
+        ));
+
+        let error_message = DecryptionErrorMessage::for_original(
+            alice_ciphertext.serialized(),
+            CiphertextMessageType::SenderKey,
+            Timestamp::from_epoch_millis(408),
+            alice_address.device_id().into(),
+        )?;
+
+        assert!(matches!(
+            handle_decryption_error_message(
+                &error_message,
+                &bob_address,
+                &alice_address,
+                None,
+                &mut alice_store.session_store,
+                &mut alice_store.sender_key_store,
+            )
+            .await,
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+        let unknown_distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a7);
+        assert!(matches!(
+            handle_decryption_error_message(
+                &error_message,
+                &bob_address,
+                &alice_address,
+                Some(unknown_distribution_id),
+                &mut alice_store.session_store,
+                &mut alice_store.sender_key_store,
+            )
+            .await,
+            Err(SignalProtocolError::NoSenderKeyState { distribution_id })
+                if distribution_id == unknown_distribution_id
+        ));
+
+        let action = handle_decryption_error_message(
+            &error_message,
+            &bob_address,
+            &alice_address,
+            Some(distribution_id),
+            &mut alice_store.session_store,
+            &mut alice_store.sender_key_store,
+        )
+        .await?;
+        let DecryptionErrorAction::ResendWithSenderKeyDistribution(distribution_message) = action
+        else {
+            panic!("unexpected action {action:?}");
+        };
+        assert_eq!(distribution_message.distribution_id()?, distribution_id);
+
+        process_sender_key_distribution_message(
+            &alice_address,
+            &SenderKeyDistributionMessage::try_from(distribution_message.serialized())?,
+            &mut bob_store,
+        )
+        .await?;
+
+        let resent_ciphertext = group_encrypt(
+            &mut alice_store,
+            &alice_address,
+            distribution_id,
+            "space camp?".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(
+            group_decrypt(
+                resent_ciphertext.serialized(),
+                &mut bob_store,
+                &alice_address
+            )
+            .await?,
+            b"space camp?"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
 #[test]
 fn group_sealed_sender() -> Result<(), SignalProtocolError> {
     async {
@@ -1280,3 +1399,438 @@ fn group_message_key_limit() -> Result<(), SignalProtocolError> {
     .now_or_never()
     .expect("sync")
 }
+
+#[test]
+fn group_custom_limits() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng;
+
+        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1.into());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        let config = ProtocolConfig {
+            max_forward_jumps: 10,
+            max_message_keys: 3,
+            max_sender_key_states: 2,
+            ..Default::default()
+        };
+
+        let sent_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        let recv_distribution_message =
+            SenderKeyDistributionMessage::try_from(sent_distribution_message.serialized())?;
+        process_sender_key_distribution_message_with_config(
+            &sender_address,
+            &recv_distribution_message,
+            &mut bob_store,
+            &config,
+        )
+        .await?;
+
+        let mut ciphertexts = vec![];
+        for i in 0..12 {
+            ciphertexts.push(
+                group_encrypt(
+                    &mut alice_store,
+                    &sender_address,
+                    distribution_id,
+                    format!("message {}", i).as_bytes(),
+                    &mut csprng,
+                )
+                .await?
+                .serialized()
+                .to_vec(),
+            );
+        }
+
+        // Too far ahead for the custom limit, though well within the default one.
+        assert!(matches!(
+            group_decrypt_with_config(&ciphertexts[11], &mut bob_store, &sender_address, &config)
+                .await,
+            Err(SignalProtocolError::InvalidMessage(
+                CiphertextMessageType::SenderKey,
+                _
+            ))
+        ));
+
+        // Skips messages 0 through 4, but only keeps keys for the most recent three.
+        assert_eq!(
+            group_decrypt_with_config(&ciphertexts[5], &mut bob_store, &sender_address, &config)
+                .await?,
+            b"message 5"
+        );
+        assert!(matches!(
+            group_decrypt_with_config(&ciphertexts[1], &mut bob_store, &sender_address, &config)
+                .await,
+            Err(SignalProtocolError::DuplicatedMessage(6, 1))
+        ));
+        assert_eq!(
+            group_decrypt_with_config(&ciphertexts[2], &mut bob_store, &sender_address, &config)
+                .await?,
+            b"message 2"
+        );
+        assert_eq!(
+            group_decrypt_with_config(&ciphertexts[11], &mut bob_store, &sender_address, &config)
+                .await?,
+            b"message 11"
+        );
+
+        // Each new sender key for the same distribution ID adds a state; only two are kept.
+        for _ in 0..2 {
+            let mut new_alice_store = test_in_memory_protocol_store()?;
+            let sent_distribution_message = create_sender_key_distribution_message(
+                &sender_address,
+                distribution_id,
+                &mut new_alice_store,
+                &mut csprng,
+            )
+            .await?;
+            let recv_distribution_message =
+                SenderKeyDistributionMessage::try_from(sent_distribution_message.serialized())?;
+            process_sender_key_distribution_message_with_config(
+                &sender_address,
+                &recv_distribution_message,
+                &mut bob_store,
+                &config,
+            )
+            .await?;
+        }
+
+        let late_ciphertext = group_encrypt(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "anyone there?".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+        assert!(matches!(
+            group_decrypt_with_config(
+                late_ciphertext.serialized(),
+                &mut bob_store,
+                &sender_address,
+                &config
+            )
+            .await,
+            Err(SignalProtocolError::NoSenderKeyState { .. })
+        ));
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn group_rotation_after_message_limit() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng;
+
+        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1.into());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        let policy = SenderKeyRotationPolicy {
+            max_messages: Some(3),
+            max_age: None,
+        };
+        let now = SystemTime::now();
+
+        // No sender key yet, so nothing to rotate.
+        assert!(rotate_sender_key_if_needed(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &policy,
+            now,
+            &mut csprng,
+        )
+        .await?
+        .is_none());
+
+        let first_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &sender_address,
+            &first_distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        let mut ciphertexts = Vec::new();
+        for i in 0..3 {
+            assert!(rotate_sender_key_if_needed(
+                &sender_address,
+                distribution_id,
+                &mut alice_store,
+                &policy,
+                now,
+                &mut csprng,
+            )
+            .await?
+            .is_none());
+            ciphertexts.push(
+                group_encrypt(
+                    &mut alice_store,
+                    &sender_address,
+                    distribution_id,
+                    format!("message {}", i).as_bytes(),
+                    &mut csprng,
+                )
+                .await?,
+            );
+        }
+
+        let second_distribution_message = rotate_sender_key_if_needed(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &policy,
+            now,
+            &mut csprng,
+        )
+        .await?
+        .expect("rotated after three messages");
+        assert_ne!(
+            first_distribution_message.chain_id()?,
+            second_distribution_message.chain_id()?
+        );
+        assert_eq!(second_distribution_message.iteration()?, 0);
+
+        let new_ciphertext = group_encrypt(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "after rotation".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+
+        // Without the new distribution message, Bob can't read the new chain...
+        assert!(matches!(
+            group_decrypt(new_ciphertext.serialized(), &mut bob_store, &sender_address).await,
+            Err(SignalProtocolError::NoSenderKeyState { .. })
+        ));
+
+        // ...but with it, he can read both chains.
+        process_sender_key_distribution_message(
+            &sender_address,
+            &second_distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+        assert_eq!(
+            group_decrypt(new_ciphertext.serialized(), &mut bob_store, &sender_address).await?,
+            b"after rotation"
+        );
+        for (i, ciphertext) in ciphertexts.iter().enumerate() {
+            assert_eq!(
+                group_decrypt(ciphertext.serialized(), &mut bob_store, &sender_address).await?,
+                format!("message {}", i).as_bytes()
+            );
+        }
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn group_rotation_after_max_age() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng;
+
+        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1.into());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+
+        let week = Duration::from_secs(60 * 60 * 24 * 7);
+        let policy = SenderKeyRotationPolicy {
+            max_messages: None,
+            max_age: Some(week),
+        };
+
+        let created_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let first_distribution_message = create_sender_key_distribution_message_at(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            created_at,
+            &mut csprng,
+        )
+        .await?;
+
+        assert!(rotate_sender_key_if_needed(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &policy,
+            created_at + week / 2,
+            &mut csprng,
+        )
+        .await?
+        .is_none());
+
+        let rotation_time = created_at + week + Duration::from_secs(1);
+        let second_distribution_message = rotate_sender_key_if_needed(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &policy,
+            rotation_time,
+            &mut csprng,
+        )
+        .await?
+        .expect("rotated after a week");
+        assert_ne!(
+            first_distribution_message.chain_id()?,
+            second_distribution_message.chain_id()?
+        );
+
+        // The new sender key's age is counted from the rotation.
+        assert!(rotate_sender_key_if_needed(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &policy,
+            rotation_time + week / 2,
+            &mut csprng,
+        )
+        .await?
+        .is_none());
+
+        // The current distribution message describes the rotated key.
+        let current_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(
+            current_distribution_message.chain_id()?,
+            second_distribution_message.chain_id()?
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn group_rotation_after_member_removal() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng;
+
+        let sender_address = ProtocolAddress::new("+14159999111".to_owned(), 1.into());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+        let mut carol_store = test_in_memory_protocol_store()?;
+
+        let policy = SenderKeyRotationPolicy::default();
+        let now = SystemTime::now();
+
+        // Marking a distribution with no sender key is allowed.
+        mark_sender_key_for_rotation(&sender_address, distribution_id, &mut alice_store).await?;
+
+        let first_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        for store in [&mut bob_store, &mut carol_store] {
+            process_sender_key_distribution_message(
+                &sender_address,
+                &first_distribution_message,
+                store,
+            )
+            .await?;
+        }
+
+        // The default policy never rotates on its own.
+        assert!(rotate_sender_key_if_needed(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &policy,
+            now + Duration::from_secs(60 * 60 * 24 * 365),
+            &mut csprng,
+        )
+        .await?
+        .is_none());
+
+        // Carol leaves the group.
+        mark_sender_key_for_rotation(&sender_address, distribution_id, &mut alice_store).await?;
+        let second_distribution_message = rotate_sender_key_if_needed(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &policy,
+            now,
+            &mut csprng,
+        )
+        .await?
+        .expect("rotation was requested");
+        process_sender_key_distribution_message(
+            &sender_address,
+            &second_distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        // The request is cleared by the rotation.
+        assert!(rotate_sender_key_if_needed(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &policy,
+            now,
+            &mut csprng,
+        )
+        .await?
+        .is_none());
+
+        let ciphertext = group_encrypt(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "carol isn't here".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(
+            group_decrypt(ciphertext.serialized(), &mut bob_store, &sender_address).await?,
+            b"carol isn't here"
+        );
+        assert!(matches!(
+            group_decrypt(ciphertext.serialized(), &mut carol_store, &sender_address).await,
+            Err(SignalProtocolError::NoSenderKeyState { .. })
+        ));
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -10,7 +10,7 @@ use futures_util::FutureExt;
 use libsignal_protocol::*;
 use rand::rngs::OsRng;
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 #[test]
@@ -133,6 +133,481 @@ fn test_sender_cert() -> Result<(), SignalProtocolError> {
     Ok(())
 }
 
+#[test]
+fn test_server_cert_batch() -> Result<(), SignalProtocolError> {
+    let mut rng = OsRng;
+    let trust_root = KeyPair::generate(&mut rng);
+    let other_root = KeyPair::generate(&mut rng);
+
+    let mut server_certs = (1..=8)
+        .map(|id| {
+            let server_key = KeyPair::generate(&mut rng);
+            ServerCertificate::new(id, server_key.public_key, &trust_root.private_key, &mut rng)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    server_certs[2] = ServerCertificate::new(
+        3,
+        KeyPair::generate(&mut rng).public_key,
+        &other_root.private_key,
+        &mut rng,
+    )?;
+    server_certs[5] = ServerCertificate::new(
+        0xDEADC357,
+        KeyPair::generate(&mut rng).public_key,
+        &trust_root.private_key,
+        &mut rng,
+    )?;
+
+    let results =
+        ServerCertificate::validate_batch(&server_certs, &trust_root.public_key, &mut rng)?;
+    let expected = server_certs
+        .iter()
+        .map(|cert| cert.validate(&trust_root.public_key))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(results, expected);
+    assert_eq!(results, [true, true, false, true, true, false, true, true]);
+
+    Ok(())
+}
+
+#[test]
+fn test_sender_cert_batch() -> Result<(), SignalProtocolError> {
+    let mut rng = OsRng;
+    let trust_root = KeyPair::generate(&mut rng);
+    let other_root = KeyPair::generate(&mut rng);
+    let server_key = KeyPair::generate(&mut rng);
+    let other_server_key = KeyPair::generate(&mut rng);
+
+    let server_cert =
+        ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut rng)?;
+    let untrusted_server_cert = ServerCertificate::new(
+        2,
+        other_server_key.public_key,
+        &other_root.private_key,
+        &mut rng,
+    )?;
+
+    let expires = Timestamp::from_epoch_millis(1605722925);
+    let mut sender_cert = |server_cert: &ServerCertificate,
+                           server_key: &KeyPair,
+                           expires: Timestamp|
+     -> Result<SenderCertificate, SignalProtocolError> {
+        SenderCertificate::new(
+            "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string(),
+            None,
+            KeyPair::generate(&mut rng).public_key,
+            42.into(),
+            expires,
+            server_cert.clone(),
+            &server_key.private_key,
+            &mut rng,
+        )
+    };
+
+    let sender_certs = vec![
+        sender_cert(&server_cert, &server_key, expires)?,
+        sender_cert(&server_cert, &server_key, expires)?,
+        // Signed by a server key other than the one in its server certificate.
+        sender_cert(&server_cert, &other_server_key, expires)?,
+        sender_cert(&untrusted_server_cert, &other_server_key, expires)?,
+        sender_cert(&server_cert, &server_key, expires.sub_millis(1))?,
+        sender_cert(&server_cert, &server_key, expires)?,
+    ];
+
+    let results = SenderCertificate::validate_batch(
+        &sender_certs,
+        &trust_root.public_key,
+        expires,
+        &mut rng,
+    )?;
+    let expected = sender_certs
+        .iter()
+        .map(|cert| cert.validate(&trust_root.public_key, expires))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(results, expected);
+    assert_eq!(results, [true, true, false, false, false, true]);
+
+    assert!(
+        SenderCertificate::validate_batch(&[], &trust_root.public_key, expires, &mut rng)?
+            .is_empty()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_trust_config() -> Result<(), SignalProtocolError> {
+    let mut rng = OsRng;
+    let old_root = KeyPair::generate(&mut rng);
+    let new_root = KeyPair::generate(&mut rng);
+
+    assert!(matches!(
+        SealedSenderTrustConfig::new(vec![]),
+        Err(SignalProtocolError::InvalidArgument(_))
+    ));
+
+    let mut trust_config = SealedSenderTrustConfig::new(vec![old_root.public_key])?;
+    assert!(trust_config.is_server_key_id_revoked(0xDEADC357));
+    assert!(matches!(
+        trust_config.remove_trust_root(&old_root.public_key),
+        Err(SignalProtocolError::InvalidArgument(_))
+    ));
+
+    let old_server_cert = ServerCertificate::new(
+        1,
+        KeyPair::generate(&mut rng).public_key,
+        &old_root.private_key,
+        &mut rng,
+    )?;
+    let new_server_cert = ServerCertificate::new(
+        2,
+        KeyPair::generate(&mut rng).public_key,
+        &new_root.private_key,
+        &mut rng,
+    )?;
+    assert!(old_server_cert.validate_with_trust_config(&trust_config)?);
+    assert!(!new_server_cert.validate_with_trust_config(&trust_config)?);
+
+    trust_config.add_trust_root(new_root.public_key);
+    trust_config.add_trust_root(new_root.public_key);
+    assert_eq!(
+        trust_config.trust_roots(),
+        [old_root.public_key, new_root.public_key]
+    );
+    assert!(old_server_cert.validate_with_trust_config(&trust_config)?);
+    assert!(new_server_cert.validate_with_trust_config(&trust_config)?);
+
+    trust_config.revoke_server_key_id(1);
+    assert!(!old_server_cert.validate_with_trust_config(&trust_config)?);
+    assert!(new_server_cert.validate_with_trust_config(&trust_config)?);
+    assert_eq!(
+        trust_config.revoked_server_key_ids().collect::<Vec<_>>(),
+        [1, 0xDEADC357]
+    );
+
+    let recovered = SealedSenderTrustConfig::deserialize(&trust_config.serialize())?;
+    assert_eq!(recovered, trust_config);
+
+    assert!(trust_config.remove_trust_root(&old_root.public_key)?);
+    assert!(!trust_config.remove_trust_root(&old_root.public_key)?);
+    assert_eq!(trust_config.trust_roots(), [new_root.public_key]);
+
+    assert!(matches!(
+        SealedSenderTrustConfig::deserialize(&[]),
+        Err(SignalProtocolError::InvalidProtobufEncoding)
+    ));
+    assert!(SealedSenderTrustConfig::deserialize(&[0xFF, 0x00]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_trust_config_deserialize_keeps_built_in_revocations() -> Result<(), SignalProtocolError> {
+    let trust_root = KeyPair::generate(&mut OsRng);
+
+    // A TrustConfig with the trust root and a revoked key ID of 1, but not 0xDEADC357.
+    let mut serialized = vec![0x0a, 33];
+    serialized.extend_from_slice(&trust_root.public_key.serialize());
+    serialized.extend_from_slice(&[0x10, 0x01]);
+
+    let trust_config = SealedSenderTrustConfig::deserialize(&serialized)?;
+    assert_eq!(trust_config.trust_roots(), [trust_root.public_key]);
+    assert_eq!(
+        trust_config.revoked_server_key_ids().collect::<Vec<_>>(),
+        [1, 0xDEADC357]
+    );
+
+    let recovered = SealedSenderTrustConfig::deserialize(&trust_config.serialize())?;
+    assert_eq!(recovered, trust_config);
+    assert_eq!(recovered.serialize(), trust_config.serialize());
+
+    Ok(())
+}
+
+#[test]
+fn test_trust_config_batch() -> Result<(), SignalProtocolError> {
+    let mut rng = OsRng;
+    let old_root = KeyPair::generate(&mut rng);
+    let new_root = KeyPair::generate(&mut rng);
+    let other_root = KeyPair::generate(&mut rng);
+
+    let mut trust_config = SealedSenderTrustConfig::new(vec![old_root.public_key])?;
+    trust_config.add_trust_root(new_root.public_key);
+    trust_config.revoke_server_key_id(4);
+
+    let server_keys = (0..6)
+        .map(|_| KeyPair::generate(&mut rng))
+        .collect::<Vec<_>>();
+    let server_certs = [
+        (1, &old_root),
+        (2, &new_root),
+        (3, &other_root),
+        (4, &new_root),
+        (0xDEADC357, &old_root),
+        (6, &new_root),
+    ]
+    .into_iter()
+    .zip(&server_keys)
+    .map(|((id, root), server_key)| {
+        ServerCertificate::new(id, server_key.public_key, &root.private_key, &mut rng)
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let results = ServerCertificate::validate_batch_with_trust_config(
+        &server_certs,
+        &trust_config,
+        &mut rng,
+    )?;
+    let expected = server_certs
+        .iter()
+        .map(|cert| cert.validate_with_trust_config(&trust_config))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(results, expected);
+    assert_eq!(results, [true, true, false, false, false, true]);
+
+    let expires = Timestamp::from_epoch_millis(1605722925);
+    let sender_certs = [
+        (0, 0, expires),
+        (1, 1, expires),
+        (2, 2, expires),
+        (3, 3, expires),
+        // Signed by a server key other than the one in its server certificate.
+        (0, 1, expires),
+        (1, 1, expires.sub_millis(1)),
+        (5, 5, expires),
+        (0, 0, expires),
+    ]
+    .into_iter()
+    .map(|(cert_index, key_index, expiration)| {
+        SenderCertificate::new(
+            "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string(),
+            None,
+            KeyPair::generate(&mut rng).public_key,
+            42.into(),
+            expiration,
+            server_certs[cert_index].clone(),
+            &server_keys[key_index].private_key,
+            &mut rng,
+        )
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let results = SenderCertificate::validate_batch_with_trust_config(
+        &sender_certs,
+        &trust_config,
+        expires,
+        &mut rng,
+    )?;
+    let expected = sender_certs
+        .iter()
+        .map(|cert| cert.validate_with_trust_config(&trust_config, expires))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(results, expected);
+    assert_eq!(
+        results,
+        [true, true, false, false, false, false, true, true]
+    );
+
+    assert!(SenderCertificate::validate_batch_with_trust_config(
+        &[],
+        &trust_config,
+        expires,
+        &mut rng
+    )?
+    .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_sender_certificate_issuer() -> Result<(), SignalProtocolError> {
+    let mut rng = OsRng;
+    let trust_root = KeyPair::generate(&mut rng);
+    let trust_config = SealedSenderTrustConfig::new(vec![trust_root.public_key])?;
+    let policy = SenderCertificateExpiryPolicy {
+        lifetime: Duration::from_secs(60 * 60),
+        renewal_window: Duration::from_secs(10 * 60),
+    };
+
+    let issuer = SenderCertificateIssuer::generate(7, &trust_root.private_key, policy, &mut rng)?;
+    assert_eq!(issuer.server_certificate().key_id()?, 7);
+    assert_eq!(issuer.expiry_policy(), policy);
+
+    let issued_at = Timestamp::from_epoch_millis(1_700_000_000_000);
+    let sender_cert = issuer.issue(
+        "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string(),
+        None,
+        KeyPair::generate(&mut rng).public_key,
+        1.into(),
+        issued_at,
+        &mut rng,
+    )?;
+    let expiration = issued_at.add_millis(60 * 60 * 1000);
+    assert_eq!(sender_cert.expiration()?, expiration);
+    assert_eq!(policy.expiration_for(issued_at), expiration);
+
+    assert!(sender_cert.validate_with_trust_config(&trust_config, issued_at)?);
+    assert!(sender_cert.validate_with_trust_config(&trust_config, expiration)?);
+    assert!(!sender_cert.validate_with_trust_config(&trust_config, expiration.add_millis(1))?);
+
+    assert!(!policy.needs_renewal(&sender_cert, issued_at)?);
+    assert!(!policy.needs_renewal(&sender_cert, expiration.sub_millis(10 * 60 * 1000 + 1))?);
+    assert!(policy.needs_renewal(&sender_cert, expiration.sub_millis(10 * 60 * 1000))?);
+
+    let restored = SenderCertificateIssuer::new(
+        issuer.server_certificate().clone(),
+        *issuer.server_key(),
+        policy,
+    )?;
+    let restored_cert = restored.issue(
+        "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string(),
+        None,
+        KeyPair::generate(&mut rng).public_key,
+        1.into(),
+        issued_at,
+        &mut rng,
+    )?;
+    assert!(restored_cert.validate_with_trust_config(&trust_config, issued_at)?);
+
+    assert!(matches!(
+        SenderCertificateIssuer::new(
+            issuer.server_certificate().clone(),
+            KeyPair::generate(&mut rng).private_key,
+            policy,
+        ),
+        Err(SignalProtocolError::InvalidArgument(_))
+    ));
+
+    let mut revoking_config = trust_config.clone();
+    revoking_config.revoke_server_key_id(7);
+    assert!(!sender_cert.validate_with_trust_config(&revoking_config, issued_at)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_sealed_sender_with_trust_config() -> Result<(), SignalProtocolError> {
+    async {
+        let mut rng = OsRng;
+
+        let alice_device_id: DeviceId = 23.into();
+        let bob_device_id: DeviceId = 42.into();
+
+        let alice_uuid = "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string();
+        let bob_uuid = "796abedb-ca4e-4f18-8803-1fde5b921f9f".to_string();
+
+        let bob_uuid_address = ProtocolAddress::new(bob_uuid.clone(), bob_device_id);
+
+        let mut alice_store = support::test_in_memory_protocol_store()?;
+        let mut bob_store = support::test_in_memory_protocol_store()?;
+
+        let alice_pubkey = *alice_store.get_identity_key_pair().await?.public_key();
+
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut rng).await?;
+
+        process_prekey_bundle(
+            &bob_uuid_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut rng,
+        )
+        .await?;
+
+        let old_root = KeyPair::generate(&mut rng);
+        let new_root = KeyPair::generate(&mut rng);
+        let issuer = SenderCertificateIssuer::generate(
+            2,
+            &new_root.private_key,
+            SenderCertificateExpiryPolicy::default(),
+            &mut rng,
+        )?;
+
+        let issued_at = Timestamp::from_epoch_millis(1_700_000_000_000);
+        let sender_cert = issuer.issue(
+            alice_uuid.clone(),
+            None,
+            alice_pubkey,
+            alice_device_id,
+            issued_at,
+            &mut rng,
+        )?;
+
+        let mut trust_config =
+            SealedSenderTrustConfig::new(vec![old_root.public_key, new_root.public_key])?;
+
+        let alice_ptext = vec![1, 2, 3, 23, 99];
+        let alice_ctext = sealed_sender_encrypt(
+            &bob_uuid_address,
+            &sender_cert,
+            &alice_ptext,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::now(),
+            &mut rng,
+        )
+        .await?;
+
+        let bob_ptext = sealed_sender_decrypt_with_trust_config(
+            &alice_ctext,
+            &trust_config,
+            issued_at,
+            None,
+            bob_uuid.clone(),
+            bob_device_id,
+            &mut bob_store.identity_store,
+            &mut bob_store.session_store,
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+        )
+        .await?;
+
+        assert_eq!(bob_ptext.message, alice_ptext);
+        assert_eq!(bob_ptext.sender_uuid, alice_uuid);
+
+        // Now revoke the server key that issued the sender certificate.
+
+        trust_config.revoke_server_key_id(2);
+
+        let alice_ctext = sealed_sender_encrypt(
+            &bob_uuid_address,
+            &sender_cert,
+            &alice_ptext,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::now(),
+            &mut rng,
+        )
+        .await?;
+
+        let bob_ptext = sealed_sender_decrypt_with_trust_config(
+            &alice_ctext,
+            &trust_config,
+            issued_at,
+            None,
+            bob_uuid.clone(),
+            bob_device_id,
+            &mut bob_store.identity_store,
+            &mut bob_store.session_store,
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+        )
+        .await;
+
+        assert!(matches!(
+            bob_ptext,
+            Err(SignalProtocolError::InvalidSealedSenderMessage(_))
+        ));
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
 #[test]
 fn test_sealed_sender() -> Result<(), SignalProtocolError> {
     async {
@@ -863,6 +1338,166 @@ fn test_sealed_sender_multi_recipient_encrypt_with_bad_registration_id(
     .expect("sync")
 }
 
+#[test]
+fn test_sealed_sender_multi_recipient_encrypt_partial() -> Result<(), SignalProtocolError> {
+    async {
+        let mut rng = OsRng;
+
+        let alice_uuid = "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string();
+        let bob_uuid = "796abedb-ca4e-4f18-8803-1fde5b921f9f".to_string();
+        let carol_uuid = "38381c3b-2606-4ca7-9310-7cb927f2ab4a".to_string();
+
+        let bob_address = ProtocolAddress::new(bob_uuid.clone(), 1.into());
+        let bob_second_address = ProtocolAddress::new(bob_uuid.clone(), 2.into());
+        let bob_third_address = ProtocolAddress::new(bob_uuid.clone(), 3.into());
+        let carol_address = ProtocolAddress::new(carol_uuid.clone(), 1.into());
+        let e164_address = ProtocolAddress::new("+14151114444".to_owned(), 1.into());
+
+        let mut alice_store = support::test_in_memory_protocol_store()?;
+        let mut bob_store = support::test_in_memory_protocol_store()?;
+
+        let alice_pubkey = *alice_store.get_identity_key_pair().await?.public_key();
+
+        for address in [&bob_address, &bob_second_address] {
+            let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut rng).await?;
+            process_prekey_bundle(
+                address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut rng,
+            )
+            .await?;
+        }
+
+        let trust_root = KeyPair::generate(&mut rng);
+        let server_key = KeyPair::generate(&mut rng);
+        let server_cert =
+            ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut rng)?;
+        let expires = Timestamp::from_epoch_millis(1605722925);
+        let sender_cert = SenderCertificate::new(
+            alice_uuid.clone(),
+            None,
+            alice_pubkey,
+            23.into(),
+            expires,
+            server_cert,
+            &server_key.private_key,
+            &mut rng,
+        )?;
+
+        let alice_ptext = vec![1, 2, 3, 23, 99];
+        let alice_message = message_encrypt(
+            &alice_ptext,
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::now(),
+        )
+        .await?;
+        let alice_usmc = UnidentifiedSenderMessageContent::new(
+            alice_message.message_type(),
+            sender_cert,
+            alice_message.serialize().to_vec(),
+            ContentHint::Default,
+            None,
+        )?;
+
+        let bob_session = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("present");
+        let mut bob_second_session = alice_store
+            .load_session(&bob_second_address)
+            .await?
+            .expect("present");
+        bob_second_session.archive_current_state()?;
+
+        let result = sealed_sender_multi_recipient_encrypt_partial(
+            &[
+                &bob_address,
+                &bob_second_address,
+                &bob_third_address,
+                &carol_address,
+                &e164_address,
+            ],
+            &[
+                Some(&bob_session),
+                Some(&bob_second_session),
+                None,
+                None,
+                Some(&bob_session),
+            ],
+            [],
+            &alice_usmc,
+            &alice_store.identity_store,
+            &mut rng,
+        )
+        .await?;
+
+        assert_eq!(
+            result.excluded,
+            [
+                (
+                    &bob_second_address,
+                    SealedSenderExclusionReason::StaleSession
+                ),
+                (
+                    &bob_third_address,
+                    SealedSenderExclusionReason::MissingSession
+                ),
+                (&carol_address, SealedSenderExclusionReason::MissingIdentity),
+                (&e164_address, SealedSenderExclusionReason::InvalidAddress),
+            ]
+            .map(|(address, reason)| SealedSenderExcludedDestination {
+                address: address.clone(),
+                reason,
+            })
+        );
+
+        let sent_message = SealedSenderV2SentMessage::parse(&result.serialized)?;
+        assert_eq!(sent_message.recipients.len(), 1);
+        let (recipient, bob_ctext) = extract_single_ssv2_received_message(&result.serialized);
+        assert_eq!(recipient.service_id_string(), bob_uuid);
+
+        let bob_ptext = sealed_sender_decrypt(
+            &bob_ctext,
+            &trust_root.public_key,
+            expires.sub_millis(1),
+            None,
+            bob_uuid.clone(),
+            bob_address.device_id(),
+            &mut bob_store.identity_store,
+            &mut bob_store.session_store,
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+        )
+        .await?;
+        assert_eq!(bob_ptext.message, alice_ptext);
+
+        // If every destination is excluded, the message has no recipients.
+        let result = sealed_sender_multi_recipient_encrypt_partial(
+            &[&carol_address],
+            &[None],
+            [],
+            &alice_usmc,
+            &alice_store.identity_store,
+            &mut rng,
+        )
+        .await?;
+        assert_eq!(result.excluded.len(), 1);
+        assert!(SealedSenderV2SentMessage::parse(&result.serialized)?
+            .recipients
+            .is_empty());
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
 #[test]
 fn test_decryption_error_in_sealed_sender() -> Result<(), SignalProtocolError> {
     async {
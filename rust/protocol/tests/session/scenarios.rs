@@ -0,0 +1,3423 @@
+//
+// Copyright 2020-2022 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Session scenarios, run against each store implementation by the test targets that include
+//! this module. The including crate provides `Store`, the store type under test.
+
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::rngs::OsRng;
+
+use std::time::{Duration, SystemTime};
+
+use crate::support::*;
+use crate::Store;
+
+type TestResult = Result<(), SignalProtocolError>;
+type TestStoreBuilder = crate::support::TestStoreBuilder<Store>;
+
+// Use this function to debug tests
+#[allow(dead_code)]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(log::LevelFilter::max())
+        .is_test(true)
+        .try_init();
+}
+
+#[test]
+fn test_basic_prekey() -> TestResult {
+    run(
+        |builder| {
+            builder.add_pre_key(IdChoice::Next);
+            builder.add_signed_pre_key(IdChoice::Next);
+        },
+        PRE_KYBER_MESSAGE_VERSION,
+    )?;
+
+    run(
+        |builder| {
+            builder.add_pre_key(IdChoice::Next);
+            builder.add_signed_pre_key(IdChoice::Next);
+            builder.add_kyber_pre_key(IdChoice::Next);
+        },
+        KYBER_AWARE_MESSAGE_VERSION,
+    )?;
+
+    fn run<F>(bob_add_keys: F, expected_session_version: u32) -> TestResult
+    where
+        F: Fn(&mut TestStoreBuilder),
+    {
+        async {
+            let mut csprng = OsRng;
+
+            let bob_device_id: DeviceId = 1.into();
+
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), bob_device_id);
+
+            let mut bob_store_builder = TestStoreBuilder::new();
+            bob_add_keys(&mut bob_store_builder);
+
+            let mut alice_store_builder = TestStoreBuilder::new();
+            let alice_store = &mut alice_store_builder.store;
+
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(bob_device_id);
+
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            assert!(alice_store.load_session(&bob_address).await?.is_some());
+            assert_eq!(
+                alice_store.session_version(&bob_address)?,
+                expected_session_version
+            );
+
+            let original_message = "L'homme est condamné à être libre";
+
+            let outgoing_message = encrypt(alice_store, &bob_address, original_message).await?;
+
+            assert_eq!(
+                outgoing_message.message_type(),
+                CiphertextMessageType::PreKey
+            );
+
+            let incoming_message = CiphertextMessage::PreKeySignalMessage(
+                PreKeySignalMessage::try_from(outgoing_message.serialize())?,
+            );
+
+            let ptext = decrypt(
+                &mut bob_store_builder.store,
+                &alice_address,
+                &incoming_message,
+            )
+            .await?;
+
+            assert_eq!(
+                String::from_utf8(ptext).expect("valid utf8"),
+                original_message
+            );
+
+            for id in bob_store_builder.store.kyber_pre_key_ids() {
+                assert!(bob_store_builder.store.kyber_pre_key_used(id));
+            }
+
+            let bobs_response = "Who watches the watchers?";
+
+            assert!(bob_store_builder
+                .store
+                .load_session(&alice_address)
+                .await?
+                .is_some());
+            let bobs_session_with_alice = bob_store_builder
+                .store
+                .load_session(&alice_address)
+                .await?
+                .expect("session found");
+            assert_eq!(
+                bobs_session_with_alice.session_version()?,
+                expected_session_version
+            );
+            assert_eq!(bobs_session_with_alice.alice_base_key()?.len(), 32 + 1);
+
+            let bob_outgoing =
+                encrypt(&mut bob_store_builder.store, &alice_address, bobs_response).await?;
+
+            assert_eq!(bob_outgoing.message_type(), CiphertextMessageType::Whisper);
+
+            let alice_decrypts = decrypt(alice_store, &bob_address, &bob_outgoing).await?;
+
+            {
+                let record_len = alice_store.session_store.load_session(&bob_address).await
+                    .expect("can load session")
+                    .expect("has session record")
+                    .serialize()
+                    .expect("can serialize session record")
+                    .len();
+                assert!(1024 > record_len, "Unexpectedly large session record ({record_len} bytes). Did you forget to clean things up?")
+            }
+
+
+            assert_eq!(
+                String::from_utf8(alice_decrypts).expect("valid utf8"),
+                bobs_response
+            );
+
+            run_interaction(
+                alice_store,
+                &alice_address,
+                &mut bob_store_builder.store,
+                &bob_address,
+            )
+            .await?;
+
+            let mut alter_alice_store = TestStoreBuilder::new().store;
+
+            bob_add_keys(&mut bob_store_builder);
+
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(bob_device_id);
+            process_prekey_bundle(
+                &bob_address,
+                &mut alter_alice_store.session_store,
+                &mut alter_alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            let outgoing_message =
+                encrypt(&mut alter_alice_store, &bob_address, original_message).await?;
+
+            assert!(matches!(
+                decrypt(&mut bob_store_builder.store, &alice_address, &outgoing_message)
+                    .await
+                    .unwrap_err(),
+                SignalProtocolError::UntrustedIdentity(a) if a == alice_address
+            ));
+
+            assert!(bob_store_builder
+                .store
+                .save_identity(
+                    &alice_address,
+                    alter_alice_store
+                        .get_identity_key_pair()
+                        .await?
+                        .identity_key(),
+                )
+                .await?);
+
+            let decrypted = decrypt(
+                &mut bob_store_builder.store,
+                &alice_address,
+                &outgoing_message,
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(decrypted).expect("valid utf8"),
+                original_message
+            );
+
+            // Sign pre-key with wrong key:
+            let bad_bob_pre_key_bundle = bob_store_builder
+                .make_bundle_with_latest_keys(bob_device_id)
+                .modify(|content| {
+                    let wrong_identity = alter_alice_store
+                        .get_identity_key_pair()
+                        .now_or_never()
+                        .expect("sync")
+                        .expect("has identity key");
+                    content.identity_key = Some(*wrong_identity.identity_key());
+                })
+                .expect("can reconstruct the bundle");
+
+            assert!(process_prekey_bundle(
+                &bob_address,
+                &mut alter_alice_store.session_store,
+                &mut alter_alice_store.identity_store,
+                &bad_bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await
+            .is_err());
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore = "slow to run locally"]
+fn test_chain_jump_over_limit() -> TestResult {
+    let mut alice_store_builder = TestStoreBuilder::new();
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(31337.into())
+        .with_signed_pre_key(22.into());
+    run(&mut alice_store_builder, &mut bob_store_builder)?;
+
+    let mut alice_store_builder = TestStoreBuilder::new();
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(31337.into())
+        .with_signed_pre_key(22.into())
+        .with_kyber_pre_key(8000.into());
+
+    run(&mut alice_store_builder, &mut bob_store_builder)?;
+    fn run(
+        alice_store_builder: &mut TestStoreBuilder,
+        bob_store_builder: &mut TestStoreBuilder,
+    ) -> TestResult {
+        async {
+            let mut csprng = OsRng;
+
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+            let alice_store = &mut alice_store_builder.store;
+
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            // Same as ProtocolConfig::DEFAULT
+            pub const MAX_FORWARD_JUMPS: usize = 25_000;
+
+            for _i in 0..(MAX_FORWARD_JUMPS + 1) {
+                let _msg =
+                    encrypt(alice_store, &bob_address, "Yet another message for you").await?;
+            }
+
+            let too_far = encrypt(alice_store, &bob_address, "Now you have gone too far").await?;
+
+            assert!(
+                decrypt(&mut bob_store_builder.store, &alice_address, &too_far)
+                    .await
+                    .is_err()
+            );
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore = "slow to run locally"]
+fn test_chain_jump_over_limit_with_self() -> TestResult {
+    let mut store_builder_one = TestStoreBuilder::new();
+    let mut store_builder_two = TestStoreBuilder::from_store(&store_builder_one.store)
+        .with_pre_key(31337.into())
+        .with_signed_pre_key(22.into());
+    run(&mut store_builder_one, &mut store_builder_two)?;
+
+    let mut store_builder_one = TestStoreBuilder::new();
+    let mut store_builder_two = TestStoreBuilder::from_store(&store_builder_one.store)
+        .with_pre_key(31337.into())
+        .with_signed_pre_key(22.into())
+        .with_kyber_pre_key(8000.into());
+    run(&mut store_builder_one, &mut store_builder_two)?;
+
+    fn run(
+        a1_store_builder: &mut TestStoreBuilder,
+        a2_store_builder: &mut TestStoreBuilder,
+    ) -> TestResult {
+        async {
+            let mut csprng = OsRng;
+
+            let device_id_1: DeviceId = 1.into();
+            let a1_address = ProtocolAddress::new("+14151111111".to_owned(), device_id_1);
+            let device_id_2: DeviceId = 2.into();
+            let a2_address = ProtocolAddress::new("+14151111111".to_owned(), device_id_2);
+
+            let a1_store = &mut a1_store_builder.store;
+
+            let a2_pre_key_bundle = a2_store_builder.make_bundle_with_latest_keys(device_id_2);
+
+            process_prekey_bundle(
+                &a2_address,
+                &mut a1_store.session_store,
+                &mut a1_store.identity_store,
+                &a2_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            // Same as ProtocolConfig::DEFAULT
+            pub const MAX_FORWARD_JUMPS: usize = 25_000;
+
+            for _i in 0..(MAX_FORWARD_JUMPS + 1) {
+                let _msg =
+                    encrypt(a1_store, &a2_address, "Yet another message for yourself").await?;
+            }
+
+            let too_far =
+                encrypt(a1_store, &a2_address, "This is the song that never ends").await?;
+
+            let ptext = decrypt(&mut a2_store_builder.store, &a1_address, &too_far).await?;
+            assert_eq!(
+                String::from_utf8(ptext).unwrap(),
+                "This is the song that never ends"
+            );
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_bad_signed_pre_key_signature() -> TestResult {
+    async {
+        let mut csprng = OsRng;
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(31337.into())
+            .with_signed_pre_key(22.into());
+
+        let good_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+        for bit in 0..8 * good_bundle
+            .signed_pre_key_signature()
+            .expect("has signature")
+            .len()
+        {
+            let mut bad_signature = good_bundle
+                .signed_pre_key_signature()
+                .expect("has signature")
+                .to_vec();
+
+            bad_signature[bit / 8] ^= 0x01u8 << (bit % 8);
+
+            let bad_bundle = good_bundle
+                .clone()
+                .modify(|content| content.ec_pre_key_signature = Some(bad_signature))
+                .expect("can recreate the bundle");
+
+            assert!(process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bad_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await
+            .is_err());
+        }
+
+        // Finally check that the non-corrupted signature is accepted:
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &good_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_repeat_bundle_message() -> TestResult {
+    let mut alice_store_builder = TestStoreBuilder::new();
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(3133.into())
+        .with_signed_pre_key(22.into());
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        PRE_KYBER_MESSAGE_VERSION,
+    )?;
+
+    let mut alice_store_builder = TestStoreBuilder::new();
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(3133.into())
+        .with_signed_pre_key(22.into())
+        .with_kyber_pre_key(8000.into());
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        KYBER_AWARE_MESSAGE_VERSION,
+    )?;
+
+    fn run(
+        alice_store_builder: &mut TestStoreBuilder,
+        bob_store_builder: &mut TestStoreBuilder,
+        expected_session_version: u32,
+    ) -> TestResult {
+        async {
+            let mut csprng = OsRng;
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+            let alice_store = &mut alice_store_builder.store;
+
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            assert!(alice_store.load_session(&bob_address).await?.is_some());
+            assert_eq!(
+                alice_store.session_version(&bob_address)?,
+                expected_session_version
+            );
+
+            let original_message = "L'homme est condamné à être libre";
+
+            let outgoing_message1 = encrypt(alice_store, &bob_address, original_message).await?;
+            let outgoing_message2 = encrypt(alice_store, &bob_address, original_message).await?;
+
+            assert_eq!(
+                outgoing_message1.message_type(),
+                CiphertextMessageType::PreKey
+            );
+            assert_eq!(
+                outgoing_message2.message_type(),
+                CiphertextMessageType::PreKey
+            );
+
+            let incoming_message = CiphertextMessage::PreKeySignalMessage(
+                PreKeySignalMessage::try_from(outgoing_message1.serialize())?,
+            );
+
+            let ptext = decrypt(
+                &mut bob_store_builder.store,
+                &alice_address,
+                &incoming_message,
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(ptext).expect("valid utf8"),
+                original_message
+            );
+
+            let bob_outgoing = encrypt(
+                &mut bob_store_builder.store,
+                &alice_address,
+                original_message,
+            )
+            .await?;
+            assert_eq!(bob_outgoing.message_type(), CiphertextMessageType::Whisper);
+            let alice_decrypts = decrypt(alice_store, &bob_address, &bob_outgoing).await?;
+            assert_eq!(
+                String::from_utf8(alice_decrypts).expect("valid utf8"),
+                original_message
+            );
+
+            // The test
+
+            let incoming_message2 = CiphertextMessage::PreKeySignalMessage(
+                PreKeySignalMessage::try_from(outgoing_message2.serialize())?,
+            );
+
+            let ptext = decrypt(
+                &mut bob_store_builder.store,
+                &alice_address,
+                &incoming_message2,
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(ptext).expect("valid utf8"),
+                original_message
+            );
+
+            let bob_outgoing = encrypt(
+                &mut bob_store_builder.store,
+                &alice_address,
+                original_message,
+            )
+            .await?;
+            let alice_decrypts = decrypt(alice_store, &bob_address, &bob_outgoing).await?;
+            assert_eq!(
+                String::from_utf8(alice_decrypts).expect("valid utf8"),
+                original_message
+            );
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_bad_message_bundle() -> TestResult {
+    let mut alice_store_builder = TestStoreBuilder::new();
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(3133.into())
+        .with_signed_pre_key(22.into());
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        PRE_KYBER_MESSAGE_VERSION,
+    )?;
+
+    let mut alice_store_builder = TestStoreBuilder::new();
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(3133.into())
+        .with_signed_pre_key(22.into())
+        .with_kyber_pre_key(8000.into());
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        KYBER_AWARE_MESSAGE_VERSION,
+    )?;
+
+    fn run(
+        alice_store_builder: &mut TestStoreBuilder,
+        bob_store_builder: &mut TestStoreBuilder,
+        expected_session_version: u32,
+    ) -> TestResult {
+        async {
+            let mut csprng = OsRng;
+
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+            let pre_key_id = bob_pre_key_bundle.pre_key_id()?.expect("has pre key id");
+
+            let alice_store = &mut alice_store_builder.store;
+            let bob_store = &mut bob_store_builder.store;
+
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            assert!(alice_store.load_session(&bob_address).await?.is_some());
+            assert_eq!(
+                alice_store.session_version(&bob_address)?,
+                expected_session_version
+            );
+
+            let original_message = "L'homme est condamné à être libre";
+
+            assert!(bob_store.get_pre_key(pre_key_id).await.is_ok());
+            let outgoing_message = encrypt(alice_store, &bob_address, original_message).await?;
+
+            assert_eq!(
+                outgoing_message.message_type(),
+                CiphertextMessageType::PreKey
+            );
+
+            let outgoing_message = outgoing_message.serialize().to_vec();
+
+            let mut corrupted_message: Vec<u8> = outgoing_message.clone();
+            corrupted_message[outgoing_message.len() - 10] ^= 1;
+
+            let incoming_message = CiphertextMessage::PreKeySignalMessage(
+                PreKeySignalMessage::try_from(corrupted_message.as_slice())?,
+            );
+
+            assert!(decrypt(bob_store, &alice_address, &incoming_message)
+                .await
+                .is_err());
+            assert!(bob_store.get_pre_key(pre_key_id).await.is_ok());
+
+            let incoming_message = CiphertextMessage::PreKeySignalMessage(
+                PreKeySignalMessage::try_from(outgoing_message.as_slice())?,
+            );
+
+            let ptext = decrypt(bob_store, &alice_address, &incoming_message).await?;
+
+            assert_eq!(
+                String::from_utf8(ptext).expect("valid utf8"),
+                original_message
+            );
+            assert!(matches!(
+                bob_store.get_pre_key(pre_key_id).await.unwrap_err(),
+                SignalProtocolError::InvalidPreKeyId
+            ));
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_optional_one_time_prekey() -> TestResult {
+    let mut alice_store_builder = TestStoreBuilder::new();
+    let mut bob_store_builder = TestStoreBuilder::new().with_signed_pre_key(22.into());
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        PRE_KYBER_MESSAGE_VERSION,
+    )?;
+
+    let mut alice_store_builder = TestStoreBuilder::new();
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_signed_pre_key(22.into())
+        .with_kyber_pre_key(8000.into());
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        KYBER_AWARE_MESSAGE_VERSION,
+    )?;
+
+    fn run(
+        alice_store_builder: &mut TestStoreBuilder,
+        bob_store_builder: &mut TestStoreBuilder,
+        expected_session_version: u32,
+    ) -> TestResult {
+        async {
+            let mut csprng = OsRng;
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+            let alice_store = &mut alice_store_builder.store;
+
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            assert_eq!(
+                alice_store.session_version(&bob_address)?,
+                expected_session_version
+            );
+
+            let original_message = "L'homme est condamné à être libre";
+
+            let outgoing_message = encrypt(alice_store, &bob_address, original_message).await?;
+
+            assert_eq!(
+                outgoing_message.message_type(),
+                CiphertextMessageType::PreKey
+            );
+
+            let incoming_message = CiphertextMessage::PreKeySignalMessage(
+                PreKeySignalMessage::try_from(outgoing_message.serialize())?,
+            );
+
+            let ptext = decrypt(
+                &mut bob_store_builder.store,
+                &alice_address,
+                &incoming_message,
+            )
+            .await?;
+
+            assert_eq!(
+                String::from_utf8(ptext).expect("valid utf8"),
+                original_message
+            );
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_basic_session() -> TestResult {
+    let (alice_session, bob_session) = initialize_sessions_v3()?;
+    run_session_interaction(alice_session, bob_session)?;
+
+    let (alice_session, bob_session) = initialize_sessions_v4()?;
+    run_session_interaction(alice_session, bob_session)?;
+    Ok(())
+}
+
+#[test]
+fn test_message_key_limits() -> TestResult {
+    run(initialize_sessions_v3()?)?;
+    run(initialize_sessions_v4()?)?;
+
+    fn run(sessions: (SessionRecord, SessionRecord)) -> TestResult {
+        async {
+            let (alice_session_record, bob_session_record) = sessions;
+
+            let alice_address = ProtocolAddress::new("+14159999999".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14158888888".to_owned(), 1.into());
+
+            let mut alice_store = TestStoreBuilder::new().store;
+            let mut bob_store = TestStoreBuilder::new().store;
+
+            alice_store
+                .store_session(&bob_address, &alice_session_record)
+                .await?;
+            bob_store
+                .store_session(&alice_address, &bob_session_record)
+                .await?;
+
+            const MAX_MESSAGE_KEYS: usize = 2000; // same value as in library
+            const TOO_MANY_MESSAGES: usize = MAX_MESSAGE_KEYS + 300;
+
+            let mut inflight = Vec::with_capacity(TOO_MANY_MESSAGES);
+
+            for i in 0..TOO_MANY_MESSAGES {
+                inflight.push(
+                    encrypt(&mut alice_store, &bob_address, &format!("It's over {}", i)).await?,
+                );
+            }
+
+            assert_eq!(
+                String::from_utf8(decrypt(&mut bob_store, &alice_address, &inflight[1000]).await?)
+                    .expect("valid utf8"),
+                "It's over 1000"
+            );
+            assert_eq!(
+                String::from_utf8(
+                    decrypt(
+                        &mut bob_store,
+                        &alice_address,
+                        &inflight[TOO_MANY_MESSAGES - 1],
+                    )
+                    .await?
+                )
+                .expect("valid utf8"),
+                format!("It's over {}", TOO_MANY_MESSAGES - 1)
+            );
+
+            let err = decrypt(&mut bob_store, &alice_address, &inflight[5])
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                SignalProtocolError::DuplicatedMessage(2300, 5)
+            ));
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_protocol_config_defaults() {
+    // Changing these affects interoperability with existing records and peers.
+    let config = ProtocolConfig::default();
+    assert_eq!(config, ProtocolConfig::DEFAULT);
+    assert_eq!(config.max_forward_jumps, 25_000);
+    assert_eq!(config.max_message_keys, 2000);
+    assert_eq!(config.max_receiver_chains, 5);
+    assert_eq!(config.max_archived_states, 40);
+    assert_eq!(config.max_sender_key_states, 5);
+    assert_eq!(
+        config.max_unacknowledged_session_age,
+        Duration::from_secs(60 * 60 * 24 * 30)
+    );
+    assert!(!config.enable_pq_ratchet);
+    assert!(!config.ephemeral_sessions);
+}
+
+#[test]
+fn test_custom_message_limits() -> TestResult {
+    async {
+        let (alice_session_record, bob_session_record) = initialize_sessions_v4()?;
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store = TestStoreBuilder::new().store;
+
+        alice_store
+            .store_session(&bob_address, &alice_session_record)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session_record)
+            .await?;
+
+        let config = ProtocolConfig {
+            max_forward_jumps: 10,
+            max_message_keys: 5,
+            ..Default::default()
+        };
+
+        let mut inflight = vec![];
+        for i in 0..20 {
+            inflight
+                .push(encrypt(&mut alice_store, &bob_address, &format!("message {}", i)).await?);
+        }
+
+        let mut decrypt_with_config = |index: usize| {
+            message_decrypt_with_config(
+                &inflight[index],
+                &alice_address,
+                &mut bob_store.session_store,
+                &mut bob_store.identity_store,
+                &mut bob_store.pre_key_store,
+                &bob_store.signed_pre_key_store,
+                &mut bob_store.kyber_pre_key_store,
+                &mut OsRng,
+                &config,
+            )
+            .now_or_never()
+            .expect("sync")
+        };
+
+        // Too far ahead for the custom limit, though well within the default one.
+        assert!(matches!(
+            decrypt_with_config(15),
+            Err(SignalProtocolError::InvalidMessage(
+                CiphertextMessageType::Whisper,
+                _
+            ))
+        ));
+
+        // Skips messages 0 through 7, but only keeps keys for the most recent five.
+        assert_eq!(decrypt_with_config(8)?, b"message 8");
+        assert!(matches!(
+            decrypt_with_config(2),
+            Err(SignalProtocolError::DuplicatedMessage(9, 2))
+        ));
+        assert_eq!(decrypt_with_config(3)?, b"message 3");
+        assert_eq!(decrypt_with_config(7)?, b"message 7");
+
+        // Now within range.
+        assert_eq!(decrypt_with_config(15)?, b"message 15");
+
+        let description = bob_store
+            .load_session(&alice_address)
+            .await?
+            .expect("session found")
+            .describe()?;
+        let chains = description
+            .current_session
+            .expect("has current session")
+            .receiver_chains;
+        assert_eq!(chains.len(), 1);
+        // Only the keys for messages 10 through 14 remain.
+        assert_eq!(chains[0].cached_message_keys, 5);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_custom_archived_state_limit() -> TestResult {
+    async {
+        let mut csprng = OsRng;
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next);
+
+        let mut start_session = |store: &mut Store, config: &ProtocolConfig| {
+            let bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+            process_prekey_bundle_with_config(
+                &bob_address,
+                &mut store.session_store,
+                &mut store.identity_store,
+                &bundle,
+                SystemTime::now(),
+                &mut csprng,
+                config,
+            )
+            .now_or_never()
+            .expect("sync")
+        };
+
+        for _ in 0..4 {
+            start_session(&mut alice_store, &ProtocolConfig::DEFAULT)?;
+        }
+        let archived_count = |store: &Store| {
+            store
+                .load_session(&bob_address)
+                .now_or_never()
+                .expect("sync")
+                .expect("can load")
+                .expect("session found")
+                .describe()
+                .expect("valid")
+                .previous_sessions
+                .len()
+        };
+        assert_eq!(archived_count(&alice_store), 3);
+
+        let config = ProtocolConfig {
+            max_archived_states: 2,
+            ..Default::default()
+        };
+        start_session(&mut alice_store, &config)?;
+        assert_eq!(archived_count(&alice_store), 2);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_ephemeral_session_keeps_no_skipped_keys() -> TestResult {
+    async {
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next);
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+        let mut bob_store = bob_store_builder.store;
+
+        let config = ProtocolConfig {
+            ephemeral_sessions: true,
+            ..Default::default()
+        };
+
+        process_prekey_bundle_with_config(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut OsRng,
+            &config,
+        )
+        .await?;
+
+        let mut inflight = vec![];
+        for i in 0..4 {
+            inflight
+                .push(encrypt(&mut alice_store, &bob_address, &format!("message {}", i)).await?);
+        }
+
+        let mut decrypt_with_config = |index: usize| {
+            message_decrypt_with_config(
+                &inflight[index],
+                &alice_address,
+                &mut bob_store.session_store,
+                &mut bob_store.identity_store,
+                &mut bob_store.pre_key_store,
+                &bob_store.signed_pre_key_store,
+                &mut bob_store.kyber_pre_key_store,
+                &mut OsRng,
+                &config,
+            )
+            .now_or_never()
+            .expect("sync")
+        };
+
+        assert_eq!(decrypt_with_config(0)?, b"message 0");
+        assert_eq!(decrypt_with_config(2)?, b"message 2");
+
+        // Message 1 was skipped over, and with it the only chance to decrypt it.
+        assert!(matches!(
+            decrypt_with_config(1),
+            Err(SignalProtocolError::DuplicatedMessage(3, 1))
+        ));
+        assert!(matches!(
+            decrypt_with_config(2),
+            Err(SignalProtocolError::DuplicatedMessage(3, 2))
+        ));
+        assert_eq!(decrypt_with_config(3)?, b"message 3");
+
+        let bob_record = bob_store
+            .load_session(&alice_address)
+            .await?
+            .expect("session found");
+        assert!(bob_record.is_ephemeral()?);
+        let chains = bob_record
+            .describe()?
+            .current_session
+            .expect("has current session")
+            .receiver_chains;
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].cached_message_keys, 0);
+
+        assert!(alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session found")
+            .is_ephemeral()?);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_ephemeral_session_drops_old_chains_and_sessions() -> TestResult {
+    async {
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        // Only Bob keeps ephemeral sessions; Alice keeps everything as usual.
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next);
+
+        let bob_config = ProtocolConfig {
+            ephemeral_sessions: true,
+            ..Default::default()
+        };
+
+        let decrypt_as_bob = |bob_store: &mut Store, msg: &CiphertextMessage| {
+            message_decrypt_with_config(
+                msg,
+                &alice_address,
+                &mut bob_store.session_store,
+                &mut bob_store.identity_store,
+                &mut bob_store.pre_key_store,
+                &bob_store.signed_pre_key_store,
+                &mut bob_store.kyber_pre_key_store,
+                &mut OsRng,
+                &bob_config,
+            )
+            .now_or_never()
+            .expect("sync")
+        };
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_store_builder.make_bundle_with_latest_keys(1.into()),
+            SystemTime::now(),
+            &mut OsRng,
+        )
+        .await?;
+
+        let first = encrypt(&mut alice_store, &bob_address, "first").await?;
+        let on_old_chain = encrypt(&mut alice_store, &bob_address, "on old chain").await?;
+        assert_eq!(
+            decrypt_as_bob(&mut bob_store_builder.store, &first)?,
+            b"first"
+        );
+
+        let reply = encrypt(&mut bob_store_builder.store, &alice_address, "reply").await?;
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &reply).await?,
+            b"reply"
+        );
+
+        // Alice has a new sending chain now, and Bob forgets the old one as soon as he sees it.
+        let on_new_chain = encrypt(&mut alice_store, &bob_address, "on new chain").await?;
+        let on_old_session = encrypt(&mut alice_store, &bob_address, "on old session").await?;
+        assert_eq!(
+            decrypt_as_bob(&mut bob_store_builder.store, &on_new_chain)?,
+            b"on new chain"
+        );
+        assert!(decrypt_as_bob(&mut bob_store_builder.store, &on_old_chain).is_err());
+
+        // A new session replaces Bob's ephemeral one without archiving it.
+        bob_store_builder.add_pre_key(IdChoice::Next);
+        bob_store_builder.add_signed_pre_key(IdChoice::Next);
+        bob_store_builder.add_kyber_pre_key(IdChoice::Next);
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_store_builder.make_bundle_with_latest_keys(1.into()),
+            SystemTime::now(),
+            &mut OsRng,
+        )
+        .await?;
+        let on_new_session = encrypt(&mut alice_store, &bob_address, "on new session").await?;
+        assert_eq!(
+            decrypt_as_bob(&mut bob_store_builder.store, &on_new_session)?,
+            b"on new session"
+        );
+        assert!(decrypt_as_bob(&mut bob_store_builder.store, &on_old_session).is_err());
+
+        let description = bob_store_builder
+            .store
+            .load_session(&alice_address)
+            .await?
+            .expect("session found")
+            .describe()?;
+        assert!(description.previous_sessions.is_empty());
+
+        // Alice's sessions aren't ephemeral, so she still has the old one.
+        let alice_record = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session found");
+        assert!(!alice_record.is_ephemeral()?);
+        assert_eq!(alice_record.describe()?.previous_sessions.len(), 1);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_handle_decryption_error_message() -> TestResult {
+    async {
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next);
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+        let mut bob_store = bob_store_builder.store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut OsRng,
+        )
+        .await?;
+
+        let first = encrypt(&mut alice_store, &bob_address, "first").await?;
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &first).await?,
+            b"first"
+        );
+        let reply = encrypt(&mut bob_store, &alice_address, "reply").await?;
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &reply).await?,
+            b"reply"
+        );
+        let second = encrypt(&mut alice_store, &bob_address, "second").await?;
+
+        let error_for = |message: &CiphertextMessage, device_id: u32| {
+            DecryptionErrorMessage::for_original(
+                message.serialize(),
+                message.message_type(),
+                Timestamp::from_epoch_millis(408),
+                device_id,
+            )
+        };
+        let mut handle = |error_message: &DecryptionErrorMessage| {
+            handle_decryption_error_message(
+                error_message,
+                &bob_address,
+                &alice_address,
+                None,
+                &mut alice_store.session_store,
+                &mut alice_store.sender_key_store,
+            )
+            .now_or_never()
+            .expect("sync")
+        };
+
+        // Sent by some other device of Alice's.
+        assert!(matches!(
+            handle(&error_for(&second, 2)?)?,
+            DecryptionErrorAction::NotForThisDevice
+        ));
+
+        // Alice's ratchet has moved on since the first message.
+        assert!(matches!(
+            handle(&error_for(&first, 1)?)?,
+            DecryptionErrorAction::Resend
+        ));
+
+        assert!(matches!(
+            handle(&error_for(&second, 1)?)?,
+            DecryptionErrorAction::ResendWithNewSession
+        ));
+        assert!(matches!(
+            handle(&error_for(&second, 1)?)?,
+            DecryptionErrorAction::ResendWithNewSession
+        ));
+
+        let description = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session found")
+            .describe()?;
+        assert!(description.current_session.is_none());
+        assert_eq!(description.previous_sessions.len(), 1);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_identity_rotation_with_succession() -> TestResult {
+    async {
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+        let alice_identity = IdentityKeyPair::generate(&mut OsRng);
+        let mut bob_store = TestStoreBuilder::new().store;
+
+        bob_store
+            .save_identity(&alice_address, alice_identity.identity_key())
+            .await?;
+
+        let (rotated_identity, succession) = alice_identity.rotate(&mut OsRng)?;
+        let (_, unrelated_succession) = IdentityKeyPair::generate(&mut OsRng).rotate(&mut OsRng)?;
+        let mut tampered_bytes = succession.serialize().into_vec();
+        // The signature is serialized last.
+        *tampered_bytes.last_mut().expect("not empty") ^= 1;
+        let tampered_succession = IdentityKeySuccession::try_from(&tampered_bytes[..])?;
+
+        assert!(matches!(
+            bob_store
+                .save_identity_with_succession(&alice_address, &unrelated_succession)
+                .await,
+            Err(SignalProtocolError::UntrustedIdentity(a)) if a == alice_address
+        ));
+        assert!(matches!(
+            bob_store
+                .save_identity_with_succession(&alice_address, &tampered_succession)
+                .await,
+            Err(SignalProtocolError::SignatureValidationFailed)
+        ));
+        assert_eq!(
+            bob_store.get_identity(&alice_address).await?,
+            Some(*alice_identity.identity_key())
+        );
+
+        assert_eq!(
+            bob_store
+                .save_identity_with_succession(&alice_address, &succession)
+                .await?,
+            IdentityChange::RotatedWithProof
+        );
+        assert_eq!(
+            bob_store.get_identity(&alice_address).await?,
+            Some(*rotated_identity.identity_key())
+        );
+        assert!(
+            bob_store
+                .is_trusted_identity(
+                    &alice_address,
+                    rotated_identity.identity_key(),
+                    Direction::Receiving
+                )
+                .await?
+        );
+
+        // Seeing the same succession again changes nothing.
+        assert_eq!(
+            bob_store
+                .save_identity_with_succession(&alice_address, &succession)
+                .await?,
+            IdentityChange::NewOrUnchanged
+        );
+
+        // Replacing an identity without a succession is still reported as a plain change.
+        assert_eq!(
+            bob_store
+                .save_identity_reporting_change(&alice_address, alice_identity.identity_key())
+                .await?,
+            IdentityChange::ReplacedExisting
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_basic_simultaneous_initiate() -> TestResult {
+    let mut alice_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random);
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random);
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        PRE_KYBER_MESSAGE_VERSION,
+    )?;
+
+    let mut alice_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random)
+        .with_kyber_pre_key(IdChoice::Random);
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random)
+        .with_kyber_pre_key(IdChoice::Random);
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        KYBER_AWARE_MESSAGE_VERSION,
+    )?;
+
+    fn run(
+        alice_store_builder: &mut TestStoreBuilder,
+        bob_store_builder: &mut TestStoreBuilder,
+        expected_session_version: u32,
+    ) -> TestResult {
+        async {
+            let mut csprng = OsRng;
+
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+            let alice_pre_key_bundle = alice_store_builder.make_bundle_with_latest_keys(1.into());
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+            let alice_store = &mut alice_store_builder.store;
+            let bob_store = &mut bob_store_builder.store;
+
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            process_prekey_bundle(
+                &alice_address,
+                &mut bob_store.session_store,
+                &mut bob_store.identity_store,
+                &alice_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            let message_for_bob = encrypt(alice_store, &bob_address, "hi bob").await?;
+            let message_for_alice = encrypt(bob_store, &alice_address, "hi alice").await?;
+
+            assert_eq!(
+                message_for_bob.message_type(),
+                CiphertextMessageType::PreKey
+            );
+            assert_eq!(
+                message_for_alice.message_type(),
+                CiphertextMessageType::PreKey
+            );
+
+            assert!(
+                !is_session_id_equal(alice_store, &alice_address, bob_store, &bob_address).await?
+            );
+
+            let alice_plaintext = decrypt(
+                alice_store,
+                &bob_address,
+                &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                    message_for_alice.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(alice_plaintext).expect("valid utf8"),
+                "hi alice"
+            );
+
+            let bob_plaintext = decrypt(
+                bob_store,
+                &alice_address,
+                &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                    message_for_bob.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(bob_plaintext).expect("valid utf8"),
+                "hi bob"
+            );
+
+            assert_eq!(
+                alice_store.session_version(&bob_address)?,
+                expected_session_version
+            );
+            assert_eq!(
+                bob_store.session_version(&alice_address)?,
+                expected_session_version
+            );
+
+            assert!(
+                !is_session_id_equal(alice_store, &alice_address, bob_store, &bob_address).await?
+            );
+
+            let alice_response = encrypt(alice_store, &bob_address, "nice to see you").await?;
+
+            assert_eq!(
+                alice_response.message_type(),
+                CiphertextMessageType::Whisper
+            );
+
+            let response_plaintext = decrypt(
+                bob_store,
+                &alice_address,
+                &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                    alice_response.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(response_plaintext).expect("valid utf8"),
+                "nice to see you"
+            );
+
+            assert!(
+                is_session_id_equal(alice_store, &alice_address, bob_store, &bob_address).await?
+            );
+
+            let bob_response = encrypt(bob_store, &alice_address, "you as well").await?;
+
+            assert_eq!(bob_response.message_type(), CiphertextMessageType::Whisper);
+
+            let response_plaintext = decrypt(
+                alice_store,
+                &bob_address,
+                &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                    bob_response.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(response_plaintext).expect("valid utf8"),
+                "you as well"
+            );
+
+            assert!(
+                is_session_id_equal(bob_store, &bob_address, alice_store, &alice_address).await?
+            );
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_simultaneous_initiate_with_lossage() -> TestResult {
+    let mut alice_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random);
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random);
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        PRE_KYBER_MESSAGE_VERSION,
+    )?;
+
+    let mut alice_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random)
+        .with_kyber_pre_key(IdChoice::Random);
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random)
+        .with_kyber_pre_key(IdChoice::Random);
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        KYBER_AWARE_MESSAGE_VERSION,
+    )?;
+
+    fn run(
+        alice_store_builder: &mut TestStoreBuilder,
+        bob_store_builder: &mut TestStoreBuilder,
+        expected_session_version: u32,
+    ) -> TestResult {
+        async {
+            let mut csprng = OsRng;
+
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+            let alice_pre_key_bundle = alice_store_builder.make_bundle_with_latest_keys(1.into());
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+            let alice_store = &mut alice_store_builder.store;
+            let bob_store = &mut bob_store_builder.store;
+
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            process_prekey_bundle(
+                &alice_address,
+                &mut bob_store.session_store,
+                &mut bob_store.identity_store,
+                &alice_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            let message_for_bob = encrypt(alice_store, &bob_address, "hi bob").await?;
+            let message_for_alice = encrypt(bob_store, &alice_address, "hi alice").await?;
+
+            assert_eq!(
+                message_for_bob.message_type(),
+                CiphertextMessageType::PreKey
+            );
+            assert_eq!(
+                message_for_alice.message_type(),
+                CiphertextMessageType::PreKey
+            );
+
+            assert!(
+                !is_session_id_equal(alice_store, &alice_address, bob_store, &bob_address).await?
+            );
+
+            let bob_plaintext = decrypt(
+                bob_store,
+                &alice_address,
+                &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                    message_for_bob.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(bob_plaintext).expect("valid utf8"),
+                "hi bob"
+            );
+
+            assert_eq!(
+                alice_store.session_version(&bob_address)?,
+                expected_session_version
+            );
+            assert_eq!(
+                bob_store.session_version(&alice_address)?,
+                expected_session_version
+            );
+
+            let alice_response = encrypt(alice_store, &bob_address, "nice to see you").await?;
+
+            assert_eq!(alice_response.message_type(), CiphertextMessageType::PreKey);
+
+            let response_plaintext = decrypt(
+                bob_store,
+                &alice_address,
+                &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                    alice_response.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(response_plaintext).expect("valid utf8"),
+                "nice to see you"
+            );
+
+            assert!(
+                is_session_id_equal(alice_store, &alice_address, bob_store, &bob_address).await?
+            );
+
+            let bob_response = encrypt(bob_store, &alice_address, "you as well").await?;
+
+            assert_eq!(bob_response.message_type(), CiphertextMessageType::Whisper);
+
+            let response_plaintext = decrypt(
+                alice_store,
+                &bob_address,
+                &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                    bob_response.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(response_plaintext).expect("valid utf8"),
+                "you as well"
+            );
+
+            assert!(
+                is_session_id_equal(bob_store, &bob_address, alice_store, &alice_address).await?
+            );
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_simultaneous_initiate_lost_message() -> TestResult {
+    let mut alice_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random);
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random);
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        PRE_KYBER_MESSAGE_VERSION,
+    )?;
+
+    let mut alice_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random)
+        .with_kyber_pre_key(IdChoice::Random);
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random)
+        .with_kyber_pre_key(IdChoice::Random);
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        KYBER_AWARE_MESSAGE_VERSION,
+    )?;
+
+    fn run(
+        alice_store_builder: &mut TestStoreBuilder,
+        bob_store_builder: &mut TestStoreBuilder,
+        expected_session_version: u32,
+    ) -> TestResult {
+        async {
+            let mut csprng = OsRng;
+
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+            let alice_pre_key_bundle = alice_store_builder.make_bundle_with_latest_keys(1.into());
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+            let alice_store = &mut alice_store_builder.store;
+            let bob_store = &mut bob_store_builder.store;
+
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            process_prekey_bundle(
+                &alice_address,
+                &mut bob_store.session_store,
+                &mut bob_store.identity_store,
+                &alice_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+
+            let message_for_bob = encrypt(alice_store, &bob_address, "hi bob").await?;
+            let message_for_alice = encrypt(bob_store, &alice_address, "hi alice").await?;
+
+            assert_eq!(
+                message_for_bob.message_type(),
+                CiphertextMessageType::PreKey
+            );
+            assert_eq!(
+                message_for_alice.message_type(),
+                CiphertextMessageType::PreKey
+            );
+
+            assert!(
+                !is_session_id_equal(alice_store, &alice_address, bob_store, &bob_address).await?
+            );
+
+            let alice_plaintext = decrypt(
+                alice_store,
+                &bob_address,
+                &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                    message_for_alice.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(alice_plaintext).expect("valid utf8"),
+                "hi alice"
+            );
+
+            let bob_plaintext = decrypt(
+                bob_store,
+                &alice_address,
+                &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                    message_for_bob.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(bob_plaintext).expect("valid utf8"),
+                "hi bob"
+            );
+
+            assert_eq!(
+                alice_store.session_version(&bob_address)?,
+                expected_session_version
+            );
+            assert_eq!(
+                bob_store.session_version(&alice_address)?,
+                expected_session_version
+            );
+
+            assert!(
+                !is_session_id_equal(alice_store, &alice_address, bob_store, &bob_address).await?
+            );
+
+            let alice_response = encrypt(alice_store, &bob_address, "nice to see you").await?;
+
+            assert_eq!(
+                alice_response.message_type(),
+                CiphertextMessageType::Whisper
+            );
+
+            assert!(
+                !is_session_id_equal(alice_store, &alice_address, bob_store, &bob_address).await?
+            );
+
+            let bob_response = encrypt(bob_store, &alice_address, "you as well").await?;
+
+            assert_eq!(bob_response.message_type(), CiphertextMessageType::Whisper);
+
+            let response_plaintext = decrypt(
+                alice_store,
+                &bob_address,
+                &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                    bob_response.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(response_plaintext).expect("valid utf8"),
+                "you as well"
+            );
+
+            assert!(
+                is_session_id_equal(bob_store, &bob_address, alice_store, &alice_address).await?
+            );
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_simultaneous_initiate_repeated_messages() -> TestResult {
+    let mut alice_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random);
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random);
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        PRE_KYBER_MESSAGE_VERSION,
+    )?;
+
+    let mut alice_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random)
+        .with_kyber_pre_key(IdChoice::Random);
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(IdChoice::Random)
+        .with_signed_pre_key(IdChoice::Random)
+        .with_kyber_pre_key(IdChoice::Random);
+    run(
+        &mut alice_store_builder,
+        &mut bob_store_builder,
+        KYBER_AWARE_MESSAGE_VERSION,
+    )?;
+
+    fn run(
+        alice_store_builder: &mut TestStoreBuilder,
+        bob_store_builder: &mut TestStoreBuilder,
+        expected_session_version: u32,
+    ) -> TestResult {
+        async {
+            let mut csprng = OsRng;
+
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+            for _ in 0..15 {
+                let alice_pre_key_bundle =
+                    alice_store_builder.make_bundle_with_latest_keys(1.into());
+                let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+                process_prekey_bundle(
+                    &bob_address,
+                    &mut alice_store_builder.store.session_store,
+                    &mut alice_store_builder.store.identity_store,
+                    &bob_pre_key_bundle,
+                    SystemTime::now(),
+                    &mut csprng,
+                )
+                .await?;
+
+                process_prekey_bundle(
+                    &alice_address,
+                    &mut bob_store_builder.store.session_store,
+                    &mut bob_store_builder.store.identity_store,
+                    &alice_pre_key_bundle,
+                    SystemTime::now(),
+                    &mut csprng,
+                )
+                .await?;
+
+                let message_for_bob =
+                    encrypt(&mut alice_store_builder.store, &bob_address, "hi bob").await?;
+                let message_for_alice =
+                    encrypt(&mut bob_store_builder.store, &alice_address, "hi alice").await?;
+
+                assert_eq!(
+                    message_for_bob.message_type(),
+                    CiphertextMessageType::PreKey
+                );
+                assert_eq!(
+                    message_for_alice.message_type(),
+                    CiphertextMessageType::PreKey
+                );
+
+                assert!(
+                    !is_session_id_equal(
+                        &alice_store_builder.store,
+                        &alice_address,
+                        &bob_store_builder.store,
+                        &bob_address,
+                    )
+                    .await?
+                );
+
+                let alice_plaintext = decrypt(
+                    &mut alice_store_builder.store,
+                    &bob_address,
+                    &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                        message_for_alice.serialize(),
+                    )?),
+                )
+                .await?;
+                assert_eq!(
+                    String::from_utf8(alice_plaintext).expect("valid utf8"),
+                    "hi alice"
+                );
+
+                let bob_plaintext = decrypt(
+                    &mut bob_store_builder.store,
+                    &alice_address,
+                    &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                        message_for_bob.serialize(),
+                    )?),
+                )
+                .await?;
+                assert_eq!(
+                    String::from_utf8(bob_plaintext).expect("valid utf8"),
+                    "hi bob"
+                );
+
+                assert_eq!(
+                    alice_store_builder.session_version(&bob_address)?,
+                    expected_session_version
+                );
+                assert_eq!(
+                    bob_store_builder.session_version(&alice_address)?,
+                    expected_session_version
+                );
+
+                assert!(
+                    !is_session_id_equal(
+                        &alice_store_builder.store,
+                        &alice_address,
+                        &bob_store_builder.store,
+                        &bob_address,
+                    )
+                    .await?
+                );
+            }
+
+            for _ in 0..50 {
+                let message_for_bob =
+                    encrypt(&mut alice_store_builder.store, &bob_address, "hi bob").await?;
+                let message_for_alice =
+                    encrypt(&mut bob_store_builder.store, &alice_address, "hi alice").await?;
+
+                assert_eq!(
+                    message_for_bob.message_type(),
+                    CiphertextMessageType::Whisper
+                );
+                assert_eq!(
+                    message_for_alice.message_type(),
+                    CiphertextMessageType::Whisper
+                );
+
+                assert!(
+                    !is_session_id_equal(
+                        &alice_store_builder.store,
+                        &alice_address,
+                        &bob_store_builder.store,
+                        &bob_address,
+                    )
+                    .await?
+                );
+
+                let alice_plaintext = decrypt(
+                    &mut alice_store_builder.store,
+                    &bob_address,
+                    &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                        message_for_alice.serialize(),
+                    )?),
+                )
+                .await?;
+                assert_eq!(
+                    String::from_utf8(alice_plaintext).expect("valid utf8"),
+                    "hi alice"
+                );
+
+                let bob_plaintext = decrypt(
+                    &mut bob_store_builder.store,
+                    &alice_address,
+                    &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                        message_for_bob.serialize(),
+                    )?),
+                )
+                .await?;
+                assert_eq!(
+                    String::from_utf8(bob_plaintext).expect("valid utf8"),
+                    "hi bob"
+                );
+
+                assert_eq!(
+                    alice_store_builder.session_version(&bob_address)?,
+                    expected_session_version
+                );
+                assert_eq!(
+                    bob_store_builder.session_version(&alice_address)?,
+                    expected_session_version
+                );
+
+                assert!(
+                    !is_session_id_equal(
+                        &alice_store_builder.store,
+                        &alice_address,
+                        &bob_store_builder.store,
+                        &bob_address,
+                    )
+                    .await?
+                );
+            }
+
+            let alice_response = encrypt(
+                &mut alice_store_builder.store,
+                &bob_address,
+                "nice to see you",
+            )
+            .await?;
+
+            assert_eq!(
+                alice_response.message_type(),
+                CiphertextMessageType::Whisper
+            );
+
+            assert!(
+                !is_session_id_equal(
+                    &alice_store_builder.store,
+                    &alice_address,
+                    &bob_store_builder.store,
+                    &bob_address,
+                )
+                .await?
+            );
+
+            let bob_response =
+                encrypt(&mut bob_store_builder.store, &alice_address, "you as well").await?;
+
+            assert_eq!(bob_response.message_type(), CiphertextMessageType::Whisper);
+
+            let response_plaintext = decrypt(
+                &mut alice_store_builder.store,
+                &bob_address,
+                &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                    bob_response.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(response_plaintext).expect("valid utf8"),
+                "you as well"
+            );
+
+            assert!(
+                is_session_id_equal(
+                    &bob_store_builder.store,
+                    &bob_address,
+                    &alice_store_builder.store,
+                    &alice_address,
+                )
+                .await?
+            );
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_simultaneous_initiate_lost_message_repeated_messages() -> TestResult {
+    run(
+        |builder| {
+            builder.add_pre_key(IdChoice::Next);
+            builder.add_signed_pre_key(IdChoice::Next);
+        },
+        PRE_KYBER_MESSAGE_VERSION,
+    )?;
+
+    run(
+        |builder| {
+            builder.add_pre_key(IdChoice::Next);
+            builder.add_signed_pre_key(IdChoice::Next);
+            builder.add_kyber_pre_key(IdChoice::Next);
+        },
+        KYBER_AWARE_MESSAGE_VERSION,
+    )?;
+
+    fn run<F>(add_keys: F, expected_session_version: u32) -> TestResult
+    where
+        F: Fn(&mut TestStoreBuilder),
+    {
+        async {
+            let mut csprng = OsRng;
+
+            let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+            let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+            let mut alice_store_builder = TestStoreBuilder::new();
+            add_keys(&mut alice_store_builder);
+            let mut bob_store_builder = TestStoreBuilder::new();
+            add_keys(&mut bob_store_builder);
+
+            let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store_builder.store.session_store,
+                &mut alice_store_builder.store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+            let lost_message_for_bob = encrypt(
+                &mut alice_store_builder.store,
+                &bob_address,
+                "it was so long ago",
+            )
+            .await?;
+
+            for i in 0..15 {
+                add_keys(&mut alice_store_builder);
+                add_keys(&mut bob_store_builder);
+
+                let alice_pre_key_bundle =
+                    alice_store_builder.make_bundle_with_latest_keys((i + 2).into());
+                let bob_pre_key_bundle =
+                    bob_store_builder.make_bundle_with_latest_keys((i + 2).into());
+
+                process_prekey_bundle(
+                    &bob_address,
+                    &mut alice_store_builder.store.session_store,
+                    &mut alice_store_builder.store.identity_store,
+                    &bob_pre_key_bundle,
+                    SystemTime::now(),
+                    &mut csprng,
+                )
+                .await?;
+
+                process_prekey_bundle(
+                    &alice_address,
+                    &mut bob_store_builder.store.session_store,
+                    &mut bob_store_builder.store.identity_store,
+                    &alice_pre_key_bundle,
+                    SystemTime::now(),
+                    &mut csprng,
+                )
+                .await?;
+
+                let message_for_bob =
+                    encrypt(&mut alice_store_builder.store, &bob_address, "hi bob").await?;
+                let message_for_alice =
+                    encrypt(&mut bob_store_builder.store, &alice_address, "hi alice").await?;
+
+                assert_eq!(
+                    message_for_bob.message_type(),
+                    CiphertextMessageType::PreKey
+                );
+                assert_eq!(
+                    message_for_alice.message_type(),
+                    CiphertextMessageType::PreKey
+                );
+
+                assert!(
+                    !is_session_id_equal(
+                        &alice_store_builder.store,
+                        &alice_address,
+                        &bob_store_builder.store,
+                        &bob_address,
+                    )
+                    .await?
+                );
+
+                let alice_plaintext = decrypt(
+                    &mut alice_store_builder.store,
+                    &bob_address,
+                    &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                        message_for_alice.serialize(),
+                    )?),
+                )
+                .await?;
+                assert_eq!(
+                    String::from_utf8(alice_plaintext).expect("valid utf8"),
+                    "hi alice"
+                );
+
+                let bob_plaintext = decrypt(
+                    &mut bob_store_builder.store,
+                    &alice_address,
+                    &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                        message_for_bob.serialize(),
+                    )?),
+                )
+                .await?;
+                assert_eq!(
+                    String::from_utf8(bob_plaintext).expect("valid utf8"),
+                    "hi bob"
+                );
+
+                assert_eq!(
+                    alice_store_builder.session_version(&bob_address)?,
+                    expected_session_version
+                );
+                assert_eq!(
+                    bob_store_builder.session_version(&alice_address)?,
+                    expected_session_version
+                );
+
+                assert!(
+                    !is_session_id_equal(
+                        &alice_store_builder.store,
+                        &alice_address,
+                        &bob_store_builder.store,
+                        &bob_address,
+                    )
+                    .await?
+                );
+            }
+
+            for _ in 0..50 {
+                let message_for_bob =
+                    encrypt(&mut alice_store_builder.store, &bob_address, "hi bob").await?;
+                let message_for_alice =
+                    encrypt(&mut bob_store_builder.store, &alice_address, "hi alice").await?;
+
+                assert_eq!(
+                    message_for_bob.message_type(),
+                    CiphertextMessageType::Whisper
+                );
+                assert_eq!(
+                    message_for_alice.message_type(),
+                    CiphertextMessageType::Whisper
+                );
+
+                assert!(
+                    !is_session_id_equal(
+                        &alice_store_builder.store,
+                        &alice_address,
+                        &bob_store_builder.store,
+                        &bob_address,
+                    )
+                    .await?
+                );
+
+                let alice_plaintext = decrypt(
+                    &mut alice_store_builder.store,
+                    &bob_address,
+                    &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                        message_for_alice.serialize(),
+                    )?),
+                )
+                .await?;
+                assert_eq!(
+                    String::from_utf8(alice_plaintext).expect("valid utf8"),
+                    "hi alice"
+                );
+
+                let bob_plaintext = decrypt(
+                    &mut bob_store_builder.store,
+                    &alice_address,
+                    &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                        message_for_bob.serialize(),
+                    )?),
+                )
+                .await?;
+                assert_eq!(
+                    String::from_utf8(bob_plaintext).expect("valid utf8"),
+                    "hi bob"
+                );
+
+                assert_eq!(
+                    alice_store_builder.session_version(&bob_address)?,
+                    expected_session_version
+                );
+                assert_eq!(
+                    bob_store_builder.session_version(&alice_address)?,
+                    expected_session_version
+                );
+
+                assert!(
+                    !is_session_id_equal(
+                        &alice_store_builder.store,
+                        &alice_address,
+                        &bob_store_builder.store,
+                        &bob_address,
+                    )
+                    .await?
+                );
+            }
+
+            let alice_response = encrypt(
+                &mut alice_store_builder.store,
+                &bob_address,
+                "nice to see you",
+            )
+            .await?;
+
+            assert_eq!(
+                alice_response.message_type(),
+                CiphertextMessageType::Whisper
+            );
+
+            assert!(
+                !is_session_id_equal(
+                    &alice_store_builder.store,
+                    &alice_address,
+                    &bob_store_builder.store,
+                    &bob_address,
+                )
+                .await?
+            );
+
+            let bob_response =
+                encrypt(&mut bob_store_builder.store, &alice_address, "you as well").await?;
+
+            assert_eq!(bob_response.message_type(), CiphertextMessageType::Whisper);
+
+            let response_plaintext = decrypt(
+                &mut alice_store_builder.store,
+                &bob_address,
+                &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                    bob_response.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(response_plaintext).expect("valid utf8"),
+                "you as well"
+            );
+
+            assert!(
+                is_session_id_equal(
+                    &bob_store_builder.store,
+                    &bob_address,
+                    &alice_store_builder.store,
+                    &alice_address,
+                )
+                .await?
+            );
+
+            let blast_from_the_past = decrypt(
+                &mut bob_store_builder.store,
+                &alice_address,
+                &CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+                    lost_message_for_bob.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(blast_from_the_past).expect("valid utf8"),
+                "it was so long ago"
+            );
+
+            assert!(
+                !is_session_id_equal(
+                    &bob_store_builder.store,
+                    &bob_address,
+                    &alice_store_builder.store,
+                    &alice_address,
+                )
+                .await?
+            );
+
+            let bob_response =
+                encrypt(&mut bob_store_builder.store, &alice_address, "so it was").await?;
+
+            assert_eq!(bob_response.message_type(), CiphertextMessageType::Whisper);
+
+            let response_plaintext = decrypt(
+                &mut alice_store_builder.store,
+                &bob_address,
+                &CiphertextMessage::SignalMessage(SignalMessage::try_from(
+                    bob_response.serialize(),
+                )?),
+            )
+            .await?;
+            assert_eq!(
+                String::from_utf8(response_plaintext).expect("valid utf8"),
+                "so it was"
+            );
+
+            assert!(
+                is_session_id_equal(
+                    &bob_store_builder.store,
+                    &bob_address,
+                    &alice_store_builder.store,
+                    &alice_address,
+                )
+                .await?
+            );
+
+            Ok(())
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_zero_is_a_valid_prekey_id() -> TestResult {
+    async {
+        let mut csprng = OsRng;
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(0.into())
+            .with_signed_pre_key(0.into())
+            .with_kyber_pre_key(0.into());
+
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        assert_eq!(
+            alice_store
+                .load_session(&bob_address)
+                .await?
+                .expect("session found")
+                .session_version()?,
+            KYBER_AWARE_MESSAGE_VERSION
+        );
+
+        let original_message = "L'homme est condamné à être libre";
+
+        let outgoing_message = encrypt(&mut alice_store, &bob_address, original_message).await?;
+
+        assert_eq!(
+            outgoing_message.message_type(),
+            CiphertextMessageType::PreKey
+        );
+
+        let incoming_message = CiphertextMessage::PreKeySignalMessage(
+            PreKeySignalMessage::try_from(outgoing_message.serialize())?,
+        );
+
+        let ptext = decrypt(
+            &mut bob_store_builder.store,
+            &alice_address,
+            &incoming_message,
+        )
+        .await?;
+
+        assert_eq!(
+            String::from_utf8(ptext).expect("valid utf8"),
+            original_message
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_unacknowledged_sessions_eventually_expire() -> TestResult {
+    async {
+        const WELL_PAST_EXPIRATION: Duration = Duration::from_secs(60 * 60 * 24 * 90);
+
+        let mut csprng = OsRng;
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(0.into())
+            .with_signed_pre_key(0.into())
+            .with_kyber_pre_key(0.into());
+
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::UNIX_EPOCH,
+            &mut csprng,
+        )
+        .await?;
+
+        let initial_session = alice_store
+            .session_store
+            .load_session(&bob_address)
+            .await
+            .expect("session can be loaded")
+            .expect("session exists");
+        assert!(initial_session
+            .has_usable_sender_chain(SystemTime::UNIX_EPOCH)
+            .expect("can check for a sender chain"));
+        assert!(!initial_session
+            .has_usable_sender_chain(SystemTime::UNIX_EPOCH + WELL_PAST_EXPIRATION)
+            .expect("can check for a sender chain"));
+
+        let original_message = "L'homme est condamné à être libre";
+        let outgoing_message = message_encrypt(
+            original_message.as_bytes(),
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+        )
+        .await?;
+
+        assert_eq!(
+            outgoing_message.message_type(),
+            CiphertextMessageType::PreKey
+        );
+
+        let updated_session = alice_store
+            .session_store
+            .load_session(&bob_address)
+            .await
+            .expect("session can be loaded")
+            .expect("session exists");
+        assert!(updated_session
+            .has_usable_sender_chain(SystemTime::UNIX_EPOCH)
+            .expect("can check for a sender chain"));
+        assert!(!updated_session
+            .has_usable_sender_chain(SystemTime::UNIX_EPOCH + WELL_PAST_EXPIRATION)
+            .expect("can check for a sender chain"));
+
+        let error = message_encrypt(
+            original_message.as_bytes(),
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::UNIX_EPOCH + WELL_PAST_EXPIRATION,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(&error, SignalProtocolError::SessionNotFound(addr) if addr == &bob_address),
+            "{:?}",
+            error
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_custom_unacknowledged_session_age() -> TestResult {
+    async {
+        const TWO_HOURS: Duration = Duration::from_secs(60 * 60 * 2);
+
+        let mut csprng = OsRng;
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(0.into())
+            .with_signed_pre_key(0.into())
+            .with_kyber_pre_key(0.into());
+
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::UNIX_EPOCH,
+            &mut csprng,
+        )
+        .await?;
+
+        let config = ProtocolConfig {
+            max_unacknowledged_session_age: Duration::from_secs(60 * 60),
+            ..Default::default()
+        };
+
+        let session = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session exists");
+        assert!(session.has_usable_sender_chain(SystemTime::UNIX_EPOCH + TWO_HOURS)?);
+        assert!(!session
+            .has_usable_sender_chain_with_config(SystemTime::UNIX_EPOCH + TWO_HOURS, &config)?);
+
+        let error = message_encrypt_with_config(
+            b"too late",
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::UNIX_EPOCH + TWO_HOURS,
+            &config,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(&error, SignalProtocolError::SessionNotFound(addr) if addr == &bob_address),
+            "{:?}",
+            error
+        );
+
+        // The default limit is more lenient.
+        message_encrypt(
+            b"not too late",
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::UNIX_EPOCH + TWO_HOURS,
+        )
+        .await?;
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_prekey_decrypt_in_transaction() -> TestResult {
+    async {
+        let mut csprng = OsRng;
+
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store = TestStoreBuilder::new()
+            .with_pre_key(7.into())
+            .with_signed_pre_key(8.into())
+            .with_kyber_pre_key(9.into())
+            .store;
+
+        let bob_pre_key_bundle =
+            TestStoreBuilder::from_store(&bob_store).make_bundle_with_latest_keys(1.into());
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let original_message = "Tout est permis";
+        let outgoing_message = encrypt(&mut alice_store, &bob_address, original_message).await?;
+        assert_eq!(
+            outgoing_message.message_type(),
+            CiphertextMessageType::PreKey
+        );
+
+        bob_store.begin_transaction().await?;
+        let ptext = decrypt(&mut bob_store, &alice_address, &outgoing_message).await?;
+        assert_eq!(
+            String::from_utf8(ptext).expect("valid utf8"),
+            original_message
+        );
+        assert!(bob_store.load_session(&alice_address).await?.is_some());
+        bob_store.rollback_transaction().await?;
+
+        // Every mutation from the decryption has been undone.
+        assert!(bob_store.load_session(&alice_address).await?.is_none());
+        assert!(bob_store.get_identity(&alice_address).await?.is_none());
+        assert!(bob_store.get_pre_key(7.into()).await.is_ok());
+
+        bob_store.begin_transaction().await?;
+        assert!(matches!(
+            bob_store.begin_transaction().await,
+            Err(SignalProtocolError::InvalidState(..))
+        ));
+        let ptext = decrypt(&mut bob_store, &alice_address, &outgoing_message).await?;
+        assert_eq!(
+            String::from_utf8(ptext).expect("valid utf8"),
+            original_message
+        );
+        bob_store.commit_transaction().await?;
+
+        assert!(bob_store.load_session(&alice_address).await?.is_some());
+        assert!(bob_store.get_identity(&alice_address).await?.is_some());
+        assert!(matches!(
+            bob_store.get_pre_key(7.into()).await,
+            Err(SignalProtocolError::InvalidPreKeyId)
+        ));
+
+        assert!(matches!(
+            bob_store.commit_transaction().await,
+            Err(SignalProtocolError::InvalidState(..))
+        ));
+        assert!(matches!(
+            bob_store.rollback_transaction().await,
+            Err(SignalProtocolError::InvalidState(..))
+        ));
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_session_description() -> TestResult {
+    async {
+        let mut csprng = OsRng;
+
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store = TestStoreBuilder::new()
+            .with_pre_key(7.into())
+            .with_signed_pre_key(8.into())
+            .with_kyber_pre_key(9.into())
+            .store;
+
+        let bob_pre_key_bundle =
+            TestStoreBuilder::from_store(&bob_store).make_bundle_with_latest_keys(1.into());
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let mut messages = vec![];
+        for i in 0..3 {
+            messages
+                .push(encrypt(&mut alice_store, &bob_address, &format!("message {}", i)).await?);
+        }
+
+        let alice_record = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session found");
+        let description = alice_record.describe()?;
+        assert!(description.previous_sessions.is_empty());
+        let current = description.current_session.expect("has current session");
+        assert_eq!(current.version, KYBER_AWARE_MESSAGE_VERSION);
+        assert_eq!(current.sender_chain.as_ref().and_then(|c| c.index), Some(3));
+        assert_eq!(current.receiver_chains.len(), 1);
+        let pending = current.pending_pre_key.expect("not yet acknowledged");
+        assert_eq!(pending.pre_key_id, Some(7.into()));
+        assert_eq!(pending.signed_pre_key_id, 8.into());
+        assert_eq!(pending.kyber_pre_key_id, Some(9.into()));
+
+        // No secrets show up in the description.
+        let debug_output = format!("{:?}", alice_record.describe()?);
+        let sender_chain_key = hex::encode(alice_record.get_sender_chain_key_bytes()?);
+        assert!(!debug_output.contains(&sender_chain_key));
+
+        decrypt(&mut bob_store, &alice_address, &messages[2]).await?;
+        let mut bob_record = bob_store
+            .load_session(&alice_address)
+            .await?
+            .expect("session found");
+        let current = bob_record
+            .describe()?
+            .current_session
+            .expect("has current session");
+        assert!(current.pending_pre_key.is_none());
+        assert_eq!(current.receiver_chains.len(), 1);
+        assert_eq!(current.receiver_chains[0].index, Some(3));
+        assert_eq!(current.receiver_chains[0].cached_message_keys, 2);
+
+        bob_record.archive_current_state()?;
+        let description = bob_record.describe()?;
+        assert!(description.current_session.is_none());
+        assert_eq!(description.previous_sessions.len(), 1);
+        assert_eq!(
+            description.previous_sessions[0].receiver_chains,
+            current.receiver_chains
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+/// The KEM recorded by Alice's and Bob's sessions after Alice starts a session from a bundle.
+type NegotiatedKems = (Option<kem::KeyType>, Option<kem::KeyType>);
+
+/// Has Alice start a session with Bob, whose bundle offers a Kyber pre-key of each of `offered`
+/// types, and returns the KEM each side ends up using.
+async fn negotiate_kem(
+    offered: &[kem::KeyType],
+    alice_preference: &'static [kem::KeyType],
+) -> Result<NegotiatedKems, SignalProtocolError> {
+    let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+    let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+    let mut alice_store = TestStoreBuilder::new().store;
+    let mut bob_store_builder = TestStoreBuilder::new()
+        .with_pre_key(1.into())
+        .with_signed_pre_key(2.into());
+    for (id, key_type) in (10..).zip(offered) {
+        bob_store_builder.add_kyber_pre_key_of_type(id.into(), *key_type);
+    }
+    let mut bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+    let mut bob_store = bob_store_builder.store;
+    for id in bob_store.kyber_pre_key_ids() {
+        let record = bob_store.get_kyber_pre_key(id).await?;
+        bob_pre_key_bundle =
+            bob_pre_key_bundle.with_kyber_pre_key(id, record.public_key()?, record.signature()?);
+    }
+    assert_eq!(bob_pre_key_bundle.kyber_pre_keys().len(), offered.len());
+
+    let config = ProtocolConfig {
+        kem_preference: alice_preference,
+        ..Default::default()
+    };
+    process_prekey_bundle_with_config(
+        &bob_address,
+        &mut alice_store.session_store,
+        &mut alice_store.identity_store,
+        &bob_pre_key_bundle,
+        SystemTime::now(),
+        &mut OsRng,
+        &config,
+    )
+    .await?;
+
+    let message = encrypt(&mut alice_store, &bob_address, "which KEM?").await?;
+    assert_eq!(
+        decrypt(&mut bob_store, &alice_address, &message).await?,
+        b"which KEM?"
+    );
+
+    let alice_kem = alice_store
+        .load_session(&bob_address)
+        .await?
+        .expect("session found")
+        .kem_key_type()?;
+    let bob_kem = bob_store
+        .load_session(&alice_address)
+        .await?
+        .expect("session found")
+        .kem_key_type()?;
+    Ok((alice_kem, bob_kem))
+}
+
+/// Checks that peers offering and preferring different combinations of Kyber1024 and `new_type`
+/// agree on a KEM.
+///
+/// `new_preference` should list `new_type` ahead of Kyber1024.
+fn check_kem_migration(
+    new_type: kem::KeyType,
+    new_preference: &'static [kem::KeyType],
+) -> TestResult {
+    use kem::KeyType::Kyber1024;
+    const OLD_PREFERENCE: &[kem::KeyType] = &[Kyber1024];
+
+    async {
+        // Both sides upgraded.
+        assert_eq!(
+            negotiate_kem(&[Kyber1024, new_type], new_preference).await?,
+            (Some(new_type), Some(new_type))
+        );
+        // The bundle's order doesn't matter, only the sender's preference.
+        assert_eq!(
+            negotiate_kem(&[new_type, Kyber1024], new_preference).await?,
+            (Some(new_type), Some(new_type))
+        );
+        // An old sender keeps using Kyber1024 with an upgraded recipient.
+        assert_eq!(
+            negotiate_kem(&[new_type, Kyber1024], OLD_PREFERENCE).await?,
+            (Some(Kyber1024), Some(Kyber1024))
+        );
+        // An upgraded sender falls back to Kyber1024 for an old recipient.
+        assert_eq!(
+            negotiate_kem(&[Kyber1024], new_preference).await?,
+            (Some(Kyber1024), Some(Kyber1024))
+        );
+        // Recipients without any Kyber pre-keys are still supported.
+        assert_eq!(negotiate_kem(&[], new_preference).await?, (None, None));
+        // But a sender won't use a KEM it hasn't opted into.
+        assert!(matches!(
+            negotiate_kem(&[new_type], OLD_PREFERENCE).await,
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+#[cfg(feature = "kyber768")]
+fn test_kem_negotiation_between_mixed_peers() -> TestResult {
+    check_kem_migration(
+        kem::KeyType::Kyber768,
+        &[kem::KeyType::Kyber768, kem::KeyType::Kyber1024],
+    )
+}
+
+#[test]
+#[cfg(feature = "mlkem1024")]
+fn test_mlkem1024_migration() -> TestResult {
+    // Supported, but not yet preferred by default.
+    assert!(ProtocolConfig::DEFAULT
+        .kem_preference
+        .starts_with(&[kem::KeyType::Kyber1024, kem::KeyType::MLKEM1024]));
+    check_kem_migration(
+        kem::KeyType::MLKEM1024,
+        &[kem::KeyType::MLKEM1024, kem::KeyType::Kyber1024],
+    )
+}
+
+#[test]
+fn test_bundle_kyber_pre_keys_survive_modify() -> TestResult {
+    let bob_store_builder = TestStoreBuilder::new()
+        .with_signed_pre_key(1.into())
+        .with_kyber_pre_key(2.into());
+    let bundle = bob_store_builder.make_bundle_with_latest_keys(1.into());
+    let first_public = bundle.kyber_pre_key_public()?.expect("has kyber").clone();
+
+    // A second key of the same type replaces the first.
+    let replacement = kem::KeyPair::generate(kem::KeyType::Kyber1024);
+    let bundle = bundle.with_kyber_pre_key(3.into(), replacement.public_key.clone(), vec![0; 64]);
+    assert_eq!(bundle.kyber_pre_keys().len(), 1);
+    assert_eq!(bundle.kyber_pre_key_id()?, Some(3.into()));
+    assert!(bundle.kyber_pre_key_public()? != Some(&first_public));
+
+    let bundle = bundle.modify(|content| {
+        content.additional_kyber_pre_keys.clear();
+    })?;
+    assert_eq!(bundle.kyber_pre_key_id()?, Some(3.into()));
+    assert!(
+        bundle.select_kyber_pre_key(&[kem::KeyType::Kyber1024])?
+            == Some((3.into(), &replacement.public_key))
+    );
+    assert!(bundle.select_kyber_pre_key(&[]).is_err());
+
+    Ok(())
+}
+
+struct PqRatchetPeer {
+    store: Store,
+    address: ProtocolAddress,
+    config: ProtocolConfig,
+}
+
+impl PqRatchetPeer {
+    fn new(name: &str, enable_pq_ratchet: bool) -> Self {
+        Self {
+            store: test_protocol_store(),
+            address: ProtocolAddress::new(name.to_owned(), 1.into()),
+            config: ProtocolConfig {
+                enable_pq_ratchet,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn encrypt(&mut self, to: &ProtocolAddress, msg: &str) -> CiphertextMessage {
+        message_encrypt_with_config(
+            msg.as_bytes(),
+            to,
+            &mut self.store.session_store,
+            &mut self.store.identity_store,
+            SystemTime::now(),
+            &self.config,
+        )
+        .now_or_never()
+        .expect("sync")
+        .expect("can encrypt")
+    }
+
+    fn decrypt(&mut self, from: &ProtocolAddress, msg: &CiphertextMessage) -> Vec<u8> {
+        message_decrypt_with_config(
+            msg,
+            from,
+            &mut self.store.session_store,
+            &mut self.store.identity_store,
+            &mut self.store.pre_key_store,
+            &self.store.signed_pre_key_store,
+            &mut self.store.kyber_pre_key_store,
+            &mut OsRng,
+            &self.config,
+        )
+        .now_or_never()
+        .expect("sync")
+        .expect("can decrypt")
+    }
+
+    fn pq_ratchet_epochs(&self, of: &ProtocolAddress) -> Option<u32> {
+        self.store
+            .load_session(of)
+            .now_or_never()
+            .expect("sync")
+            .expect("can load")
+            .expect("session found")
+            .pq_ratchet_epochs()
+            .expect("current session")
+    }
+}
+
+fn initialize_pq_ratchet_peers(alice_pq: bool, bob_pq: bool) -> (PqRatchetPeer, PqRatchetPeer) {
+    let mut alice = PqRatchetPeer::new("+14151111111", alice_pq);
+    let mut bob = PqRatchetPeer::new("+14151111112", bob_pq);
+
+    let bob_pre_key_bundle = create_pre_key_bundle(&mut bob.store, &mut OsRng)
+        .now_or_never()
+        .expect("sync")
+        .expect("valid bundle");
+    process_prekey_bundle_with_config(
+        &bob.address,
+        &mut alice.store.session_store,
+        &mut alice.store.identity_store,
+        &bob_pre_key_bundle,
+        SystemTime::now(),
+        &mut OsRng,
+        &alice.config,
+    )
+    .now_or_never()
+    .expect("sync")
+    .expect("valid bundle");
+
+    (alice, bob)
+}
+
+/// Sends `count` messages from `sender` to `receiver`, delivering those not in `dropped`.
+fn send_pq_ratchet_messages(
+    sender: &mut PqRatchetPeer,
+    receiver: &mut PqRatchetPeer,
+    count: usize,
+    dropped: &[bool],
+) {
+    for i in 0..count {
+        let ptext = format!("message {i} from {}", sender.address.name());
+        let ctext = sender.encrypt(&receiver.address, &ptext);
+        if dropped.get(i).copied().unwrap_or(false) {
+            continue;
+        }
+        assert_eq!(receiver.decrypt(&sender.address, &ctext), ptext.as_bytes());
+    }
+}
+
+#[test]
+fn test_pq_ratchet_advances_epochs() {
+    let (mut alice, mut bob) = initialize_pq_ratchet_peers(true, true);
+    assert_eq!(alice.pq_ratchet_epochs(&bob.address), Some(0));
+
+    for _ in 0..20 {
+        send_pq_ratchet_messages(&mut alice, &mut bob, 8, &[]);
+        send_pq_ratchet_messages(&mut bob, &mut alice, 8, &[]);
+        if alice.pq_ratchet_epochs(&bob.address) >= Some(2)
+            && bob.pq_ratchet_epochs(&alice.address) >= Some(2)
+        {
+            return;
+        }
+    }
+    panic!(
+        "post-quantum ratchet did not advance: alice at {:?}, bob at {:?}",
+        alice.pq_ratchet_epochs(&bob.address),
+        bob.pq_ratchet_epochs(&alice.address),
+    );
+}
+
+#[test]
+fn test_pq_ratchet_falls_back_when_responder_does_not_support_it() {
+    let (mut alice, mut bob) = initialize_pq_ratchet_peers(true, false);
+    send_pq_ratchet_messages(&mut alice, &mut bob, 3, &[]);
+    assert_eq!(alice.pq_ratchet_epochs(&bob.address), Some(0));
+    assert_eq!(bob.pq_ratchet_epochs(&alice.address), None);
+
+    send_pq_ratchet_messages(&mut bob, &mut alice, 3, &[]);
+    assert_eq!(alice.pq_ratchet_epochs(&bob.address), None);
+
+    send_pq_ratchet_messages(&mut alice, &mut bob, 3, &[]);
+    send_pq_ratchet_messages(&mut bob, &mut alice, 3, &[]);
+    assert_eq!(alice.pq_ratchet_epochs(&bob.address), None);
+    assert_eq!(bob.pq_ratchet_epochs(&alice.address), None);
+}
+
+#[test]
+fn test_pq_ratchet_not_offered_by_initiator() {
+    let (mut alice, mut bob) = initialize_pq_ratchet_peers(false, true);
+    for _ in 0..3 {
+        send_pq_ratchet_messages(&mut alice, &mut bob, 8, &[]);
+        send_pq_ratchet_messages(&mut bob, &mut alice, 8, &[]);
+    }
+    assert_eq!(alice.pq_ratchet_epochs(&bob.address), None);
+    assert_eq!(bob.pq_ratchet_epochs(&alice.address), None);
+}
+
+#[test]
+fn test_pq_ratchet_random_schedules() {
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    /// Messages are delivered at most this many steps after they're sent, so that they stay
+    /// within the receiver's `max_receiver_chains`.
+    const MAX_STEPS_IN_FLIGHT: usize = 3;
+
+    struct InFlight {
+        sent_at: usize,
+        ptext: Vec<u8>,
+        ctext: CiphertextMessage,
+    }
+
+    // Each step, one side sends a few messages, some of which are dropped, and then some of the
+    // messages in flight towards it are delivered, in a random order. Messages held back are
+    // delivered in later steps, by which time both sides may have moved on to later chains and
+    // later post-quantum ratchet epochs.
+    let step = (
+        any::<bool>(),
+        prop::collection::vec(any::<bool>(), 1..12),
+        prop::collection::vec(any::<Index>(), 0..12),
+    );
+    // Long enough to go through several epochs, each of which takes a whole public key and
+    // ciphertext's worth of chunks.
+    let schedule = prop::collection::vec(step, 40..80);
+    proptest!(ProptestConfig::with_cases(16), |(schedule in schedule)| {
+        let (mut alice, mut bob) = initialize_pq_ratchet_peers(true, true);
+        // Alice has to get a message through before Bob can reply.
+        send_pq_ratchet_messages(&mut alice, &mut bob, 1, &[]);
+
+        let mut to_bob: Vec<InFlight> = vec![];
+        let mut to_alice: Vec<InFlight> = vec![];
+        for (step, (from_alice, dropped, deliveries)) in schedule.into_iter().enumerate() {
+            let (sender, receiver, in_flight) = if from_alice {
+                (&mut alice, &mut bob, &mut to_bob)
+            } else {
+                (&mut bob, &mut alice, &mut to_alice)
+            };
+            for (i, dropped) in dropped.into_iter().enumerate() {
+                let ptext = format!("message {i} from {} in step {step}", sender.address.name());
+                let ctext = sender.encrypt(&receiver.address, &ptext);
+                if !dropped {
+                    in_flight.push(InFlight { sent_at: step, ptext: ptext.into_bytes(), ctext });
+                }
+            }
+
+            let mut delivered = vec![];
+            for index in deliveries {
+                if in_flight.is_empty() {
+                    break;
+                }
+                delivered.push(in_flight.swap_remove(index.index(in_flight.len())));
+            }
+            let (overdue, still_in_flight) = std::mem::take(in_flight)
+                .into_iter()
+                .partition::<Vec<_>, _>(|m| step - m.sent_at >= MAX_STEPS_IN_FLIGHT);
+            *in_flight = still_in_flight;
+            delivered.extend(overdue);
+            for message in delivered {
+                prop_assert_eq!(receiver.decrypt(&sender.address, &message.ctext), message.ptext);
+            }
+
+            let alice_epochs = alice.pq_ratchet_epochs(&bob.address).expect("enabled");
+            let bob_epochs = bob.pq_ratchet_epochs(&alice.address).expect("enabled");
+            prop_assert!(alice_epochs.abs_diff(bob_epochs) <= 1);
+        }
+
+        for message in to_bob {
+            prop_assert_eq!(bob.decrypt(&alice.address, &message.ctext), message.ptext);
+        }
+        for message in to_alice {
+            prop_assert_eq!(alice.decrypt(&bob.address, &message.ctext), message.ptext);
+        }
+    });
+}
+
+#[allow(clippy::needless_range_loop)]
+fn run_session_interaction(alice_session: SessionRecord, bob_session: SessionRecord) -> TestResult {
+    async {
+        use rand::seq::SliceRandom;
+
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), 1.into());
+        let bob_address = ProtocolAddress::new("+14158888888".to_owned(), 1.into());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store = TestStoreBuilder::new().store;
+
+        alice_store
+            .store_session(&bob_address, &alice_session)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session)
+            .await?;
+
+        let alice_plaintext = "This is Alice's message";
+        let alice_ciphertext = encrypt(&mut alice_store, &bob_address, alice_plaintext).await?;
+        let bob_decrypted = decrypt(&mut bob_store, &alice_address, &alice_ciphertext).await?;
+        assert_eq!(
+            String::from_utf8(bob_decrypted).expect("valid utf8"),
+            alice_plaintext
+        );
+
+        let bob_plaintext = "This is Bob's reply";
+
+        let bob_ciphertext = encrypt(&mut bob_store, &alice_address, bob_plaintext).await?;
+        let alice_decrypted = decrypt(&mut alice_store, &bob_address, &bob_ciphertext).await?;
+        assert_eq!(
+            String::from_utf8(alice_decrypted).expect("valid utf8"),
+            bob_plaintext
+        );
+
+        const ALICE_MESSAGE_COUNT: usize = 50;
+        const BOB_MESSAGE_COUNT: usize = 50;
+
+        let mut alice_messages = Vec::with_capacity(ALICE_MESSAGE_COUNT);
+
+        for i in 0..ALICE_MESSAGE_COUNT {
+            let ptext = format!("смерть за смерть {}", i);
+            let ctext = encrypt(&mut alice_store, &bob_address, &ptext).await?;
+            alice_messages.push((ptext, ctext));
+        }
+
+        let mut rng = rand::rngs::OsRng;
+
+        alice_messages.shuffle(&mut rng);
+
+        for i in 0..ALICE_MESSAGE_COUNT / 2 {
+            let ptext = decrypt(&mut bob_store, &alice_address, &alice_messages[i].1).await?;
+            assert_eq!(
+                String::from_utf8(ptext).expect("valid utf8"),
+                alice_messages[i].0
+            );
+        }
+
+        let mut bob_messages = Vec::with_capacity(BOB_MESSAGE_COUNT);
+
+        for i in 0..BOB_MESSAGE_COUNT {
+            let ptext = format!("Relax in the safety of your own delusions. {}", i);
+            let ctext = encrypt(&mut bob_store, &alice_address, &ptext).await?;
+            bob_messages.push((ptext, ctext));
+        }
+
+        bob_messages.shuffle(&mut rng);
+
+        for i in 0..BOB_MESSAGE_COUNT / 2 {
+            let ptext = decrypt(&mut alice_store, &bob_address, &bob_messages[i].1).await?;
+            assert_eq!(
+                String::from_utf8(ptext).expect("valid utf8"),
+                bob_messages[i].0
+            );
+        }
+
+        for i in ALICE_MESSAGE_COUNT / 2..ALICE_MESSAGE_COUNT {
+            let ptext = decrypt(&mut bob_store, &alice_address, &alice_messages[i].1).await?;
+            assert_eq!(
+                String::from_utf8(ptext).expect("valid utf8"),
+                alice_messages[i].0
+            );
+        }
+
+        for i in BOB_MESSAGE_COUNT / 2..BOB_MESSAGE_COUNT {
+            let ptext = decrypt(&mut alice_store, &bob_address, &bob_messages[i].1).await?;
+            assert_eq!(
+                String::from_utf8(ptext).expect("valid utf8"),
+                bob_messages[i].0
+            );
+        }
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+async fn run_interaction(
+    alice_store: &mut Store,
+    alice_address: &ProtocolAddress,
+    bob_store: &mut Store,
+    bob_address: &ProtocolAddress,
+) -> TestResult {
+    let alice_ptext = "It's rabbit season";
+
+    let alice_message = encrypt(alice_store, bob_address, alice_ptext).await?;
+    assert_eq!(alice_message.message_type(), CiphertextMessageType::Whisper);
+    assert_eq!(
+        String::from_utf8(decrypt(bob_store, alice_address, &alice_message).await?)
+            .expect("valid utf8"),
+        alice_ptext
+    );
+
+    let bob_ptext = "It's duck season";
+
+    let bob_message = encrypt(bob_store, alice_address, bob_ptext).await?;
+    assert_eq!(bob_message.message_type(), CiphertextMessageType::Whisper);
+    assert_eq!(
+        String::from_utf8(decrypt(alice_store, bob_address, &bob_message).await?)
+            .expect("valid utf8"),
+        bob_ptext
+    );
+
+    for i in 0..10 {
+        let alice_ptext = format!("A->B message {}", i);
+        let alice_message = encrypt(alice_store, bob_address, &alice_ptext).await?;
+        assert_eq!(alice_message.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            String::from_utf8(decrypt(bob_store, alice_address, &alice_message).await?)
+                .expect("valid utf8"),
+            alice_ptext
+        );
+    }
+
+    for i in 0..10 {
+        let bob_ptext = format!("B->A message {}", i);
+        let bob_message = encrypt(bob_store, alice_address, &bob_ptext).await?;
+        assert_eq!(bob_message.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            String::from_utf8(decrypt(alice_store, bob_address, &bob_message).await?)
+                .expect("valid utf8"),
+            bob_ptext
+        );
+    }
+
+    let mut alice_ooo_messages = vec![];
+
+    for i in 0..10 {
+        let alice_ptext = format!("A->B OOO message {}", i);
+        let alice_message = encrypt(alice_store, bob_address, &alice_ptext).await?;
+        alice_ooo_messages.push((alice_ptext, alice_message));
+    }
+
+    for i in 0..10 {
+        let alice_ptext = format!("A->B post-OOO message {}", i);
+        let alice_message = encrypt(alice_store, bob_address, &alice_ptext).await?;
+        assert_eq!(alice_message.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            String::from_utf8(decrypt(bob_store, alice_address, &alice_message).await?)
+                .expect("valid utf8"),
+            alice_ptext
+        );
+    }
+
+    for i in 0..10 {
+        let bob_ptext = format!("B->A message post-OOO {}", i);
+        let bob_message = encrypt(bob_store, alice_address, &bob_ptext).await?;
+        assert_eq!(bob_message.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            String::from_utf8(decrypt(alice_store, bob_address, &bob_message).await?)
+                .expect("valid utf8"),
+            bob_ptext
+        );
+    }
+
+    for (ptext, ctext) in alice_ooo_messages {
+        assert_eq!(
+            String::from_utf8(decrypt(bob_store, alice_address, &ctext).await?)
+                .expect("valid utf8"),
+            ptext
+        );
+    }
+
+    Ok(())
+}
+
+async fn is_session_id_equal(
+    alice_store: &dyn ProtocolStore,
+    alice_address: &ProtocolAddress,
+    bob_store: &dyn ProtocolStore,
+    bob_address: &ProtocolAddress,
+) -> Result<bool, SignalProtocolError> {
+    Ok(alice_store
+        .load_session(bob_address)
+        .await?
+        .expect("session found")
+        .alice_base_key()?
+        == bob_store
+            .load_session(alice_address)
+            .await?
+            .expect("session found")
+            .alice_base_key()?)
+}
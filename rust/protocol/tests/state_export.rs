@@ -0,0 +1,325 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+mod support;
+
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::rngs::OsRng;
+use rand::Rng;
+
+use std::time::SystemTime;
+use support::*;
+use uuid::Uuid;
+
+const DISTRIBUTION_ID: Uuid = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+struct Conversation {
+    alice_address: ProtocolAddress,
+    bob_address: ProtocolAddress,
+    alice_store: InMemSignalProtocolStore,
+    bob_store: InMemSignalProtocolStore,
+}
+
+/// Sets up a session between Alice and Bob, and has Bob share a sender key with Alice.
+async fn establish_conversation() -> Result<Conversation, SignalProtocolError> {
+    let mut csprng = OsRng;
+
+    let alice_address = ProtocolAddress::new("+14151111111".to_owned(), 1.into());
+    let bob_address = ProtocolAddress::new("+14151111112".to_owned(), 1.into());
+
+    let mut alice_store = test_in_memory_protocol_store()?;
+    let mut bob_store = test_in_memory_protocol_store()?;
+
+    let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+    process_prekey_bundle(
+        &bob_address,
+        &mut alice_store.session_store,
+        &mut alice_store.identity_store,
+        &bob_pre_key_bundle,
+        SystemTime::now(),
+        &mut csprng,
+    )
+    .await?;
+
+    let message = encrypt(&mut alice_store, &bob_address, "hi bob").await?;
+    assert_eq!(
+        decrypt(&mut bob_store, &alice_address, &message).await?,
+        b"hi bob"
+    );
+    let reply = encrypt(&mut bob_store, &alice_address, "hi alice").await?;
+    assert_eq!(
+        decrypt(&mut alice_store, &bob_address, &reply).await?,
+        b"hi alice"
+    );
+
+    let distribution_message = create_sender_key_distribution_message(
+        &bob_address,
+        DISTRIBUTION_ID,
+        &mut bob_store,
+        &mut csprng,
+    )
+    .await?;
+    process_sender_key_distribution_message(&bob_address, &distribution_message, &mut alice_store)
+        .await?;
+
+    Ok(Conversation {
+        alice_address,
+        bob_address,
+        alice_store,
+        bob_store,
+    })
+}
+
+async fn export_alice(
+    conversation: &mut Conversation,
+    key: &[u8; STATE_EXPORT_KEY_SIZE],
+) -> Result<Vec<u8>, SignalProtocolError> {
+    export_protocol_state(
+        key,
+        &[conversation.bob_address.clone()],
+        &[(conversation.bob_address.clone(), DISTRIBUTION_ID)],
+        &conversation.alice_store.session_store,
+        &conversation.alice_store.identity_store,
+        &mut conversation.alice_store.sender_key_store,
+        &mut OsRng,
+    )
+    .await
+}
+
+async fn fresh_store_for(
+    store: &InMemSignalProtocolStore,
+) -> Result<InMemSignalProtocolStore, SignalProtocolError> {
+    InMemSignalProtocolStore::new(
+        store.get_identity_key_pair().await?,
+        store.get_local_registration_id().await?,
+    )
+}
+
+async fn import_into(
+    store: &mut InMemSignalProtocolStore,
+    key: &[u8; STATE_EXPORT_KEY_SIZE],
+    exported: &[u8],
+) -> Result<ProtocolStateImportSummary, SignalProtocolError> {
+    import_protocol_state(
+        key,
+        exported,
+        &mut store.session_store,
+        &mut store.identity_store,
+        &mut store.sender_key_store,
+    )
+    .await
+}
+
+#[test]
+fn test_export_import_round_trip() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng;
+        let key: [u8; STATE_EXPORT_KEY_SIZE] = csprng.gen();
+
+        let mut conversation = establish_conversation().await?;
+        let exported = export_alice(&mut conversation, &key).await?;
+
+        let mut new_alice_store = fresh_store_for(&conversation.alice_store).await?;
+        let summary = import_into(&mut new_alice_store, &key, &exported).await?;
+        assert_eq!(
+            summary,
+            ProtocolStateImportSummary {
+                imported_sessions: 1,
+                imported_identities: 1,
+                imported_sender_keys: 1,
+                ..Default::default()
+            }
+        );
+
+        let alice_address = conversation.alice_address.clone();
+        let bob_address = conversation.bob_address.clone();
+
+        // The migrated store picks up the conversation where the old one left off.
+        let message = encrypt(&mut new_alice_store, &bob_address, "still here").await?;
+        assert_eq!(
+            decrypt(&mut conversation.bob_store, &alice_address, &message).await?,
+            b"still here"
+        );
+        let reply = encrypt(&mut conversation.bob_store, &alice_address, "welcome back").await?;
+        assert_eq!(
+            decrypt(&mut new_alice_store, &bob_address, &reply).await?,
+            b"welcome back"
+        );
+
+        let group_message = group_encrypt(
+            &mut conversation.bob_store,
+            &bob_address,
+            DISTRIBUTION_ID,
+            b"group news",
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(
+            group_decrypt(
+                group_message.serialized(),
+                &mut new_alice_store,
+                &bob_address
+            )
+            .await?,
+            b"group news"
+        );
+
+        assert_eq!(
+            new_alice_store.get_identity(&bob_address).await?,
+            Some(
+                *conversation
+                    .bob_store
+                    .get_identity_key_pair()
+                    .await?
+                    .identity_key()
+            )
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_import_rejects_wrong_key_and_tampering() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng;
+        let key: [u8; STATE_EXPORT_KEY_SIZE] = csprng.gen();
+
+        let mut conversation = establish_conversation().await?;
+        let exported = export_alice(&mut conversation, &key).await?;
+        let mut new_alice_store = fresh_store_for(&conversation.alice_store).await?;
+
+        let wrong_key: [u8; STATE_EXPORT_KEY_SIZE] = csprng.gen();
+        assert!(matches!(
+            import_into(&mut new_alice_store, &wrong_key, &exported).await,
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+
+        for i in [0, 1, exported.len() / 2, exported.len() - 1] {
+            let mut tampered = exported.clone();
+            tampered[i] ^= 0x01;
+            assert!(matches!(
+                import_into(&mut new_alice_store, &key, &tampered).await,
+                Err(SignalProtocolError::InvalidArgument(_))
+            ));
+        }
+
+        assert!(matches!(
+            import_into(&mut new_alice_store, &key, &exported[..20]).await,
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+
+        // Nothing was written by the failed attempts.
+        assert!(new_alice_store
+            .load_session(&conversation.bob_address)
+            .await?
+            .is_none());
+        assert!(new_alice_store
+            .get_identity(&conversation.bob_address)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_import_rejects_different_local_identity() -> Result<(), SignalProtocolError> {
+    async {
+        let key: [u8; STATE_EXPORT_KEY_SIZE] = OsRng.gen();
+
+        let mut conversation = establish_conversation().await?;
+        let exported = export_alice(&mut conversation, &key).await?;
+
+        let mut other_store = test_in_memory_protocol_store()?;
+        assert!(matches!(
+            import_into(&mut other_store, &key, &exported).await,
+            Err(SignalProtocolError::InvalidState(
+                "import_protocol_state",
+                _
+            ))
+        ));
+        assert!(other_store
+            .load_session(&conversation.bob_address)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_import_keeps_existing_records() -> Result<(), SignalProtocolError> {
+    async {
+        let key: [u8; STATE_EXPORT_KEY_SIZE] = OsRng.gen();
+
+        let mut conversation = establish_conversation().await?;
+        let exported = export_alice(&mut conversation, &key).await?;
+        let bob_address = conversation.bob_address.clone();
+
+        let mut new_alice_store = fresh_store_for(&conversation.alice_store).await?;
+        import_into(&mut new_alice_store, &key, &exported).await?;
+
+        // Importing the same export again changes nothing.
+        let session_before = new_alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("imported")
+            .serialize()?;
+        let summary = import_into(&mut new_alice_store, &key, &exported).await?;
+        assert_eq!(
+            summary,
+            ProtocolStateImportSummary {
+                existing_sessions: vec![bob_address.clone()],
+                existing_sender_keys: vec![(bob_address.clone(), DISTRIBUTION_ID)],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            new_alice_store
+                .load_session(&bob_address)
+                .await?
+                .expect("present")
+                .serialize()?,
+            session_before
+        );
+
+        // A destination that already trusts a different identity for Bob keeps it, and doesn't
+        // take Bob's session either.
+        let mut conflicting_store = fresh_store_for(&conversation.alice_store).await?;
+        let other_identity = *IdentityKeyPair::generate(&mut OsRng).identity_key();
+        conflicting_store
+            .save_identity(&bob_address, &other_identity)
+            .await?;
+        let summary = import_into(&mut conflicting_store, &key, &exported).await?;
+        assert_eq!(
+            summary,
+            ProtocolStateImportSummary {
+                imported_sender_keys: 1,
+                conflicting_identities: vec![bob_address.clone()],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            conflicting_store.get_identity(&bob_address).await?,
+            Some(other_identity)
+        );
+        assert!(conflicting_store
+            .load_session(&bob_address)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -19,44 +19,191 @@ use std::time::SystemTime;
 pub(crate) const PRE_KYBER_MESSAGE_VERSION: u32 = 3;
 pub(crate) const KYBER_AWARE_MESSAGE_VERSION: u32 = 4;
 
-pub fn test_in_memory_protocol_store() -> Result<InMemSignalProtocolStore, SignalProtocolError> {
+/// A [ProtocolStore] that the shared session scenarios can be run against.
+pub trait TestStore: ProtocolStore + Sized {
+    /// Creates an empty store for the given identity.
+    fn create(identity_key: IdentityKeyPair, registration_id: u32) -> Self;
+
+    /// Creates an independent copy of the store's current contents.
+    fn duplicate(&self) -> Self;
+
+    fn pre_key_ids(&self) -> Vec<PreKeyId>;
+    fn signed_pre_key_ids(&self) -> Vec<SignedPreKeyId>;
+    fn kyber_pre_key_ids(&self) -> Vec<KyberPreKeyId>;
+    fn kyber_pre_key_used(&self, id: KyberPreKeyId) -> bool;
+
+    /// Borrows the individual stores, for functions that take them separately.
+    fn parts(&mut self) -> StoreParts<'_>;
+}
+
+pub struct StoreParts<'a> {
+    pub session_store: &'a mut dyn SessionStore,
+    pub pre_key_store: &'a mut dyn PreKeyStore,
+    pub signed_pre_key_store: &'a mut dyn SignedPreKeyStore,
+    pub kyber_pre_key_store: &'a mut dyn KyberPreKeyStore,
+    pub identity_store: &'a mut dyn IdentityKeyStore,
+}
+
+impl TestStore for InMemSignalProtocolStore {
+    fn create(identity_key: IdentityKeyPair, registration_id: u32) -> Self {
+        Self::new(identity_key, registration_id).expect("can create store")
+    }
+
+    fn duplicate(&self) -> Self {
+        self.clone()
+    }
+
+    fn pre_key_ids(&self) -> Vec<PreKeyId> {
+        let mut ids = self.all_pre_key_ids().copied().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    fn signed_pre_key_ids(&self) -> Vec<SignedPreKeyId> {
+        let mut ids = self.all_signed_pre_key_ids().copied().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    fn kyber_pre_key_ids(&self) -> Vec<KyberPreKeyId> {
+        let mut ids = self.all_kyber_pre_key_ids().copied().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    fn kyber_pre_key_used(&self, id: KyberPreKeyId) -> bool {
+        self.is_kyber_pre_key_used(id)
+    }
+
+    fn parts(&mut self) -> StoreParts<'_> {
+        StoreParts {
+            session_store: &mut self.session_store,
+            pre_key_store: &mut self.pre_key_store,
+            signed_pre_key_store: &mut self.signed_pre_key_store,
+            kyber_pre_key_store: &mut self.kyber_pre_key_store,
+            identity_store: &mut self.identity_store,
+        }
+    }
+}
+
+#[cfg(feature = "fs-store")]
+thread_local! {
+    // Cleaned up when the test's thread exits.
+    static FS_STORE_ROOT: tempfile::TempDir =
+        tempfile::TempDir::new().expect("can create temporary directory");
+}
+
+/// Picks a fresh directory for a [FsSignalProtocolStore].
+#[cfg(feature = "fs-store")]
+pub fn fs_store_path() -> std::path::PathBuf {
+    FS_STORE_ROOT.with(|root| {
+        tempfile::Builder::new()
+            .prefix("store")
+            .tempdir_in(root.path())
+            .expect("can create temporary directory")
+            .into_path()
+    })
+}
+
+#[cfg(feature = "fs-store")]
+impl TestStore for FsSignalProtocolStore {
+    fn create(identity_key: IdentityKeyPair, registration_id: u32) -> Self {
+        Self::create(fs_store_path(), identity_key, registration_id).expect("can create store")
+    }
+
+    fn duplicate(&self) -> Self {
+        fn copy_dir(from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+            std::fs::create_dir_all(to)?;
+            for entry in std::fs::read_dir(from)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+                } else {
+                    std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+                }
+            }
+            Ok(())
+        }
+
+        let path = fs_store_path();
+        copy_dir(self.path(), &path).expect("can copy store");
+        Self::open(path).expect("can open copied store")
+    }
+
+    fn pre_key_ids(&self) -> Vec<PreKeyId> {
+        self.all_pre_key_ids().expect("can list pre keys")
+    }
+
+    fn signed_pre_key_ids(&self) -> Vec<SignedPreKeyId> {
+        self.all_signed_pre_key_ids()
+            .expect("can list signed pre keys")
+    }
+
+    fn kyber_pre_key_ids(&self) -> Vec<KyberPreKeyId> {
+        self.all_kyber_pre_key_ids()
+            .expect("can list kyber pre keys")
+    }
+
+    fn kyber_pre_key_used(&self, id: KyberPreKeyId) -> bool {
+        self.is_kyber_pre_key_used(id)
+            .expect("can read kyber pre key state")
+    }
+
+    fn parts(&mut self) -> StoreParts<'_> {
+        StoreParts {
+            session_store: &mut self.session_store,
+            pre_key_store: &mut self.pre_key_store,
+            signed_pre_key_store: &mut self.signed_pre_key_store,
+            kyber_pre_key_store: &mut self.kyber_pre_key_store,
+            identity_store: &mut self.identity_store,
+        }
+    }
+}
+
+pub fn test_protocol_store<S: TestStore>() -> S {
     let mut csprng = OsRng;
     let identity_key = IdentityKeyPair::generate(&mut csprng);
     // Valid registration IDs fit in 14 bits.
     let registration_id: u8 = csprng.gen();
 
-    InMemSignalProtocolStore::new(identity_key, registration_id as u32)
+    S::create(identity_key, registration_id as u32)
+}
+
+pub fn test_in_memory_protocol_store() -> Result<InMemSignalProtocolStore, SignalProtocolError> {
+    Ok(test_protocol_store())
 }
 
 pub async fn encrypt(
-    store: &mut InMemSignalProtocolStore,
+    store: &mut impl TestStore,
     remote_address: &ProtocolAddress,
     msg: &str,
 ) -> Result<CiphertextMessage, SignalProtocolError> {
+    let store = store.parts();
     message_encrypt(
         msg.as_bytes(),
         remote_address,
-        &mut store.session_store,
-        &mut store.identity_store,
+        store.session_store,
+        store.identity_store,
         SystemTime::now(),
     )
     .await
 }
 
 pub async fn decrypt(
-    store: &mut InMemSignalProtocolStore,
+    store: &mut impl TestStore,
     remote_address: &ProtocolAddress,
     msg: &CiphertextMessage,
 ) -> Result<Vec<u8>, SignalProtocolError> {
     let mut csprng = OsRng;
+    let store = store.parts();
     message_decrypt(
         msg,
         remote_address,
-        &mut store.session_store,
-        &mut store.identity_store,
-        &mut store.pre_key_store,
-        &store.signed_pre_key_store,
-        &mut store.kyber_pre_key_store,
+        store.session_store,
+        store.identity_store,
+        store.pre_key_store,
+        store.signed_pre_key_store,
+        store.kyber_pre_key_store,
         &mut csprng,
     )
     .await
@@ -244,32 +391,25 @@ impl From<u32> for IdChoice {
     }
 }
 
-pub struct TestStoreBuilder {
+pub struct TestStoreBuilder<S> {
     rng: OsRng,
-    pub(crate) store: InMemSignalProtocolStore,
+    pub(crate) store: S,
     id_range: RangeFrom<u32>,
 }
 
-impl TestStoreBuilder {
+impl<S: TestStore> TestStoreBuilder<S> {
     pub fn new() -> Self {
-        let mut rng = OsRng;
-        let identity_key = IdentityKeyPair::generate(&mut rng);
-        // Valid registration IDs fit in 14 bits.
-        let registration_id: u8 = rng.gen();
-
-        let store = InMemSignalProtocolStore::new(identity_key, registration_id as u32)
-            .expect("can create store");
         Self {
-            rng,
-            store,
+            rng: OsRng,
+            store: test_protocol_store(),
             id_range: 0..,
         }
     }
 
-    pub fn from_store(store: &InMemSignalProtocolStore) -> Self {
+    pub fn from_store(store: &S) -> Self {
         Self {
             rng: OsRng,
-            store: store.clone(),
+            store: store.duplicate(),
             id_range: 0..,
         }
     }
@@ -282,7 +422,7 @@ impl TestStoreBuilder {
     pub fn add_pre_key(&mut self, id_choice: IdChoice) {
         let id = self.gen_id(id_choice);
         // TODO: this requirement can be removed if store returns ids in the insertion order
-        if let Some(latest_id) = self.store.all_pre_key_ids().last() {
+        if let Some(latest_id) = self.store.pre_key_ids().last() {
             assert!(id > (*latest_id).into(), "Pre key ids should be increasing");
         }
         let pair = KeyPair::generate(&mut self.rng);
@@ -300,7 +440,7 @@ impl TestStoreBuilder {
 
     pub fn add_signed_pre_key(&mut self, id_choice: IdChoice) {
         let id = self.gen_id(id_choice);
-        if let Some(latest_id) = self.store.all_signed_pre_key_ids().last() {
+        if let Some(latest_id) = self.store.signed_pre_key_ids().last() {
             assert!(
                 id > (*latest_id).into(),
                 "Signed pre key ids should be increasing"
@@ -328,14 +468,18 @@ impl TestStoreBuilder {
     }
 
     pub fn add_kyber_pre_key(&mut self, id_choice: IdChoice) {
+        self.add_kyber_pre_key_of_type(id_choice, kem::KeyType::Kyber1024)
+    }
+
+    pub fn add_kyber_pre_key_of_type(&mut self, id_choice: IdChoice, key_type: kem::KeyType) {
         let id = self.gen_id(id_choice);
-        if let Some(latest_id) = self.store.all_kyber_pre_key_ids().last() {
+        if let Some(latest_id) = self.store.kyber_pre_key_ids().last() {
             assert!(
                 id > (*latest_id).into(),
                 "Signed pre key ids should be increasing"
             );
         }
-        let pair = kem::KeyPair::generate(kem::KeyType::Kyber1024);
+        let pair = kem::KeyPair::generate(key_type);
         let public = pair.public_key.serialize();
         let signature = self.sign(&public);
         let record = KyberPreKeyRecord::new(
@@ -358,9 +502,8 @@ impl TestStoreBuilder {
             .now_or_never()
             .expect("sync")
             .expect("contains local registration id");
-        let maybe_pre_key_record = self.store.all_pre_key_ids().max().map(|id| {
+        let maybe_pre_key_record = self.store.pre_key_ids().last().map(|id| {
             self.store
-                .pre_key_store
                 .get_pre_key(*id)
                 .now_or_never()
                 .expect("syng")
@@ -375,8 +518,8 @@ impl TestStoreBuilder {
         let identity_key = identity_key_pair.identity_key();
         let signed_pre_key_record = self
             .store
-            .all_signed_pre_key_ids()
-            .max()
+            .signed_pre_key_ids()
+            .last()
             .map(|id| {
                 self.store
                     .get_signed_pre_key(*id)
@@ -385,7 +528,7 @@ impl TestStoreBuilder {
                     .expect("has signed pre key")
             })
             .expect("contains at least one signed pre key");
-        let maybe_kyber_pre_key_record = self.store.all_kyber_pre_key_ids().max().map(|id| {
+        let maybe_kyber_pre_key_record = self.store.kyber_pre_key_ids().last().map(|id| {
             self.store
                 .get_kyber_pre_key(*id)
                 .now_or_never()
@@ -448,13 +591,13 @@ pub trait HasSessionVersion {
     fn session_version(&self, address: &ProtocolAddress) -> Result<u32, SignalProtocolError>;
 }
 
-impl HasSessionVersion for TestStoreBuilder {
+impl<S: TestStore> HasSessionVersion for TestStoreBuilder<S> {
     fn session_version(&self, address: &ProtocolAddress) -> Result<u32, SignalProtocolError> {
         self.store.session_version(address)
     }
 }
 
-impl HasSessionVersion for InMemSignalProtocolStore {
+impl<S: TestStore> HasSessionVersion for S {
     fn session_version(&self, address: &ProtocolAddress) -> Result<u32, SignalProtocolError> {
         self.load_session(address)
             .now_or_never()
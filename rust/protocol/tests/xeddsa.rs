@@ -0,0 +1,184 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use libsignal_protocol::*;
+use rand::{CryptoRng, RngCore};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SignatureTest {
+    tc_id: usize,
+    private_key: String,
+    public_key: String,
+    msg: String,
+    random: String,
+    sig: String,
+    #[serde(default)]
+    output: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug)]
+struct SignatureTestSet {
+    header: Vec<String>,
+    xeddsa: Vec<SignatureTest>,
+    vxeddsa: Vec<SignatureTest>,
+}
+
+fn test_set() -> SignatureTestSet {
+    let data = include_bytes!("data/xeddsa_test.json");
+    serde_json::from_slice(data).expect("valid JSON")
+}
+
+/// Supplies the fixed "random" bytes from a vector.
+struct FixedRng(Vec<u8>);
+
+impl RngCore for FixedRng {
+    fn next_u32(&mut self) -> u32 {
+        unreachable!("only fill_bytes is used for signing")
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        unreachable!("only fill_bytes is used for signing")
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let rest = self.0.split_off(dest.len());
+        dest.copy_from_slice(&self.0);
+        self.0 = rest;
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for FixedRng {}
+
+struct DecodedTest {
+    tc_id: usize,
+    private_key: PrivateKey,
+    public_key: PublicKey,
+    msg: Vec<u8>,
+    rng: FixedRng,
+    sig: Vec<u8>,
+}
+
+fn decode(test: &SignatureTest) -> Result<DecodedTest, SignalProtocolError> {
+    let private_key = PrivateKey::deserialize(&hex::decode(&test.private_key).expect("valid hex"))?;
+    let public_key =
+        PublicKey::from_djb_public_key_bytes(&hex::decode(&test.public_key).expect("valid hex"))?;
+    assert_eq!(private_key.public_key()?, public_key, "tcId {}", test.tc_id);
+    Ok(DecodedTest {
+        tc_id: test.tc_id,
+        private_key,
+        public_key,
+        msg: hex::decode(&test.msg).expect("valid hex"),
+        rng: FixedRng(hex::decode(&test.random).expect("valid hex")),
+        sig: hex::decode(&test.sig).expect("valid hex"),
+    })
+}
+
+#[test]
+fn test_xeddsa_vectors() -> Result<(), SignalProtocolError> {
+    for test in test_set().xeddsa {
+        let DecodedTest {
+            tc_id,
+            private_key,
+            public_key,
+            msg,
+            mut rng,
+            sig,
+        } = decode(&test)?;
+
+        let signature = private_key.calculate_signature(&msg, &mut rng)?;
+        assert_eq!(hex::encode(&signature), test.sig, "tcId {tc_id}");
+        assert!(public_key.verify_signature(&msg, &sig)?);
+
+        for byte in 0..sig.len() {
+            let mut tampered = sig.clone();
+            tampered[byte] ^= 0x01;
+            assert!(
+                !public_key.verify_signature(&msg, &tampered)?,
+                "tcId {tc_id}, byte {byte}"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_vxeddsa_vectors() -> Result<(), SignalProtocolError> {
+    for test in test_set().vxeddsa {
+        let DecodedTest {
+            tc_id,
+            private_key,
+            public_key,
+            msg,
+            mut rng,
+            sig,
+        } = decode(&test)?;
+
+        let (signature, output) = private_key.calculate_vrf_signature(&msg, &mut rng)?;
+        assert_eq!(hex::encode(&signature), test.sig, "tcId {tc_id}");
+        assert_eq!(Some(hex::encode(output)), test.output, "tcId {tc_id}");
+        assert_eq!(public_key.verify_vrf_signature(&msg, &sig)?, Some(output));
+
+        // The first and last bytes of each of V, h, and s.
+        for byte in [0, 31, 32, 63, 64, 95] {
+            let mut tampered = sig.clone();
+            tampered[byte] ^= 0x01;
+            assert_eq!(
+                public_key.verify_vrf_signature(&msg, &tampered)?,
+                None,
+                "tcId {tc_id}, byte {byte}"
+            );
+        }
+        assert_eq!(
+            public_key.verify_vrf_signature(b"another message", &sig)?,
+            None
+        );
+        assert_eq!(public_key.verify_vrf_signature(&msg, &sig[1..])?, None);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_vxeddsa_output_is_deterministic() -> Result<(), SignalProtocolError> {
+    let mut csprng = rand::rngs::OsRng;
+    let key_pair = KeyPair::generate(&mut csprng);
+
+    let (signature, output) = key_pair
+        .private_key
+        .calculate_vrf_signature(b"lottery", &mut csprng)?;
+    let (another_signature, another_output) = key_pair
+        .private_key
+        .calculate_vrf_signature(b"lottery", &mut csprng)?;
+    assert_ne!(signature, another_signature);
+    assert_eq!(output, another_output);
+    assert_eq!(
+        key_pair
+            .public_key
+            .verify_vrf_signature(b"lottery", &another_signature)?,
+        Some(output)
+    );
+
+    let (_, other_output) = key_pair
+        .private_key
+        .calculate_vrf_signature(b"other lottery", &mut csprng)?;
+    assert_ne!(output, other_output);
+
+    let other_key_pair = KeyPair::generate(&mut csprng);
+    assert_eq!(
+        other_key_pair
+            .public_key
+            .verify_vrf_signature(b"lottery", &signature)?,
+        None
+    );
+
+    Ok(())
+}
@@ -68,3 +68,115 @@ public class Aes256GcmSiv: NativeHandleOwner {
         }
     }
 }
+
+/// Accepts the plaintext in pieces. Use Aes256GcmSiv if you already have the whole message.
+///
+/// GCM-SIV can't produce any ciphertext until it has seen the whole message, so the entire
+/// plaintext is kept in memory until ``finish()``. Use Aes256GcmEncryption to encrypt messages too
+/// large to hold in memory.
+public class Aes256GcmSivEncryption: NativeHandleOwner {
+    public convenience init(
+        key: some ContiguousBytes,
+        nonce: some ContiguousBytes,
+        associatedData: some ContiguousBytes
+    ) throws {
+        let handle: OpaquePointer? = try key.withUnsafeBorrowedBuffer { keyBuffer in
+            try nonce.withUnsafeBorrowedBuffer { nonceBuffer in
+                try associatedData.withUnsafeBorrowedBuffer { adBuffer in
+                    var result: OpaquePointer?
+                    try checkError(signal_aes256_gcm_siv_encryption_new(
+                        &result,
+                        keyBuffer,
+                        nonceBuffer,
+                        adBuffer
+                    ))
+                    return result
+                }
+            }
+        }
+        self.init(owned: handle!)
+    }
+
+    override internal class func destroyNativeHandle(_ handle: OpaquePointer) -> SignalFfiErrorRef? {
+        return signal_aes256_gcm_siv_encryption_destroy(handle)
+    }
+
+    public func update(_ message: some ContiguousBytes) throws {
+        try withNativeHandle { nativeHandle in
+            try message.withUnsafeBorrowedBuffer { messageBuffer in
+                try checkError(signal_aes256_gcm_siv_encryption_update(
+                    nativeHandle,
+                    messageBuffer,
+                    0,
+                    UInt32(messageBuffer.length)
+                ))
+            }
+        }
+    }
+
+    /// Returns the ciphertext followed by the tag.
+    public func finish() throws -> Data {
+        return try withNativeHandle { nativeHandle in
+            try invokeFnReturningData {
+                signal_aes256_gcm_siv_encryption_finalize($0, nativeHandle)
+            }
+        }
+    }
+}
+
+/// Accepts the ciphertext in pieces. Use Aes256GcmSiv if you already have the whole message.
+///
+/// The entire ciphertext is kept in memory, and nothing is decrypted until the tag has been
+/// checked by ``finish(tag:)``.
+public class Aes256GcmSivDecryption: NativeHandleOwner {
+    public convenience init(
+        key: some ContiguousBytes,
+        nonce: some ContiguousBytes,
+        associatedData: some ContiguousBytes
+    ) throws {
+        let handle: OpaquePointer? = try key.withUnsafeBorrowedBuffer { keyBuffer in
+            try nonce.withUnsafeBorrowedBuffer { nonceBuffer in
+                try associatedData.withUnsafeBorrowedBuffer { adBuffer in
+                    var result: OpaquePointer?
+                    try checkError(signal_aes256_gcm_siv_decryption_new(
+                        &result,
+                        keyBuffer,
+                        nonceBuffer,
+                        adBuffer
+                    ))
+                    return result
+                }
+            }
+        }
+        self.init(owned: handle!)
+    }
+
+    override internal class func destroyNativeHandle(_ handle: OpaquePointer) -> SignalFfiErrorRef? {
+        return signal_aes256_gcm_siv_decryption_destroy(handle)
+    }
+
+    public func update(_ message: some ContiguousBytes) throws {
+        try withNativeHandle { nativeHandle in
+            try message.withUnsafeBorrowedBuffer { messageBuffer in
+                try checkError(signal_aes256_gcm_siv_decryption_update(
+                    nativeHandle,
+                    messageBuffer,
+                    0,
+                    UInt32(messageBuffer.length)
+                ))
+            }
+        }
+    }
+
+    /// Checks `tag` and returns the plaintext, throwing ``SignalError/invalidMessage(_:)`` if it
+    /// doesn't match.
+    public func finish(tag: some ContiguousBytes) throws -> Data {
+        return try withNativeHandle { nativeHandle in
+            try tag.withUnsafeBorrowedBuffer { tagBuffer in
+                try invokeFnReturningData {
+                    signal_aes256_gcm_siv_decryption_finalize($0, nativeHandle, tagBuffer)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import Foundation
+import SignalFfi
+
+/// Streamed ChaCha20-Poly1305 encryption, as specified in RFC 8439.
+public class ChaCha20Poly1305Encryption: NativeHandleOwner {
+    public static let keyLength: Int = 32
+    public static let nonceLength: Int = 12
+    public static let authenticationTagLength: Int = 16
+
+    public convenience init(
+        key: some ContiguousBytes,
+        nonce: some ContiguousBytes,
+        associatedData: some ContiguousBytes
+    ) throws {
+        let handle: OpaquePointer? = try key.withUnsafeBorrowedBuffer { keyBuffer in
+            try nonce.withUnsafeBorrowedBuffer { nonceBuffer in
+                try associatedData.withUnsafeBorrowedBuffer { adBuffer in
+                    var result: OpaquePointer?
+                    try checkError(signal_cha_cha20_poly1305_encryption_new(
+                        &result,
+                        keyBuffer,
+                        nonceBuffer,
+                        adBuffer
+                    ))
+                    return result
+                }
+            }
+        }
+        self.init(owned: handle!)
+    }
+
+    override internal class func destroyNativeHandle(_ handle: OpaquePointer) -> SignalFfiErrorRef? {
+        return signal_cha_cha20_poly1305_encryption_destroy(handle)
+    }
+
+    public func encrypt(_ message: inout Data) throws {
+        try withNativeHandle { nativeHandle in
+            try message.withUnsafeMutableBytes { messageBytes in
+                try checkError(signal_cha_cha20_poly1305_encryption_update(
+                    nativeHandle,
+                    SignalBorrowedMutableBuffer(messageBytes),
+                    0,
+                    UInt32(messageBytes.count)
+                ))
+            }
+        }
+    }
+
+    public func computeTag() throws -> Data {
+        return try withNativeHandle { nativeHandle in
+            try invokeFnReturningData {
+                signal_cha_cha20_poly1305_encryption_compute_tag($0, nativeHandle)
+            }
+        }
+    }
+}
+
+/// Streamed ChaCha20-Poly1305 decryption, as specified in RFC 8439.
+///
+/// The decrypted data must not be used until ``verifyTag(_:)`` has returned `true`.
+public class ChaCha20Poly1305Decryption: NativeHandleOwner {
+    public convenience init(
+        key: some ContiguousBytes,
+        nonce: some ContiguousBytes,
+        associatedData: some ContiguousBytes
+    ) throws {
+        let handle: OpaquePointer? = try key.withUnsafeBorrowedBuffer { keyBuffer in
+            try nonce.withUnsafeBorrowedBuffer { nonceBuffer in
+                try associatedData.withUnsafeBorrowedBuffer { adBuffer in
+                    var result: OpaquePointer?
+                    try checkError(signal_cha_cha20_poly1305_decryption_new(
+                        &result,
+                        keyBuffer,
+                        nonceBuffer,
+                        adBuffer
+                    ))
+                    return result
+                }
+            }
+        }
+        self.init(owned: handle!)
+    }
+
+    override internal class func destroyNativeHandle(_ handle: OpaquePointer) -> SignalFfiErrorRef? {
+        return signal_cha_cha20_poly1305_decryption_destroy(handle)
+    }
+
+    public func decrypt(_ message: inout Data) throws {
+        try withNativeHandle { nativeHandle in
+            try message.withUnsafeMutableBytes { messageBytes in
+                try checkError(signal_cha_cha20_poly1305_decryption_update(
+                    nativeHandle,
+                    SignalBorrowedMutableBuffer(messageBytes),
+                    0,
+                    UInt32(messageBytes.count)
+                ))
+            }
+        }
+    }
+
+    public func verifyTag(_ tag: some ContiguousBytes) throws -> Bool {
+        return try withNativeHandle { nativeHandle in
+            try tag.withUnsafeBorrowedBuffer { tagBuffer in
+                var result = false
+                try checkError(signal_cha_cha20_poly1305_decryption_verify_tag(
+                    &result,
+                    nativeHandle,
+                    tagBuffer
+                ))
+                return result
+            }
+        }
+    }
+}
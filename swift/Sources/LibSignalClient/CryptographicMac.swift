@@ -0,0 +1,55 @@
+//
+// Copyright 2024 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import Foundation
+import SignalFfi
+
+public class CryptographicMac: NativeHandleOwner {
+    public enum Algorithm: String {
+        case hmacSha1 = "HmacSha1"
+        case hmacSha256 = "HmacSha256"
+        case hmacSha512 = "HmacSha512"
+    }
+
+    public convenience init(algorithm: Algorithm, key: some ContiguousBytes) throws {
+        let handle: OpaquePointer? = try key.withUnsafeBorrowedBuffer { keyBuffer in
+            var result: OpaquePointer?
+            try checkError(signal_cryptographic_mac_new(&result, algorithm.rawValue, keyBuffer))
+            return result
+        }
+        self.init(owned: handle!)
+    }
+
+    override internal class func destroyNativeHandle(_ handle: OpaquePointer) -> SignalFfiErrorRef? {
+        return signal_cryptographic_mac_destroy(handle)
+    }
+
+    public func update(_ input: some ContiguousBytes) throws {
+        try withNativeHandle { nativeHandle in
+            try input.withUnsafeBorrowedBuffer { inputBuffer in
+                try checkError(signal_cryptographic_mac_update(nativeHandle, inputBuffer))
+            }
+        }
+    }
+
+    public func finish() throws -> [UInt8] {
+        return try withNativeHandle { nativeHandle in
+            try invokeFnReturningArray {
+                signal_cryptographic_mac_finalize($0, nativeHandle)
+            }
+        }
+    }
+
+    /// Finishes the MAC and checks, in constant time, whether it matches `expected`.
+    public func verify(_ expected: some ContiguousBytes) throws -> Bool {
+        return try withNativeHandle { nativeHandle in
+            try expected.withUnsafeBorrowedBuffer { expectedBuffer in
+                var result = false
+                try checkError(signal_cryptographic_mac_verify(&result, nativeHandle, expectedBuffer))
+                return result
+            }
+        }
+    }
+}
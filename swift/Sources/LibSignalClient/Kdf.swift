@@ -48,3 +48,44 @@ public func hkdf(
         info: info
     )
 }
+
+public enum HkdfAlgorithm: String {
+    case sha256 = "HkdfSha256"
+    case sha512 = "HkdfSha512"
+}
+
+/// Runs the HKDF-Extract step of RFC 5869, returning the pseudorandom key.
+///
+/// An empty `salt` is the same as not providing one.
+public func hkdfExtract(
+    algorithm: HkdfAlgorithm,
+    salt: some ContiguousBytes,
+    inputKeyMaterial: some ContiguousBytes
+) throws -> [UInt8] {
+    return try salt.withUnsafeBorrowedBuffer { saltBuffer in
+        try inputKeyMaterial.withUnsafeBorrowedBuffer { inputBuffer in
+            try invokeFnReturningArray {
+                signal_hkdf_extract($0, algorithm.rawValue, saltBuffer, inputBuffer)
+            }
+        }
+    }
+}
+
+/// Runs the HKDF-Expand step of RFC 5869.
+///
+/// Throws if `prk` is shorter than the hash output, or `outputLength` is more than 255 times the
+/// hash output.
+public func hkdfExpand(
+    algorithm: HkdfAlgorithm,
+    prk: some ContiguousBytes,
+    info: some ContiguousBytes,
+    outputLength: Int
+) throws -> [UInt8] {
+    return try prk.withUnsafeBorrowedBuffer { prkBuffer in
+        try info.withUnsafeBorrowedBuffer { infoBuffer in
+            try invokeFnReturningArray {
+                signal_hkdf_expand($0, algorithm.rawValue, prkBuffer, infoBuffer, UInt32(outputLength))
+            }
+        }
+    }
+}
@@ -210,14 +210,24 @@ typedef struct SignalAes256GcmEncryption SignalAes256GcmEncryption;
 
 typedef struct SignalAes256GcmSiv SignalAes256GcmSiv;
 
+typedef struct SignalAes256GcmSivDecryption SignalAes256GcmSivDecryption;
+
+typedef struct SignalAes256GcmSivEncryption SignalAes256GcmSivEncryption;
+
 typedef struct SignalCdsiLookup SignalCdsiLookup;
 
+typedef struct SignalChaCha20Poly1305Decryption SignalChaCha20Poly1305Decryption;
+
+typedef struct SignalChaCha20Poly1305Encryption SignalChaCha20Poly1305Encryption;
+
 typedef struct SignalChat SignalChat;
 
 typedef struct SignalCiphertextMessage SignalCiphertextMessage;
 
 typedef struct SignalConnectionManager SignalConnectionManager;
 
+typedef struct SignalCryptographicMac SignalCryptographicMac;
+
 typedef struct SignalDecryptionErrorMessage SignalDecryptionErrorMessage;
 
 typedef struct SignalFingerprint SignalFingerprint;
@@ -770,6 +780,8 @@ SignalFfiError *signal_sealed_session_cipher_decrypt(SignalOwnedBuffer *out, con
 
 bool signal_init_logger(SignalLogLevel max_level, SignalFfiLogger logger);
 
+SignalFfiError *signal_cryptographic_mac_destroy(SignalCryptographicMac *p);
+
 SignalFfiError *signal_aes256_gcm_siv_destroy(SignalAes256GcmSiv *p);
 
 SignalFfiError *signal_aes256_ctr32_destroy(SignalAes256Ctr32 *p);
@@ -778,6 +790,14 @@ SignalFfiError *signal_aes256_gcm_encryption_destroy(SignalAes256GcmEncryption *
 
 SignalFfiError *signal_aes256_gcm_decryption_destroy(SignalAes256GcmDecryption *p);
 
+SignalFfiError *signal_cha_cha20_poly1305_encryption_destroy(SignalChaCha20Poly1305Encryption *p);
+
+SignalFfiError *signal_cha_cha20_poly1305_decryption_destroy(SignalChaCha20Poly1305Decryption *p);
+
+SignalFfiError *signal_aes256_gcm_siv_encryption_destroy(SignalAes256GcmSivEncryption *p);
+
+SignalFfiError *signal_aes256_gcm_siv_decryption_destroy(SignalAes256GcmSivDecryption *p);
+
 SignalFfiError *signal_aes256_ctr32_new(SignalAes256Ctr32 **out, SignalBorrowedBuffer key, SignalBorrowedBuffer nonce, uint32_t initial_ctr);
 
 SignalFfiError *signal_aes256_ctr32_process(SignalAes256Ctr32 *ctr, SignalBorrowedMutableBuffer data, uint32_t offset, uint32_t length);
@@ -794,12 +814,50 @@ SignalFfiError *signal_aes256_gcm_decryption_update(SignalAes256GcmDecryption *g
 
 SignalFfiError *signal_aes256_gcm_decryption_verify_tag(bool *out, SignalAes256GcmDecryption *gcm, SignalBorrowedBuffer tag);
 
+SignalFfiError *signal_cha_cha20_poly1305_encryption_new(SignalChaCha20Poly1305Encryption **out, SignalBorrowedBuffer key, SignalBorrowedBuffer nonce, SignalBorrowedBuffer associated_data);
+
+SignalFfiError *signal_cha_cha20_poly1305_encryption_update(SignalChaCha20Poly1305Encryption *chacha, SignalBorrowedMutableBuffer data, uint32_t offset, uint32_t length);
+
+SignalFfiError *signal_cha_cha20_poly1305_encryption_compute_tag(SignalOwnedBuffer *out, SignalChaCha20Poly1305Encryption *chacha);
+
+SignalFfiError *signal_cha_cha20_poly1305_decryption_new(SignalChaCha20Poly1305Decryption **out, SignalBorrowedBuffer key, SignalBorrowedBuffer nonce, SignalBorrowedBuffer associated_data);
+
+SignalFfiError *signal_cha_cha20_poly1305_decryption_update(SignalChaCha20Poly1305Decryption *chacha, SignalBorrowedMutableBuffer data, uint32_t offset, uint32_t length);
+
+SignalFfiError *signal_cha_cha20_poly1305_decryption_verify_tag(bool *out, SignalChaCha20Poly1305Decryption *chacha, SignalBorrowedBuffer tag);
+
 SignalFfiError *signal_aes256_gcm_siv_new(SignalAes256GcmSiv **out, SignalBorrowedBuffer key);
 
 SignalFfiError *signal_aes256_gcm_siv_encrypt(SignalOwnedBuffer *out, const SignalAes256GcmSiv *aes_gcm_siv_obj, SignalBorrowedBuffer ptext, SignalBorrowedBuffer nonce, SignalBorrowedBuffer associated_data);
 
 SignalFfiError *signal_aes256_gcm_siv_decrypt(SignalOwnedBuffer *out, const SignalAes256GcmSiv *aes_gcm_siv, SignalBorrowedBuffer ctext, SignalBorrowedBuffer nonce, SignalBorrowedBuffer associated_data);
 
+SignalFfiError *signal_aes256_gcm_siv_encryption_new(SignalAes256GcmSivEncryption **out, SignalBorrowedBuffer key, SignalBorrowedBuffer nonce, SignalBorrowedBuffer associated_data);
+
+SignalFfiError *signal_aes256_gcm_siv_encryption_update(SignalAes256GcmSivEncryption *gcm_siv, SignalBorrowedBuffer data, uint32_t offset, uint32_t length);
+
+SignalFfiError *signal_aes256_gcm_siv_encryption_finalize(SignalOwnedBuffer *out, SignalAes256GcmSivEncryption *gcm_siv);
+
+SignalFfiError *signal_aes256_gcm_siv_decryption_new(SignalAes256GcmSivDecryption **out, SignalBorrowedBuffer key, SignalBorrowedBuffer nonce, SignalBorrowedBuffer associated_data);
+
+SignalFfiError *signal_aes256_gcm_siv_decryption_update(SignalAes256GcmSivDecryption *gcm_siv, SignalBorrowedBuffer data, uint32_t offset, uint32_t length);
+
+SignalFfiError *signal_aes256_gcm_siv_decryption_finalize(SignalOwnedBuffer *out, SignalAes256GcmSivDecryption *gcm_siv, SignalBorrowedBuffer tag);
+
+SignalFfiError *signal_cryptographic_mac_new(SignalCryptographicMac **out, const char *algo, SignalBorrowedBuffer key);
+
+SignalFfiError *signal_cryptographic_mac_update(SignalCryptographicMac *mac, SignalBorrowedBuffer input);
+
+SignalFfiError *signal_cryptographic_mac_update_with_offset(SignalCryptographicMac *mac, SignalBorrowedBuffer input, uint32_t offset, uint32_t len);
+
+SignalFfiError *signal_cryptographic_mac_finalize(SignalOwnedBuffer *out, SignalCryptographicMac *mac);
+
+SignalFfiError *signal_cryptographic_mac_verify(bool *out, SignalCryptographicMac *mac, SignalBorrowedBuffer expected);
+
+SignalFfiError *signal_hkdf_extract(SignalOwnedBuffer *out, const char *algo, SignalBorrowedBuffer salt, SignalBorrowedBuffer ikm);
+
+SignalFfiError *signal_hkdf_expand(SignalOwnedBuffer *out, const char *algo, SignalBorrowedBuffer prk, SignalBorrowedBuffer info, uint32_t output_length);
+
 SignalFfiError *signal_ciphertext_message_destroy(SignalCiphertextMessage *p);
 
 SignalFfiError *signal_decryption_error_message_destroy(SignalDecryptionErrorMessage *p);
@@ -67,6 +67,32 @@ class CryptoTests: TestCaseBase {
         XCTAssertThrowsError(try gcm_siv.decrypt(ctext, nonce: ad, associatedData: nonce))
     }
 
+    func testAesGcmSivIncremental() {
+        let ptext = [UInt8](fromHexString: "671fdd4fbdc66f146545fc880c94a95198")!
+        let expectedCtext = Data([UInt8](fromHexString: "9209cfae7372e0a3ec2e5d072d5e26b7b9f3acb73908e54cddf7be1864914e13cf")!)
+        let key = [UInt8](fromHexString: "bae8e37fc83441b16034566b7a806c46bb91c3c5aedb64a6c590bc84d1a5e269")!
+        let nonce = [UInt8](fromHexString: "e4b47801afc0577e34699b9e")!
+        let ad = [UInt8](fromHexString: "874296d5cc1fd16132")!
+
+        let gcmSivEnc = try! Aes256GcmSivEncryption(key: key, nonce: nonce, associatedData: ad)
+        try! gcmSivEnc.update(ptext[..<1])
+        try! gcmSivEnc.update(ptext[1...])
+        let ctext = try! gcmSivEnc.finish()
+        XCTAssertEqual(ctext, expectedCtext)
+
+        let tag = ctext.suffix(16)
+        let gcmSivDec = try! Aes256GcmSivDecryption(key: key, nonce: nonce, associatedData: ad)
+        try! gcmSivDec.update(ctext.prefix(1))
+        try! gcmSivDec.update(ctext.dropFirst(1).dropLast(16))
+        XCTAssertEqual(try! gcmSivDec.finish(tag: tag), Data(ptext))
+
+        var badTag = Data(tag)
+        badTag[0] ^= 1
+        let gcmSivDec2 = try! Aes256GcmSivDecryption(key: key, nonce: nonce, associatedData: ad)
+        try! gcmSivDec2.update(ctext.dropLast(16))
+        XCTAssertThrowsError(try gcmSivDec2.finish(tag: badTag))
+    }
+
     func testAesGcm() {
         let plainTextData = Data("Super🔥secret🔥test🔥data🏁🏁".utf8)
         XCTAssertEqual(39, plainTextData.count)
@@ -132,6 +158,54 @@ class CryptoTests: TestCaseBase {
         XCTAssert(try! gcmDec2.verifyTag(tag))
     }
 
+    func testChaCha20Poly1305Kat() {
+        // RFC 8439 section 2.8.2
+        let key = [UInt8](fromHexString: "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f")!
+        let nonce = [UInt8](fromHexString: "070000004041424344454647")!
+        let ad = [UInt8](fromHexString: "50515253c0c1c2c3c4c5c6c7")!
+        let plaintext = Data("Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".utf8)
+        let expectedCiphertext = Data([UInt8](fromHexString: "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b6116")!)
+        let expectedTag = Data([UInt8](fromHexString: "1ae10b594f09e26a7e902ecbd0600691")!)
+
+        let chachaEnc = try! ChaCha20Poly1305Encryption(key: key, nonce: nonce, associatedData: ad)
+        var ciphertext = plaintext
+        try! chachaEnc.encrypt(&ciphertext[..<1])
+        try! chachaEnc.encrypt(&ciphertext[1...])
+        let tag = try! chachaEnc.computeTag()
+        XCTAssertEqual(ciphertext, expectedCiphertext)
+        XCTAssertEqual(tag, expectedTag)
+
+        let chachaDec = try! ChaCha20Poly1305Decryption(key: key, nonce: nonce, associatedData: ad)
+        var decrypted = ciphertext
+        try! chachaDec.decrypt(&decrypted[..<1])
+        try! chachaDec.decrypt(&decrypted[1...])
+        XCTAssertEqual(decrypted, plaintext)
+        XCTAssert(try! chachaDec.verifyTag(tag))
+
+        var badTag = tag
+        badTag[0] ^= 1
+        let chachaDec2 = try! ChaCha20Poly1305Decryption(key: key, nonce: nonce, associatedData: ad)
+        var decrypted2 = ciphertext
+        try! chachaDec2.decrypt(&decrypted2)
+        XCTAssertFalse(try! chachaDec2.verifyTag(badTag))
+    }
+
+    func testHmacVerify() {
+        // RFC 4231 test case 2
+        let key = Array("Jefe".utf8)
+        let data = Array("what do ya want for nothing?".utf8)
+        var expected = [UInt8](fromHexString: "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")!
+
+        let mac = try! CryptographicMac(algorithm: .hmacSha256, key: key)
+        try! mac.update(data)
+        XCTAssert(try! mac.verify(expected))
+
+        let badMac = try! CryptographicMac(algorithm: .hmacSha256, key: key)
+        try! badMac.update(data)
+        expected[0] ^= 1
+        XCTAssertFalse(try! badMac.verify(expected))
+    }
+
     func testAesCtr() {
         let plainTextData = Data("Super🔥secret🔥test🔥data🏁🏁".utf8)
         let key = self.generateAesKey()
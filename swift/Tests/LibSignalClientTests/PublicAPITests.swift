@@ -124,6 +124,21 @@ class PublicAPITests: TestCaseBase {
         XCTAssertEqual(derived, okm)
     }
 
+    func testHkdfExtractAndExpand() {
+        // https://tools.ietf.org/html/rfc5869 A.1
+        let ikm = [UInt8](repeating: 0x0B, count: 22)
+        let salt = [UInt8](fromHexString: "000102030405060708090a0b0c")!
+        let info = [UInt8](fromHexString: "f0f1f2f3f4f5f6f7f8f9")!
+
+        let prk = try! hkdfExtract(algorithm: .sha256, salt: salt, inputKeyMaterial: ikm)
+        XCTAssertEqual(prk.hexString, "077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5")
+
+        let okm = try! hkdfExpand(algorithm: .sha256, prk: prk, info: info, outputLength: 42)
+        XCTAssertEqual(okm.hexString, "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865")
+
+        XCTAssertThrowsError(try hkdfExpand(algorithm: .sha256, prk: prk, info: info, outputLength: 255 * 32 + 1))
+    }
+
     func testAddress() {
         let addr = try! ProtocolAddress(name: "addr1", deviceId: 5)
         XCTAssertEqual(addr.name, "addr1")